@@ -11,7 +11,7 @@ use fedimint_core::{Amount, PeerId};
 use fedimint_server::config::io::{write_server_config, PLAINTEXT_PASSWORD, SALT_FILE};
 use fedimint_server::config::ServerConfig;
 use fedimint_testing::federation::local_config_gen_params;
-use fedimint_wallet_client::config::WalletClientConfig;
+use fedimint_wallet_client::config::{PegInDescriptorType, WalletClientConfig};
 use fedimintd::attach_default_module_gen_params;
 use fedimintd::fedimintd::Fedimintd as FedimintBuilder;
 use tokio::fs;
@@ -241,6 +241,8 @@ pub async fn run_config_gen(
         Amount::from_sats(100_000_000),
         Network::Regtest,
         10,
+        None,
+        PegInDescriptorType::Wsh,
     );
 
     let peers: Vec<_> = (0..servers).map(|id| PeerId::from(id as u16)).collect();
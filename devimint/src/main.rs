@@ -202,6 +202,17 @@ async fn cli_tests(dev_fed: DevFed) -> Result<()> {
 
     fed.pegin_gateway(99_999, &gw_cln).await?;
 
+    info!("Testing audit with admin client");
+    let audit = cmd!(fed, "admin", "audit")
+        .env("FM_PASSWORD", "pass0")
+        .env("FM_OUR_ID", "0")
+        .out_json()
+        .await?;
+    anyhow::ensure!(
+        audit["net_assets"].as_i64().unwrap() >= 0,
+        "Federation has more liabilities than assets: {audit}"
+    );
+
     let connect_string = fs::read_to_string(format!("{data_dir}/client-connect")).await?;
     fs::remove_file(format!("{data_dir}/client.json")).await?;
     cmd!(fed, "join-federation", connect_string.clone())
@@ -753,7 +764,9 @@ async fn cli_tests_backup_and_restore(fed_cli: &Federation) -> Result<()> {
     //             .as_u64()
     //             .unwrap()
     //     );
-    //     let _ = cmd!(fed_cli, "restore", &secret,).out_json().await?;
+    //     let _ = cmd!(fed_cli, "restore", "--mnemonic", &secret,)
+    //         .out_json()
+    //         .await?;
 
     //     let post_notes = cmd!(fed_cli, "info").out_json().await?;
     //     let post_balance = post_notes["total_msat"].as_u64().unwrap();
@@ -774,7 +787,9 @@ async fn cli_tests_backup_and_restore(fed_cli: &Federation) -> Result<()> {
                 .as_u64()
                 .unwrap()
         );
-        let _ = cmd!(fed_cli, "restore", &secret,).out_json().await?;
+        let _ = cmd!(fed_cli, "restore", "--mnemonic", &secret,)
+            .out_json()
+            .await?;
 
         let post_notes = cmd!(fed_cli, "info").out_json().await?;
         let post_balance = post_notes["total_msat"].as_u64().unwrap();
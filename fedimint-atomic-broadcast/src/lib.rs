@@ -132,12 +132,19 @@ use bitcoin_hashes::{sha256, Hash};
 /// This function runs the broadcast until a shutdown is intiated either via the
 /// shutdown sender or the [OrderedItem] receiver is dropped.
 pub use broadcast::run;
+/// This function loads the [SignedBlock] for a completed session from the
+/// database. Fedimint Consensus can use it to detect that a session has been
+/// completed by the broadcast.
+pub use db::load_block;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::PeerId;
 /// This keychain implements naive threshold schnorr signatures over secp256k1.
 /// The broadcasts uses this keychain to sign messages for peers and create
 /// the threshold signatures for the signed blocks.
 pub use keychain::Keychain;
+/// The version of secp256k1 used by the [Keychain], which differs from the one
+/// used in the rest of Fedimint.
+pub use secp256k1;
 
 /// The majority of these messages need to be delivered to the intended
 /// [Recipient] in order for the broadcast to make progress. However, the
@@ -2,6 +2,7 @@
 
 use std::io::{Read, Write};
 
+pub use bip39::{Error, Language, Mnemonic};
 use fedimint_client::derivable_secret::DerivableSecret;
 use fedimint_client::secret::RootSecretStrategy;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
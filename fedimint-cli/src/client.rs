@@ -1,26 +1,26 @@
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail};
 use bitcoin::secp256k1;
 use bitcoin_hashes::hex;
 use bitcoin_hashes::hex::ToHex;
-use clap::Subcommand;
+use clap::{Args, Subcommand, ValueEnum};
+use fedimint_bip39::Mnemonic;
 use fedimint_client::backup::Metadata;
-use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::oplog::{operation_history_to_csv, OperationLogQuery, OutcomeFilter};
 use fedimint_client::sm::OperationId;
 use fedimint_client::Client;
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
-use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::time::now;
-use fedimint_core::{Amount, ParseAmountError, TieredMulti, TieredSummary};
+use fedimint_core::{Amount, ParseAmountError, TieredSummary};
 use fedimint_ln_client::contracts::ContractId;
 use fedimint_ln_client::{
     InternalPayState, LightningClientExt, LnPayState, LnReceiveState, PayType,
 };
-use fedimint_mint_client::{MintClientExt, MintClientModule, SpendableNote};
+use fedimint_mint_client::{MintClientExt, MintClientModule, OOBNotes};
 use fedimint_wallet_client::{WalletClientExt, WithdrawState};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
@@ -52,14 +52,17 @@ pub enum ClientCmd {
     /// Display wallet info (holdings, tiers)
     Info,
     /// Reissue notes received from a third party to avoid double spends
-    Reissue {
-        #[clap(value_parser = parse_ecash)]
-        notes: TieredMulti<SpendableNote>,
-    },
+    Reissue { notes: OOBNotes },
+    /// Check that notes received from a third party are valid and unspent
+    /// without reissuing them
+    Validate { notes: OOBNotes },
     /// Prepare notes to send to a third party as a payment
     Spend {
         #[clap(value_parser = parse_fedimint_amount)]
         amount: Amount,
+        /// Message for the recipient that is included with the notes
+        #[clap(long)]
+        memo: Option<String>,
     },
     /// Create a lightning invoice to receive payment via gateway
     LnInvoice {
@@ -69,11 +72,49 @@ pub enum ClientCmd {
         description: String,
         #[clap(long)]
         expiry_time: Option<u64>,
+        /// Hold the payment until it is accepted or cancelled, it is
+        /// cancelled automatically after this many blocks
+        #[clap(long)]
+        hold_timeout_blocks: Option<u64>,
     },
     /// Wait for incoming invoice to be paid
     WaitInvoice { operation_id: OperationId },
+    /// Accept the held payment of a hold invoice
+    AcceptHeldInvoice { operation_id: OperationId },
+    /// Cancel the held payment of a hold invoice, refunding the payer
+    CancelHeldInvoice { operation_id: OperationId },
+    /// Register a Lightning Address served by the gateway while we are offline
+    LnurlRegister {
+        username: String,
+        #[clap(long, default_value = "")]
+        description: String,
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        min_sendable: Amount,
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        max_sendable: Amount,
+        /// Number of payments that can be received before registering again
+        #[clap(long, default_value = "10")]
+        num_offers: usize,
+    },
+    /// Create a BOLT12 offer served by the gateway while we are offline
+    Bolt12Register {
+        #[clap(long, default_value = "")]
+        description: String,
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        min_amount: Amount,
+        /// Number of payments that can be received before registering again
+        #[clap(long, default_value = "10")]
+        num_offers: usize,
+    },
     /// Pay a lightning invoice via a gateway
     LnPay { bolt11: lightning_invoice::Invoice },
+    /// Pay a BOLT12 offer via the active gateway
+    LnPayOffer {
+        offer: String,
+        /// Only required if the offer doesn't specify an amount
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        amount: Option<Amount>,
+    },
     /// List registered gateways
     ListGateways,
     /// Switch active gateway
@@ -99,7 +140,13 @@ pub enum ClientCmd {
         /// possibly multiple times)
         // TODO: Can we make it `*Map<String, String>` and avoid custom parsing?
         metadata: Vec<String>,
+        /// Write the encrypted backup to this file instead of uploading it to
+        /// the federation
+        #[clap(long)]
+        output: Option<PathBuf>,
     },
+    /// List the backups stored by the federation, newest first
+    ListBackups,
     /// Wipe the state of the client (mostly for testing purposes)
     #[clap(hide = true)]
     Wipe {
@@ -110,14 +157,98 @@ pub enum ClientCmd {
     /// federation
     #[clap(hide = true)]
     DiscoverVersion,
+    /// Create the secret of a new client, either from a given mnemonic or a
+    /// newly generated one that gets printed
+    Init {
+        #[clap(long, value_parser = parse_mnemonic)]
+        mnemonic: Option<Mnemonic>,
+    },
     /// Restore the previously created backup of mint notes (with `backup`
     /// command)
     Restore {
-        #[clap(value_parser = parse_secret)]
-        secret: [u8; 64],
+        /// Mnemonic of the client to restore
+        #[clap(long, value_parser = parse_mnemonic, required_unless_present = "secret")]
+        mnemonic: Option<Mnemonic>,
+        /// Hex secret of clients created before mnemonics were supported
+        #[clap(long, value_parser = parse_secret, conflicts_with = "mnemonic")]
+        secret: Option<[u8; 64]>,
+        /// Restore from the federation backup in this slot (see `list-backups`)
+        /// instead of the newest one
+        #[clap(long)]
+        slot: Option<u16>,
+        /// Restore from a backup file written by `backup --output` instead of
+        /// a backup stored by the federation
+        #[clap(long, conflicts_with = "slot")]
+        file: Option<PathBuf>,
     },
-    /// Print the secret key of the client
+    /// Print the secret of the client, a mnemonic unless the client was
+    /// created before mnemonics were supported
     PrintSecret,
+    /// List operations from the operation log, newest first
+    ListOperations {
+        #[clap(long, default_value = "10")]
+        limit: usize,
+        #[clap(flatten)]
+        query: OperationQueryArgs,
+    },
+    /// Export the operation history including the amounts of the operations,
+    /// e.g. for bookkeeping
+    ExportHistory {
+        #[clap(long, value_enum, default_value = "json")]
+        format: HistoryFormat,
+        /// Write the history to this file instead of printing it
+        #[clap(long)]
+        output: Option<PathBuf>,
+        #[clap(flatten)]
+        query: OperationQueryArgs,
+    },
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct OperationQueryArgs {
+    /// Only include operations of this type (e.g. `ln`, `mint` or `wallet`),
+    /// can be given multiple times
+    #[clap(long = "type")]
+    operation_types: Vec<String>,
+    /// Only include operations created at or after this unix timestamp
+    #[clap(long)]
+    since: Option<u64>,
+    /// Only include operations created before this unix timestamp
+    #[clap(long)]
+    until: Option<u64>,
+    /// Only include operations whose outcome is known (`finished`) or not
+    /// (`pending`)
+    #[clap(long, value_enum, default_value = "any")]
+    outcome: OutcomeArg,
+}
+
+impl From<OperationQueryArgs> for OperationLogQuery {
+    fn from(args: OperationQueryArgs) -> Self {
+        let from_timestamp = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        OperationLogQuery {
+            operation_types: args.operation_types.into_iter().collect(),
+            start_time: args.since.map(from_timestamp),
+            end_time: args.until.map(from_timestamp),
+            outcome: match args.outcome {
+                OutcomeArg::Any => OutcomeFilter::Any,
+                OutcomeArg::Finished => OutcomeFilter::Finished,
+                OutcomeArg::Pending => OutcomeFilter::Pending,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutcomeArg {
+    Any,
+    Finished,
+    Pending,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum HistoryFormat {
+    Json,
+    Csv,
 }
 
 pub fn parse_gateway_pub_key(s: &str) -> Result<secp256k1::XOnlyPublicKey, secp256k1::Error> {
@@ -128,6 +259,10 @@ fn parse_secret(s: &str) -> Result<[u8; 64], hex::Error> {
     hex::FromHex::from_hex(s)
 }
 
+fn parse_mnemonic(s: &str) -> Result<Mnemonic, fedimint_bip39::Error> {
+    Mnemonic::from_str(s)
+}
+
 pub async fn handle_ng_command(
     command: ClientCmd,
     _config: ClientConfig,
@@ -157,32 +292,93 @@ pub async fn handle_ng_command(
 
             Ok(serde_json::to_value(amount).unwrap())
         }
-        ClientCmd::Spend { amount } => {
-            let (operation, notes) = client
+        ClientCmd::Validate { notes } => {
+            let amount = client.validate_notes(&notes).await?;
+
+            Ok(json!({
+                "amount": amount,
+                "memo": notes.memo(),
+            }))
+        }
+        ClientCmd::Spend { amount, memo } => {
+            let (operation, mut notes) = client
                 .spend_notes(amount, Duration::from_secs(3600), ())
                 .await?;
             info!("Spend e-cash operation: {operation}");
 
+            if let Some(memo) = memo {
+                notes = notes.with_memo(memo);
+            }
+
             Ok(json!({
-                "notes": serialize_ecash(&notes),
+                "notes": notes,
             }))
         }
         ClientCmd::LnInvoice {
             amount,
             description,
             expiry_time,
+            hold_timeout_blocks,
         } => {
             client.select_active_gateway().await?;
 
-            let (operation_id, invoice) = client
-                .create_bolt11_invoice(amount, description, expiry_time)
-                .await?;
+            let (operation_id, invoice) = match hold_timeout_blocks {
+                Some(timeout_blocks) => {
+                    client
+                        .create_bolt11_hold_invoice(
+                            amount,
+                            description,
+                            expiry_time,
+                            timeout_blocks,
+                        )
+                        .await?
+                }
+                None => {
+                    client
+                        .create_bolt11_invoice(amount, description, expiry_time)
+                        .await?
+                }
+            };
             Ok(serde_json::to_value(LnInvoiceResponse {
                 operation_id,
                 invoice: invoice.to_string(),
             })
             .unwrap())
         }
+        ClientCmd::LnurlRegister {
+            username,
+            description,
+            min_sendable,
+            max_sendable,
+            num_offers,
+        } => {
+            let (operation_id, lightning_address) = client
+                .register_lnurl_pay(
+                    username,
+                    description,
+                    min_sendable,
+                    max_sendable,
+                    num_offers,
+                )
+                .await?;
+            Ok(json!({
+                "operation_id": operation_id,
+                "lightning_address": lightning_address,
+            }))
+        }
+        ClientCmd::Bolt12Register {
+            description,
+            min_amount,
+            num_offers,
+        } => {
+            let (operation_id, offer) = client
+                .register_bolt12_offer(description, min_amount, num_offers)
+                .await?;
+            Ok(json!({
+                "operation_id": operation_id,
+                "offer": offer,
+            }))
+        }
         ClientCmd::WaitInvoice { operation_id } => {
             let mut updates = client
                 .subscribe_ln_receive(operation_id)
@@ -204,69 +400,23 @@ pub async fn handle_ng_command(
 
             return Err(anyhow::anyhow!("Lightning receive failed"));
         }
+        ClientCmd::AcceptHeldInvoice { operation_id } => {
+            client.accept_held_payment(operation_id).await?;
+            Ok(json!({ "operation_id": operation_id }))
+        }
+        ClientCmd::CancelHeldInvoice { operation_id } => {
+            client.cancel_held_payment(operation_id).await?;
+            Ok(json!({ "operation_id": operation_id }))
+        }
         ClientCmd::LnPay { bolt11 } => {
             client.select_active_gateway().await?;
 
             let (pay_type, contract_id) = client.pay_bolt11_invoice(bolt11).await?;
-
-            match pay_type {
-                PayType::Internal(operation_id) => {
-                    let mut updates = client
-                        .subscribe_internal_pay(operation_id)
-                        .await?
-                        .into_stream();
-
-                    while let Some(update) = updates.next().await {
-                        match update {
-                            InternalPayState::Preimage(preimage) => {
-                                return Ok(serde_json::to_value(PayInvoiceResponse {
-                                    operation_id,
-                                    contract_id,
-                                    preimage: preimage.to_public_key()?.to_string(),
-                                })
-                                .unwrap());
-                            }
-                            InternalPayState::RefundSuccess(outpoint) => {
-                                let e = format!(
-                                    "Internal payment failed. A refund was issued to {outpoint}"
-                                );
-                                return Err(anyhow!(e));
-                            }
-                            InternalPayState::Error(e) => {
-                                return Err(anyhow!(e));
-                            }
-                            _ => {}
-                        }
-
-                        info!("Update: {:?}", update);
-                    }
-                }
-                PayType::Lightning(operation_id) => {
-                    let mut updates = client.subscribe_ln_pay(operation_id).await?.into_stream();
-
-                    while let Some(update) = updates.next().await {
-                        match update {
-                            LnPayState::Success { preimage } => {
-                                return Ok(serde_json::to_value(PayInvoiceResponse {
-                                    operation_id,
-                                    contract_id,
-                                    preimage,
-                                })
-                                .unwrap());
-                            }
-                            LnPayState::Refunded { gateway_error } => {
-                                info!("{gateway_error}");
-                                return get_note_summary(&client).await;
-                            }
-                            _ => {}
-                        }
-
-                        info!("Update: {:?}", update);
-                    }
-                }
-            };
-
-            return Err(anyhow::anyhow!("Lightning Payment failed"));
+            await_payment(&client, pay_type, contract_id).await
+        }
+        ClientCmd::LnPayOffer { offer, amount } => {
+            let (pay_type, contract_id) = client.pay_bolt12_offer(offer, amount).await?;
+            await_payment(&client, pay_type, contract_id).await
         }
         ClientCmd::ListGateways => {
             let gateways = client.fetch_registered_gateways().await?;
@@ -321,15 +471,47 @@ pub async fn handle_ng_command(
             Ok(serde_json::to_value(()).unwrap())
         }
 
-        ClientCmd::Backup { metadata } => {
-            let metadata = metadata_from_clap_cli(metadata)?;
+        ClientCmd::Backup { metadata, output } => {
+            let metadata = Metadata::from_json_serialized(metadata_from_clap_cli(metadata)?);
 
-            client
-                .backup_to_federation(Metadata::from_json_serialized(metadata))
-                .await?;
-            Ok(serde_json::to_value(()).unwrap())
+            match output {
+                Some(path) => {
+                    let backup = client.create_encrypted_backup(metadata).await?;
+                    std::fs::write(&path, backup.into_bytes())?;
+                    Ok(json!({
+                        "path": path,
+                    }))
+                }
+                None => {
+                    client.backup_to_federation(metadata).await?;
+                    Ok(serde_json::to_value(()).unwrap())
+                }
+            }
+        }
+        ClientCmd::ListBackups => {
+            let backups = client
+                .download_backup_versions_from_federation()
+                .await?
+                .into_iter()
+                .map(|version| {
+                    json!({
+                        "slot": version.slot,
+                        "timestamp": version
+                            .timestamp
+                            .duration_since(UNIX_EPOCH)
+                            .expect("Backups are created after 1970")
+                            .as_secs(),
+                        "epoch_count": version.backup.epoch_count(),
+                        "metadata": version.backup.metadata().to_json_value().ok(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            Ok(json!({
+                "backups": backups,
+            }))
         }
-        ClientCmd::Restore { .. } => {
+        ClientCmd::Init { .. } | ClientCmd::Restore { .. } | ClientCmd::PrintSecret => {
             panic!("Has to be handled before initializing client")
         }
         ClientCmd::Wipe { force } => {
@@ -339,14 +521,56 @@ pub async fn handle_ng_command(
             client.wipe_state().await?;
             Ok(serde_json::to_value(()).unwrap())
         }
-        ClientCmd::PrintSecret => {
-            let secret = client.get_secret::<PlainRootSecretStrategy>().await;
-            let hex_secret = hex::ToHex::to_hex(&secret[..]);
+        ClientCmd::ListOperations { limit, query } => {
+            let operations = client
+                .operation_log()
+                .query_operations(&query.into(), limit, None)
+                .await
+                .into_iter()
+                .map(|(key, entry)| {
+                    json!({
+                        "id": key.operation_id,
+                        "creation_time": key
+                            .creation_time
+                            .duration_since(UNIX_EPOCH)
+                            .expect("Operations are created after 1970")
+                            .as_secs(),
+                        "operation_type": entry.operation_type(),
+                        "meta": entry.meta_json(),
+                        "outcome": entry.outcome_json(),
+                    })
+                })
+                .collect::<Vec<_>>();
 
             Ok(json!({
-                "secret": hex_secret,
+                "operations": operations,
             }))
         }
+        ClientCmd::ExportHistory {
+            format,
+            output,
+            query,
+        } => {
+            let history = client.export_operation_history(&query.into()).await;
+            let exported = match format {
+                HistoryFormat::Json => serde_json::to_string_pretty(&history)?,
+                HistoryFormat::Csv => operation_history_to_csv(&history),
+            };
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, exported)?;
+                    Ok(json!({
+                        "path": path,
+                        "operations": history.len(),
+                    }))
+                }
+                None => match format {
+                    HistoryFormat::Json => Ok(serde_json::to_value(history)?),
+                    HistoryFormat::Csv => Ok(serde_json::Value::String(exported)),
+                },
+            }
+        }
         ClientCmd::Withdraw { amount, address } => {
             let fees = client.get_withdraw_fee(address.clone(), amount).await?;
             let absolute_fees = fees.amount();
@@ -385,6 +609,71 @@ pub async fn handle_ng_command(
     }
 }
 
+/// Waits for a payment started with [`LightningClientExt`] to complete
+async fn await_payment(
+    client: &Client,
+    pay_type: PayType,
+    contract_id: ContractId,
+) -> anyhow::Result<serde_json::Value> {
+    match pay_type {
+        PayType::Internal(operation_id) => {
+            let mut updates = client
+                .subscribe_internal_pay(operation_id)
+                .await?
+                .into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    InternalPayState::Preimage(preimage) => {
+                        return Ok(serde_json::to_value(PayInvoiceResponse {
+                            operation_id,
+                            contract_id,
+                            preimage: preimage.to_public_key()?.to_string(),
+                        })
+                        .unwrap());
+                    }
+                    InternalPayState::RefundSuccess(outpoint) => {
+                        let e =
+                            format!("Internal payment failed. A refund was issued to {outpoint}");
+                        return Err(anyhow!(e));
+                    }
+                    InternalPayState::Error(e) => {
+                        return Err(anyhow!(e));
+                    }
+                    _ => {}
+                }
+
+                info!("Update: {:?}", update);
+            }
+        }
+        PayType::Lightning(operation_id) => {
+            let mut updates = client.subscribe_ln_pay(operation_id).await?.into_stream();
+
+            while let Some(update) = updates.next().await {
+                match update {
+                    LnPayState::Success { preimage } => {
+                        return Ok(serde_json::to_value(PayInvoiceResponse {
+                            operation_id,
+                            contract_id,
+                            preimage,
+                        })
+                        .unwrap());
+                    }
+                    LnPayState::Refunded { gateway_error } => {
+                        info!("{gateway_error}");
+                        return get_note_summary(client).await;
+                    }
+                    _ => {}
+                }
+
+                info!("Update: {:?}", update);
+            }
+        }
+    };
+
+    Err(anyhow::anyhow!("Lightning Payment failed"))
+}
+
 async fn get_note_summary(client: &Client) -> anyhow::Result<serde_json::Value> {
     let (mint_client, _) = client.get_first_module::<MintClientModule>(&fedimint_mint_client::KIND);
     let summary = mint_client
@@ -413,23 +702,9 @@ pub fn parse_fedimint_amount(s: &str) -> Result<fedimint_core::Amount, ParseAmou
     }
 }
 
-pub fn parse_ecash(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
-    let bytes = base64::decode(s)?;
-    Ok(Decodable::consensus_decode(
-        &mut std::io::Cursor::new(bytes),
-        &ModuleDecoderRegistry::default(),
-    )?)
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct PayInvoiceResponse {
     operation_id: OperationId,
     contract_id: ContractId,
     preimage: String,
 }
-
-pub fn serialize_ecash(c: &TieredMulti<SpendableNote>) -> String {
-    let mut bytes = Vec::new();
-    Encodable::consensus_encode(c, &mut bytes).expect("encodes correctly");
-    base64::encode(&bytes)
-}
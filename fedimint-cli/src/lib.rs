@@ -12,19 +12,25 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, result};
 
+use bitcoin_hashes::hex::ToHex;
 use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
+use fedimint_bip39::Bip39RootSecretStrategy;
+use fedimint_client::backup::{EncryptedClientBackup, RestoreSource};
 use fedimint_client::module::gen::{ClientModuleGen, ClientModuleGenRegistry, IClientModuleGen};
-use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::sm::OperationId;
-use fedimint_client::{ClientBuilder, ClientSecret};
+use fedimint_client::{
+    client_root_secret_uses_strategy, get_client_root_secret_encoding,
+    set_client_root_secret_if_missing, ClientBuilder, ClientSecret,
+};
 use fedimint_core::admin_client::WsAdminClient;
 use fedimint_core::api::{
     ClientConfigDownloadToken, FederationApiExt, FederationError, GlobalFederationApi,
     IFederationApi, IGlobalFederationApi, WsClientConnectInfo, WsFederationApi,
 };
 use fedimint_core::config::{load_from_file, ClientConfig, FederationId};
-use fedimint_core::db::DatabaseValue;
+use fedimint_core::db::{Database, DatabaseValue};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::epoch::{SerdeEpochHistory, SignedEpochOutcome};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
@@ -41,7 +47,7 @@ use fedimint_wallet_client::{WalletClientGen, WalletClientModule};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use thiserror::Error;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use url::Url;
 use utils::{from_hex, parse_peer_id};
 
@@ -234,6 +240,16 @@ impl fmt::Display for CliError {
     }
 }
 
+/// Kind of root secret stored in the client database
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ClientSecretKind {
+    /// 64 random bytes, used by clients created before mnemonics were supported
+    LegacyHex,
+    /// BIP39 mnemonic
+    Mnemonic,
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Opts {
@@ -290,6 +306,25 @@ impl Opts {
             .map_err_cli_msg(CliErrorKind::IOError, "could not open transaction db")
     }
 
+    /// Opens the client database and detects which kind of secret the client
+    /// uses
+    async fn load_rocks_db_with_secret_kind(
+        &self,
+    ) -> CliResult<(fedimint_rocksdb::RocksDb, ClientSecretKind)> {
+        let db = self.load_rocks_db()?;
+        let secret_kind = match client_root_secret_uses_strategy::<PlainRootSecretStrategy>(&db)
+            .await
+        {
+            Some(true) => {
+                warn!("Client uses a legacy hex secret, funds can only be restored with it and not with a mnemonic");
+                ClientSecretKind::LegacyHex
+            }
+            // Clients without a secret get a new mnemonic when they are started
+            Some(false) | None => ClientSecretKind::Mnemonic,
+        };
+        Ok((db, secret_kind))
+    }
+
     fn load_decoders(
         &self,
         cfg: &ClientConfig,
@@ -311,24 +346,35 @@ impl Opts {
         ))
     }
 
+    // TODO: open the client through `fedimint_client::multi::MultiFederationClient` once the
+    // single-federation `client.db` and legacy hex secrets can be migrated into it
     async fn build_client_ng(
         &self,
         module_gens: &ClientModuleGenRegistry,
     ) -> CliResult<fedimint_client::Client> {
         let mut tg = TaskGroup::new();
-        let client_builder = self.build_client_ng_builder(module_gens).await?;
-        client_builder
-            .build::<PlainRootSecretStrategy>(&mut tg)
-            .await
-            .map_err_cli_general()
+        let (client_builder, secret_kind) = self.build_client_ng_builder(module_gens).await?;
+        match secret_kind {
+            ClientSecretKind::LegacyHex => {
+                client_builder
+                    .build::<PlainRootSecretStrategy>(&mut tg)
+                    .await
+            }
+            ClientSecretKind::Mnemonic => {
+                client_builder
+                    .build::<Bip39RootSecretStrategy>(&mut tg)
+                    .await
+            }
+        }
+        .map_err_cli_general()
     }
 
     async fn build_client_ng_builder(
         &self,
         module_gens: &ClientModuleGenRegistry,
-    ) -> CliResult<fedimint_client::ClientBuilder> {
+    ) -> CliResult<(fedimint_client::ClientBuilder, ClientSecretKind)> {
         let cfg = self.load_config()?;
-        let db = self.load_rocks_db()?;
+        let (db, secret_kind) = self.load_rocks_db_with_secret_kind().await?;
 
         let mut client_builder = ClientBuilder::default();
         client_builder.with_module_gens(module_gens.clone());
@@ -336,7 +382,7 @@ impl Opts {
         client_builder.with_config(cfg);
         client_builder.with_database(db);
 
-        Ok(client_builder)
+        Ok((client_builder, secret_kind))
     }
 }
 
@@ -378,6 +424,9 @@ enum AdminCmd {
 
     /// Signal a consensus upgrade
     SignalUpgrade,
+
+    /// Show the balance sheet of the federation, broken down by module
+    Audit,
 }
 
 #[derive(Debug, Clone, Subcommand)]
@@ -493,7 +542,7 @@ impl FedimintCli {
 
     pub fn with_default_modules(self) -> Self {
         self.with_module(LightningClientGen)
-            .with_module(MintClientGen)
+            .with_module(MintClientGen::default())
             .with_module(WalletClientGen::default())
     }
 
@@ -541,18 +590,89 @@ impl FedimintCli {
             Command::VersionHash => Ok(CliOutput::VersionHash {
                 hash: env!("CODE_VERSION").to_string(),
             }),
-            Command::Client(ClientCmd::Restore { secret }) => {
+            Command::Client(ClientCmd::Init { mnemonic }) => {
+                let db = cli.load_rocks_db()?;
+                let mnemonic = mnemonic.unwrap_or_else(|| {
+                    Bip39RootSecretStrategy::<12>::random(&mut rand::thread_rng())
+                });
+                set_client_root_secret_if_missing(
+                    &db,
+                    &ClientSecret::<Bip39RootSecretStrategy>::new(mnemonic.clone()),
+                )
+                .await
+                .map_err_cli_general()?
+                .then_some(())
+                .ok_or_cli_msg(
+                    CliErrorKind::GeneralFailure,
+                    "client was already initialized",
+                )?;
+
+                Ok(CliOutput::Raw(json!({
+                    "mnemonic": mnemonic.to_string(),
+                })))
+            }
+            Command::Client(ClientCmd::PrintSecret) => {
+                let (db, secret_kind) = cli.load_rocks_db_with_secret_kind().await?;
+                let db = Database::new(db, Default::default());
+                let secret = match secret_kind {
+                    ClientSecretKind::LegacyHex => {
+                        get_client_root_secret_encoding::<PlainRootSecretStrategy>(&db)
+                            .await
+                            .to_hex()
+                    }
+                    ClientSecretKind::Mnemonic => {
+                        get_client_root_secret_encoding::<Bip39RootSecretStrategy>(&db)
+                            .await
+                            .to_string()
+                    }
+                };
+
+                Ok(CliOutput::Raw(json!({
+                    "secret": secret,
+                    "format": secret_kind,
+                })))
+            }
+            Command::Client(ClientCmd::Restore {
+                mnemonic,
+                secret,
+                slot,
+                file,
+            }) => {
                 let mut tg = TaskGroup::new();
-                let (client, metadata) = cli
+                let (client_builder, _) = cli
                     .build_client_ng_builder(&self.module_gens)
                     .await
-                    .map_err_cli_msg(CliErrorKind::GeneralFailure, "failure")?
-                    .build_restoring_from_backup(
-                        &mut tg,
-                        ClientSecret::<PlainRootSecretStrategy>::new(secret),
-                    )
-                    .await
                     .map_err_cli_msg(CliErrorKind::GeneralFailure, "failure")?;
+                let source = match (slot, file) {
+                    (_, Some(path)) => RestoreSource::Backup(EncryptedClientBackup::from_bytes(
+                        std::fs::read(path)
+                            .map_err_cli_msg(CliErrorKind::IOError, "could not read backup file")?,
+                    )),
+                    (Some(slot), None) => RestoreSource::FederationBackupSlot(slot),
+                    (None, None) => RestoreSource::NewestFederationBackup,
+                };
+                let (client, metadata) = match mnemonic {
+                    Some(mnemonic) => {
+                        client_builder
+                            .build_restoring_from(
+                                &mut tg,
+                                ClientSecret::<Bip39RootSecretStrategy>::new(mnemonic),
+                                source,
+                            )
+                            .await
+                    }
+                    None => {
+                        let secret = secret.expect("clap requires either a mnemonic or a secret");
+                        client_builder
+                            .build_restoring_from(
+                                &mut tg,
+                                ClientSecret::<PlainRootSecretStrategy>::new(secret),
+                                source,
+                            )
+                            .await
+                    }
+                }
+                .map_err_cli_msg(CliErrorKind::GeneralFailure, "failure")?;
 
                 info!("Waiting for restore to complete");
                 client
@@ -611,6 +731,13 @@ impl FedimintCli {
                 cli.admin_client().await?.signal_upgrade().await?;
                 Ok(CliOutput::SignalUpgrade)
             }
+            Command::Admin(AdminCmd::Audit) => {
+                let audit = cli.admin_client().await?.audit().await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(audit)
+                        .map_err_cli_msg(CliErrorKind::GeneralFailure, "invalid response")?,
+                ))
+            }
             Command::Dev(DevCmd::Api {
                 method,
                 params,
@@ -873,7 +873,10 @@ impl Client<UserClientConfig> {
                 let amount = c.amount;
                 (contract_id, amount)
             }
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
+            LightningOutput::Offer(_)
+            | LightningOutput::CancelOutgoing { .. }
+            | LightningOutput::HoldOffer { .. }
+            | LightningOutput::SettleHeld { .. } => {
                 panic!()
             } // FIXME: impl TryFrom
         };
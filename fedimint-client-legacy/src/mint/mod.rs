@@ -166,14 +166,14 @@ impl ClientModule for MintClient {
     fn input_amount(&self, input: &MintInput) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: input.total_amount(),
-            fee: self.config.fee_consensus.note_spend_abs * (input.count_items() as u64),
+            fee: self.config.fee_consensus.spend_fee(&input.0),
         }
     }
 
     fn output_amount(&self, output: &MintOutput) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: output.total_amount(),
-            fee: self.config.fee_consensus.note_issuance_abs * (output.count_items() as u64),
+            fee: self.config.fee_consensus.issuance_fee(&output.0),
         }
     }
 }
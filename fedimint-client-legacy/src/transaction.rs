@@ -214,6 +214,7 @@ impl Final for OutputOutcome {
                 ContractOutcome::Outgoing(_) => true,
             },
             OutputOutcome::LN(LightningOutputOutcome::CancelOutgoingContract { .. }) => true,
+            OutputOutcome::LN(LightningOutputOutcome::SettleHeldContract { .. }) => true,
         }
     }
 }
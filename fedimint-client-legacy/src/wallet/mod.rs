@@ -161,16 +161,24 @@ impl WalletClient {
     ) -> Result<bitcoin::Txid> {
         // TODO: define timeout centrally
         let timeout = std::time::Duration::from_secs(15);
-        let outcome = self
-            .context
-            .api
-            .await_output_outcome::<WalletOutputOutcome>(
-                out_point,
-                timeout,
-                &<WalletClientModule as fedimint_client::module::ClientModule>::decoder(),
-            )
-            .await?;
-        Ok(outcome.0)
+        loop {
+            let outcome = self
+                .context
+                .api
+                .await_output_outcome::<WalletOutputOutcome>(
+                    out_point,
+                    timeout,
+                    &<WalletClientModule as fedimint_client::module::ClientModule>::decoder(),
+                )
+                .await?;
+            match outcome {
+                WalletOutputOutcome::Transaction(txid) => return Ok(txid),
+                WalletOutputOutcome::Queued => {
+                    debug!(%out_point, "Peg-out is waiting for its batch");
+                    fedimint_core::task::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
     }
 }
 
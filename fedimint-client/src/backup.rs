@@ -1,67 +1,136 @@
-use std::cmp::Reverse;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::time::SystemTime;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bitcoin::secp256k1;
-use fedimint_core::api::GlobalFederationApi;
+use fedimint_core::api::{FederationError, GlobalFederationApi};
 use fedimint_core::core::backup::{BackupRequest, SignedBackupRequest};
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_derive_secret::DerivableSecret;
+use fedimint_derive_secret::{ChildId, DerivableSecret};
 use fedimint_logging::{LOG_CLIENT, LOG_CLIENT_BACKUP, LOG_CLIENT_RECOVERY};
 use secp256k1_zkp::{KeyPair, Secp256k1};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tracing::{debug, info, warn};
 
 use super::Client;
+use crate::db::NextBackupSlotKey;
 use crate::get_client_root_secret_encoding;
 use crate::secret::{DeriveableSecretClientExt, RootSecretStrategy};
 
+/// Number of backups the federation keeps for each client. Every backup
+/// overwrites the oldest one, so a client can be restored from one of the
+/// previous backups if the newest one is unusable.
+pub const BACKUP_VERSIONS: u16 = 4;
+
+/// Version of the schema of the application data stored in [`Metadata`]
+#[derive(
+    Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Encodable, Decodable,
+)]
+pub struct MetadataSchemaVersion(pub u16);
+
+impl MetadataSchemaVersion {
+    /// Version of the metadata of backups created before metadata was
+    /// versioned, also used if the application doesn't version its metadata
+    pub const UNVERSIONED: MetadataSchemaVersion = MetadataSchemaVersion(0);
+}
+
+/// Application data with a schema version, so that metadata of backups made by
+/// older versions of the application can be migrated on restore
+pub trait VersionedMetadata: Serialize + serde::de::DeserializeOwned {
+    /// Schema version of the current application version
+    const SCHEMA_VERSION: MetadataSchemaVersion;
+
+    /// Convert json metadata of an older schema `version` into the current one
+    fn migrate(version: MetadataSchemaVersion, value: serde_json::Value) -> Result<Self>;
+}
+
 /// Backup metadata
 ///
 /// A backup can have a blob of extra data encoded in it. We provide methods to
 /// use json encoding, but clients are free to use their own encoding.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Encodable, Decodable, Clone)]
-pub struct Metadata(Vec<u8>);
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Metadata {
+    schema_version: MetadataSchemaVersion,
+    data: Vec<u8>,
+}
 
 impl Metadata {
     /// Create empty metadata
     pub fn empty() -> Self {
-        Self(vec![])
+        Self::from_raw(vec![])
     }
 
     pub fn from_raw(bytes: Vec<u8>) -> Self {
-        Self(bytes)
+        Self {
+            schema_version: MetadataSchemaVersion::UNVERSIONED,
+            data: bytes,
+        }
     }
 
     pub fn into_raw(self) -> Vec<u8> {
-        self.0
+        self.data
+    }
+
+    /// Set the schema version of the data
+    pub fn with_schema_version(self, schema_version: MetadataSchemaVersion) -> Self {
+        Self {
+            schema_version,
+            ..self
+        }
+    }
+
+    pub fn schema_version(&self) -> MetadataSchemaVersion {
+        self.schema_version
     }
 
     /// Is metadata empty
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.data.is_empty()
     }
 
     /// Create metadata as json from typed `val`
     pub fn from_json_serialized<T: Serialize>(val: T) -> Self {
-        Self(serde_json::to_vec(&val).expect("serializing to vec can't fail"))
+        Self::from_raw(serde_json::to_vec(&val).expect("serializing to vec can't fail"))
     }
 
     /// Attempt to deserialize metadata as typed json
     pub fn to_json_deserialized<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
-        Ok(serde_json::from_slice(&self.0)?)
+        Ok(serde_json::from_slice(&self.data)?)
     }
 
     /// Attempt to deserialize metadata as untyped json (`serde_json::Value`)
     pub fn to_json_value(&self) -> Result<serde_json::Value> {
-        Ok(serde_json::from_slice(&self.0)?)
+        Ok(serde_json::from_slice(&self.data)?)
+    }
+
+    /// Create metadata as json from `val`, tagged with the current schema
+    /// version of `T`
+    pub fn from_versioned<T: VersionedMetadata>(val: &T) -> Self {
+        Self::from_json_serialized(val).with_schema_version(T::SCHEMA_VERSION)
+    }
+
+    /// Deserialize metadata created with [`Metadata::from_versioned`],
+    /// migrating it if it was created with an older schema version
+    pub fn to_versioned<T: VersionedMetadata>(&self) -> Result<T> {
+        match self.schema_version.cmp(&T::SCHEMA_VERSION) {
+            Ordering::Equal => self.to_json_deserialized(),
+            Ordering::Less => T::migrate(self.schema_version, self.to_json_value()?),
+            Ordering::Greater => bail!(
+                "Metadata schema version {} is newer than the supported version {}",
+                self.schema_version.0,
+                T::SCHEMA_VERSION.0
+            ),
+        }
     }
 }
 
 /// Client state backup
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Encodable, Decodable)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 pub struct ClientBackup {
     /// Epoch count taken right before taking the backup
     epoch_count: u64,
@@ -71,7 +140,58 @@ pub struct ClientBackup {
     modules: BTreeMap<ModuleInstanceId, Vec<u8>>,
 }
 
+impl Encodable for ClientBackup {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        len += self.epoch_count.consensus_encode(writer)?;
+        len += self.metadata.data.consensus_encode(writer)?;
+        len += self.modules.consensus_encode(writer)?;
+        // Appended after the fields of the original format, see `consensus_decode`
+        len += self.metadata.schema_version.consensus_encode(writer)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for ClientBackup {
+    fn consensus_decode<R: Read>(
+        r: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let epoch_count = u64::consensus_decode(r, modules)?;
+        let data = Vec::<u8>::consensus_decode(r, modules)?;
+        let module_backups = BTreeMap::consensus_decode(r, modules)?;
+
+        // Backups created before metadata was versioned are only followed by the zero
+        // padding, which is also how the unversioned schema version is encoded
+        let mut rest = vec![];
+        r.read_to_end(&mut rest).map_err(DecodeError::from_err)?;
+        let schema_version = if rest.iter().all(|byte| *byte == 0) {
+            MetadataSchemaVersion::UNVERSIONED
+        } else {
+            MetadataSchemaVersion::consensus_decode(&mut rest.as_slice(), modules)?
+        };
+
+        Ok(ClientBackup {
+            epoch_count,
+            metadata: Metadata {
+                schema_version,
+                data,
+            },
+            modules: module_backups,
+        })
+    }
+}
+
 impl ClientBackup {
+    /// Epoch count taken right before taking the backup
+    pub fn epoch_count(&self) -> u64 {
+        self.epoch_count
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     /// Align an ecoded message size up for better privacy
     fn get_alignment_size(len: usize) -> usize {
         let padding_alignment = 16 * 1024;
@@ -107,9 +227,21 @@ impl ClientBackup {
 }
 
 /// Encrypted version of [`ClientBackup`].
+///
+/// Besides uploading it to the federation it can be stored elsewhere (e.g. in a
+/// file) using [`EncryptedClientBackup::into_bytes`] and restored with
+/// [`RestoreSource::Backup`].
 pub struct EncryptedClientBackup(Vec<u8>);
 
 impl EncryptedClientBackup {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
     pub fn decrypt_with(mut self, key: &fedimint_aead::LessSafeKey) -> Result<ClientBackup> {
         let decrypted = fedimint_aead::decrypt(&mut self.0, key)?;
         ClientBackup::decode(decrypted)
@@ -135,6 +267,57 @@ impl EncryptedClientBackup {
     }
 }
 
+/// Why the federation didn't accept a backup
+#[derive(Debug, Error)]
+pub enum BackupUploadError {
+    #[error("Backup is larger than the guardians accept: {0}")]
+    TooLarge(String),
+    #[error("Guardians accept no more new backups right now, try again later: {0}")]
+    RateLimited(String),
+    #[error("Guardians don't have space for more backups: {0}")]
+    QuotaExceeded(String),
+    #[error("Failed to upload backup: {0}")]
+    Other(anyhow::Error),
+}
+
+impl From<FederationError> for BackupUploadError {
+    fn from(error: FederationError) -> Self {
+        // Guardians enforce their limits independently, so it's enough if one of them
+        // rejected the backup for a reason we can report
+        for (_, api_error) in error.member_api_errors() {
+            match api_error.code {
+                413 => return BackupUploadError::TooLarge(api_error.message),
+                429 => return BackupUploadError::RateLimited(api_error.message),
+                507 => return BackupUploadError::QuotaExceeded(api_error.message),
+                _ => {}
+            }
+        }
+        BackupUploadError::Other(error.into())
+    }
+}
+
+/// Backup stored by the federation in one of the [`BACKUP_VERSIONS`] slots
+#[derive(Debug)]
+pub struct FederationBackupVersion {
+    pub slot: u16,
+    /// Time the backup was uploaded at
+    pub timestamp: SystemTime,
+    pub backup: ClientBackup,
+}
+
+/// What to restore a client from
+pub enum RestoreSource {
+    /// The newest backup stored by the federation, or from scratch if there is
+    /// none
+    NewestFederationBackup,
+    /// The backup stored by the federation in the given slot, see
+    /// [`Client::download_backup_versions_from_federation`]
+    FederationBackupSlot(u16),
+    /// A backup created with [`Client::create_encrypted_backup`] that was
+    /// stored outside of the federation
+    Backup(EncryptedClientBackup),
+}
+
 impl Client {
     /// Create a backup, include provided `metadata`
     pub async fn create_backup(&self, metadata: Metadata) -> anyhow::Result<ClientBackup> {
@@ -218,44 +401,94 @@ impl Client {
         Ok(())
     }
 
-    /// Upload `backup` to federation
-    pub async fn upload_backup(&self, backup: EncryptedClientBackup) -> Result<()> {
+    /// Upload `backup` to federation, replacing the oldest of the
+    /// [`BACKUP_VERSIONS`] backups stored there
+    pub async fn upload_backup(
+        &self,
+        backup: EncryptedClientBackup,
+    ) -> Result<(), BackupUploadError> {
+        let slot = self
+            .db()
+            .begin_transaction()
+            .await
+            .get_value(&NextBackupSlotKey)
+            .await
+            .unwrap_or(0);
+
         let size = backup.len();
         info!(
             target: LOG_CLIENT_BACKUP,
-            size, "Uploading backup to federation"
+            size, slot, "Uploading backup to federation"
         );
-        let backup_request = backup.into_backup_request(&self.get_derived_backup_signing_key())?;
+        let backup_request = backup
+            .into_backup_request(&self.get_derived_backup_signing_key(slot))
+            .map_err(BackupUploadError::Other)?;
         self.inner.api.upload_backup(&backup_request).await?;
         info!(
             target: LOG_CLIENT_BACKUP,
-            size, "Uploaded backup to federation"
+            size, slot, "Uploaded backup to federation"
         );
+
+        let mut dbtx = self.db().begin_transaction().await;
+        dbtx.insert_entry(&NextBackupSlotKey, &((slot + 1) % BACKUP_VERSIONS))
+            .await;
+        dbtx.commit_tx().await;
+
         Ok(())
     }
 
-    /// Restore client state from backup download from federation (if found) or
-    /// from scratch
+    /// Restore client state from `source`, or from scratch if `source` is
+    /// [`RestoreSource::NewestFederationBackup`] and the federation doesn't
+    /// have a backup
     ///
     /// This will restore (or initialize restoration process) in all sub-modules
     /// that support it.
-    pub(crate) async fn restore_from_backup(&self) -> Result<Metadata> {
+    pub(crate) async fn restore_from_backup(&self, source: RestoreSource) -> Result<Metadata> {
         info!(target: LOG_CLIENT_RECOVERY, "Restoring from backup");
-        let backup = if let Some(backup) = self.download_backup_from_federation().await? {
-            info!(
-                target: LOG_CLIENT_RECOVERY,
-                epoch = backup.epoch_count,
-                "Found backup"
-            );
-            Some(backup)
-        } else {
+        let backup = match source {
+            RestoreSource::Backup(backup) => {
+                let backup = backup.decrypt_with(&self.get_derived_backup_encryption_key())?;
+                info!(
+                    target: LOG_CLIENT_RECOVERY,
+                    epoch = backup.epoch_count,
+                    "Using provided backup"
+                );
+                Some(backup)
+            }
+            source => {
+                let versions = self.download_backup_versions_from_federation().await?;
+                self.set_next_backup_slot(&versions).await;
+
+                let version = if let RestoreSource::FederationBackupSlot(slot) = source {
+                    let version = versions
+                        .into_iter()
+                        .find(|version| version.slot == slot)
+                        .ok_or_else(|| {
+                            anyhow::format_err!("Federation has no valid backup in slot {slot}")
+                        })?;
+                    Some(version)
+                } else {
+                    Self::newest_backup_version(versions)
+                };
+                version.map(|version| {
+                    info!(
+                        target: LOG_CLIENT_RECOVERY,
+                        slot = version.slot,
+                        epoch = version.backup.epoch_count,
+                        "Found backup"
+                    );
+                    version.backup
+                })
+            }
+        };
+
+        if backup.is_none() {
             warn!(
                 target: LOG_CLIENT_RECOVERY,
                 id=%self.get_backup_id(),
                 "Could not find any valid existing backup. Will attempt to restore from scratch. This might take a long time."
             );
-            None
-        };
+        }
 
         let metadata = backup
             .as_ref()
@@ -292,20 +525,48 @@ impl Client {
 
     /// Download most recent valid backup found from the Federation
     pub async fn download_backup_from_federation(&self) -> Result<Option<ClientBackup>> {
+        let versions = self.download_backup_versions_from_federation().await?;
+        Ok(Self::newest_backup_version(versions).map(|version| version.backup))
+    }
+
+    /// Download the valid backups of all slots from the Federation, newest
+    /// first
+    pub async fn download_backup_versions_from_federation(
+        &self,
+    ) -> Result<Vec<FederationBackupVersion>> {
+        let mut versions = vec![];
+        for slot in 0..BACKUP_VERSIONS {
+            if let Some(version) = self.download_backup_slot_from_federation(slot).await? {
+                versions.push(version);
+            }
+        }
+        versions.sort_by_key(|version| Reverse(version.timestamp));
+        Ok(versions)
+    }
+
+    async fn download_backup_slot_from_federation(
+        &self,
+        slot: u16,
+    ) -> Result<Option<FederationBackupVersion>> {
         let mut responses: Vec<_> = self
             .inner
             .api
-            .download_backup(&self.get_backup_id())
+            .download_backup(&self.get_backup_id_for_slot(slot))
             .await?
             .into_iter()
             .filter_map(|backup| {
                 match EncryptedClientBackup(backup.data)
                     .decrypt_with(&self.get_derived_backup_encryption_key())
                 {
-                    Ok(valid) => Some(valid),
+                    Ok(valid) => Some(FederationBackupVersion {
+                        slot,
+                        timestamp: backup.timestamp,
+                        backup: valid,
+                    }),
                     Err(e) => {
                         warn!(
                             target: LOG_CLIENT_RECOVERY,
+                            slot,
                             "Invalid backup returned by one of the peers: {e}"
                         );
                         None
@@ -316,19 +577,57 @@ impl Client {
 
         debug!(
             target: LOG_CLIENT_RECOVERY,
+            slot,
             "Received {} valid responses",
             responses.len()
         );
         // Use the newest (highest epoch)
-        responses.sort_by_key(|backup| Reverse(backup.epoch_count));
+        responses.sort_by_key(|version| Reverse(version.backup.epoch_count));
 
         Ok(responses.into_iter().next())
     }
 
+    /// Picks the backup with the highest epoch, preferring the more recently
+    /// uploaded one if multiple backups were taken in the same epoch
+    fn newest_backup_version(
+        versions: Vec<FederationBackupVersion>,
+    ) -> Option<FederationBackupVersion> {
+        versions
+            .into_iter()
+            .max_by_key(|version| (version.backup.epoch_count, version.timestamp))
+    }
+
+    /// Makes the next backup overwrite an empty slot, or the oldest backup if
+    /// all slots are used
+    async fn set_next_backup_slot(&self, versions: &[FederationBackupVersion]) {
+        let next_slot = (0..BACKUP_VERSIONS)
+            .find(|slot| versions.iter().all(|version| version.slot != *slot))
+            .or_else(|| {
+                versions
+                    .iter()
+                    .min_by_key(|version| version.timestamp)
+                    .map(|version| version.slot)
+            })
+            .unwrap_or(0);
+
+        let mut dbtx = self.db().begin_transaction().await;
+        dbtx.insert_entry(&NextBackupSlotKey, &next_slot).await;
+        dbtx.commit_tx().await;
+    }
+
     /// Backup id derived from the root secret key (public key used to self-sign
     /// backup requests)
+    ///
+    /// This is the id of the first backup slot, which is also the one used by
+    /// clients that didn't keep multiple backups yet.
     pub fn get_backup_id(&self) -> bitcoin::XOnlyPublicKey {
-        self.get_derived_backup_signing_key().x_only_public_key().0
+        self.get_backup_id_for_slot(0)
+    }
+
+    fn get_backup_id_for_slot(&self, slot: u16) -> bitcoin::XOnlyPublicKey {
+        self.get_derived_backup_signing_key(slot)
+            .x_only_public_key()
+            .0
     }
 
     /// Static version of [`Self::get_derived_backup_encryption_key`] for
@@ -341,18 +640,27 @@ impl Client {
 
     /// Static version of [`Self::get_derived_backup_signing_key`] for testing
     /// without creating whole `MintClient`
-    fn get_derived_backup_signing_key_static(secret: &DerivableSecret) -> secp256k1_zkp::KeyPair {
-        secret
-            .derive_backup_secret()
-            .to_secp_key(&Secp256k1::<secp256k1::SignOnly>::gen_new())
+    fn get_derived_backup_signing_key_static(
+        secret: &DerivableSecret,
+        slot: u16,
+    ) -> secp256k1_zkp::KeyPair {
+        let backup_secret = secret.derive_backup_secret();
+        // The first slot uses the key that was used before multiple backups were kept,
+        // so existing backups can still be found
+        let slot_secret = if slot == 0 {
+            backup_secret
+        } else {
+            backup_secret.child_key(ChildId(u64::from(slot)))
+        };
+        slot_secret.to_secp_key(&Secp256k1::<secp256k1::SignOnly>::gen_new())
     }
 
     fn get_derived_backup_encryption_key(&self) -> fedimint_aead::LessSafeKey {
         Self::get_derived_backup_encryption_key_static(&self.root_secret())
     }
 
-    fn get_derived_backup_signing_key(&self) -> secp256k1::KeyPair {
-        Self::get_derived_backup_signing_key_static(&self.root_secret())
+    fn get_derived_backup_signing_key(&self, slot: u16) -> secp256k1::KeyPair {
+        Self::get_derived_backup_signing_key_static(&self.root_secret(), slot)
     }
 
     pub async fn get_secret<S>(&self) -> S::Encoding
@@ -1,7 +1,17 @@
-use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, Result};
+use bitcoin::secp256k1;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::encoding::Encodable;
 use fedimint_derive_secret::DerivableSecret;
+use secp256k1_zkp::Secp256k1;
+use serde::{Deserialize, Serialize};
 
-use crate::backup::{ClientBackup, Metadata};
+use crate::backup::{
+    ClientBackup, Metadata, MetadataSchemaVersion, VersionedMetadata, BACKUP_VERSIONS,
+};
+use crate::secret::DeriveableSecretClientExt;
 use crate::Client;
 
 #[test]
@@ -48,3 +58,112 @@ fn sanity_ecash_backup_encrypt_decrypt() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn sanity_ecash_backup_decode_unversioned() -> Result<()> {
+    let modules = BTreeMap::<ModuleInstanceId, Vec<u8>>::from([(1, vec![4, 5])]);
+
+    // Format of backups created before the metadata schema version was added
+    let mut encoded = vec![];
+    7u64.consensus_encode(&mut encoded)?;
+    vec![1u8, 2, 3].consensus_encode(&mut encoded)?;
+    modules.consensus_encode(&mut encoded)?;
+
+    let expected = ClientBackup {
+        epoch_count: 7,
+        metadata: Metadata::from_raw(vec![1, 2, 3]),
+        modules,
+    };
+    assert_eq!(expected, ClientBackup::decode(&encoded)?);
+
+    encoded.extend([0; 3]);
+    assert_eq!(expected, ClientBackup::decode(&encoded)?);
+
+    Ok(())
+}
+
+#[test]
+fn sanity_ecash_backup_decode_encode_versioned() -> Result<()> {
+    let orig = ClientBackup {
+        epoch_count: 3,
+        metadata: Metadata::from_raw(vec![1, 2, 3]).with_schema_version(MetadataSchemaVersion(2)),
+        modules: Default::default(),
+    };
+
+    let decoded = ClientBackup::decode(&orig.encode()?)?;
+    assert_eq!(
+        decoded.metadata().schema_version(),
+        MetadataSchemaVersion(2)
+    );
+    assert_eq!(orig, decoded);
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct AppState {
+    name: String,
+    contacts: Vec<String>,
+}
+
+impl VersionedMetadata for AppState {
+    const SCHEMA_VERSION: MetadataSchemaVersion = MetadataSchemaVersion(1);
+
+    fn migrate(version: MetadataSchemaVersion, value: serde_json::Value) -> Result<Self> {
+        if version != MetadataSchemaVersion::UNVERSIONED {
+            bail!("Unknown version {version:?}");
+        }
+        Ok(AppState {
+            name: serde_json::from_value(value)?,
+            contacts: vec![],
+        })
+    }
+}
+
+#[test]
+fn metadata_versioned_migration() -> Result<()> {
+    let state = AppState {
+        name: "alice".to_string(),
+        contacts: vec!["bob".to_string()],
+    };
+    let current = Metadata::from_versioned(&state);
+    assert_eq!(current.to_versioned::<AppState>()?, state);
+
+    let old = Metadata::from_json_serialized("carol");
+    assert_eq!(
+        old.to_versioned::<AppState>()?,
+        AppState {
+            name: "carol".to_string(),
+            contacts: vec![],
+        }
+    );
+
+    let newer =
+        Metadata::from_json_serialized(&state).with_schema_version(MetadataSchemaVersion(2));
+    assert!(newer.to_versioned::<AppState>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn backup_slot_keys() {
+    let secret = DerivableSecret::new_root(&[1; 32], &[1, 32]);
+
+    // Backups made before multiple versions were kept have to stay discoverable
+    let legacy_key = secret
+        .derive_backup_secret()
+        .to_secp_key(&Secp256k1::<secp256k1::SignOnly>::gen_new());
+    assert_eq!(
+        Client::get_derived_backup_signing_key_static(&secret, 0).x_only_public_key(),
+        legacy_key.x_only_public_key()
+    );
+
+    let ids = (0..BACKUP_VERSIONS)
+        .map(|slot| {
+            Client::get_derived_backup_signing_key_static(&secret, slot)
+                .x_only_public_key()
+                .0
+        })
+        .collect::<BTreeSet<_>>();
+    assert_eq!(ids.len(), BACKUP_VERSIONS as usize);
+}
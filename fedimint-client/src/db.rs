@@ -1,6 +1,7 @@
 use std::io::{Error, Read, Write};
 use std::marker::PhantomData;
 
+use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{impl_db_lookup, impl_db_record};
@@ -18,6 +19,10 @@ pub enum DbKeyPrefix {
     ClientSecret = 0x29,
     OperationLog = 0x2c,
     ChronologicalOperationLog = 0x2d,
+    OperationTypeLog = 0x2e,
+    OperationTypeIndexBackfilled = 0x2f,
+    FederationConfig = 0x30,
+    NextBackupSlot = 0x31,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -91,3 +96,72 @@ impl_db_lookup!(
     key = ChronologicalOperationLogKey,
     query_prefix = ChronologicalOperationLogKeyPrefix
 );
+
+/// Key used to lookup operation log entries of a certain type in chronological
+/// order
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationTypeLogKey {
+    pub operation_type: String,
+    pub creation_time: std::time::SystemTime,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationTypeLogKeyPrefix {
+    pub operation_type: String,
+}
+
+impl_db_record!(
+    key = OperationTypeLogKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationTypeLog
+);
+
+impl_db_lookup!(
+    key = OperationTypeLogKey,
+    query_prefix = OperationTypeLogKeyPrefix
+);
+
+/// Marks that operations logged before [`OperationTypeLogKey`] was introduced
+/// have been added to the index
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OperationTypeIndexBackfilledKey;
+
+impl_db_record!(
+    key = OperationTypeIndexBackfilledKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationTypeIndexBackfilled
+);
+
+/// Config of a federation joined through a
+/// [`crate::multi::MultiFederationClient`], stored in the database namespace of
+/// the multi-federation client itself
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct FederationConfigKey {
+    pub id: FederationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct FederationConfigKeyPrefix;
+
+impl_db_record!(
+    key = FederationConfigKey,
+    value = ClientConfig,
+    db_prefix = DbKeyPrefix::FederationConfig
+);
+
+impl_db_lookup!(
+    key = FederationConfigKey,
+    query_prefix = FederationConfigKeyPrefix
+);
+
+/// Slot of the federation backup that gets overwritten by the next backup, see
+/// [`crate::backup::BACKUP_VERSIONS`]
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct NextBackupSlotKey;
+
+impl_db_record!(
+    key = NextBackupSlotKey,
+    value = u16,
+    db_prefix = DbKeyPrefix::NextBackupSlot
+);
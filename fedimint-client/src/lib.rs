@@ -72,7 +72,7 @@ use std::fmt::{Debug, Formatter};
 use std::io::{Error, Read, Write};
 use std::sync::Arc;
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, ensure};
 use async_stream::stream;
 use fedimint_core::api::{
     ApiVersionSet, DynGlobalApi, DynModuleApi, GlobalFederationApi, IGlobalFederationApi,
@@ -80,7 +80,9 @@ use fedimint_core::api::{
 };
 use fedimint_core::config::{ClientConfig, FederationId, ModuleGenRegistry};
 use fedimint_core::core::{DynInput, DynOutput, IInput, IOutput, ModuleInstanceId, ModuleKind};
-use fedimint_core::db::{AutocommitError, Database, DatabaseTransaction, IDatabase};
+use fedimint_core::db::{
+    AutocommitError, Database, DatabaseKeyPrefix, DatabaseTransaction, IDatabase,
+};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
@@ -105,13 +107,13 @@ use secret::DeriveableSecretClientExt;
 use serde::Serialize;
 use tracing::info;
 
-use crate::backup::Metadata;
+use crate::backup::{Metadata, RestoreSource};
 use crate::db::ClientSecretKey;
 use crate::module::gen::{
     ClientModuleGen, ClientModuleGenRegistry, DynClientModuleGen, IClientModuleGen,
 };
 use crate::module::{ClientModule, ClientModuleRegistry, IClientModule, StateGenerator};
-use crate::oplog::OperationLog;
+use crate::oplog::{OperationHistoryEntry, OperationLog, OperationLogQuery};
 use crate::secret::RootSecretStrategy;
 use crate::sm::executor::{
     ActiveOperationStateKeyPrefix, ContextGen, InactiveOperationStateKeyPrefix,
@@ -122,8 +124,8 @@ use crate::sm::{
 };
 use crate::transaction::{
     tx_submission_sm_decoder, ClientInput, ClientOutput, TransactionBuilder,
-    TransactionBuilderBalance, TxSubmissionContext, TxSubmissionError, TxSubmissionStates,
-    TRANSACTION_SUBMISSION_MODULE_INSTANCE,
+    TransactionBuilderBalance, TransactionPreview, TxSubmissionContext, TxSubmissionError,
+    TxSubmissionStates, TRANSACTION_SUBMISSION_MODULE_INSTANCE,
 };
 
 /// Client backup
@@ -132,6 +134,9 @@ pub mod backup;
 pub mod db;
 /// Module client interface definitions
 pub mod module;
+/// Management of clients of multiple federations sharing one database and
+/// root secret
+pub mod multi;
 /// Operation log subsystem of the client
 pub mod oplog;
 /// Secret handling & derivation
@@ -188,6 +193,17 @@ pub trait IGlobalClientContext: Debug + MaybeSend + MaybeSync + 'static {
         output: InstancelessDynClientOutput,
     ) -> anyhow::Result<(TransactionId, Option<OutPoint>)>;
 
+    /// This function is mostly meant for internal use, you are probably looking
+    /// for [`DynGlobalClientContext::fund_output_with_input`].
+    /// Returns transaction id of the funding transaction and an optional
+    /// `OutPoint` that represents change if change was added.
+    async fn fund_output_with_input_dyn(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        input: InstancelessDynClientInput,
+        output: InstancelessDynClientOutput,
+    ) -> anyhow::Result<(TransactionId, Option<OutPoint>)>;
+
     /// Adds a state machine to the executor.
     async fn add_state_machine_dyn(
         &self,
@@ -302,6 +318,39 @@ impl DynGlobalClientContext {
         .await
     }
 
+    /// Creates a transaction spending the given input into the given output.
+    /// Any difference in value is balanced by the primary module, either as
+    /// change or as additional funding. If the primary module does not have
+    /// the required funds this function fails.
+    ///
+    /// The caller is responsible for the state machines of both the input and
+    /// the output, should there be any required.
+    pub async fn fund_output_with_input<I, O, S>(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        input: ClientInput<I, S>,
+        output: ClientOutput<O, S>,
+    ) -> anyhow::Result<(TransactionId, Option<OutPoint>)>
+    where
+        I: IInput + MaybeSend + MaybeSync + 'static,
+        O: IOutput + MaybeSend + MaybeSync + 'static,
+        S: IState<DynGlobalClientContext> + MaybeSend + MaybeSync + 'static,
+    {
+        self.fund_output_with_input_dyn(
+            dbtx,
+            InstancelessDynClientInput {
+                input: Box::new(input.input),
+                keys: input.keys,
+                state_machines: states_to_instanceless_dyn(input.state_machines),
+            },
+            InstancelessDynClientOutput {
+                output: Box::new(output.output),
+                state_machines: states_to_instanceless_dyn(output.state_machines),
+            },
+        )
+        .await
+    }
+
     /// Allows adding state machines from inside a transition to the executor.
     /// The added state machine belongs to the same module instance as the state
     /// machine from inside which it was spawned.
@@ -425,6 +474,33 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
             .await
     }
 
+    async fn fund_output_with_input_dyn(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        input: InstancelessDynClientInput,
+        output: InstancelessDynClientOutput,
+    ) -> anyhow::Result<(TransactionId, Option<OutPoint>)> {
+        let instance_input = ClientInput {
+            input: DynInput::from_parts(self.module_instance_id, input.input),
+            keys: input.keys,
+            state_machines: states_add_instance(self.module_instance_id, input.state_machines),
+        };
+        let instance_output = ClientOutput {
+            output: DynOutput::from_parts(self.module_instance_id, output.output),
+            state_machines: states_add_instance(self.module_instance_id, output.state_machines),
+        };
+
+        self.client
+            .finalize_and_submit_transaction(
+                dbtx.global_tx(),
+                self.operation,
+                TransactionBuilder::new()
+                    .with_input(instance_input)
+                    .with_output(instance_output),
+            )
+            .await
+    }
+
     async fn add_state_machine_dyn(
         &self,
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
@@ -486,7 +562,11 @@ impl Client {
         self.inner
             .executor
             .start_executor(tg, self.inner.context_gen())
-            .await
+            .await;
+
+        for (_, _, module) in self.inner.modules.iter_modules() {
+            module.start(self, tg).await;
+        }
     }
 
     pub fn api(&self) -> &(dyn IGlobalFederationApi + 'static) {
@@ -578,6 +658,87 @@ impl Client {
         }
     }
 
+    /// Like [`Client::finalize_and_submit_transaction`], but uses an existing
+    /// database transaction so that modules can atomically update their own
+    /// state with the submission. The caller is responsible for adding an
+    /// operation log entry.
+    pub async fn finalize_and_submit_transaction_inner(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        tx_builder: TransactionBuilder,
+    ) -> anyhow::Result<(TransactionId, Option<OutPoint>)> {
+        if ClientInner::operation_exists(dbtx, operation_id).await {
+            bail!("There already exists an operation with id {operation_id:?}")
+        }
+
+        self.inner
+            .finalize_and_submit_transaction(dbtx, operation_id, tx_builder)
+            .await
+    }
+
+    /// Calculates the input and change output the primary module would add to
+    /// `tx_builder` and what fees the resulting transaction would pay. Only
+    /// reads the client's funds, nothing is reserved or submitted.
+    ///
+    /// Funding a transaction depends on the current state of the primary
+    /// module (e.g. which notes are available), so the actual transaction
+    /// created later by [`Client::finalize_and_submit_transaction`] can differ
+    /// if the client's funds change in between.
+    pub async fn preview_transaction(
+        &self,
+        tx_builder: &TransactionBuilder,
+    ) -> anyhow::Result<TransactionPreview> {
+        let (mut total_input, mut total_output, mut total_fee) =
+            self.inner.transaction_builder_sums(tx_builder);
+
+        let mut dbtx = self.db().begin_transaction().await;
+        let mut funding_input = None;
+        if total_input < total_output + total_fee {
+            let input = self
+                .inner
+                .primary_module()
+                .preview_sufficient_input(
+                    self.inner.primary_module_instance,
+                    &mut dbtx,
+                    total_output + total_fee - total_input,
+                )
+                .await?;
+            total_input += input.amount;
+            total_fee += input.fee;
+            funding_input = Some(input);
+        }
+
+        let mut change_output = None;
+        if total_output + total_fee < total_input {
+            let output = self
+                .inner
+                .primary_module()
+                .preview_exact_output(
+                    self.inner.primary_module_instance,
+                    &mut dbtx,
+                    total_input - total_output - total_fee,
+                )
+                .await?;
+            total_output += output.amount;
+            total_fee += output.fee;
+            change_output = Some(output);
+        }
+
+        ensure!(
+            total_input == total_output + total_fee,
+            "The primary module cannot return the exact change"
+        );
+
+        Ok(TransactionPreview {
+            funding_input,
+            change_output,
+            total_input,
+            total_output,
+            total_fee,
+        })
+    }
+
     pub async fn add_state_machines(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -598,6 +759,34 @@ impl Client {
         &self.inner.operation_log
     }
 
+    /// Returns all operations matching `query`, newest first, together with the
+    /// amounts the modules derive from the operations' meta
+    pub async fn export_operation_history(
+        &self,
+        query: &OperationLogQuery,
+    ) -> Vec<OperationHistoryEntry> {
+        self.operation_log()
+            .query_operations(query, usize::MAX, None)
+            .await
+            .into_iter()
+            .map(|(key, entry)| {
+                let amount = self
+                    .get_first_instance(&ModuleKind::clone_from_str(entry.operation_type()))
+                    .and_then(|instance| self.inner.try_get_module(instance))
+                    .and_then(|module| module.operation_amount(entry.meta_json()));
+
+                OperationHistoryEntry {
+                    operation_id: key.operation_id,
+                    creation_time: key.creation_time,
+                    operation_type: entry.operation_type().to_owned(),
+                    amount,
+                    meta: entry.meta_json().clone(),
+                    outcome: entry.outcome_json().cloned(),
+                }
+            })
+            .collect()
+    }
+
     /// Returns a reference to a typed module client instance by kind
     pub fn get_first_module<M: ClientModule>(
         &self,
@@ -708,6 +897,19 @@ impl Client {
             .await
     }
 
+    /// Returns a stream that yields the id of an operation every time one of
+    /// its state machines transitions. Only transitions happening after
+    /// subscribing are reported.
+    pub fn subscribe_operation_updates(&self) -> BoxStream<'static, OperationId> {
+        Box::pin(
+            self.inner
+                .executor
+                .notifier()
+                .subscribe_all_operations()
+                .map(|state| state.operation_id()),
+        )
+    }
+
     /// Returns a stream that yields the current client balance every time it
     /// changes.
     pub async fn subscribe_balance_changes(&self) -> BoxStream<'_, Amount> {
@@ -832,11 +1034,8 @@ impl ClientInner {
         Some(self.modules.get(instance)?.as_ref())
     }
 
-    /// Determines if a transaction is underfunded, overfunded or balanced
-    fn transaction_builder_balance(
-        &self,
-        builder: &TransactionBuilder,
-    ) -> TransactionBuilderBalance {
+    /// Returns the sum of inputs, outputs and fees of a transaction
+    fn transaction_builder_sums(&self, builder: &TransactionBuilder) -> (Amount, Amount, Amount) {
         // FIXME: prevent overflows, currently not suitable for untrusted input
         let mut in_amount = Amount::ZERO;
         let mut out_amount = Amount::ZERO;
@@ -856,6 +1055,15 @@ impl ClientInner {
             fee_amount += item_amount.fee;
         }
 
+        (in_amount, out_amount, fee_amount)
+    }
+
+    /// Determines if a transaction is underfunded, overfunded or balanced
+    fn transaction_builder_balance(
+        &self,
+        builder: &TransactionBuilder,
+    ) -> TransactionBuilderBalance {
+        let (in_amount, out_amount, fee_amount) = self.transaction_builder_sums(builder);
         let total_out_amount = out_amount + fee_amount;
 
         match total_out_amount.cmp(&in_amount) {
@@ -872,12 +1080,30 @@ impl ClientInner {
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
         operation_id: OperationId,
-        mut partial_transaction: TransactionBuilder,
+        partial_transaction: TransactionBuilder,
     ) -> anyhow::Result<(
         Transaction,
         Vec<DynState<DynGlobalClientContext>>,
         Option<u64>,
     )> {
+        let (partial_transaction, change_idx) = self
+            .fund_transaction(dbtx, operation_id, partial_transaction)
+            .await?;
+
+        let (tx, states) = partial_transaction.build(&self.secp_ctx, thread_rng());
+
+        Ok((tx, states, change_idx))
+    }
+
+    /// Lets the primary module add inputs or change outputs until the
+    /// transaction is balanced, returns the balanced transaction and the index
+    /// of the change output if one was added
+    async fn fund_transaction(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        mut partial_transaction: TransactionBuilder,
+    ) -> anyhow::Result<(TransactionBuilder, Option<u64>)> {
         if let TransactionBuilderBalance::Underfunded(missing_amount) =
             self.transaction_builder_balance(&partial_transaction)
         {
@@ -910,17 +1136,15 @@ impl ClientInner {
             partial_transaction.outputs.push(output);
         }
 
-        assert!(
+        ensure!(
             matches!(
                 self.transaction_builder_balance(&partial_transaction),
                 TransactionBuilderBalance::Balanced
             ),
-            "Transaction is balanced after the previous two operations"
+            "The primary module cannot return the exact change"
         );
 
-        let (tx, states) = partial_transaction.build(&self.secp_ctx, thread_rng());
-
-        Ok((tx, states, change_idx))
+        Ok((partial_transaction, change_idx))
     }
 
     async fn finalize_and_submit_transaction(
@@ -1116,11 +1340,27 @@ impl ClientBuilder {
         );
     }
 
+    /// Build a [`Client`] with the given `secret` and restore it from the
+    /// newest backup stored by the federation
     pub async fn build_restoring_from_backup<S>(
         self,
         tg: &mut TaskGroup,
         secret: ClientSecret<S>,
     ) -> anyhow::Result<(Client, Metadata)>
+    where
+        S: RootSecretStrategy,
+    {
+        self.build_restoring_from(tg, secret, RestoreSource::NewestFederationBackup)
+            .await
+    }
+
+    /// Build a [`Client`] with the given `secret` and restore it from `source`
+    pub async fn build_restoring_from<S>(
+        self,
+        tg: &mut TaskGroup,
+        secret: ClientSecret<S>,
+        source: RestoreSource,
+    ) -> anyhow::Result<(Client, Metadata)>
     where
         S: RootSecretStrategy,
     {
@@ -1154,7 +1394,7 @@ impl ClientBuilder {
         dbtx.commit_tx().await;
 
         let client = self.build::<S>(tg).await?;
-        let metadata = client.restore_from_backup().await?;
+        let metadata = client.restore_from_backup(source).await?;
 
         Ok((client, metadata))
     }
@@ -1257,6 +1497,9 @@ impl ClientBuilder {
             executor_builder.build(db.clone(), notifier).await
         };
 
+        let operation_log = OperationLog::new(db.clone());
+        operation_log.backfill_operation_type_index().await;
+
         let client_inner = Arc::new(ClientInner {
             config: config.clone(),
             decoders,
@@ -1269,7 +1512,7 @@ impl ClientBuilder {
             api,
             secp_ctx: Secp256k1::new(),
             root_secret,
-            operation_log: OperationLog::new(db),
+            operation_log,
         });
 
         Ok(Client {
@@ -1331,6 +1574,51 @@ where
     S::to_root_secret(&encoding)
 }
 
+/// Checks which kind of root secret a client database that wasn't opened yet
+/// contains.
+///
+/// Returns `None` if it doesn't contain a secret, otherwise whether the secret
+/// can be decoded by strategy `S` without any bytes left over. This allows
+/// applications that switched to a different [`RootSecretStrategy`] to keep
+/// opening databases created with the old one. Only strategies with fixed
+/// length encodings should be probed this way, decoding the secret of another
+/// strategy could otherwise request arbitrarily large allocations.
+pub async fn client_root_secret_uses_strategy<S>(db: &dyn IDatabase) -> Option<bool>
+where
+    S: RootSecretStrategy,
+{
+    let mut dbtx = db.begin_transaction().await;
+    let secret_bytes = dbtx
+        .raw_get_bytes(&ClientSecretKey::<S>::default().to_bytes())
+        .await
+        .expect("Unrecoverable error when reading from database")?;
+
+    let mut reader = secret_bytes.as_slice();
+    Some(S::consensus_decode(&mut reader).is_ok() && reader.is_empty())
+}
+
+/// Stores `secret` as root secret of a client database that wasn't opened yet,
+/// unless it already contains a secret of any kind. Returns if the secret was
+/// stored.
+pub async fn set_client_root_secret_if_missing<S>(
+    db: &dyn IDatabase,
+    secret: &ClientSecret<S>,
+) -> anyhow::Result<bool>
+where
+    S: RootSecretStrategy,
+{
+    let key_bytes = ClientSecretKey::<S>::default().to_bytes();
+    let mut dbtx = db.begin_transaction().await;
+    if dbtx.raw_get_bytes(&key_bytes).await?.is_some() {
+        return Ok(false);
+    }
+
+    dbtx.raw_insert_bytes(&key_bytes, &secret.consensus_encode_to_vec()?)
+        .await?;
+    dbtx.commit_tx().await?;
+    Ok(true)
+}
+
 /// Secret input key material from which the [`DerivableSecret`] used by the
 /// client will be seeded
 pub struct ClientSecret<S: RootSecretStrategy>(S::Encoding);
@@ -8,13 +8,14 @@ use fedimint_core::core::{Decoder, DynInput, DynOutput, IntoDynInstance, ModuleI
 use fedimint_core::db::{DatabaseTransaction, ModuleDatabaseTransaction};
 use fedimint_core::module::registry::ModuleRegistry;
 use fedimint_core::module::{ModuleCommon, MultiApiVersion, TransactionItemAmount};
-use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
 use fedimint_core::util::BoxStream;
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, maybe_add_send_sync, Amount, OutPoint,
     TransactionId,
 };
 
+use crate::oplog::OperationAmount;
 use crate::sm::{Context, DynContext, DynState, Executor, OperationId, State};
 use crate::transaction::{ClientInput, ClientOutput};
 use crate::{Client, DynGlobalClientContext};
@@ -61,6 +62,10 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         ))
     }
 
+    /// Spawns the background tasks of the module into `task_group` once the
+    /// executor of `client`, the client the module belongs to, was started
+    async fn start(&self, _client: &Client, _task_group: &mut TaskGroup) {}
+
     /// Returns the amount represented by the input and the fee its processing
     /// requires
     fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount;
@@ -72,6 +77,13 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         output: &<Self::Common as ModuleCommon>::Output,
     ) -> TransactionItemAmount;
 
+    /// Returns the amount the operation with the given meta moved into or out
+    /// of the client, used to export the operation history. The meta is the
+    /// JSON value the module logged the operation with.
+    fn operation_amount(&self, _meta: &serde_json::Value) -> Option<OperationAmount> {
+        None
+    }
+
     fn supports_backup(&self) -> bool {
         false
     }
@@ -113,6 +125,8 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     ///
     /// * [`Self::create_sufficient_input`]
     /// * [`Self::create_exact_output`]
+    /// * [`Self::preview_sufficient_input`]
+    /// * [`Self::preview_exact_output`]
     /// * [`Self::await_primary_module_output`]
     /// * [`Self::get_balance`]
     /// * [`Self::subscribe_balance_changes`]
@@ -127,9 +141,10 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     /// If successful it returns:
     /// * A set of private keys belonging to the input for signing the
     ///   transaction
-    /// * The input of **at least** `min_amount`, the actual amount might be
-    ///   larger, the caller has to handle this case and possibly generate
-    ///   change using `create_change_output`.
+    /// * The input of **at least** `min_amount` after subtracting the fees
+    ///   for spending it, the actual amount might be larger, the caller has to
+    ///   handle this case and possibly generate change using
+    ///   `create_change_output`.
     /// * A closure that generates states belonging to the input. This closure
     ///   takes the transaction id of the transaction in which the input was
     ///   used and the input index as input since these cannot be known at time
@@ -150,7 +165,8 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     /// holdings managed by the module.
     ///
     /// It returns:
-    /// * The output of **exactly** `amount`.
+    /// * The output of **exactly** `amount` including the fees for issuing
+    ///   it.
     /// * A closure that generates states belonging to the output. This closure
     ///   takes the transaction id of the transaction in which the output was
     ///   used and the output index as input since these cannot be known at time
@@ -164,6 +180,36 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         unimplemented!()
     }
 
+    /// Returns the amount and fee of the input [`Self::create_sufficient_input`]
+    /// would currently create for `min_amount`. Only reads the module's
+    /// holdings, nothing is reserved for the input.
+    ///
+    /// The function returns an error if the client's funds are not sufficient
+    /// to create the requested input or the module can't preview inputs.
+    async fn preview_sufficient_input(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _min_amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        Err(anyhow::format_err!(
+            "Preview not supported by primary module"
+        ))
+    }
+
+    /// Returns the amount and fee of the output [`Self::create_exact_output`]
+    /// would currently create for `amount`. Only reads the module's holdings.
+    ///
+    /// The function returns an error if the module can't preview outputs.
+    async fn preview_exact_output(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        Err(anyhow::format_err!(
+            "Preview not supported by primary module"
+        ))
+    }
+
     /// Waits for the funds from an output created by
     /// [`Self::create_exact_output`] to become available. This function
     /// returning typically implies a change in the output of
@@ -206,10 +252,14 @@ pub trait IClientModule: Debug {
         args: &[ffi::OsString],
     ) -> anyhow::Result<serde_json::Value>;
 
+    async fn start(&self, client: &Client, task_group: &mut TaskGroup);
+
     fn input_amount(&self, input: &DynInput) -> TransactionItemAmount;
 
     fn output_amount(&self, output: &DynOutput) -> TransactionItemAmount;
 
+    fn operation_amount(&self, meta: &serde_json::Value) -> Option<OperationAmount>;
+
     fn supports_backup(&self) -> bool;
 
     async fn backup(
@@ -255,6 +305,20 @@ pub trait IClientModule: Debug {
         amount: Amount,
     ) -> ClientOutput;
 
+    async fn preview_sufficient_input(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        min_amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount>;
+
+    async fn preview_exact_output(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount>;
+
     async fn await_primary_module_output(
         &self,
         operation_id: OperationId,
@@ -300,6 +364,10 @@ where
         <T as ClientModule>::handle_cli_command(self, client, args).await
     }
 
+    async fn start(&self, client: &Client, task_group: &mut TaskGroup) {
+        <T as ClientModule>::start(self, client, task_group).await
+    }
+
     fn input_amount(&self, input: &DynInput) -> TransactionItemAmount {
         <T as ClientModule>::input_amount(
             self,
@@ -320,6 +388,10 @@ where
         )
     }
 
+    fn operation_amount(&self, meta: &serde_json::Value) -> Option<OperationAmount> {
+        <T as ClientModule>::operation_amount(self, meta)
+    }
+
     fn supports_backup(&self) -> bool {
         <T as ClientModule>::supports_backup(self)
     }
@@ -393,6 +465,34 @@ where
         .into_dyn(module_instance)
     }
 
+    async fn preview_sufficient_input(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        min_amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        <T as ClientModule>::preview_sufficient_input(
+            self,
+            &mut dbtx.with_module_prefix(module_instance),
+            min_amount,
+        )
+        .await
+    }
+
+    async fn preview_exact_output(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        <T as ClientModule>::preview_exact_output(
+            self,
+            &mut dbtx.with_module_prefix(module_instance),
+            amount,
+        )
+        .await
+    }
+
     async fn await_primary_module_output(
         &self,
         operation_id: OperationId,
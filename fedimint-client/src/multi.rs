@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use async_stream::stream;
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::prefix::PrefixDatabase;
+use fedimint_core::db::{Database, IDatabase};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::util::BoxStream;
+use fedimint_core::Amount;
+use fedimint_derive_secret::DerivableSecret;
+use futures::stream::select_all;
+use futures::StreamExt;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::db::{
+    ChronologicalOperationLogKey, ClientSecretKey, FederationConfigKey, FederationConfigKeyPrefix,
+};
+use crate::module::gen::ClientModuleGenRegistry;
+use crate::oplog::{OperationLogEntry, OperationLogQuery};
+use crate::secret::{DeriveableSecretClientExt, PlainRootSecretStrategy, RootSecretStrategy};
+use crate::sm::OperationId;
+use crate::{
+    get_client_root_secret, get_client_root_secret_encoding, Client, ClientBuilder, ClientSecret,
+};
+
+/// Database namespace holding the root secret and the list of joined
+/// federations
+const MULTI_FEDERATION_DB_PREFIX: u8 = 0x00;
+/// Database namespace under which each federation client gets its own
+/// sub-namespace keyed by the federation id
+const FEDERATION_DB_PREFIX: u8 = 0x01;
+
+/// An operation of one of the federations managed by a
+/// [`MultiFederationClient`]
+#[derive(Debug)]
+pub struct FederationOperation {
+    pub federation_id: FederationId,
+    pub key: ChronologicalOperationLogKey,
+    pub entry: OperationLogEntry,
+}
+
+/// Progress of a logged operation of one of the federations managed by a
+/// [`MultiFederationClient`]
+#[derive(Debug)]
+pub struct FederationOperationUpdate {
+    pub federation_id: FederationId,
+    pub operation_id: OperationId,
+    /// Log entry of the operation at the time of the update
+    pub entry: OperationLogEntry,
+}
+
+/// Manages clients of multiple federations that share one database and one
+/// root secret.
+///
+/// Each federation client gets its own key prefix in the shared database. Its
+/// root secret is derived from the shared one using the federation id, so
+/// backing up the shared secret (e.g. a mnemonic) is sufficient to recover the
+/// funds of all federations.
+pub struct MultiFederationClient {
+    raw_db: Arc<dyn IDatabase>,
+    db: Database,
+    module_gens: ClientModuleGenRegistry,
+    primary_module_instance: ModuleInstanceId,
+    root_secret: DerivableSecret,
+    clients: RwLock<BTreeMap<FederationId, Client>>,
+    task_group: TaskGroup,
+}
+
+impl MultiFederationClient {
+    /// Opens a multi-federation client, generating a new root secret if the
+    /// database doesn't contain one yet, and starts the clients of all
+    /// federations joined previously
+    pub async fn new<S>(
+        db: impl IDatabase,
+        module_gens: ClientModuleGenRegistry,
+        primary_module_instance: ModuleInstanceId,
+        task_group: TaskGroup,
+    ) -> anyhow::Result<MultiFederationClient>
+    where
+        S: RootSecretStrategy,
+    {
+        Self::open::<S>(
+            Arc::new(db),
+            None,
+            module_gens,
+            primary_module_instance,
+            task_group,
+        )
+        .await
+    }
+
+    /// Same as [`MultiFederationClient::new`] but uses `secret` as root secret.
+    /// Fails if the database already contains a different root secret.
+    pub async fn new_with_secret<S>(
+        db: impl IDatabase,
+        secret: ClientSecret<S>,
+        module_gens: ClientModuleGenRegistry,
+        primary_module_instance: ModuleInstanceId,
+        task_group: TaskGroup,
+    ) -> anyhow::Result<MultiFederationClient>
+    where
+        S: RootSecretStrategy,
+    {
+        Self::open::<S>(
+            Arc::new(db),
+            Some(secret),
+            module_gens,
+            primary_module_instance,
+            task_group,
+        )
+        .await
+    }
+
+    async fn open<S>(
+        raw_db: Arc<dyn IDatabase>,
+        secret: Option<ClientSecret<S>>,
+        module_gens: ClientModuleGenRegistry,
+        primary_module_instance: ModuleInstanceId,
+        task_group: TaskGroup,
+    ) -> anyhow::Result<MultiFederationClient>
+    where
+        S: RootSecretStrategy,
+    {
+        let db = Database::new(
+            PrefixDatabase::new(raw_db.clone(), vec![MULTI_FEDERATION_DB_PREFIX]),
+            Default::default(),
+        );
+
+        if let Some(secret) = secret {
+            let mut dbtx = db.begin_transaction().await;
+            if dbtx
+                .get_value(&ClientSecretKey::<S>::default())
+                .await
+                .is_some()
+            {
+                bail!("Database already contains a root secret");
+            }
+            dbtx.insert_new_entry(&ClientSecretKey::<S>::default(), &secret)
+                .await;
+            dbtx.commit_tx_result().await?;
+        }
+        let root_secret = get_client_root_secret::<S>(&db).await;
+
+        let multi_client = MultiFederationClient {
+            raw_db,
+            db,
+            module_gens,
+            primary_module_instance,
+            root_secret,
+            clients: RwLock::new(BTreeMap::new()),
+            task_group,
+        };
+
+        let configs = multi_client
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&FederationConfigKeyPrefix)
+            .await
+            .map(|(_, config)| config)
+            .collect::<Vec<ClientConfig>>()
+            .await;
+
+        {
+            let mut clients = multi_client.clients.write().await;
+            for config in configs {
+                let federation_id = config.federation_id;
+                let client = multi_client.build_client(config).await?;
+                clients.insert(federation_id, client);
+            }
+        }
+
+        Ok(multi_client)
+    }
+
+    /// Returns the root secret all federation secrets are derived from, e.g.
+    /// to display it to the user as a backup
+    pub async fn root_secret_encoding<S>(&self) -> S::Encoding
+    where
+        S: RootSecretStrategy,
+    {
+        get_client_root_secret_encoding::<S>(&self.db).await
+    }
+
+    /// Remembers the federation and starts a client for it
+    pub async fn join_federation(&self, config: ClientConfig) -> anyhow::Result<Client> {
+        let federation_id = config.federation_id;
+        let mut clients = self.clients.write().await;
+        if clients.contains_key(&federation_id) {
+            bail!("Federation {federation_id} was already joined");
+        }
+
+        let client = self.build_client(config.clone()).await?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_new_entry(&FederationConfigKey { id: federation_id }, &config)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        info!(%federation_id, "Joined federation");
+        clients.insert(federation_id, client.clone());
+        Ok(client)
+    }
+
+    async fn build_client(&self, config: ClientConfig) -> anyhow::Result<Client> {
+        let federation_id = config.federation_id;
+
+        let mut db_prefix = vec![FEDERATION_DB_PREFIX];
+        federation_id
+            .consensus_encode(&mut db_prefix)
+            .expect("Writing to vec can't fail");
+        let db = PrefixDatabase::new(self.raw_db.clone(), db_prefix);
+
+        // The federation client only ever sees its derived secret, never the shared
+        // one, so we have to provide it before the client generates a random one
+        let federation_secret = self
+            .root_secret
+            .derive_federation_secret(&federation_id)
+            .to_random_bytes::<64>();
+        {
+            let secret_db = Database::new(db.clone(), Default::default());
+            let mut dbtx = secret_db.begin_transaction().await;
+            match dbtx
+                .get_value(&ClientSecretKey::<PlainRootSecretStrategy>::default())
+                .await
+            {
+                Some(existing) if existing.0 != federation_secret => {
+                    bail!("Client of federation {federation_id} uses an unexpected root secret");
+                }
+                Some(_) => {}
+                None => {
+                    dbtx.insert_new_entry(
+                        &ClientSecretKey::<PlainRootSecretStrategy>::default(),
+                        &ClientSecret::new(federation_secret),
+                    )
+                    .await;
+                }
+            }
+            dbtx.commit_tx_result().await?;
+        }
+
+        let mut client_builder = ClientBuilder::default();
+        client_builder.with_module_gens(self.module_gens.clone());
+        client_builder.with_primary_module(self.primary_module_instance);
+        client_builder.with_config(config);
+        client_builder.with_database(db);
+        client_builder
+            .build::<PlainRootSecretStrategy>(&mut self.task_group.make_subgroup().await)
+            .await
+            .with_context(|| format!("Failed to start client of federation {federation_id}"))
+    }
+
+    /// Returns the client of a previously joined federation
+    pub async fn get(&self, federation_id: &FederationId) -> Option<Client> {
+        self.clients.read().await.get(federation_id).cloned()
+    }
+
+    /// Returns the clients of all joined federations
+    pub async fn clients(&self) -> BTreeMap<FederationId, Client> {
+        self.clients.read().await.clone()
+    }
+
+    pub async fn federation_ids(&self) -> Vec<FederationId> {
+        self.clients.read().await.keys().copied().collect()
+    }
+
+    /// Returns the balance of each joined federation
+    pub async fn get_balances(&self) -> BTreeMap<FederationId, Amount> {
+        let mut balances = BTreeMap::new();
+        for (federation_id, client) in self.clients().await {
+            balances.insert(federation_id, client.get_balance().await);
+        }
+        balances
+    }
+
+    /// Returns the sum of the balances of all joined federations
+    pub async fn get_total_balance(&self) -> Amount {
+        self.get_balances().await.into_values().sum()
+    }
+
+    /// Returns a stream that yields the total balance every time the balance
+    /// of any federation changes.
+    ///
+    /// Only federations that were joined at the time of calling are taken into
+    /// account.
+    pub async fn subscribe_total_balance_changes(&self) -> BoxStream<'static, Amount> {
+        let clients = self.clients().await;
+        Box::pin(stream! {
+            let mut balances = BTreeMap::new();
+            let mut balance_changes = Vec::with_capacity(clients.len());
+            for (federation_id, client) in &clients {
+                let federation_id = *federation_id;
+                balances.insert(federation_id, client.get_balance().await);
+                let changes = client
+                    .subscribe_balance_changes()
+                    .await
+                    .map(move |balance| (federation_id, balance));
+                balance_changes.push(changes);
+            }
+            let mut balance_changes = select_all(balance_changes);
+
+            yield balances.values().copied().sum::<Amount>();
+            while let Some((federation_id, balance)) = balance_changes.next().await {
+                balances.insert(federation_id, balance);
+                yield balances.values().copied().sum::<Amount>();
+            }
+        })
+    }
+
+    /// Lists operations of all joined federations, newest first. See
+    /// [`crate::oplog::OperationLog::query_operations`] for the meaning of the
+    /// arguments, `start_after` should be the key of the last operation of the
+    /// previous page.
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<FederationOperation> {
+        let mut operations = Vec::new();
+        for (federation_id, client) in self.clients().await {
+            let federation_operations = client
+                .operation_log()
+                .query_operations(query, limit, start_after)
+                .await;
+            operations.extend(federation_operations.into_iter().map(|(key, entry)| {
+                FederationOperation {
+                    federation_id,
+                    key,
+                    entry,
+                }
+            }));
+        }
+
+        operations.sort_by(|a, b| {
+            (a.key.creation_time, a.key.operation_id.0)
+                .cmp(&(b.key.creation_time, b.key.operation_id.0))
+                .reverse()
+        });
+        operations.truncate(limit);
+        operations
+    }
+
+    /// Returns a stream that yields the log entry of an operation every time
+    /// the operation progresses in any federation. Operations that are not
+    /// logged in the operation log of their federation are skipped.
+    ///
+    /// Only federations that were joined at the time of calling are taken into
+    /// account.
+    pub async fn subscribe_operation_updates(
+        &self,
+    ) -> BoxStream<'static, FederationOperationUpdate> {
+        let clients = self.clients().await;
+        let updates = select_all(clients.into_iter().map(|(federation_id, client)| {
+            client
+                .subscribe_operation_updates()
+                .map(move |operation_id| (federation_id, client.clone(), operation_id))
+        }));
+
+        Box::pin(
+            updates.filter_map(|(federation_id, client, operation_id)| async move {
+                let entry = client.operation_log().get_operation(operation_id).await?;
+                Some(FederationOperationUpdate {
+                    federation_id,
+                    operation_id,
+                    entry,
+                })
+            }),
+        )
+    }
+}
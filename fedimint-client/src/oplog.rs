@@ -1,6 +1,8 @@
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::future;
 use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_stream::stream;
 use fedimint_core::db::{Database, DatabaseTransaction};
@@ -9,6 +11,7 @@ use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
 use fedimint_core::util::BoxStream;
+use fedimint_core::Amount;
 use futures::{stream, Stream, StreamExt};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -16,6 +19,7 @@ use tracing::{error, instrument, warn};
 
 use crate::db::{
     ChronologicalOperationLogKey, ChronologicalOperationLogKeyPrefix, OperationLogKey,
+    OperationTypeIndexBackfilledKey, OperationTypeLogKey, OperationTypeLogKeyPrefix,
 };
 use crate::sm::OperationId;
 
@@ -46,9 +50,20 @@ impl OperationLog {
             },
         )
         .await;
+
+        let creation_time = now();
         dbtx.insert_new_entry(
             &ChronologicalOperationLogKey {
-                creation_time: now(),
+                creation_time,
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+        dbtx.insert_new_entry(
+            &OperationTypeLogKey {
+                operation_type: operation_type.to_string(),
+                creation_time,
                 operation_id,
             },
             &(),
@@ -56,6 +71,48 @@ impl OperationLog {
         .await;
     }
 
+    /// Adds operations that were logged before operation types were indexed to
+    /// the [`OperationTypeLogKey`] index. Only does work the first time it is
+    /// called on a database.
+    pub(crate) async fn backfill_operation_type_index(&self) {
+        let mut dbtx = self.db.begin_transaction().await;
+        if dbtx
+            .get_value(&OperationTypeIndexBackfilledKey)
+            .await
+            .is_some()
+        {
+            return;
+        }
+
+        let operations = dbtx
+            .find_by_prefix(&ChronologicalOperationLogKeyPrefix)
+            .await
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        for key in operations {
+            let Some(entry) = Self::get_operation_inner(&mut dbtx, key.operation_id).await else {
+                warn!(operation_id = %key.operation_id, "Operation log entry missing");
+                continue;
+            };
+
+            dbtx.insert_entry(
+                &OperationTypeLogKey {
+                    operation_type: entry.operation_type,
+                    creation_time: key.creation_time,
+                    operation_id: key.operation_id,
+                },
+                &(),
+            )
+            .await;
+        }
+
+        dbtx.insert_entry(&OperationTypeIndexBackfilledKey, &())
+            .await;
+        dbtx.commit_tx().await;
+    }
+
     /// Returns the last `limit` operations. To fetch the next page, pass the
     /// last operation's [`ChronologicalOperationLogKey`] as `start_after`.
     pub async fn list_operations(
@@ -63,42 +120,111 @@ impl OperationLog {
         limit: usize,
         start_after: Option<ChronologicalOperationLogKey>,
     ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
-        let mut dbtx = self.db.begin_transaction().await;
-        let operations: Vec<ChronologicalOperationLogKey> = dbtx
-            .find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+        self.query_operations(&OperationLogQuery::default(), limit, start_after)
             .await
-            .map(|(key, _)| key)
-            // FIXME: this is a schlemil-the-painter algorithm that will take longer the further
-            // back in history one goes. To avoid that I see two options:
-            //   1. Add a reference to the previous operation to each operation log entry,
-            //      essentially creating a linked list, which seem a little bit inelegant.
-            //   2. Add an option to prefix queries that allows to specify a start key
-            //
-            // The current implementation may also skip operations due to `SystemTime` not being
-            // guaranteed to be monotonous. The linked list approach would also fix that.
-            .skip_while(move |key| {
-                let skip = if let Some(start_after) = start_after {
-                    key.creation_time >= start_after.creation_time
-                } else {
-                    false
-                };
+    }
 
-                std::future::ready(skip)
-            })
-            .take(limit)
-            .collect::<Vec<_>>()
-            .await;
+    /// Returns the last `limit` operations matching `query`, newest first. To
+    /// fetch the next page, pass the last operation's
+    /// [`ChronologicalOperationLogKey`] as `start_after`.
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+        limit: usize,
+        start_after: Option<ChronologicalOperationLogKey>,
+    ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        let mut dbtx = self.db.begin_transaction().await;
 
-        let mut operation_entries = Vec::with_capacity(operations.len());
+        // Only operations created before this time can be part of the page
+        let end_time = match (start_after, query.end_time) {
+            (Some(start_after), Some(end_time)) => Some(start_after.creation_time.min(end_time)),
+            (Some(start_after), None) => Some(start_after.creation_time),
+            (None, end_time) => end_time,
+        };
+        let in_time_range = |creation_time: SystemTime| {
+            end_time.map_or(true, |end_time| creation_time < end_time)
+                && query
+                    .start_time
+                    .map_or(true, |start_time| start_time <= creation_time)
+        };
+
+        let operations: Vec<ChronologicalOperationLogKey> = if query.operation_types.is_empty() {
+            dbtx.find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+                .await
+                .map(|(key, _)| key)
+                // FIXME: this is a schlemil-the-painter algorithm that will take longer the
+                // further back in history one goes. To avoid that I see two options:
+                //   1. Add a reference to the previous operation to each operation log entry,
+                //      essentially creating a linked list, which seem a little bit inelegant.
+                //   2. Add an option to prefix queries that allows to specify a start key
+                //
+                // The current implementation may also skip operations due to `SystemTime` not
+                // being guaranteed to be monotonous. The linked list approach would also fix
+                // that.
+                .skip_while(move |key| {
+                    std::future::ready(
+                        end_time.map_or(false, |end_time| key.creation_time >= end_time),
+                    )
+                })
+                .take_while(move |key| std::future::ready(in_time_range(key.creation_time)))
+                // Without an outcome filter every key yields an entry
+                .take(if query.outcome == OutcomeFilter::Any {
+                    limit
+                } else {
+                    usize::MAX
+                })
+                .collect()
+                .await
+        } else {
+            let mut operations = vec![];
+            for operation_type in &query.operation_types {
+                let typed_operations = dbtx
+                    .find_by_prefix_sorted_descending(&OperationTypeLogKeyPrefix {
+                        operation_type: operation_type.clone(),
+                    })
+                    .await
+                    .map(|(key, _)| ChronologicalOperationLogKey {
+                        creation_time: key.creation_time,
+                        operation_id: key.operation_id,
+                    })
+                    // Each operation type is paged like the unfiltered log above, at most
+                    // `limit` operations of every type can end up in the page
+                    .skip_while(move |key| {
+                        std::future::ready(
+                            end_time.map_or(false, |end_time| key.creation_time >= end_time),
+                        )
+                    })
+                    .take_while(move |key| std::future::ready(in_time_range(key.creation_time)))
+                    .take(if query.outcome == OutcomeFilter::Any {
+                        limit
+                    } else {
+                        usize::MAX
+                    })
+                    .collect::<Vec<_>>()
+                    .await;
+                operations.extend(typed_operations);
+            }
+            operations.sort_by_key(|key| std::cmp::Reverse(key.creation_time));
+            operations
+        };
+
+        let mut operation_entries = Vec::with_capacity(limit.min(operations.len()));
 
         for operation in operations {
+            if operation_entries.len() >= limit {
+                break;
+            }
+
             let entry = dbtx
                 .get_value(&OperationLogKey {
                     operation_id: operation.operation_id,
                 })
                 .await
                 .expect("Inconsistent DB");
-            operation_entries.push((operation, entry));
+
+            if query.outcome.matches(&entry) {
+                operation_entries.push((operation, entry));
+            }
         }
 
         operation_entries
@@ -151,6 +277,115 @@ impl OperationLog {
     }
 }
 
+/// Filters applied by [`OperationLog::query_operations`], the default matches
+/// all operations
+#[derive(Debug, Clone, Default)]
+pub struct OperationLogQuery {
+    /// Only return operations of these types (usually the module kind), all
+    /// types if empty
+    pub operation_types: BTreeSet<String>,
+    /// Only return operations created at or after this time
+    pub start_time: Option<SystemTime>,
+    /// Only return operations created before this time
+    pub end_time: Option<SystemTime>,
+    pub outcome: OutcomeFilter,
+}
+
+/// Filters operations by whether their outcome is known
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutcomeFilter {
+    #[default]
+    Any,
+    /// Operations whose outcome was cached in the operation log
+    Finished,
+    /// Operations without a cached outcome, either because they are still
+    /// running or because nobody subscribed to their updates until they
+    /// finished
+    Pending,
+}
+
+impl OutcomeFilter {
+    fn matches(&self, entry: &OperationLogEntry) -> bool {
+        match self {
+            OutcomeFilter::Any => true,
+            OutcomeFilter::Finished => entry.outcome.is_some(),
+            OutcomeFilter::Pending => entry.outcome.is_none(),
+        }
+    }
+}
+
+/// Amount an operation moved into or out of the client, derived from its meta
+/// by [`crate::module::ClientModule::operation_amount`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationAmount {
+    Incoming(Amount),
+    Outgoing(Amount),
+}
+
+/// Flattened operation log entry for exporting the history of the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationHistoryEntry {
+    pub operation_id: OperationId,
+    pub creation_time: SystemTime,
+    pub operation_type: String,
+    /// `None` if the module can't tell the amount from the operation's meta
+    pub amount: Option<OperationAmount>,
+    pub meta: serde_json::Value,
+    pub outcome: Option<serde_json::Value>,
+}
+
+/// Renders the history as CSV with one row per operation, meta and outcome
+/// are included as JSON strings
+pub fn operation_history_to_csv(history: &[OperationHistoryEntry]) -> String {
+    fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+
+    let mut csv =
+        "operation_id,creation_time,operation_type,direction,amount_msat,meta,outcome\n".to_owned();
+    for entry in history {
+        let (direction, amount) = match entry.amount {
+            Some(OperationAmount::Incoming(amount)) => ("incoming", amount.msats.to_string()),
+            Some(OperationAmount::Outgoing(amount)) => ("outgoing", amount.msats.to_string()),
+            None => ("", String::new()),
+        };
+        let creation_time = entry
+            .creation_time
+            .duration_since(UNIX_EPOCH)
+            .expect("Operations are created after 1970")
+            .as_secs();
+        let outcome = entry
+            .outcome
+            .as_ref()
+            .map(|outcome| outcome.to_string())
+            .unwrap_or_default();
+
+        let row = [
+            entry.operation_id.to_string(),
+            creation_time.to_string(),
+            entry.operation_type.clone(),
+            direction.to_owned(),
+            amount,
+            entry.meta.to_string(),
+            outcome,
+        ]
+        .iter()
+        .map(|field| escape(field))
+        .collect::<Vec<_>>()
+        .join(",");
+
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+
+    csv
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OperationLogEntry {
     operation_type: String,
@@ -164,6 +399,17 @@ impl OperationLogEntry {
         &self.operation_type
     }
 
+    /// Returns the raw JSON meta the operation was logged with
+    pub fn meta_json(&self) -> &serde_json::Value {
+        &self.meta
+    }
+
+    /// Returns the raw JSON of the last state update of the operation, if any
+    /// was cached yet
+    pub fn outcome_json(&self) -> Option<&serde_json::Value> {
+        self.outcome.as_ref()
+    }
+
     pub fn meta<M: DeserializeOwned>(&self) -> M {
         serde_json::from_value(self.meta.clone()).expect("JSON deserialization should not fail")
     }
@@ -298,14 +544,22 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+    use std::time::{Duration, UNIX_EPOCH};
+
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
+    use fedimint_core::time::now;
+    use fedimint_core::Amount;
     use futures::stream::StreamExt;
     use serde::{Deserialize, Serialize};
 
     use super::UpdateStreamOrOutcome;
-    use crate::db::ChronologicalOperationLogKey;
-    use crate::oplog::{OperationLog, OperationLogEntry};
+    use crate::db::{ChronologicalOperationLogKey, OperationLogKey};
+    use crate::oplog::{
+        operation_history_to_csv, OperationAmount, OperationHistoryEntry, OperationLog,
+        OperationLogEntry, OperationLogQuery, OutcomeFilter,
+    };
     use crate::sm::OperationId;
 
     #[test]
@@ -447,4 +701,129 @@ mod tests {
         assert_eq!(page.len(), 8);
         assert_page_entries(page, 9);
     }
+
+    #[tokio::test]
+    async fn test_query_operations() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+
+        for operation_idx in 0u8..10 {
+            let operation_type = if operation_idx % 2 == 0 {
+                "even"
+            } else {
+                "odd"
+            };
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx,
+                    OperationId([operation_idx; 32]),
+                    operation_type,
+                    operation_idx,
+                )
+                .await;
+            dbtx.commit_tx().await;
+
+            if operation_idx < 3 {
+                OperationLog::set_operation_outcome(&db, OperationId([operation_idx; 32]), &())
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let odd_query = OperationLogQuery {
+            operation_types: BTreeSet::from(["odd".to_owned()]),
+            ..Default::default()
+        };
+        let odd = op_log.query_operations(&odd_query, 10, None).await;
+        let odd_metas = odd
+            .iter()
+            .map(|(_, entry)| entry.meta::<u8>())
+            .collect::<Vec<_>>();
+        assert_eq!(odd_metas, vec![9, 7, 5, 3, 1]);
+
+        let finished_query = OperationLogQuery {
+            outcome: OutcomeFilter::Finished,
+            ..Default::default()
+        };
+        let finished = op_log.query_operations(&finished_query, 10, None).await;
+        assert_eq!(finished.len(), 3);
+
+        let pending_odd_query = OperationLogQuery {
+            outcome: OutcomeFilter::Pending,
+            ..odd_query
+        };
+        let page = op_log.query_operations(&pending_odd_query, 2, None).await;
+        assert_eq!(page.len(), 2);
+        let next_page = op_log
+            .query_operations(&pending_odd_query, 10, Some(page[1].0))
+            .await;
+        assert!(next_page
+            .iter()
+            .all(|(_, entry)| entry.meta::<u8>() < page[1].1.meta::<u8>()));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_operation_type_index() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+
+        // Simulate an operation logged before operation types were indexed
+        let operation_id = OperationId([0x42; 32]);
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &OperationLogKey { operation_id },
+            &OperationLogEntry {
+                operation_type: "foo".to_string(),
+                meta: serde_json::to_value(()).unwrap(),
+                outcome: None,
+            },
+        )
+        .await;
+        dbtx.insert_new_entry(
+            &ChronologicalOperationLogKey {
+                creation_time: now(),
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let query = OperationLogQuery {
+            operation_types: BTreeSet::from(["foo".to_owned()]),
+            ..Default::default()
+        };
+        assert!(op_log.query_operations(&query, 10, None).await.is_empty());
+
+        op_log.backfill_operation_type_index().await;
+        assert_eq!(op_log.query_operations(&query, 10, None).await.len(), 1);
+    }
+
+    #[test]
+    fn test_operation_history_to_csv() {
+        let history = vec![OperationHistoryEntry {
+            operation_id: OperationId([0x01; 32]),
+            creation_time: UNIX_EPOCH + Duration::from_secs(1_000),
+            operation_type: "mint".to_owned(),
+            amount: Some(OperationAmount::Outgoing(Amount::from_msats(5_000))),
+            meta: serde_json::json!({ "note": "a, b" }),
+            outcome: None,
+        }];
+
+        let csv = operation_history_to_csv(&history);
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("operation_id,creation_time,operation_type,direction,amount_msat,meta,outcome")
+        );
+        assert_eq!(
+            lines.next().map(str::to_owned),
+            Some(format!(
+                "{},1000,mint,outgoing,5000,\"{{\"\"note\"\":\"\"a, b\"\"}}\",",
+                OperationId([0x01; 32])
+            ))
+        );
+        assert_eq!(lines.next(), None);
+    }
 }
@@ -1,6 +1,8 @@
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
@@ -8,10 +10,14 @@ use rand::{CryptoRng, Rng, RngCore};
 
 const TYPE_MODULE: ChildId = ChildId(0);
 const TYPE_BACKUP: ChildId = ChildId(1);
+const TYPE_FEDERATION: ChildId = ChildId(2);
 
 pub trait DeriveableSecretClientExt {
     fn derive_module_secret(&self, module_instance_id: ModuleInstanceId) -> DerivableSecret;
     fn derive_backup_secret(&self) -> DerivableSecret;
+    /// Derives the secret a client of `federation_id` is started with when
+    /// `self` is shared by clients of multiple federations
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret;
 }
 
 impl DeriveableSecretClientExt for DerivableSecret {
@@ -25,6 +31,20 @@ impl DeriveableSecretClientExt for DerivableSecret {
         assert_eq!(self.level(), 0);
         self.child_key(TYPE_BACKUP)
     }
+
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret {
+        assert_eq!(self.level(), 0);
+        // Federation ids are too long to be used as child ids directly, so we use
+        // the first 8 bytes of their hash instead
+        let federation_hash = federation_id.consensus_hash::<sha256::Hash>().into_inner();
+        let federation_child_id = u64::from_be_bytes(
+            federation_hash[..8]
+                .try_into()
+                .expect("sha256 hash has more than 8 bytes"),
+        );
+        self.child_key(TYPE_FEDERATION)
+            .child_key(ChildId(federation_child_id))
+    }
 }
 
 /// Trait defining a way to generate, serialize and deserialize a root secret.
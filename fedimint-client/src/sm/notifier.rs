@@ -53,6 +53,28 @@ impl<GC> Notifier<GC> {
     }
 }
 
+impl<GC> Notifier<GC>
+where
+    GC: GlobalContext,
+{
+    /// Subscribe to all future state transitions of all modules
+    pub fn subscribe_all_operations(&self) -> BoxStream<'static, DynState<GC>> {
+        Box::pin(
+            BroadcastStream::new(self.broadcast.subscribe())
+                .take_while(|res| {
+                    let cont = if let Err(err) = res {
+                        error!(?err, "Notifier stream stopped on error");
+                        false
+                    } else {
+                        true
+                    };
+                    std::future::ready(cont)
+                })
+                .map(|res| res.expect("We filtered out errors above")),
+        )
+    }
+}
+
 /// State transition notifier for a specific module instance that can only
 /// subscribe to transitions belonging to that module
 #[derive(Debug)]
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use fedimint_core::core::{DynInput, DynOutput, IntoDynInstance, KeyPair, ModuleInstanceId};
+use fedimint_core::module::TransactionItemAmount;
 use fedimint_core::transaction::Transaction;
 use fedimint_core::Amount;
 use itertools::multiunzip;
@@ -124,6 +125,24 @@ impl TransactionBuilder {
     }
 }
 
+/// What a [`TransactionBuilder`] would turn into if it was submitted, as
+/// returned by [`crate::Client::preview_transaction`]
+#[derive(Debug, Clone)]
+pub struct TransactionPreview {
+    /// Amount and fee of the input the primary module would add to fund the
+    /// transaction
+    pub funding_input: Option<TransactionItemAmount>,
+    /// Amount and fee of the output the primary module would add to return
+    /// any excess as change
+    pub change_output: Option<TransactionItemAmount>,
+    /// Sum of all inputs, including `funding_input`
+    pub total_input: Amount,
+    /// Sum of all outputs excluding fees, including `change_output`
+    pub total_output: Amount,
+    /// Sum of the fees of all inputs and outputs
+    pub total_fee: Amount,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum TransactionBuilderBalance {
     Underfunded(Amount),
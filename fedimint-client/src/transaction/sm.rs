@@ -405,6 +405,15 @@ mod tests {
             unimplemented!()
         }
 
+        async fn fund_output_with_input_dyn(
+            &self,
+            _dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+            _input: InstancelessDynClientInput,
+            _output: InstancelessDynClientOutput,
+        ) -> anyhow::Result<(TransactionId, Option<OutPoint>)> {
+            unimplemented!()
+        }
+
         async fn add_state_machine_dyn(
             &self,
             _dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::str::FromStr;
 
 use bitcoin_hashes::sha256;
 use fedimint_core::task::MaybeSend;
@@ -13,7 +14,9 @@ use crate::api::{
     StatusResponse, WsFederationApi,
 };
 use crate::config::ServerModuleGenParamsRegistry;
+use crate::encoding::{Decodable, Encodable};
 use crate::epoch::{SerdeEpochHistory, SignedEpochOutcome};
+use crate::module::audit::AuditSummary;
 use crate::module::registry::ModuleDecoderRegistry;
 use crate::module::{ApiAuth, ApiRequestErased};
 use crate::PeerId;
@@ -156,6 +159,12 @@ impl WsAdminClient {
             .await
     }
 
+    /// Returns the balance sheet of the federation, broken down by module
+    pub async fn audit(&self) -> FederationResult<AuditSummary> {
+        self.request_auth("audit", ApiRequestErased::default())
+            .await
+    }
+
     /// Returns the status of the server
     pub async fn status(&self) -> FederationResult<StatusResponse> {
         self.request_auth("status", ApiRequestErased::default())
@@ -211,6 +220,31 @@ pub struct PeerServerParams {
     pub status: Option<ServerStatus>,
 }
 
+/// The algorithm the federation uses to agree on the order of consensus items
+#[derive(
+    Debug, Copy, Clone, Default, Serialize, Deserialize, Eq, PartialEq, Encodable, Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusEngine {
+    /// Epoch based consensus using HoneyBadgerBFT
+    #[default]
+    Hbbft,
+    /// Session based consensus using `fedimint-atomic-broadcast`
+    AtomicBroadcast,
+}
+
+impl FromStr for ConsensusEngine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hbbft" => Ok(ConsensusEngine::Hbbft),
+            "atomic_broadcast" | "atomic-broadcast" => Ok(ConsensusEngine::AtomicBroadcast),
+            _ => Err(anyhow::format_err!("Unknown consensus engine: {s}")),
+        }
+    }
+}
+
 /// The config gen params that need to be in consensus, sent by the config gen
 /// leader to all the other guardians
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -221,6 +255,9 @@ pub struct ConfigGenParamsConsensus {
     pub meta: BTreeMap<String, String>,
     /// Config gen params (also contains local params from us)
     pub modules: ServerModuleGenParamsRegistry,
+    /// The consensus algorithm the federation will run
+    #[serde(default)]
+    pub consensus_engine: ConsensusEngine,
 }
 
 /// The config gen params response which includes our peer id
@@ -239,6 +276,9 @@ pub struct ConfigGenParamsRequest {
     pub meta: BTreeMap<String, String>,
     /// Set the params (if leader) or just the local params (if follower)
     pub modules: ServerModuleGenParamsRegistry,
+    /// The consensus algorithm the federation will run (only used if leader)
+    #[serde(default)]
+    pub consensus_engine: ConsensusEngine,
 }
 
 mod serde_tls_cert {
@@ -45,7 +45,7 @@ use crate::backup::ClientBackupSnapshot;
 use crate::core::backup::SignedBackupRequest;
 use crate::core::{Decoder, OutputOutcome};
 use crate::epoch::{SerdeEpochHistory, SignedEpochOutcome};
-use crate::module::{ApiRequestErased, ApiVersion, SupportedApiVersionsSummary};
+use crate::module::{ApiError, ApiRequestErased, ApiVersion, SupportedApiVersionsSummary};
 use crate::outcome::TransactionStatus;
 use crate::query::{
     CurrentConsensus, DiscoverApiVersionSet, EventuallyConsistent, QueryStep, QueryStrategy,
@@ -121,6 +121,20 @@ impl FederationError {
     pub fn is_retryable(&self) -> bool {
         self.members.iter().any(|(_, e)| e.is_retryable())
     }
+
+    /// Errors returned by the API handlers of the members, i.e. excluding
+    /// transport and deserialization errors
+    pub fn member_api_errors(&self) -> Vec<(PeerId, ApiError)> {
+        self.members
+            .iter()
+            .filter_map(|(peer, e)| match e {
+                MemberError::Rpc(JsonRpcError::Call(e)) => {
+                    Some((*peer, ApiError::new(e.code(), e.message().to_owned())))
+                }
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
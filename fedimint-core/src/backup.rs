@@ -1,10 +1,31 @@
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{impl_db_lookup, impl_db_record};
+use futures::StreamExt;
+use secp256k1_zkp::XOnlyPublicKey;
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
-use crate::db::DbKeyPrefix;
+use crate::db::{
+    DatabaseKey, DatabaseLookup, DatabaseRecord, DbKeyPrefix, ModuleDatabaseTransaction,
+};
+use crate::module::ApiError;
+
+/// Env var for the maximum size of a single backup in bytes
+pub const FM_BACKUP_MAX_SIZE: &str = "FM_BACKUP_MAX_SIZE";
+/// Env var for the maximum number of backups a guardian stores
+pub const FM_BACKUP_MAX_COUNT: &str = "FM_BACKUP_MAX_COUNT";
+/// Env var for the number of days after which backups that weren't updated
+/// can be deleted
+pub const FM_BACKUP_MAX_AGE_DAYS: &str = "FM_BACKUP_MAX_AGE_DAYS";
+/// Env var for the number of uploads accepted per backup id and minute
+pub const FM_BACKUP_UPLOADS_PER_MINUTE: &str = "FM_BACKUP_UPLOADS_PER_MINUTE";
+/// Env var for the number of backups with new ids accepted per minute
+pub const FM_BACKUP_NEW_PER_MINUTE: &str = "FM_BACKUP_NEW_PER_MINUTE";
 
 /// Key used to store user's ecash backups
 #[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
@@ -20,6 +41,28 @@ impl_db_record!(
 );
 impl_db_lookup!(key = ClientBackupKey, query_prefix = ClientBackupKeyPrefix);
 
+/// Number of stored [`ClientBackupKey`]s, missing if the backups were not
+/// counted yet
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ClientBackupCountKey;
+
+impl_db_record!(
+    key = ClientBackupCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ClientBackupCount,
+);
+
+/// Time the guardian received the latest upload of the [`ClientBackupKey`]
+/// with the same id
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ClientBackupReceivedKey(pub secp256k1_zkp::XOnlyPublicKey);
+
+impl_db_record!(
+    key = ClientBackupReceivedKey,
+    value = SystemTime,
+    db_prefix = DbKeyPrefix::ClientBackupReceived,
+);
+
 /// User's backup, received at certain time, containing encrypted payload
 #[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
 pub struct ClientBackupSnapshot {
@@ -27,3 +70,414 @@ pub struct ClientBackupSnapshot {
     #[serde(with = "fedimint_core::hex::serde")]
     pub data: Vec<u8>,
 }
+
+/// Limits a guardian puts on the backups it stores for clients, so clients
+/// can't fill its disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupLimits {
+    /// Maximum size of the encrypted payload of a single backup in bytes
+    pub max_size: usize,
+    /// Maximum number of stored backups, backups with new ids are rejected
+    /// once it is reached and no backup has expired
+    pub max_backups: u64,
+    /// Backups that weren't updated for longer expire and are deleted once
+    /// the maximum number of backups is reached
+    pub max_age: Duration,
+    /// Maximum number of uploads accepted per minute for a single backup id
+    pub max_uploads_per_minute: u32,
+    /// Maximum number of backups with new ids accepted per minute in total,
+    /// so clients can't claim all free backup slots with fresh keys
+    pub max_new_backups_per_minute: u32,
+}
+
+impl Default for BackupLimits {
+    fn default() -> Self {
+        BackupLimits {
+            max_size: 512 * 1024,
+            max_backups: 100_000,
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            max_uploads_per_minute: 10,
+            max_new_backups_per_minute: 60,
+        }
+    }
+}
+
+impl BackupLimits {
+    /// Reads the limits from the `FM_BACKUP_*` env vars, using the defaults
+    /// for the ones that aren't set
+    pub fn from_env_vars() -> anyhow::Result<Self> {
+        fn parse_env<T>(name: &str) -> anyhow::Result<Option<T>>
+        where
+            T: std::str::FromStr,
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            match env::var(name) {
+                Ok(value) => {
+                    Ok(Some(value.parse().map_err(|e| {
+                        anyhow::format_err!("Invalid value of {name}: {e}")
+                    })?))
+                }
+                Err(_) => Ok(None),
+            }
+        }
+
+        let defaults = BackupLimits::default();
+        Ok(BackupLimits {
+            max_size: parse_env(FM_BACKUP_MAX_SIZE)?.unwrap_or(defaults.max_size),
+            max_backups: parse_env(FM_BACKUP_MAX_COUNT)?.unwrap_or(defaults.max_backups),
+            max_age: parse_env::<u64>(FM_BACKUP_MAX_AGE_DAYS)?
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(defaults.max_age),
+            max_uploads_per_minute: parse_env(FM_BACKUP_UPLOADS_PER_MINUTE)?
+                .unwrap_or(defaults.max_uploads_per_minute),
+            max_new_backups_per_minute: parse_env(FM_BACKUP_NEW_PER_MINUTE)?
+                .unwrap_or(defaults.max_new_backups_per_minute),
+        })
+    }
+}
+
+/// Enforces [`BackupLimits`] on the backups uploaded to a guardian
+#[derive(Debug)]
+pub struct BackupQuota {
+    limits: BackupLimits,
+    /// Start of the current rate limiting window of every backup id that
+    /// uploaded recently and the number of uploads accepted in it
+    recent_uploads: Mutex<HashMap<XOnlyPublicKey, (SystemTime, u32)>>,
+    /// Start of the current rate limiting window for backups with new ids and
+    /// the number of new backups accepted in it
+    new_backups: Mutex<(SystemTime, u32)>,
+}
+
+impl BackupQuota {
+    pub fn new(limits: BackupLimits) -> BackupQuota {
+        BackupQuota {
+            limits,
+            recent_uploads: Mutex::new(HashMap::new()),
+            new_backups: Mutex::new((SystemTime::UNIX_EPOCH, 0)),
+        }
+    }
+
+    pub fn limits(&self) -> &BackupLimits {
+        &self.limits
+    }
+
+    /// Checks whether a backup of `size` bytes may be stored under `id`,
+    /// `is_new` has to be set if there is no backup with the same id yet.
+    ///
+    /// The backups are stored under `prefix` and counted under `count_key`,
+    /// the time the guardian received a backup is stored under
+    /// `received_key` of its id and `backup_id` returns the id of a stored
+    /// backup. The count and receive time are updated in `dbtx`, so they only
+    /// change once the backup is committed. If the maximum number of backups
+    /// is reached expired backups get deleted in `dbtx`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn check_upload<KP, CK, RK>(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        prefix: &KP,
+        count_key: &CK,
+        id: XOnlyPublicKey,
+        size: usize,
+        is_new: bool,
+        received_key: impl Fn(XOnlyPublicKey) -> RK,
+        backup_id: impl Fn(&KP::Record) -> XOnlyPublicKey,
+    ) -> Result<(), ApiError>
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+        CK: DatabaseKey + DatabaseRecord<Value = u64>,
+        RK: DatabaseKey + DatabaseRecord<Value = SystemTime>,
+    {
+        if self.limits.max_size < size {
+            return Err(ApiError::payload_too_large(format!(
+                "Backup of {size} bytes exceeds the maximum size of {} bytes",
+                self.limits.max_size
+            )));
+        }
+
+        self.check_upload_rate(id)?;
+
+        if is_new {
+            self.check_new_backup_rate()?;
+
+            let mut stored_backups = match dbtx.get_value(count_key).await {
+                Some(stored_backups) => stored_backups,
+                None => dbtx.find_by_prefix(prefix).await.count().await as u64,
+            };
+            if self.limits.max_backups <= stored_backups {
+                stored_backups = self
+                    .delete_expired_backups(dbtx, prefix, &received_key, &backup_id)
+                    .await;
+            }
+            if self.limits.max_backups <= stored_backups {
+                dbtx.insert_entry(count_key, &stored_backups).await;
+                return Err(ApiError::insufficient_storage(format!(
+                    "Guardian already stores the maximum number of {} backups",
+                    self.limits.max_backups
+                )));
+            }
+
+            dbtx.insert_entry(count_key, &(stored_backups + 1)).await;
+        }
+
+        // The timestamp inside the backup is chosen by the client, so backups
+        // expire based on when the guardian received them
+        dbtx.insert_entry(&received_key(id), &crate::time::now())
+            .await;
+        Ok(())
+    }
+
+    fn check_upload_rate(&self, id: XOnlyPublicKey) -> Result<(), ApiError> {
+        let now = crate::time::now();
+        let mut recent_uploads = self.recent_uploads.lock().expect("lock poisoned");
+        // Only ids that uploaded within the last window are kept
+        recent_uploads.retain(|_, (start, _)| !rate_window_elapsed(now, *start));
+        let (_, uploads) = recent_uploads.entry(id).or_insert((now, 0));
+
+        if self.limits.max_uploads_per_minute <= *uploads {
+            return Err(ApiError::too_many_requests(
+                "Too many backups uploaded, try again later".to_string(),
+            ));
+        }
+        *uploads += 1;
+        Ok(())
+    }
+
+    fn check_new_backup_rate(&self) -> Result<(), ApiError> {
+        let now = crate::time::now();
+        let mut new_backups = self.new_backups.lock().expect("lock poisoned");
+        if rate_window_elapsed(now, new_backups.0) {
+            *new_backups = (now, 0);
+        }
+
+        if self.limits.max_new_backups_per_minute <= new_backups.1 {
+            return Err(ApiError::too_many_requests(
+                "Too many new backups, try again later".to_string(),
+            ));
+        }
+        new_backups.1 += 1;
+        Ok(())
+    }
+
+    /// Deletes backups received longer than [`BackupLimits::max_age`] ago and
+    /// returns the number of remaining backups
+    async fn delete_expired_backups<KP, RK>(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        prefix: &KP,
+        received_key: impl Fn(XOnlyPublicKey) -> RK,
+        backup_id: impl Fn(&KP::Record) -> XOnlyPublicKey,
+    ) -> u64
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+        RK: DatabaseKey + DatabaseRecord<Value = SystemTime>,
+    {
+        let now = crate::time::now();
+        let keys = dbtx
+            .find_by_prefix(prefix)
+            .await
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut remaining = 0;
+        let mut expired = 0;
+        for key in keys {
+            let received_key = received_key(backup_id(&key));
+            match dbtx.get_value(&received_key).await {
+                Some(received) => {
+                    if matches!(now.duration_since(received), Ok(age) if self.limits.max_age < age)
+                    {
+                        dbtx.remove_entry(&key).await;
+                        dbtx.remove_entry(&received_key).await;
+                        expired += 1;
+                    } else {
+                        remaining += 1;
+                    }
+                }
+                // Backups stored before receive times were recorded start aging now
+                None => {
+                    dbtx.insert_entry(&received_key, &now).await;
+                    remaining += 1;
+                }
+            }
+        }
+
+        info!(expired, remaining, "Deleted expired client backups");
+        remaining
+    }
+}
+
+/// Whether the rate limiting window that started at `start` has elapsed at
+/// `now`
+fn rate_window_elapsed(now: SystemTime, start: SystemTime) -> bool {
+    match now.duration_since(start) {
+        Ok(elapsed) => Duration::from_secs(60) <= elapsed,
+        // The clock went backwards, start a new window
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use secp256k1_zkp::{KeyPair, Secp256k1};
+
+    use super::{
+        BackupLimits, BackupQuota, ClientBackupCountKey, ClientBackupKey, ClientBackupKeyPrefix,
+        ClientBackupReceivedKey, ClientBackupSnapshot,
+    };
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::Database;
+
+    fn backup_key(seed: u8) -> ClientBackupKey {
+        let keypair = KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32]).unwrap();
+        ClientBackupKey(keypair.x_only_public_key().0)
+    }
+
+    async fn upload(db: &Database, quota: &BackupQuota, seed: u8, size: usize) -> Result<(), i32> {
+        let mut dbtx = db.begin_transaction().await;
+        let key = backup_key(seed);
+        let is_new = dbtx.get_value(&key).await.is_none();
+        let result = quota
+            .check_upload(
+                &mut dbtx.get_isolated(),
+                &ClientBackupKeyPrefix,
+                &ClientBackupCountKey,
+                key.0,
+                size,
+                is_new,
+                ClientBackupReceivedKey,
+                |key: &ClientBackupKey| key.0,
+            )
+            .await;
+        match result {
+            Ok(()) => {
+                dbtx.insert_entry(
+                    &key,
+                    &ClientBackupSnapshot {
+                        timestamp: SystemTime::now(),
+                        data: vec![0; size],
+                    },
+                )
+                .await;
+                dbtx.commit_tx().await;
+                Ok(())
+            }
+            Err(e) => Err(e.code),
+        }
+    }
+
+    async fn set_received(db: &Database, seed: u8, received: SystemTime) {
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&ClientBackupReceivedKey(backup_key(seed).0), &received)
+            .await;
+        dbtx.commit_tx().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_backup_size_limit() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let quota = BackupQuota::new(BackupLimits {
+            max_size: 10,
+            ..Default::default()
+        });
+
+        assert_eq!(upload(&db, &quota, 1, 10).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 1, 11).await, Err(413));
+        assert_eq!(upload(&db, &quota, 2, 11).await, Err(413));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_backup_count_limit_and_expiry() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let quota = BackupQuota::new(BackupLimits {
+            max_backups: 2,
+            max_age: Duration::from_secs(60 * 60),
+            ..Default::default()
+        });
+
+        let old = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        assert_eq!(upload(&db, &quota, 1, 1).await, Ok(()));
+        set_received(&db, 1, old).await;
+        assert_eq!(upload(&db, &quota, 2, 1).await, Ok(()));
+
+        // The expired backup makes room for a new one
+        assert_eq!(upload(&db, &quota, 3, 1).await, Ok(()));
+        assert!(db
+            .begin_transaction()
+            .await
+            .get_value(&backup_key(1))
+            .await
+            .is_none());
+
+        assert_eq!(upload(&db, &quota, 4, 1).await, Err(507));
+        // Existing backups can still be updated, which restarts their expiry
+        set_received(&db, 2, old).await;
+        assert_eq!(upload(&db, &quota, 2, 1).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 4, 1).await, Err(507));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_backup_count_changes_on_commit_only() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let quota = BackupQuota::new(BackupLimits {
+            max_backups: 1,
+            ..Default::default()
+        });
+
+        // An upload that passes the quota check but is never committed doesn't
+        // take up room
+        let mut dbtx = db.begin_transaction().await;
+        let result = quota
+            .check_upload(
+                &mut dbtx.get_isolated(),
+                &ClientBackupKeyPrefix,
+                &ClientBackupCountKey,
+                backup_key(1).0,
+                1,
+                true,
+                ClientBackupReceivedKey,
+                |key: &ClientBackupKey| key.0,
+            )
+            .await;
+        assert!(result.is_ok());
+        drop(dbtx);
+
+        assert_eq!(upload(&db, &quota, 2, 1).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 3, 1).await, Err(507));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_backup_rate_limit_per_id() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let quota = BackupQuota::new(BackupLimits {
+            max_uploads_per_minute: 2,
+            ..Default::default()
+        });
+
+        assert_eq!(upload(&db, &quota, 1, 1).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 1, 1).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 1, 1).await, Err(429));
+        // Other clients are not affected
+        assert_eq!(upload(&db, &quota, 2, 1).await, Ok(()));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_new_backup_rate_limit() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let quota = BackupQuota::new(BackupLimits {
+            max_new_backups_per_minute: 2,
+            ..Default::default()
+        });
+
+        assert_eq!(upload(&db, &quota, 1, 1).await, Ok(()));
+        assert_eq!(upload(&db, &quota, 2, 1).await, Ok(()));
+        // Fresh keys don't get a budget of their own
+        assert_eq!(upload(&db, &quota, 3, 1).await, Err(429));
+        // Existing backups can still be updated
+        assert_eq!(upload(&db, &quota, 1, 1).await, Ok(()));
+    }
+}
@@ -23,6 +23,7 @@ use crate::{async_trait_maybe_send, maybe_add_send, timing};
 
 pub mod mem_impl;
 pub mod notifications;
+pub mod prefix;
 
 pub use test_utils::*;
 
@@ -1456,6 +1457,8 @@ impl std::fmt::Display for DbKeyPrefix {
 pub enum DbKeyPrefix {
     DatabaseVersion = 0x50,
     ClientBackup = 0x51,
+    ClientBackupCount = 0x52,
+    ClientBackupReceived = 0x53,
 }
 
 #[derive(Debug, Error)]
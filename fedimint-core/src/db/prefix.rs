@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::StreamExt;
+use macro_rules_attribute::apply;
+
+use super::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream};
+use crate::async_trait_maybe_send;
+
+/// A view of a database that transparently prepends `prefix` to every key.
+///
+/// This allows multiple independent users (e.g. clients of different
+/// federations) to share one underlying database without their keys
+/// colliding. Prefixes of different views must not be prefixes of each other.
+#[derive(Debug, Clone)]
+pub struct PrefixDatabase {
+    inner: Arc<dyn IDatabase>,
+    prefix: Vec<u8>,
+}
+
+impl PrefixDatabase {
+    pub fn new(inner: Arc<dyn IDatabase>, prefix: Vec<u8>) -> PrefixDatabase {
+        PrefixDatabase { inner, prefix }
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IDatabase for PrefixDatabase {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(PrefixDatabaseTransaction {
+            inner: self.inner.begin_transaction().await,
+            prefix: &self.prefix,
+        })
+    }
+}
+
+struct PrefixDatabaseTransaction<'a> {
+    inner: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+    prefix: &'a [u8],
+}
+
+impl<'a> PrefixDatabaseTransaction<'a> {
+    fn prefixed(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(self.prefix.len() + key.len());
+        prefixed.extend_from_slice(self.prefix);
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+
+    fn strip_prefix(prefix_len: usize, stream: PrefixStream<'_>) -> PrefixStream<'_> {
+        Box::pin(stream.map(move |(key, value)| (key[prefix_len..].to_vec(), value)))
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<'a> ISingleUseDatabaseTransaction<'a> for PrefixDatabaseTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_insert_bytes(&key, value).await
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_get_bytes(&key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_remove_entry(&key).await
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let key_prefix = self.prefixed(key_prefix);
+        let prefix_len = self.prefix.len();
+        let stream = self.inner.raw_find_by_prefix(&key_prefix).await?;
+        Ok(Self::strip_prefix(prefix_len, stream))
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let key_prefix = self.prefixed(key_prefix);
+        let prefix_len = self.prefix.len();
+        let stream = self
+            .inner
+            .raw_find_by_prefix_sorted_descending(&key_prefix)
+            .await?;
+        Ok(Self::strip_prefix(prefix_len, stream))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        let key_prefix = self.prefixed(key_prefix);
+        self.inner.raw_remove_by_prefix(&key_prefix).await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        self.inner.commit_tx().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = self.prefixed(key);
+        self.inner.add_notification_key(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::PrefixDatabase;
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{Database, IDatabase, ISingleUseDatabaseTransaction};
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    fn database() -> Database {
+        let inner: Arc<dyn IDatabase> = Arc::new(MemDatabase::new());
+        Database::new(
+            PrefixDatabase::new(inner, vec![0x42, 0x01]),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_insert_elements() {
+        fedimint_core::db::verify_insert_elements(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_read_own_writes() {
+        fedimint_core::db::verify_read_own_writes(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_prefix() {
+        fedimint_core::db::verify_find_by_prefix(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_commit() {
+        fedimint_core::db::verify_commit(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_rollback_to_savepoint() {
+        fedimint_core::db::verify_rollback_to_savepoint(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_by_prefix() {
+        fedimint_core::db::verify_remove_by_prefix(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_prefixes_are_isolated() {
+        let inner: Arc<dyn IDatabase> = Arc::new(MemDatabase::new());
+        let db_a = PrefixDatabase::new(inner.clone(), vec![0x01]);
+        let db_b = PrefixDatabase::new(inner.clone(), vec![0x02]);
+
+        let mut dbtx = db_a.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0xaa], &[1]).await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+
+        let mut dbtx = db_b.begin_transaction().await;
+        assert_eq!(dbtx.raw_get_bytes(&[0xaa]).await.unwrap(), None);
+        dbtx.raw_insert_bytes(&[0xaa], &[2]).await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+
+        let mut dbtx = db_a.begin_transaction().await;
+        assert_eq!(dbtx.raw_get_bytes(&[0xaa]).await.unwrap(), Some(vec![1]));
+
+        let mut dbtx = inner.begin_transaction().await;
+        assert_eq!(
+            dbtx.raw_get_bytes(&[0x02, 0xaa]).await.unwrap(),
+            Some(vec![2])
+        );
+    }
+}
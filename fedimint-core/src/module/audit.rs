@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 
+use crate::core::{ModuleInstanceId, ModuleKind};
 use crate::db::{DatabaseKey, DatabaseLookup, DatabaseRecord, ModuleDatabaseTransaction};
 
 #[derive(Default)]
@@ -56,6 +59,7 @@ impl Display for Audit {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AuditItem {
     pub name: String,
     pub milli_sat: i64,
@@ -67,3 +71,47 @@ impl Display for AuditItem {
         formatter.write_fmt(format_args!("{:>+15.3}|{}", sats, self.name))
     }
 }
+
+/// The balance sheet of the federation broken down by module, assets are
+/// positive and liabilities negative
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditSummary {
+    /// Sum of all assets and liabilities in msat
+    pub net_assets: i64,
+    pub module_summaries: BTreeMap<ModuleInstanceId, ModuleSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleSummary {
+    pub kind: ModuleKind,
+    /// Sum of the assets and liabilities of the module in msat
+    pub net_assets: i64,
+    pub items: Vec<AuditItem>,
+}
+
+impl AuditSummary {
+    /// Summarizes the audits of the individual modules
+    pub fn from_module_audits(
+        audits: impl IntoIterator<Item = (ModuleInstanceId, ModuleKind, Audit)>,
+    ) -> Self {
+        let module_summaries = audits
+            .into_iter()
+            .map(|(module_instance_id, kind, audit)| {
+                let summary = ModuleSummary {
+                    kind,
+                    net_assets: audit.sum().milli_sat,
+                    items: audit.items,
+                };
+                (module_instance_id, summary)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        AuditSummary {
+            net_assets: module_summaries
+                .values()
+                .map(|summary| summary.net_assets)
+                .sum(),
+            module_summaries,
+        }
+    }
+}
@@ -139,6 +139,19 @@ impl ApiError {
     pub fn server_error(message: String) -> Self {
         Self::new(500, message)
     }
+
+    pub fn payload_too_large(message: String) -> Self {
+        Self::new(413, message)
+    }
+
+    pub fn too_many_requests(message: String) -> Self {
+        Self::new(429, message)
+    }
+
+    /// The server can't store the request, e.g. because a quota was exceeded
+    pub fn insufficient_storage(message: String) -> Self {
+        Self::new(507, message)
+    }
 }
 
 /// State made available to all API endpoints for handling a request
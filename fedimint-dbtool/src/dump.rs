@@ -243,6 +243,14 @@ impl<'a> DatabaseDump<'a> {
                         "Client Config Download"
                     );
                 }
+                ConsensusRange::DbKeyPrefix::AtomicBroadcastSession => {
+                    let session = dbtx
+                        .get_value(&ConsensusRange::AtomicBroadcastSessionKey)
+                        .await;
+                    if let Some(session) = session {
+                        consensus.insert("AtomicBroadcastSession".to_string(), Box::new(session));
+                    }
+                }
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -12,14 +12,12 @@ use fedimint_client::transaction::TransactionBuilder;
 use fedimint_client::{Client, ClientBuilder};
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::IntoDynInstance;
-use fedimint_core::encoding::Decodable;
-use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::CommonModuleGen;
 use fedimint_core::task::TaskGroup;
-use fedimint_core::{Amount, OutPoint, TieredMulti, TieredSummary};
+use fedimint_core::{Amount, OutPoint, TieredSummary};
 use fedimint_ln_client::{LightningClientExt, LightningClientGen, LnPayState};
 use fedimint_mint_client::{
-    MintClientExt, MintClientGen, MintClientModule, MintCommonGen, SpendableNote,
+    MintClientExt, MintClientGen, MintClientModule, MintCommonGen, OOBNotes,
 };
 use fedimint_wallet_client::WalletClientGen;
 use futures::StreamExt;
@@ -29,20 +27,17 @@ use tracing::info;
 
 use crate::MetricEvent;
 
-pub async fn get_notes_cli(amount: &Amount) -> anyhow::Result<TieredMulti<SpendableNote>> {
+pub async fn get_notes_cli(amount: &Amount) -> anyhow::Result<OOBNotes> {
     cmd!(FedimintCli, "spend", amount.msats.to_string())
         .out_json()
         .await?["notes"]
         .as_str()
-        .map(parse_ecash)
+        .map(OOBNotes::from_str)
         .transpose()?
         .context("missing notes output")
 }
 
-pub async fn try_get_notes_cli(
-    amount: &Amount,
-    tries: usize,
-) -> anyhow::Result<TieredMulti<SpendableNote>> {
+pub async fn try_get_notes_cli(amount: &Amount, tries: usize) -> anyhow::Result<OOBNotes> {
     for _ in 0..tries {
         match get_notes_cli(amount).await {
             Ok(notes) => return Ok(notes),
@@ -57,7 +52,7 @@ pub async fn try_get_notes_cli(
 
 pub async fn reissue_notes(
     client: &Client,
-    notes: TieredMulti<SpendableNote>,
+    notes: OOBNotes,
     event_sender: &mpsc::UnboundedSender<MetricEvent>,
 ) -> anyhow::Result<()> {
     let m = fedimint_core::time::now();
@@ -81,7 +76,7 @@ pub async fn reissue_notes(
 pub async fn do_spend_notes(
     client: &Client,
     amount: Amount,
-) -> anyhow::Result<(OperationId, TieredMulti<SpendableNote>)> {
+) -> anyhow::Result<(OperationId, OOBNotes)> {
     let (operation_id, notes) = client
         .spend_notes(amount, Duration::from_secs(600), ())
         .await?;
@@ -128,7 +123,7 @@ pub async fn build_client(
     rocksdb: Option<&PathBuf>,
 ) -> anyhow::Result<Client> {
     let mut client_builder = ClientBuilder::default();
-    client_builder.with_module(MintClientGen);
+    client_builder.with_module(MintClientGen::default());
     client_builder.with_module(LightningClientGen);
     client_builder.with_module(WalletClientGen::default());
     client_builder.with_primary_module(1);
@@ -142,14 +137,6 @@ pub async fn build_client(
     Ok(client)
 }
 
-pub fn parse_ecash(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
-    let bytes = base64::decode(s)?;
-    Ok(Decodable::consensus_decode(
-        &mut std::io::Cursor::new(bytes),
-        &ModuleDecoderRegistry::default(),
-    )?)
-}
-
 pub async fn lnd_create_invoice(amount: Amount) -> anyhow::Result<(Invoice, String)> {
     let result = cmd!(LnCli, "addinvoice", "--amt_msat", amount.msats)
         .out_json()
@@ -197,7 +184,11 @@ pub async fn gateway_pay_invoice(
             LnPayState::Success { preimage: _ } => {
                 break;
             }
-            LnPayState::Created | LnPayState::Funded | LnPayState::AwaitingChange => {}
+            LnPayState::Created
+            | LnPayState::Funded
+            | LnPayState::AwaitingChange
+            | LnPayState::WaitingForRefund { .. }
+            | LnPayState::Retrying { .. } => {}
             other => bail!("Failed to pay invoice: {other:?}"),
         }
     }
@@ -9,7 +9,7 @@ use anyhow::{bail, Context};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use common::{
     cln_create_invoice, cln_wait_invoice_payment, gateway_pay_invoice, get_note_summary,
-    lnd_create_invoice, lnd_wait_invoice_payment, parse_ecash, reissue_notes,
+    lnd_create_invoice, lnd_wait_invoice_payment, reissue_notes,
 };
 use devimint::cmd;
 use fedimint_client::Client;
@@ -18,8 +18,8 @@ use fedimint_core::config::{load_from_file, ClientConfig};
 use fedimint_core::module::ApiRequestErased;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::BoxFuture;
-use fedimint_core::{Amount, TieredMulti};
-use fedimint_mint_client::SpendableNote;
+use fedimint_core::Amount;
+use fedimint_mint_client::OOBNotes;
 use lightning_invoice::Invoice;
 use serde::{Deserialize, Serialize};
 use tokio::fs::OpenOptions;
@@ -113,8 +113,11 @@ struct LoadTestArgs {
     #[clap(flatten)]
     connect_common_args: ConnectCommonArgs,
 
-    #[arg(long, value_parser = parse_ecash, help = "Notes for the test. If none, will call fedimint-cli spend")]
-    initial_notes: Option<TieredMulti<SpendableNote>>,
+    #[arg(
+        long,
+        help = "Notes for the test. If none, will call fedimint-cli spend"
+    )]
+    initial_notes: Option<OOBNotes>,
 
     #[arg(
         long,
@@ -319,7 +322,7 @@ async fn run_load_test(
     archive_dir: Option<PathBuf>,
     users: u16,
     cfg: ClientConfig,
-    initial_notes: TieredMulti<SpendableNote>,
+    initial_notes: OOBNotes,
     generate_invoice_with: Option<LnInvoiceGeneration>,
     generated_invoices_per_user: u16,
     ln_payment_sleep: Duration,
@@ -416,7 +419,7 @@ async fn run_load_test(
 #[allow(clippy::too_many_arguments)]
 async fn do_user_task(
     client: Client,
-    notes: Vec<TieredMulti<SpendableNote>>,
+    notes: Vec<OOBNotes>,
     generated_invoices_per_user: u16,
     ln_payment_sleep: Duration,
     invoice_amount: Amount,
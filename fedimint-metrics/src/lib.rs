@@ -0,0 +1,62 @@
+//! Prometheus metrics of `fedimintd` and its modules
+//!
+//! All metrics are registered with [`REGISTRY`] and exported in the text
+//! format on `/metrics` by [`run_api_server`].
+
+use std::net::SocketAddr;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use fedimint_core::task::TaskGroup;
+use once_cell::sync::Lazy;
+pub use prometheus::{
+    self, histogram_opts, opts, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry,
+};
+use prometheus::{Encoder, TextEncoder};
+use tracing::{error, info};
+
+/// The registry all metrics of `fedimintd` are registered with, their names
+/// are prefixed with `fedimint_`
+pub static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    Registry::new_custom(Some("fedimint".into()), None).expect("Prefix is a valid metric name")
+});
+
+async fn get_metrics() -> (StatusCode, String) {
+    let mut buffer = vec![];
+    match TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        Ok(()) => (
+            StatusCode::OK,
+            String::from_utf8(buffer).expect("Text encoder only writes UTF-8"),
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// Serves the metrics on `/metrics` until the task group is shut down
+pub async fn run_api_server(
+    bind_address: SocketAddr,
+    task_group: &mut TaskGroup,
+) -> anyhow::Result<()> {
+    let app = Router::new().route("/metrics", get(get_metrics));
+    let server = axum::Server::try_bind(&bind_address)?.serve(app.into_make_service());
+
+    info!("Starting metrics server on http://{bind_address}/metrics");
+    task_group
+        .spawn("metrics-server", move |handle| async move {
+            let shutdown_rx = handle.make_shutdown_rx().await;
+            let graceful = server.with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+
+            if let Err(e) = graceful.await {
+                error!("Error shutting down metrics server: {e:?}");
+            }
+        })
+        .await;
+
+    Ok(())
+}
@@ -172,6 +172,7 @@ impl ConfigGenApi {
                 peers: state.get_peer_info(),
                 meta: request.meta.clone(),
                 modules: request.modules.clone(),
+                consensus_engine: request.consensus_engine,
             },
         };
 
@@ -691,6 +692,7 @@ mod tests {
             let default_params = ConfigGenParamsRequest {
                 meta: Default::default(),
                 modules,
+                consensus_engine: Default::default(),
             };
             let settings = ConfigGenSettings {
                 download_token_limit: None,
@@ -799,6 +801,7 @@ mod tests {
             let request = ConfigGenParamsRequest {
                 meta: BTreeMap::from([("test".to_string(), self.name.clone())]),
                 modules,
+                consensus_engine: Default::default(),
             };
 
             self.client.set_config_gen_params(request).await.unwrap();
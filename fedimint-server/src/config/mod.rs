@@ -4,7 +4,8 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use anyhow::{bail, format_err};
-use fedimint_core::admin_client::ConfigGenParamsConsensus;
+use bitcoin::secp256k1;
+use fedimint_core::admin_client::{ConfigGenParamsConsensus, ConsensusEngine};
 use fedimint_core::api::{ClientConfigDownloadToken, WsClientConnectInfo};
 use fedimint_core::cancellable::Cancelled;
 pub use fedimint_core::config::*;
@@ -33,7 +34,7 @@ use tokio_rustls::rustls;
 use tracing::{error, info};
 
 use crate::config::api::ConfigGenParamsLocal;
-use crate::config::distributedgen::{DkgRunner, ThresholdKeys};
+use crate::config::distributedgen::{DkgRunner, PeerHandleOps, ThresholdKeys};
 use crate::config::io::CODE_VERSION;
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::NumPeers;
@@ -100,6 +101,10 @@ pub struct ServerConfigPrivate {
     /// Secret key for signing consensus epochs
     #[serde(with = "serde_binary_human_readable")]
     pub epoch_sks: SerdeSecret<hbbft::crypto::SecretKeyShare>,
+    /// Secret key for the atomic broadcast to sign messages and blocks, missing
+    /// in configs created before the atomic broadcast was introduced
+    #[serde(default)]
+    pub broadcast_secret_key: Option<secp256k1::SecretKey>,
     /// Secret material from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
 }
@@ -110,6 +115,9 @@ pub struct ServerConfigConsensus {
     pub code_version: String,
     /// Agreed on core consensus version
     pub version: CoreConsensusVersion,
+    /// The consensus algorithm the federation runs
+    #[serde(default)]
+    pub consensus_engine: ConsensusEngine,
     /// Public keys authenticating members of the federation and the configs
     #[serde(with = "serde_binary_human_readable")]
     pub auth_pk_set: hbbft::crypto::PublicKeySet,
@@ -119,6 +127,9 @@ pub struct ServerConfigConsensus {
     /// Public keys for signing consensus epochs from all peers
     #[serde(with = "serde_binary_human_readable")]
     pub epoch_pk_set: hbbft::crypto::PublicKeySet,
+    /// Public keys for the atomic broadcast from all peers
+    #[serde(default)]
+    pub broadcast_public_keys: BTreeMap<PeerId, secp256k1::PublicKey>,
     /// Network addresses and names for all peer APIs
     pub api_endpoints: BTreeMap<PeerId, PeerUrl>,
     /// Certs for TLS communication, required for peer authentication
@@ -215,6 +226,8 @@ impl ServerConfig {
         auth_keys: ThresholdKeys,
         epoch_keys: ThresholdKeys,
         hbbft_keys: ThresholdKeys,
+        broadcast_public_keys: BTreeMap<PeerId, secp256k1::PublicKey>,
+        broadcast_secret_key: secp256k1::SecretKey,
         modules: BTreeMap<ModuleInstanceId, ServerModuleConfig>,
     ) -> Self {
         let private = ServerConfigPrivate {
@@ -223,6 +236,7 @@ impl ServerConfig {
             auth_sks: auth_keys.secret_key_share,
             hbbft_sks: hbbft_keys.secret_key_share,
             epoch_sks: epoch_keys.secret_key_share,
+            broadcast_secret_key: Some(broadcast_secret_key),
             modules: Default::default(),
         };
         let local = ServerConfigLocal {
@@ -238,9 +252,11 @@ impl ServerConfig {
         let consensus = ServerConfigConsensus {
             code_version: CODE_VERSION.to_string(),
             version: CORE_CONSENSUS_VERSION,
+            consensus_engine: params.consensus.consensus_engine,
             auth_pk_set: auth_keys.public_key_set,
             hbbft_pk_set: hbbft_keys.public_key_set,
             epoch_pk_set: epoch_keys.public_key_set,
+            broadcast_public_keys,
             api_endpoints: params.api_urls(),
             tls_certs: params.tls_certs(),
             modules: Default::default(),
@@ -364,6 +380,23 @@ impl ServerConfig {
         if private.hbbft_sks.public_key_share() != consensus.hbbft_pk_set.public_key_share(id) {
             bail!("HBBFT private key doesn't match pubkey share");
         }
+        // Configs created before the atomic broadcast have no broadcast keys, they are
+        // only needed to run it
+        if consensus.consensus_engine == ConsensusEngine::AtomicBroadcast {
+            let Some(broadcast_sk) = private.broadcast_secret_key else {
+                bail!("Broadcast private key is missing");
+            };
+            let broadcast_pk = secp256k1::PublicKey::from_secret_key(
+                &secp256k1::Secp256k1::signing_only(),
+                &broadcast_sk,
+            );
+            if consensus.broadcast_public_keys.get(identity) != Some(&broadcast_pk) {
+                bail!("Broadcast private key doesn't match pubkey");
+            }
+            if consensus.broadcast_public_keys.len() != peers.len() {
+                bail!("Broadcast public keys are not set for all peers");
+            }
+        }
         if peers.keys().max().copied().map(|id| id.to_usize()) != Some(peers.len() - 1) {
             bail!("Peer ids are not indexed from 0");
         }
@@ -402,6 +435,17 @@ impl ServerConfig {
         let authinfo = NetworkInfo::generate_map(peer0.peer_ids(), &mut rng)
             .expect("Could not generate HBBFT netinfo");
 
+        let secp = secp256k1::Secp256k1::new();
+        let broadcast_keys: BTreeMap<PeerId, (secp256k1::SecretKey, secp256k1::PublicKey)> = peer0
+            .peer_ids()
+            .into_iter()
+            .map(|peer_id| (peer_id, secp.generate_keypair(&mut rng)))
+            .collect();
+        let broadcast_public_keys: BTreeMap<PeerId, secp256k1::PublicKey> = broadcast_keys
+            .iter()
+            .map(|(peer_id, (_, pk))| (*peer_id, *pk))
+            .collect();
+
         let modules = peer0.consensus.modules.iter_modules();
         let module_configs: BTreeMap<_, _> = modules
             .map(|(module_id, kind, module_params)| {
@@ -424,6 +468,8 @@ impl ServerConfig {
                     Self::extract_keys(authinfo.get(&id).expect("peer exists")),
                     Self::extract_keys(epochinfo.get(&id).expect("peer exists")),
                     Self::extract_keys(netinfo.get(&id).expect("peer exists")),
+                    broadcast_public_keys.clone(),
+                    broadcast_keys[&id].0,
                     module_configs
                         .iter()
                         .map(|(module_id, cfgs)| (*module_id, cfgs[&id].clone()))
@@ -484,6 +530,18 @@ impl ServerConfig {
         let hbbft_keys = keys[&KeyType::Hbbft].threshold_crypto();
         let epoch_keys = keys[&KeyType::Epoch].threshold_crypto();
 
+        // exchange the keys used by the atomic broadcast
+        let (broadcast_secret_key, broadcast_pk) =
+            secp256k1::Secp256k1::new().generate_keypair(&mut OsRng);
+        let broadcast_public_keys = PeerHandle::new(
+            &connections,
+            MODULE_INSTANCE_ID_GLOBAL,
+            *our_id,
+            peers.clone(),
+        )
+        .exchange_pubkeys("broadcast".to_string(), broadcast_pk)
+        .await?;
+
         let mut registered_modules = registry.kinds();
         let mut module_cfgs: BTreeMap<ModuleInstanceId, ServerModuleConfig> = Default::default();
         let modules = params.consensus.modules.iter_modules();
@@ -557,6 +615,8 @@ impl ServerConfig {
             auth_keys,
             epoch_keys,
             hbbft_keys,
+            broadcast_public_keys,
+            broadcast_secret_key,
             module_cfgs,
         );
 
@@ -792,3 +852,110 @@ mod serde_tls_key {
         Ok(rustls::PrivateKey(bytes))
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use fedimint_core::admin_client::{
+        ConfigGenParamsConsensus, ConsensusEngine, PeerServerParams,
+    };
+    use fedimint_core::config::{ServerModuleGenParamsRegistry, ServerModuleGenRegistry};
+    use fedimint_core::module::ApiAuth;
+    use fedimint_core::PeerId;
+
+    use crate::config::api::ConfigGenParamsLocal;
+    use crate::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+
+    /// Generates the configs of a federation without modules running
+    /// `consensus_engine`
+    pub(crate) fn trusted_dealer_configs(
+        num_peers: u16,
+        consensus_engine: ConsensusEngine,
+    ) -> BTreeMap<PeerId, ServerConfig> {
+        let peers = (0..num_peers).map(PeerId::from).collect::<Vec<_>>();
+        let tls_keys = peers
+            .iter()
+            .map(|peer| (*peer, gen_cert_and_key(&format!("peer-{peer}")).unwrap()))
+            .collect::<BTreeMap<_, _>>();
+        let connections = peers
+            .iter()
+            .map(|peer| {
+                let port = 10000 + u16::from(*peer) * 2;
+                let params = PeerServerParams {
+                    cert: tls_keys[peer].0.clone(),
+                    p2p_url: format!("fedimint://127.0.0.1:{port}").parse().unwrap(),
+                    api_url: format!("ws://127.0.0.1:{}", port + 1).parse().unwrap(),
+                    name: format!("peer-{peer}"),
+                    status: None,
+                };
+                (*peer, params)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let params = peers
+            .iter()
+            .map(|peer| {
+                let port = 10000 + u16::from(*peer) * 2;
+                let params = ConfigGenParams {
+                    local: ConfigGenParamsLocal {
+                        our_id: *peer,
+                        our_private_key: tls_keys[peer].1.clone(),
+                        api_auth: ApiAuth(format!("pass{peer}")),
+                        p2p_bind: format!("127.0.0.1:{port}").parse().unwrap(),
+                        api_bind: format!("127.0.0.1:{}", port + 1).parse().unwrap(),
+                        download_token_limit: None,
+                        max_connections: 10,
+                    },
+                    consensus: ConfigGenParamsConsensus {
+                        peers: connections.clone(),
+                        meta: BTreeMap::new(),
+                        modules: ServerModuleGenParamsRegistry::default(),
+                        consensus_engine,
+                    },
+                };
+                (*peer, params)
+            })
+            .collect::<HashMap<_, _>>();
+
+        ServerConfig::trusted_dealer_gen(&params, ServerModuleGenRegistry::default())
+    }
+
+    #[test]
+    fn configs_without_broadcast_fields_still_load() {
+        let registry = ServerModuleGenRegistry::default();
+        let cfg = trusted_dealer_configs(4, ConsensusEngine::Hbbft)
+            .remove(&PeerId::from(0))
+            .unwrap();
+
+        // Remove the fields added for the atomic broadcast, like in configs written by
+        // older versions
+        let mut json = serde_json::to_value(&cfg).unwrap();
+        json["private"]
+            .as_object_mut()
+            .unwrap()
+            .remove("broadcast_secret_key");
+        let consensus = json["consensus"].as_object_mut().unwrap();
+        consensus.remove("consensus_engine");
+        consensus.remove("broadcast_public_keys");
+
+        let old_cfg: ServerConfig = serde_json::from_value(json).unwrap();
+        assert_eq!(old_cfg.consensus.consensus_engine, ConsensusEngine::Hbbft);
+        assert!(old_cfg.private.broadcast_secret_key.is_none());
+        old_cfg
+            .validate_config(&old_cfg.local.identity, &registry)
+            .unwrap();
+    }
+
+    #[test]
+    fn atomic_broadcast_requires_broadcast_keys() {
+        let registry = ServerModuleGenRegistry::default();
+        let mut cfg = trusted_dealer_configs(4, ConsensusEngine::AtomicBroadcast)
+            .remove(&PeerId::from(0))
+            .unwrap();
+        cfg.validate_config(&cfg.local.identity, &registry).unwrap();
+
+        cfg.private.broadcast_secret_key = None;
+        assert!(cfg.validate_config(&cfg.local.identity, &registry).is_err());
+    }
+}
@@ -0,0 +1,650 @@
+//! Runs Fedimint Consensus on top of the atomic broadcast implemented in
+//! `fedimint-atomic-broadcast` instead of HBBFT
+//!
+//! The broadcast has no notion of epochs, it orders individual consensus items
+//! and records the accepted ones in a signed block per session. We apply all
+//! items of a session to a single database transaction that is only committed
+//! once the broadcast has completed the session. If we crash mid session the
+//! transaction is lost and the broadcast replays the session from its backup.
+//!
+//! Transactions are applied as soon as they are ordered, while module
+//! consensus items are collected and handed to the modules at the end of the
+//! session since modules like the wallet need to see the items of all peers at
+//! once. Therefore every guardian proposes its module consensus items once at
+//! the beginning of every session.
+//!
+//! Guardians that have been offline catch up by downloading the signed blocks
+//! of the sessions they missed from their peers, which is handled by the
+//! broadcast itself.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use fedimint_atomic_broadcast::{secp256k1, Decision, Keychain, Message, Recipient};
+use fedimint_core::api::DynGlobalApi;
+use fedimint_core::cancellable::Cancellable;
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::core::DynModuleConsensusItem;
+use fedimint_core::db::{Database, DatabaseTransaction};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::{ConsensusItem, ConsensusUpgrade, SerdeSignatureShare};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::net::peers::{IPeerConnections, PeerConnections};
+use fedimint_core::task::{TaskGroup, TaskHandle};
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::consensus::server::{
+    build_consensus, confirm_consensus_config_hash, federation_api, init_modules,
+};
+use crate::consensus::TransactionSubmissionError::TransactionReplayError;
+use crate::consensus::{AcceptedTransaction, ApiEvent, FedimintConsensus, HbbftConsensusOutcome};
+use crate::db::{AcceptedTransactionKey, AtomicBroadcastSessionKey, RejectedTransactionKey};
+use crate::metrics;
+use crate::net::connect::{Connector, TlsTcpConnector};
+use crate::net::peers::{DelayCalculator, PeerConnector, ReconnectPeerConnections};
+
+/// How many consensus items can be buffered before blocking the API
+const MEMPOOL_BUFFER_SIZE: usize = 1000;
+
+/// How many messages can be buffered between the network and the broadcast
+const MESSAGE_BUFFER_SIZE: usize = 256;
+
+/// How often we check whether the broadcast has completed the current session
+const SESSION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long we wait for lagging peers to complete the last session before
+/// shutting down for an upgrade
+const UPGRADE_SHUTDOWN_DELAY: Duration = Duration::from_secs(60);
+
+/// Runs the main server consensus loop on top of the atomic broadcast
+pub struct AtomicBroadcastServer {
+    /// `TaskGroup` that is running the server
+    pub task_group: TaskGroup,
+    /// Delegate for processing consensus information
+    pub consensus: FedimintConsensus,
+    /// Receives event notifications from the API
+    pub api_receiver: mpsc::Receiver<ApiEvent>,
+    /// P2P connections relaying the consensus encoded broadcast messages
+    pub connections: PeerConnections<Vec<u8>>,
+    /// Our configuration
+    pub cfg: ServerConfig,
+    /// Used to make API calls to our peers
+    pub api: DynGlobalApi,
+    /// Used for decoding module specific-values
+    pub decoders: ModuleDecoderRegistry,
+}
+
+impl AtomicBroadcastServer {
+    /// Creates a server with real network and no delays
+    pub async fn new(
+        cfg: ServerConfig,
+        db: Database,
+        module_inits: ServerModuleGenRegistry,
+        task_group: &mut TaskGroup,
+    ) -> anyhow::Result<Self> {
+        let connector: PeerConnector<Vec<u8>> =
+            TlsTcpConnector::new(cfg.tls_config(), cfg.local.identity).into_dyn();
+
+        Self::new_with(
+            cfg,
+            db,
+            module_inits,
+            connector,
+            DelayCalculator::PROD_DEFAULT,
+            task_group,
+        )
+        .await
+    }
+
+    /// Creates a server that can simulate network and delays
+    ///
+    /// Initializes modules and runs any database migrations
+    pub async fn new_with(
+        cfg: ServerConfig,
+        db: Database,
+        module_inits: ServerModuleGenRegistry,
+        connector: PeerConnector<Vec<u8>>,
+        delay_calculator: DelayCalculator,
+        task_group: &mut TaskGroup,
+    ) -> anyhow::Result<Self> {
+        let modules = init_modules(&cfg, &db, &module_inits, task_group).await?;
+
+        // Check the configs are valid
+        cfg.validate_config(&cfg.local.identity, &module_inits)?;
+
+        let (connections, peer_status_channels) = ReconnectPeerConnections::new(
+            cfg.network_config(),
+            delay_calculator,
+            connector,
+            task_group,
+        )
+        .await;
+
+        let (consensus, api_receiver, _) =
+            build_consensus(&cfg, &db, module_inits, modules, peer_status_channels)?;
+        let decoders = consensus.decoders();
+
+        Ok(AtomicBroadcastServer {
+            task_group: task_group.clone(),
+            consensus,
+            api_receiver,
+            connections: connections.into_dyn(),
+            api: federation_api(&cfg),
+            cfg,
+            decoders,
+        })
+    }
+
+    /// Runs the broadcast and processes the ordered items until shut down
+    pub async fn run_consensus(self, task_handle: TaskHandle) -> anyhow::Result<()> {
+        confirm_consensus_config_hash(&self.api, &self.cfg).await?;
+
+        let AtomicBroadcastServer {
+            mut task_group,
+            consensus,
+            mut api_receiver,
+            connections,
+            cfg,
+            decoders,
+            ..
+        } = self;
+
+        let keychain = broadcast_keychain(&cfg)?;
+
+        let session_index = consensus
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&AtomicBroadcastSessionKey)
+            .await
+            .unwrap_or(0);
+
+        info!(
+            target: LOG_CONSENSUS,
+            "Starting atomic broadcast at session {}", session_index
+        );
+
+        let (mempool_item_sender, mempool_item_receiver) =
+            async_channel::bounded(MEMPOOL_BUFFER_SIZE);
+        let (incoming_message_sender, incoming_message_receiver) =
+            async_channel::bounded(MESSAGE_BUFFER_SIZE);
+        let (outgoing_message_sender, outgoing_message_receiver) =
+            async_channel::bounded(MESSAGE_BUFFER_SIZE);
+        let (ordered_item_sender, mut ordered_item_receiver) = mpsc::channel(32);
+        let (shutdown_sender, shutdown_receiver) = watch::channel(None);
+
+        let other_peers: Vec<PeerId> = cfg
+            .local
+            .p2p_endpoints
+            .keys()
+            .copied()
+            .filter(|peer_id| *peer_id != cfg.local.identity)
+            .collect();
+
+        task_group
+            .spawn("atomic broadcast message relay", move |_| async move {
+                relay_messages(
+                    connections,
+                    other_peers,
+                    incoming_message_sender,
+                    outgoing_message_receiver,
+                )
+                .await
+                .ok();
+            })
+            .await;
+
+        let mut broadcast_handle = tokio::spawn(fedimint_atomic_broadcast::run(
+            keychain,
+            consensus.db.clone(),
+            session_index,
+            mempool_item_receiver,
+            incoming_message_receiver,
+            outgoing_message_sender,
+            ordered_item_sender,
+            shutdown_receiver,
+        ));
+
+        let mut session = Session::open(&consensus.db, session_index).await;
+        submit_proposal(&consensus, &mempool_item_sender).await;
+
+        let mut poll_interval = tokio::time::interval(SESSION_POLL_INTERVAL);
+
+        while !task_handle.is_shutting_down() {
+            tokio::select! {
+                ordered_item = ordered_item_receiver.recv() => {
+                    let Some((ordered_item, index, decision_sender)) = ordered_item else {
+                        break;
+                    };
+
+                    // the first item of a session implies that all previous sessions are complete
+                    while session.index < index {
+                        session = consensus.complete_session(session).await;
+                        on_session_start(&consensus, &mempool_item_sender, &shutdown_sender, session.index).await;
+                    }
+
+                    let decision = match ConsensusItem::consensus_decode(
+                        &mut ordered_item.item.as_slice(),
+                        &decoders,
+                    ) {
+                        Ok(item) => {
+                            consensus
+                                .process_ordered_item(&mut session, item, ordered_item.peer_id)
+                                .await
+                        }
+                        Err(e) => {
+                            warn!(
+                                target: LOG_CONSENSUS,
+                                "Received invalid item from peer {}: {}", ordered_item.peer_id, e
+                            );
+                            Decision::Discard
+                        }
+                    };
+
+                    decision_sender.send(decision).ok();
+                }
+                api_event = api_receiver.recv() => {
+                    match api_event {
+                        Some(ApiEvent::Transaction(transaction)) => {
+                            submit_item(&mempool_item_sender, &ConsensusItem::Transaction(transaction)).await;
+                        }
+                        Some(ApiEvent::UpgradeSignal) => {
+                            submit_item(
+                                &mempool_item_sender,
+                                &ConsensusItem::ConsensusUpgrade(ConsensusUpgrade),
+                            )
+                            .await;
+                        }
+                        Some(ApiEvent::ForceProcessOutcome(_)) => {
+                            warn!(
+                                target: LOG_CONSENSUS,
+                                "Forcing epoch outcomes is not supported by the atomic broadcast"
+                            );
+                        }
+                        None => break,
+                    }
+                }
+                _ = poll_interval.tick() => {
+                    // the broadcast only stores the signed block once all its items have been decided
+                    if fedimint_atomic_broadcast::load_block(&consensus.db, session.index).await.is_some() {
+                        session = consensus.complete_session(session).await;
+                        on_session_start(&consensus, &mempool_item_sender, &shutdown_sender, session.index).await;
+                    }
+                }
+                shutdown = &mut broadcast_handle => {
+                    info!(target: LOG_CONSENSUS, ?shutdown, "Atomic broadcast shut down");
+
+                    if fedimint_atomic_broadcast::load_block(&consensus.db, session.index).await.is_some() {
+                        consensus.complete_session(session).await;
+                    }
+
+                    return Ok(());
+                }
+            }
+        }
+
+        broadcast_handle.abort();
+
+        info!(target: LOG_CONSENSUS, "Consensus task shut down");
+        Ok(())
+    }
+}
+
+/// The state of the session the broadcast is currently ordering items for
+struct Session<'a> {
+    /// Index of the session
+    index: u64,
+    /// Contains the changes of all items processed in this session
+    dbtx: DatabaseTransaction<'a>,
+    /// Module consensus items which are processed at the end of the session
+    module_items: Vec<(PeerId, DynModuleConsensusItem)>,
+    /// Signals of peers that want to shut down for an upgrade
+    upgrade_items: Vec<(PeerId, ConsensusUpgrade)>,
+    /// Shares for the threshold signature of the client config
+    client_config_signature_shares: BTreeMap<PeerId, SerdeSignatureShare>,
+}
+
+impl<'a> Session<'a> {
+    async fn open(db: &'a Database, index: u64) -> Session<'a> {
+        Session {
+            index,
+            dbtx: db.begin_transaction().await,
+            module_items: vec![],
+            upgrade_items: vec![],
+            client_config_signature_shares: BTreeMap::new(),
+        }
+    }
+
+    /// The peers that contributed module consensus items in this session
+    fn module_peers(&self) -> BTreeSet<PeerId> {
+        self.module_items.iter().map(|(peer, _)| *peer).collect()
+    }
+}
+
+impl FedimintConsensus {
+    /// Applies an ordered item to the session and decides whether it becomes
+    /// part of the session's block. Items that would not change our state
+    /// are discarded.
+    async fn process_ordered_item(
+        &self,
+        session: &mut Session<'_>,
+        item: ConsensusItem,
+        peer: PeerId,
+    ) -> Decision {
+        match item {
+            ConsensusItem::Transaction(transaction) => {
+                let txid = transaction.tx_hash();
+                let caches = self.build_verification_caches(std::iter::once(&transaction));
+
+                session
+                    .dbtx
+                    .set_tx_savepoint()
+                    .await
+                    .expect("Error setting transaction savepoint");
+
+                match self
+                    .process_transaction(&mut session.dbtx, transaction.clone(), &caches)
+                    .await
+                {
+                    Ok(()) => {
+                        // the session index takes the place of the epoch
+                        session
+                            .dbtx
+                            .insert_entry(
+                                &AcceptedTransactionKey(txid),
+                                &AcceptedTransaction {
+                                    epoch: session.index,
+                                    transaction,
+                                },
+                            )
+                            .await;
+                        metrics::CONSENSUS_TRANSACTIONS_ACCEPTED.inc();
+
+                        Decision::Accept
+                    }
+                    Err(error) => {
+                        session
+                            .dbtx
+                            .rollback_tx_to_savepoint()
+                            .await
+                            .expect("Error rolling back to transaction savepoint");
+                        warn!(target: LOG_CONSENSUS, %error, "Transaction failed");
+                        // do not insert a RejectedTransactionKey because there must already be
+                        // AcceptedTransactionKey
+                        if !matches!(error, TransactionReplayError(_)) {
+                            session
+                                .dbtx
+                                .insert_entry(&RejectedTransactionKey(txid), &format!("{error:?}"))
+                                .await;
+                            metrics::CONSENSUS_TRANSACTIONS_REJECTED.inc();
+                        }
+
+                        Decision::Discard
+                    }
+                }
+            }
+            ConsensusItem::Module(module_item) => {
+                session.module_items.push((peer, module_item));
+                Decision::Accept
+            }
+            ConsensusItem::ConsensusUpgrade(upgrade) => {
+                if session.upgrade_items.iter().any(|(p, _)| *p == peer) {
+                    return Decision::Discard;
+                }
+
+                session.upgrade_items.push((peer, upgrade));
+                Decision::Accept
+            }
+            ConsensusItem::ClientConfigSignatureShare(share) => {
+                if session.client_config_signature_shares.contains_key(&peer) {
+                    return Decision::Discard;
+                }
+
+                session.client_config_signature_shares.insert(peer, share);
+                Decision::Accept
+            }
+            // the broadcast signs the blocks itself, so there is no epoch history to sign
+            ConsensusItem::EpochOutcomeSignatureShare(_) => Decision::Discard,
+        }
+    }
+
+    /// Hands the collected items to the modules, commits the session and opens
+    /// the next one
+    async fn complete_session<'a>(&'a self, mut session: Session<'a>) -> Session<'a> {
+        let peers = session.module_peers();
+
+        self.process_module_consensus_items(&mut session.dbtx, &session.module_items, &peers)
+            .await;
+        self.process_upgrade_items(&mut session.dbtx, &session.upgrade_items)
+            .await;
+
+        if !session.client_config_signature_shares.is_empty() {
+            let outcome = HbbftConsensusOutcome {
+                epoch: session.index,
+                contributions: session
+                    .client_config_signature_shares
+                    .iter()
+                    .map(|(peer, share)| {
+                        (
+                            *peer,
+                            vec![ConsensusItem::ClientConfigSignatureShare(share.clone())],
+                        )
+                    })
+                    .collect(),
+            };
+
+            // the broadcast tolerates faulty peers, so we do not drop any
+            self.save_client_config_sig(&mut session.dbtx, &outcome, &mut vec![])
+                .await;
+        }
+
+        for (module_key, _, module) in self.modules.iter_modules() {
+            module
+                .end_consensus_epoch(&peers, &mut session.dbtx.with_module_prefix(module_key))
+                .await;
+        }
+
+        session
+            .dbtx
+            .insert_entry(&AtomicBroadcastSessionKey, &(session.index + 1))
+            .await;
+
+        session
+            .dbtx
+            .commit_tx_result()
+            .await
+            .expect("Committing consensus session failed");
+
+        info!(
+            target: LOG_CONSENSUS,
+            "Completed session with index {}", session.index
+        );
+        metrics::CONSENSUS_EPOCHS_PROCESSED.inc();
+        metrics::CONSENSUS_EPOCH.set(session.index as i64);
+
+        let audit = self.audit().await;
+        if audit.sum().milli_sat < 0 {
+            panic!("Balance sheet of the fed has gone negative, this should never happen! {audit}")
+        }
+
+        Session::open(&self.db, session.index + 1).await
+    }
+}
+
+/// Proposes our consensus items for the new session and initiates a clean
+/// shutdown at its end if a threshold of peers signaled an upgrade
+async fn on_session_start(
+    consensus: &FedimintConsensus,
+    mempool_item_sender: &async_channel::Sender<Vec<u8>>,
+    shutdown_sender: &watch::Sender<Option<(u64, Duration)>>,
+    index: u64,
+) {
+    if consensus.is_at_upgrade_threshold().await {
+        info!(
+            target: LOG_CONSENSUS,
+            "Received a threshold of upgrade signals, shutting down after session {}", index
+        );
+        shutdown_sender
+            .send(Some((index, UPGRADE_SHUTDOWN_DELAY)))
+            .ok();
+    }
+
+    submit_proposal(consensus, mempool_item_sender).await;
+}
+
+async fn submit_proposal(
+    consensus: &FedimintConsensus,
+    mempool_item_sender: &async_channel::Sender<Vec<u8>>,
+) {
+    for item in consensus.get_consensus_proposal().await.items {
+        if !matches!(item, ConsensusItem::EpochOutcomeSignatureShare(_)) {
+            submit_item(mempool_item_sender, &item).await;
+        }
+    }
+}
+
+async fn submit_item(mempool_item_sender: &async_channel::Sender<Vec<u8>>, item: &ConsensusItem) {
+    let item = item
+        .consensus_encode_to_vec()
+        .expect("Writing to a vec cannot fail");
+
+    mempool_item_sender.send(item).await.ok();
+}
+
+/// Relays the messages of the broadcast between our peer connections and
+/// the broadcast instance
+async fn relay_messages(
+    mut connections: PeerConnections<Vec<u8>>,
+    other_peers: Vec<PeerId>,
+    incoming_message_sender: async_channel::Sender<(Message, PeerId)>,
+    outgoing_message_receiver: async_channel::Receiver<(Message, Recipient)>,
+) -> Cancellable<()> {
+    loop {
+        tokio::select! {
+            message = outgoing_message_receiver.recv() => {
+                let Ok((message, recipient)) = message else {
+                    return Ok(());
+                };
+
+                let peers = match recipient {
+                    Recipient::Everyone => other_peers.clone(),
+                    Recipient::Peer(peer_id) if other_peers.contains(&peer_id) => vec![peer_id],
+                    Recipient::Peer(_) => continue,
+                };
+
+                let message = message
+                    .consensus_encode_to_vec()
+                    .expect("Writing to a vec cannot fail");
+
+                connections.send(&peers, message).await?;
+            }
+            received = connections.receive() => {
+                let (peer_id, message) = received?;
+
+                match Message::consensus_decode(
+                    &mut message.as_slice(),
+                    &ModuleDecoderRegistry::default(),
+                ) {
+                    // the broadcast implements its own retry logic, so we can drop messages if it is busy
+                    Ok(message) => {
+                        incoming_message_sender.try_send((message, peer_id)).ok();
+                    }
+                    Err(e) => {
+                        warn!(
+                            target: LOG_CONSENSUS,
+                            "Received invalid message from peer {}: {}", peer_id, e
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Converts the broadcast keys in our config to the version of secp256k1 used
+/// by the broadcast
+fn broadcast_keychain(cfg: &ServerConfig) -> anyhow::Result<Keychain> {
+    let public_keys = cfg
+        .consensus
+        .broadcast_public_keys
+        .iter()
+        .map(|(peer_id, public_key)| {
+            let public_key = secp256k1::PublicKey::from_slice(&public_key.serialize())
+                .expect("Public key was valid in our config");
+            (*peer_id, public_key.x_only_public_key().0)
+        })
+        .collect();
+
+    let secret_key = cfg
+        .private
+        .broadcast_secret_key
+        .ok_or_else(|| anyhow::format_err!("Config has no broadcast secret key"))?;
+    let secret_key = secp256k1::SecretKey::from_slice(&secret_key.secret_bytes())
+        .expect("Secret key was valid in our config");
+
+    Ok(Keychain::new(cfg.local.identity, public_keys, secret_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::admin_client::ConsensusEngine;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::encoding::Decodable;
+    use fedimint_core::epoch::{ConsensusItem, ConsensusUpgrade};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::PeerId;
+
+    use super::{broadcast_keychain, submit_item, Session};
+    use crate::config::tests::trusted_dealer_configs;
+
+    #[test]
+    fn keychain_contains_all_peers() {
+        for (peer_id, cfg) in trusted_dealer_configs(4, ConsensusEngine::AtomicBroadcast) {
+            let keychain = broadcast_keychain(&cfg).unwrap();
+            assert_eq!(keychain.peer_id(), peer_id);
+            assert_eq!(keychain.peer_count(), 4);
+            assert_eq!(keychain.threshold(), 3);
+        }
+    }
+
+    #[test]
+    fn keychain_requires_broadcast_secret_key() {
+        let mut cfg = trusted_dealer_configs(4, ConsensusEngine::AtomicBroadcast)
+            .remove(&PeerId::from(0))
+            .unwrap();
+        cfg.private.broadcast_secret_key = None;
+
+        assert!(broadcast_keychain(&cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn submitted_items_decode_to_consensus_items() {
+        let (sender, receiver) = async_channel::bounded(1);
+        let item = ConsensusItem::ConsensusUpgrade(ConsensusUpgrade);
+
+        submit_item(&sender, &item).await;
+
+        let bytes = receiver.recv().await.unwrap();
+        let decoded = ConsensusItem::consensus_decode(
+            &mut bytes.as_slice(),
+            &ModuleDecoderRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(decoded, item);
+    }
+
+    #[tokio::test]
+    async fn new_session_has_no_module_items() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let session = Session::open(&db, 7).await;
+
+        assert_eq!(session.index, 7);
+        assert!(session.module_peers().is_empty());
+        assert!(session.upgrade_items.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(clippy::let_unit_value)]
 
+pub mod broadcast;
 pub mod debug;
 pub mod server;
 
@@ -30,6 +31,7 @@ use crate::db::{
     AcceptedTransactionKey, ClientConfigSignatureKey, ConsensusUpgradeKey, DropPeerKey,
     DropPeerKeyPrefix, EpochHistoryKey, LastEpochKey, RejectedTransactionKey,
 };
+use crate::metrics;
 use crate::net::api::ConsensusApi;
 use crate::transaction::{Transaction, TransactionError};
 
@@ -189,6 +191,23 @@ impl FedimintConsensus {
             .await
             .expect("Committing consensus epoch failed");
 
+        let txids: HashSet<TransactionId> = epoch_history
+            .outcome
+            .items
+            .iter()
+            .flat_map(|(_, items)| items.iter())
+            .filter_map(|item| match item {
+                ConsensusItem::Transaction(tx) => Some(tx.tx_hash()),
+                _ => None,
+            })
+            .collect();
+        let rejected = epoch_history.outcome.rejected_txs.len();
+        metrics::CONSENSUS_TRANSACTIONS_ACCEPTED
+            .inc_by(txids.len().saturating_sub(rejected) as u64);
+        metrics::CONSENSUS_TRANSACTIONS_REJECTED.inc_by(rejected as u64);
+        metrics::CONSENSUS_EPOCHS_PROCESSED.inc();
+        metrics::CONSENSUS_EPOCH.set(epoch_history.outcome.epoch as i64);
+
         let audit = self.audit().await;
         if audit.sum().milli_sat < 0 {
             panic!("Balance sheet of the fed has gone negative, this should never happen! {audit}")
@@ -7,6 +7,7 @@ use anyhow::bail;
 use fedimint_core::api::{
     ConsensusContribution, DynGlobalApi, GlobalFederationApi, WsFederationApi,
 };
+use fedimint_core::backup::{BackupLimits, BackupQuota};
 use fedimint_core::cancellable::Cancellable;
 use fedimint_core::config::ServerModuleGenRegistry;
 use fedimint_core::db::{apply_migrations, Database};
@@ -14,7 +15,9 @@ use fedimint_core::encoding::DecodeError;
 use fedimint_core::epoch::{
     ConsensusItem, EpochOutcome, EpochVerifyError, SerdeConsensusItem, SignedEpochOutcome,
 };
-use fedimint_core::module::registry::{ModuleDecoderRegistry, ModuleRegistry};
+use fedimint_core::module::registry::{
+    ModuleDecoderRegistry, ModuleRegistry, ServerModuleRegistry,
+};
 use fedimint_core::net::peers::PeerConnections;
 use fedimint_core::task::{sleep, RwLock, TaskGroup, TaskHandle};
 use fedimint_core::{NumPeers, PeerId};
@@ -41,7 +44,9 @@ use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::net::peers::IPeerConnections;
 use crate::net::api::{ConsensusApi, ExpiringCache};
 use crate::net::connect::{Connector, TlsTcpConnector};
-use crate::net::peers::{DelayCalculator, PeerConnector, PeerSlice, ReconnectPeerConnections};
+use crate::net::peers::{
+    DelayCalculator, PeerConnector, PeerSlice, PeerStatusChannels, ReconnectPeerConnections,
+};
 use crate::{LOG_CONSENSUS, LOG_CORE};
 type PeerMessage = (PeerId, EpochMessage);
 
@@ -138,39 +143,7 @@ impl ConsensusServer {
         delay_calculator: DelayCalculator,
         task_group: &mut TaskGroup,
     ) -> anyhow::Result<Self> {
-        // Apply database migrations and build `ServerModuleRegistry`
-        let mut modules = BTreeMap::new();
-
-        apply_migrations(
-            &db,
-            "Global".to_string(),
-            GLOBAL_DATABASE_VERSION,
-            get_global_database_migrations(),
-        )
-        .await?;
-
-        for (module_id, module_cfg) in &cfg.consensus.modules {
-            let kind = module_cfg.kind.clone();
-            let Some(init) = module_inits.get(&kind) else {
-                bail!("Detected configuration for unsupported module kind: {kind}")
-            };
-            info!(target: LOG_CORE,
-                module_instance_id = *module_id, kind = %kind, "Init module");
-
-            let isolated_db = db.new_isolated(*module_id);
-            apply_migrations(
-                &isolated_db,
-                init.module_kind().to_string(),
-                init.database_version(),
-                init.get_database_migrations(),
-            )
-            .await?;
-
-            let module = init
-                .init(cfg.get_module_config(*module_id)?, isolated_db, task_group)
-                .await?;
-            modules.insert(*module_id, (kind, module));
-        }
+        let modules = init_modules(&cfg, &db, &module_inits, task_group).await?;
 
         // Check the configs are valid
         cfg.validate_config(&cfg.local.identity, &module_inits)?;
@@ -195,45 +168,12 @@ impl ConsensusServer {
         let hbbft: HoneyBadger<Vec<SerdeConsensusItem>, _> =
             HoneyBadger::builder(Arc::new(net_info)).build();
 
-        let api_endpoints = cfg
-            .consensus
-            .api_endpoints
-            .clone()
-            .into_iter()
-            .map(|(id, node)| (id, node.url));
-        let api = WsFederationApi::new(api_endpoints.collect());
         let mut other_peers: BTreeSet<_> = cfg.local.p2p_endpoints.keys().cloned().collect();
         other_peers.remove(&cfg.local.identity);
 
-        // Build API that can handle requests
-        let (api_sender, api_receiver) = mpsc::channel(TRANSACTION_BUFFER_SIZE);
-        let client_cfg = cfg.consensus.to_client_config(&module_inits)?;
-        let modules = ModuleRegistry::from(modules);
-
-        let latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>> = Default::default();
-        let consensus_api = ConsensusApi {
-            cfg: cfg.clone(),
-            db: db.clone(),
-            modules: modules.clone(),
-            client_cfg,
-            api_sender,
-            supported_api_versions: ServerConfig::supported_api_versions_summary(&modules),
-            latest_contribution_by_peer: Arc::clone(&latest_contribution_by_peer),
-            peer_status_channels,
-            // keep the status for a short time to protect the system against a denial-of-service
-            // attack
-            consensus_status_cache: ExpiringCache::new(Duration::from_millis(500)),
-        };
-
-        // Build consensus processor
-        let consensus = FedimintConsensus {
-            cfg: cfg.clone(),
-            module_inits,
-            modules: modules.clone(),
-            db: db.clone(),
-            api: consensus_api,
-            api_event_cache: Default::default(),
-        };
+        let (consensus, api_receiver, latest_contribution_by_peer) =
+            build_consensus(&cfg, &db, module_inits, modules, peer_status_channels)?;
+        let decoders = consensus.decoders();
 
         Ok(ConsensusServer {
             task_group: task_group.clone(),
@@ -242,32 +182,19 @@ impl ConsensusServer {
             consensus,
             api_receiver: ReceiverStream::new(api_receiver).peekable(),
             cfg: cfg.clone(),
-            api: api.into(),
+            api: federation_api(&cfg),
             other_peers,
             rejoin_at_epoch: Default::default(),
             latest_contribution_by_peer,
             pending_forced_epochs: 0,
             last_processed_epoch: None,
-            decoders: modules.decoder_registry(),
+            decoders,
         })
     }
 
     /// Loop `run_conensus_epoch` until shut down
     pub async fn run_consensus(mut self, task_handle: TaskHandle) -> anyhow::Result<()> {
-        let our_hash = self.cfg.consensus.consensus_hash();
-
-        // Confirm our hash matches with peers
-        loop {
-            info!(target: LOG_CONSENSUS, "Waiting for peers config {our_hash}");
-            match self.api.consensus_config_hash().await {
-                Ok(consensus_hash) if consensus_hash == our_hash => break,
-                Ok(_) => bail!("Our consensus config doesn't match peers!"),
-                Err(e) => {
-                    warn!(target: LOG_CONSENSUS, "ERROR {:?}", e)
-                }
-            }
-            sleep(Duration::from_millis(100)).await;
-        }
+        confirm_consensus_config_hash(&self.api, &self.cfg).await?;
 
         let mut rng = OsRng;
         self.start_consensus().await;
@@ -646,6 +573,126 @@ impl ConsensusServer {
     }
 }
 
+/// Applies database migrations and initializes all modules found in the config
+pub(crate) async fn init_modules(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_inits: &ServerModuleGenRegistry,
+    task_group: &mut TaskGroup,
+) -> anyhow::Result<ServerModuleRegistry> {
+    let mut modules = BTreeMap::new();
+
+    apply_migrations(
+        db,
+        "Global".to_string(),
+        GLOBAL_DATABASE_VERSION,
+        get_global_database_migrations(),
+    )
+    .await?;
+
+    for (module_id, module_cfg) in &cfg.consensus.modules {
+        let kind = module_cfg.kind.clone();
+        let Some(init) = module_inits.get(&kind) else {
+            bail!("Detected configuration for unsupported module kind: {kind}")
+        };
+        info!(target: LOG_CORE,
+            module_instance_id = *module_id, kind = %kind, "Init module");
+
+        let isolated_db = db.new_isolated(*module_id);
+        apply_migrations(
+            &isolated_db,
+            init.module_kind().to_string(),
+            init.database_version(),
+            init.get_database_migrations(),
+        )
+        .await?;
+
+        let module = init
+            .init(cfg.get_module_config(*module_id)?, isolated_db, task_group)
+            .await?;
+        modules.insert(*module_id, (kind, module));
+    }
+
+    Ok(ModuleRegistry::from(modules))
+}
+
+/// Builds the API that can handle requests and the consensus processor, both
+/// are independent of the algorithm used to order consensus items
+#[allow(clippy::type_complexity)]
+pub(crate) fn build_consensus(
+    cfg: &ServerConfig,
+    db: &Database,
+    module_inits: ServerModuleGenRegistry,
+    modules: ServerModuleRegistry,
+    peer_status_channels: PeerStatusChannels,
+) -> anyhow::Result<(
+    FedimintConsensus,
+    mpsc::Receiver<ApiEvent>,
+    Arc<RwLock<LatestContributionByPeer>>,
+)> {
+    let (api_sender, api_receiver) = mpsc::channel(TRANSACTION_BUFFER_SIZE);
+    let client_cfg = cfg.consensus.to_client_config(&module_inits)?;
+
+    let latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>> = Default::default();
+    let consensus_api = ConsensusApi {
+        cfg: cfg.clone(),
+        db: db.clone(),
+        modules: modules.clone(),
+        client_cfg,
+        api_sender,
+        supported_api_versions: ServerConfig::supported_api_versions_summary(&modules),
+        latest_contribution_by_peer: Arc::clone(&latest_contribution_by_peer),
+        peer_status_channels,
+        // keep the status for a short time to protect the system against a denial-of-service
+        // attack
+        consensus_status_cache: ExpiringCache::new(Duration::from_millis(500)),
+        backup_quota: Arc::new(BackupQuota::new(BackupLimits::from_env_vars()?)),
+    };
+
+    let consensus = FedimintConsensus {
+        cfg: cfg.clone(),
+        module_inits,
+        modules,
+        db: db.clone(),
+        api: consensus_api,
+        api_event_cache: Default::default(),
+    };
+
+    Ok((consensus, api_receiver, latest_contribution_by_peer))
+}
+
+/// Creates a client for the APIs of all peers in the federation
+pub(crate) fn federation_api(cfg: &ServerConfig) -> DynGlobalApi {
+    let api_endpoints = cfg
+        .consensus
+        .api_endpoints
+        .clone()
+        .into_iter()
+        .map(|(id, node)| (id, node.url));
+    WsFederationApi::new(api_endpoints.collect()).into()
+}
+
+/// Waits until our peers confirm that they are running with the same
+/// consensus config as we are
+pub(crate) async fn confirm_consensus_config_hash(
+    api: &DynGlobalApi,
+    cfg: &ServerConfig,
+) -> anyhow::Result<()> {
+    let our_hash = cfg.consensus.consensus_hash();
+
+    loop {
+        info!(target: LOG_CONSENSUS, "Waiting for peers config {our_hash}");
+        match api.consensus_config_hash().await {
+            Ok(consensus_hash) if consensus_hash == our_hash => return Ok(()),
+            Ok(_) => bail!("Our consensus config doesn't match peers!"),
+            Err(e) => {
+                warn!(target: LOG_CONSENSUS, "ERROR {:?}", e)
+            }
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
 fn module_parse_outcome(
     outcome: HbbftSerdeConsensusOutcome,
     module_registry: &ModuleDecoderRegistry,
@@ -24,6 +24,7 @@ pub enum DbKeyPrefix {
     ClientConfigSignature = 0x07,
     ConsensusUpgrade = 0x08,
     ClientConfigDownload = 0x09,
+    AtomicBroadcastSession = 0x0a,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -144,6 +145,18 @@ impl_db_lookup!(
     query_prefix = ClientConfigDownloadKeyPrefix
 );
 
+/// Index of the atomic broadcast session whose items have not been committed
+/// yet. Prefixes `0x00` and `0x01` are used by `fedimint-atomic-broadcast` to
+/// store signed blocks and its session backups.
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct AtomicBroadcastSessionKey;
+
+impl_db_record!(
+    key = AtomicBroadcastSessionKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::AtomicBroadcastSession,
+);
+
 pub fn get_global_database_migrations<'a>() -> MigrationMap<'a> {
     MigrationMap::new()
 }
@@ -396,6 +409,8 @@ mod fedimint_migration_tests {
                                     "validate_migrations was not able to read any ClientConfigDownloadKey"
                                 );
                             }
+                            // Only written by the atomic broadcast, which postdates the v0 snapshot
+                            DbKeyPrefix::AtomicBroadcastSession => {}
                             // Module prefix is reserved for modules, no migration testing is needed
                             DbKeyPrefix::Module => {}
                     }
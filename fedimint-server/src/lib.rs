@@ -11,6 +11,7 @@ use anyhow::{anyhow as format_err, Context};
 use async_trait::async_trait;
 use config::io::PLAINTEXT_PASSWORD;
 use config::ServerConfig;
+use fedimint_core::admin_client::ConsensusEngine;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::Database;
 use fedimint_core::epoch::ConsensusItem;
@@ -29,9 +30,10 @@ use tokio::runtime::Runtime;
 use tracing::{error, info};
 
 use crate::config::api::{ConfigGenApi, ConfigGenSettings};
+use crate::consensus::broadcast::AtomicBroadcastServer;
 use crate::consensus::server::ConsensusServer;
 use crate::consensus::HbbftConsensusOutcome;
-use crate::net::api::RpcHandlerCtx;
+use crate::net::api::{ConsensusApi, RpcHandlerCtx};
 use crate::net::connect::TlsTcpConnector;
 use crate::net::peers::ReconnectPeerConnections;
 
@@ -50,6 +52,9 @@ pub mod config;
 /// Implementation of multiplexed peer connections
 pub mod multiplexed;
 
+/// Prometheus metrics exported by the server
+mod metrics;
+
 /// How long to wait before timing out client connections
 const API_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -85,20 +90,44 @@ impl FedimintServer {
             .run_config_gen(task_group.make_subgroup().await)
             .await?;
 
-        let server = ConsensusServer::new(
-            cfg,
-            self.db.clone(),
-            self.settings.registry.clone(),
-            &mut task_group,
-        )
-        .await
-        .unwrap();
+        match cfg.consensus.consensus_engine {
+            ConsensusEngine::Hbbft => {
+                let server = ConsensusServer::new(
+                    cfg,
+                    self.db.clone(),
+                    self.settings.registry.clone(),
+                    &mut task_group,
+                )
+                .await
+                .unwrap();
 
-        info!(target: LOG_CONSENSUS, "Starting consensus API");
-        let handler = Self::spawn_consensus_api(&server, true).await;
+                info!(target: LOG_CONSENSUS, "Starting consensus API");
+                let handler = Self::spawn_consensus_api(&server, true).await;
+                metrics::spawn_peer_metrics_task(server.consensus.api.clone(), &mut task_group)
+                    .await;
 
-        server.run_consensus(task_group.make_handle()).await?;
-        handler.stop().await;
+                server.run_consensus(task_group.make_handle()).await?;
+                handler.stop().await;
+            }
+            ConsensusEngine::AtomicBroadcast => {
+                let server = AtomicBroadcastServer::new(
+                    cfg,
+                    self.db.clone(),
+                    self.settings.registry.clone(),
+                    &mut task_group,
+                )
+                .await
+                .unwrap();
+
+                info!(target: LOG_CONSENSUS, "Starting consensus API");
+                let handler = Self::spawn_api_for(&server.consensus.api, true).await;
+                metrics::spawn_peer_metrics_task(server.consensus.api.clone(), &mut task_group)
+                    .await;
+
+                server.run_consensus(task_group.make_handle()).await?;
+                handler.stop().await;
+            }
+        }
 
         info!(target: LOG_CONSENSUS, "Shutting down tasks");
         task_group.shutdown().await;
@@ -150,7 +179,12 @@ impl FedimintServer {
         server: &ConsensusServer,
         force_shutdown: bool,
     ) -> FedimintApiHandler {
-        let api = &server.consensus.api;
+        Self::spawn_api_for(&server.consensus.api, force_shutdown).await
+    }
+
+    /// Runs the endpoints of a `ConsensusApi` independent of the consensus
+    /// engine driving it
+    async fn spawn_api_for(api: &ConsensusApi, force_shutdown: bool) -> FedimintApiHandler {
         let cfg = &api.cfg.local;
         let mut rpc_module = RpcHandlerCtx::new_module(api.clone());
         Self::attach_endpoints(&mut rpc_module, net::api::server_endpoints(), None);
@@ -234,6 +268,9 @@ impl FedimintServer {
                 .register_async_method(path, move |params, rpc_state| async move {
                     let params = params.one::<serde_json::Value>()?;
                     let rpc_context = &rpc_state.rpc_context;
+                    let _timer = metrics::API_REQUEST_DURATION_SECONDS
+                        .with_label_values(&[path])
+                        .start_timer();
 
                     // Using AssertUnwindSafe here is far from ideal. In theory this means we could
                     // end up with an inconsistent state in theory. In practice most API functions
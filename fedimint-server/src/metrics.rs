@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use fedimint_core::api::PeerConnectionStatus;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_metrics::{
+    histogram_opts, opts, register_histogram_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, HistogramVec,
+    IntCounter, IntGauge, IntGaugeVec, REGISTRY,
+};
+use once_cell::sync::Lazy;
+
+use crate::net::api::ConsensusApi;
+
+pub(crate) static CONSENSUS_EPOCHS_PROCESSED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        opts!(
+            "consensus_epochs_processed_total",
+            "Number of epochs (or sessions) processed by this guardian"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static CONSENSUS_EPOCH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        opts!(
+            "consensus_epoch",
+            "Last epoch (or session) processed by this guardian"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static CONSENSUS_TRANSACTIONS_ACCEPTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        opts!(
+            "consensus_transactions_accepted_total",
+            "Number of transactions accepted by consensus"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static CONSENSUS_TRANSACTIONS_REJECTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        opts!(
+            "consensus_transactions_rejected_total",
+            "Number of transactions rejected by consensus"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static PEER_CONNECTED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        opts!(
+            "peer_connected",
+            "Whether we are connected to a peer (1) or not (0)"
+        ),
+        &["peer_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static PEER_LAST_CONTRIBUTION: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        opts!(
+            "peer_last_contribution_epoch",
+            "Last epoch a peer contributed to, guardians falling behind contribute to older epochs"
+        ),
+        &["peer_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static API_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "api_request_duration_seconds",
+            "Duration of API requests by endpoint"
+        ),
+        &["method"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// How often the peer gauges are refreshed
+const PEER_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Periodically exports the connection state and latest consensus contribution
+/// of every peer, so operators can alert on guardians falling behind
+pub(crate) async fn spawn_peer_metrics_task(api: ConsensusApi, task_group: &mut TaskGroup) {
+    task_group
+        .spawn("peer metrics", move |handle| async move {
+            while !handle.is_shutting_down() {
+                for (peer_id, status) in api.peer_status_channels.get_all_status().await {
+                    let connected = matches!(status, Ok(PeerConnectionStatus::Connected));
+                    PEER_CONNECTED
+                        .with_label_values(&[&peer_id.to_string()])
+                        .set(connected.into());
+                }
+
+                for (peer_id, contribution) in api.latest_contribution_by_peer.read().await.iter() {
+                    PEER_LAST_CONTRIBUTION
+                        .with_label_values(&[&peer_id.to_string()])
+                        .set(contribution.value as i64);
+                }
+
+                sleep(PEER_METRICS_INTERVAL).await;
+            }
+        })
+        .await;
+}
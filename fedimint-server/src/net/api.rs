@@ -10,12 +10,16 @@ use fedimint_core::api::{
     ConsensusStatus, PeerConnectionStatus, PeerConsensusStatus, ServerStatus, StatusResponse,
     WsClientConnectInfo,
 };
-use fedimint_core::backup::ClientBackupKey;
+use fedimint_core::backup::{
+    BackupQuota, ClientBackupCountKey, ClientBackupKey, ClientBackupKeyPrefix,
+    ClientBackupReceivedKey,
+};
 use fedimint_core::config::{ClientConfig, ClientConfigResponse};
 use fedimint_core::core::backup::SignedBackupRequest;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{Database, DatabaseTransaction, ModuleDatabaseTransaction};
 use fedimint_core::epoch::{SerdeEpochHistory, SignedEpochOutcome};
+use fedimint_core::module::audit::{Audit, AuditSummary};
 use fedimint_core::module::registry::ServerModuleRegistry;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
@@ -85,6 +89,8 @@ pub struct ConsensusApi {
     pub latest_contribution_by_peer: Arc<RwLock<LatestContributionByPeer>>,
     pub consensus_status_cache: ExpiringCache<ApiResult<ConsensusStatus>>,
     pub supported_api_versions: SupportedApiVersionsSummary,
+    /// Limits on the client backups we store
+    pub backup_quota: Arc<BackupQuota>,
 }
 
 impl ConsensusApi {
@@ -317,6 +323,21 @@ impl ConsensusApi {
         ))
     }
 
+    /// Audits every module separately, so the balance sheet can be broken down
+    /// by module
+    pub async fn get_federation_audit(&self) -> AuditSummary {
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut module_audits = vec![];
+        for (module_instance_id, kind, module) in self.modules.iter_modules() {
+            let mut audit = Audit::default();
+            module
+                .audit(&mut dbtx.with_module_prefix(module_instance_id), &mut audit)
+                .await;
+            module_audits.push((module_instance_id, kind.clone(), audit));
+        }
+        AuditSummary::from_module_audits(module_audits)
+    }
+
     async fn handle_backup_request(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
@@ -327,13 +348,31 @@ impl ConsensusApi {
             .map_err(|_| ApiError::bad_request("invalid request".into()))?;
 
         debug!(target: LOG_NET_API, id = %request.id, len = request.payload.len(), "Received client backup request");
-        if let Some(prev) = dbtx.get_value(&ClientBackupKey(request.id)).await {
+        let prev = dbtx.get_value(&ClientBackupKey(request.id)).await;
+        if let Some(prev) = &prev {
             if request.timestamp <= prev.timestamp {
                 debug!(id = %request.id, len = request.payload.len(), "Received client backup request with old timestamp - ignoring");
                 return Err(ApiError::bad_request("timestamp too small".into()));
             }
         }
 
+        self.backup_quota
+            .check_upload(
+                dbtx,
+                &ClientBackupKeyPrefix,
+                &ClientBackupCountKey,
+                request.id,
+                request.payload.len(),
+                prev.is_none(),
+                ClientBackupReceivedKey,
+                |key: &ClientBackupKey| key.0,
+            )
+            .await
+            .map_err(|e| {
+                info!(target: LOG_NET_API, id = %request.id, len = request.payload.len(), code = e.code, "Rejected client backup: {}", e.message);
+                e
+            })?;
+
         info!(target: LOG_NET_API, id = %request.id, len = request.payload.len(), "Storing new client backup");
         dbtx.insert_entry(
             &ClientBackupKey(request.id),
@@ -598,6 +637,16 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 }
             }
         },
+        api_endpoint! {
+            "audit",
+            async |fedimint: &ConsensusApi, context, _v: ()| -> AuditSummary {
+                if context.has_auth() {
+                    Ok(fedimint.get_federation_audit().await)
+                } else {
+                    Err(ApiError::unauthorized())
+                }
+            }
+        },
         api_endpoint! {
             "backup",
             async |fedimint: &ConsensusApi, context, request: SignedBackupRequest| -> () {
@@ -41,12 +41,15 @@ impl FederationTest {
 
     /// Create a client connected to this fed
     pub async fn new_client(&self) -> Client {
-        let client_config = self.configs[&PeerId::from(0)]
+        self.new_client_with_config(self.client_config()).await
+    }
+
+    /// Return the config clients of this fed are started with
+    pub fn client_config(&self) -> ClientConfig {
+        self.configs[&PeerId::from(0)]
             .consensus
             .to_client_config(&self.server_gen)
-            .unwrap();
-
-        self.new_client_with_config(client_config).await
+            .unwrap()
     }
 
     pub async fn new_client_with_config(&self, client_config: ClientConfig) -> Client {
@@ -68,11 +71,7 @@ impl FederationTest {
 
     ///  Return first id for gateways
     pub fn id(&self) -> FederationId {
-        self.configs[&PeerId::from(0)]
-            .consensus
-            .to_client_config(&self.server_gen)
-            .unwrap()
-            .federation_id
+        self.client_config().federation_id
     }
 
     pub(crate) async fn new(
@@ -189,6 +188,7 @@ pub fn local_config_gen_params(
                         "federation_name".to_string(),
                     )]),
                     modules: server_config_gen.clone(),
+                    consensus_engine: Default::default(),
                 },
             };
             Ok((*peer, params))
@@ -4,6 +4,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{env, fs};
 
+use fedimint_bip39::Mnemonic;
 use fedimint_bitcoind::create_bitcoind;
 use fedimint_client::module::gen::{ClientModuleGenRegistry, DynClientModuleGen, IClientModuleGen};
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
@@ -128,6 +129,16 @@ impl Fixtures {
 
     /// Starts a new gateway with a given lightning node
     pub async fn new_gateway(&self, ln: Arc<dyn LightningTest>) -> GatewayTest {
+        self.new_gateway_with_mnemonic(ln, None).await
+    }
+
+    /// Starts a new gateway with a given lightning node whose ecash is derived
+    /// from `mnemonic`, or from a random one if it is `None`
+    pub async fn new_gateway_with_mnemonic(
+        &self,
+        ln: Arc<dyn LightningTest>,
+        mnemonic: Option<Mnemonic>,
+    ) -> GatewayTest {
         // TODO: Make construction easier
         let server_gens = ServerModuleGenRegistry::from(self.servers.clone());
         let module_kinds = self.params.iter_modules().map(|(id, kind, _)| (id, kind));
@@ -138,6 +149,7 @@ impl Fixtures {
             BASE_PORT.fetch_add(1, Ordering::Relaxed),
             rand::random::<u64>().to_string(),
             ln,
+            mnemonic,
             decoders,
             ClientModuleGenRegistry::from_iter(clients.filter(|client| {
                 // Remove LN module because the gateway adds one
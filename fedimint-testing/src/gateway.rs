@@ -1,13 +1,18 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use fedimint_bip39::Mnemonic;
 use fedimint_client::module::gen::ClientModuleGenRegistry;
 use fedimint_client::Client;
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::Amount;
 use lightning::routing::gossip::RoutingFees;
-use ln_gateway::client::StandardGatewayClientBuilder;
+use lightning_invoice::Invoice;
+use ln_gateway::client::{load_or_generate_mnemonic, StandardGatewayClientBuilder};
+use ln_gateway::liquidity::LiquidityConfig;
+use ln_gateway::lnrpc_client::ILnRpcClient;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::rpc_server::run_webserver;
 use ln_gateway::rpc::{ConnectFedPayload, FederationInfo};
@@ -17,6 +22,7 @@ use url::Url;
 
 use crate::federation::FederationTest;
 use crate::fixtures::test_dir;
+use crate::ln::mock::FakeLightningTest;
 use crate::ln::LightningTest;
 
 /// Fixture for creating a gateway
@@ -27,6 +33,8 @@ pub struct GatewayTest {
     api: Url,
     /// Handle of the running gateway
     gateway: Gateway,
+    /// Database of the gateway
+    gatewayd_db: Database,
     /// Temporary dir that stores the gateway config
     _config_dir: Option<TempDir>,
 }
@@ -42,6 +50,38 @@ impl GatewayTest {
         self.gateway.remove_client(fed.id()).await.unwrap()
     }
 
+    /// Returns the client of a federation the gateway is connected to
+    pub async fn select_client(&self, fed: &FederationTest) -> Arc<Client> {
+        self.gateway.select_client(fed.id()).await.unwrap()
+    }
+
+    /// Database the gateway stores its own state in
+    pub fn gatewayd_db(&self) -> &Database {
+        &self.gatewayd_db
+    }
+
+    /// Routes the HTLCs `lightning` intercepts through the gateway, which the
+    /// gateways of the fixtures don't do by default
+    pub async fn route_htlcs(&mut self, lightning: &FakeLightningTest) {
+        let lightning = lightning.clone();
+        self.gateway
+            .route_htlcs_from(move || {
+                let lightning = lightning.clone();
+                async move { Box::new(lightning) as Box<dyn ILnRpcClient> }
+            })
+            .await
+            .unwrap();
+    }
+
+    /// Issues an invoice for `amount` to the LNURL-pay `username`, like the
+    /// gateway does when the payer calls the LNURL callback
+    pub async fn lnurl_pay_invoice(&self, username: &str, amount: Amount) -> Invoice {
+        self.gateway
+            .handle_lnurl_pay_callback(username.to_string(), amount)
+            .await
+            .unwrap()
+    }
+
     /// Connects to a new federation and stores the info
     pub async fn connect_fed(&mut self, fed: &FederationTest) -> FederationInfo {
         let connect = fed.connection_code().to_string();
@@ -55,6 +95,7 @@ impl GatewayTest {
         base_port: u16,
         password: String,
         lightning: Arc<dyn LightningTest>,
+        mnemonic: Option<Mnemonic>,
         decoders: ModuleDecoderRegistry,
         registry: ClientModuleGenRegistry,
     ) -> Self {
@@ -67,6 +108,11 @@ impl GatewayTest {
             StandardGatewayClientBuilder::new(path.clone(), registry, 0);
 
         let gatewayd_db = Database::new(MemDatabase::new(), decoders.clone());
+        if let Some(mnemonic) = mnemonic {
+            load_or_generate_mnemonic(&gatewayd_db, Some(mnemonic))
+                .await
+                .unwrap();
+        }
         let gateway = Gateway::new_with_lightning_connection(
             lightning.as_rpc(),
             client_builder.clone(),
@@ -74,7 +120,8 @@ impl GatewayTest {
                 base_msat: 0,
                 proportional_millionths: 0,
             },
-            gatewayd_db,
+            LiquidityConfig::default(),
+            gatewayd_db.clone(),
             address.clone(),
         )
         .await
@@ -89,6 +136,7 @@ impl GatewayTest {
             api: address,
             _config_dir,
             gateway,
+            gatewayd_db,
         }
     }
 }
@@ -1,5 +1,6 @@
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use bitcoin::hashes::{sha256, Hash};
@@ -15,24 +16,43 @@ use lightning_invoice::{
     DEFAULT_EXPIRY_TIME,
 };
 use ln_gateway::gatewaylnrpc::{
-    self, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse, PayInvoiceRequest,
-    PayInvoiceResponse,
+    self, CreateOfferRequest, CreateOfferResponse, FetchInvoiceRequest, FetchInvoiceResponse,
+    GetChannelBalancesResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
+    InterceptHtlcResponse, InvoiceRequestResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
-use ln_gateway::lnrpc_client::{ILnRpcClient, RouteHtlcStream};
+use ln_gateway::lnrpc_client::{ILnRpcClient, RouteHtlcStream, RouteInvoiceRequestStream};
 use ln_gateway::GatewayError;
 use rand::rngs::OsRng;
+use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
 use super::LightningTest;
 
 pub const INVALID_INVOICE_DESCRIPTION: &str = "INVALID";
 
+/// Total capacity of the fake lightning node's channels, initially all on our
+/// side
+pub const FAKE_CHANNEL_CAPACITY_MSAT: u64 = 100_000_000_000;
+
+/// Preimage of the BOLT12 invoices fetched from the fake lightning node, which
+/// differs from the one of its BOLT11 invoices so paying the wrong invoice is
+/// noticed
+pub const FAKE_BOLT12_PREIMAGE: [u8; 32] = [1; 32];
+
 #[derive(Clone, Debug)]
 pub struct FakeLightningTest {
     pub preimage: Preimage,
     pub gateway_node_pub_key: secp256k1::PublicKey,
     gateway_node_sec_key: secp256k1::SecretKey,
     amount_sent: Arc<Mutex<u64>>,
+    /// Amounts of the BOLT12 invoices fetched so far
+    bolt12_invoices: Arc<Mutex<BTreeMap<String, u64>>>,
+    /// HTLCs passed to the gateway routing the HTLCs of the fake node
+    htlc_sender: mpsc::UnboundedSender<InterceptHtlcRequest>,
+    htlc_receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<InterceptHtlcRequest>>>,
+    /// Responses of the gateway to the HTLCs
+    htlc_response_sender: mpsc::UnboundedSender<InterceptHtlcResponse>,
+    htlc_response_receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<InterceptHtlcResponse>>>,
 }
 
 impl FakeLightningTest {
@@ -40,14 +60,39 @@ impl FakeLightningTest {
         let ctx = bitcoin::secp256k1::Secp256k1::new();
         let kp = KeyPair::new(&ctx, &mut OsRng);
         let amount_sent = Arc::new(Mutex::new(0));
+        let (htlc_sender, htlc_receiver) = mpsc::unbounded_channel();
+        let (htlc_response_sender, htlc_response_receiver) = mpsc::unbounded_channel();
 
         FakeLightningTest {
             preimage: Preimage([0; 32]),
             gateway_node_sec_key: SecretKey::from_keypair(&kp),
             gateway_node_pub_key: PublicKey::from_keypair(&kp),
             amount_sent,
+            bolt12_invoices: Arc::new(Mutex::new(BTreeMap::new())),
+            htlc_sender,
+            htlc_receiver: Arc::new(tokio::sync::Mutex::new(htlc_receiver)),
+            htlc_response_sender,
+            htlc_response_receiver: Arc::new(tokio::sync::Mutex::new(htlc_response_receiver)),
         }
     }
+
+    /// Passes an HTLC to the gateway routing the HTLCs of the fake node, as if
+    /// the node intercepted it
+    pub fn intercept_htlc(&self, htlc: InterceptHtlcRequest) {
+        self.htlc_sender
+            .send(htlc)
+            .expect("The fake node holds the receiver");
+    }
+
+    /// Waits for the next response of the gateway to an intercepted HTLC
+    pub async fn next_htlc_response(&self) -> InterceptHtlcResponse {
+        self.htlc_response_receiver
+            .lock()
+            .await
+            .recv()
+            .await
+            .expect("The fake node holds the sender")
+    }
 }
 
 impl Default for FakeLightningTest {
@@ -107,7 +152,22 @@ impl ILnRpcClient for FakeLightningTest {
         })
     }
 
+    async fn channel_balances(&self) -> ln_gateway::Result<GetChannelBalancesResponse> {
+        let amount_sent = *self.amount_sent.lock().unwrap();
+        Ok(GetChannelBalancesResponse {
+            local_balance_msat: FAKE_CHANNEL_CAPACITY_MSAT.saturating_sub(amount_sent),
+            remote_balance_msat: amount_sent,
+        })
+    }
+
     async fn pay(&self, invoice: PayInvoiceRequest) -> ln_gateway::Result<PayInvoiceResponse> {
+        if let Some(amount_msat) = self.bolt12_invoices.lock().unwrap().get(&invoice.invoice) {
+            *self.amount_sent.lock().unwrap() += *amount_msat;
+            return Ok(PayInvoiceResponse {
+                preimage: FAKE_BOLT12_PREIMAGE.to_vec(),
+            });
+        }
+
         let signed = invoice.invoice.parse::<SignedRawInvoice>().unwrap();
         let invoice = Invoice::from_signed(signed).unwrap();
         *self.amount_sent.lock().unwrap() += invoice.amount_milli_satoshis().unwrap();
@@ -132,6 +192,7 @@ impl ILnRpcClient for FakeLightningTest {
         events: ReceiverStream<InterceptHtlcResponse>,
         task_group: &mut TaskGroup,
     ) -> Result<RouteHtlcStream<'a>, GatewayError> {
+        let htlc_responses = self.htlc_response_sender.clone();
         task_group
             .spawn("FakeRoutingThread", |handle| async move {
                 let mut stream = events.into_inner();
@@ -140,10 +201,62 @@ impl ILnRpcClient for FakeLightningTest {
                         break;
                     }
                     tracing::debug!("FakeLightningTest received HTLC message {:?}", route_htlc);
+                    // Nobody might be waiting for responses
+                    let _ = htlc_responses.send(route_htlc);
                 }
             })
             .await;
 
+        Ok(Box::pin(stream::unfold(
+            self.htlc_receiver.clone(),
+            |htlcs| async move {
+                let htlc = htlcs.lock().await.recv().await?;
+                Some((Ok(htlc), htlcs))
+            },
+        )))
+    }
+
+    async fn fetch_invoice(
+        &self,
+        request: FetchInvoiceRequest,
+    ) -> Result<FetchInvoiceResponse, GatewayError> {
+        // Offers of the fake node never specify an amount
+        let amount_msat = request.amount_msat.ok_or_else(|| {
+            GatewayError::Other(anyhow::anyhow!("Offer requires an amount to be specified"))
+        })?;
+
+        let mut bolt12_invoices = self.bolt12_invoices.lock().unwrap();
+        let invoice = format!("lni1fake{}", bolt12_invoices.len());
+        bolt12_invoices.insert(invoice.clone(), amount_msat);
+
+        let created_at = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        Ok(FetchInvoiceResponse {
+            invoice,
+            payment_hash: sha256::Hash::hash(&FAKE_BOLT12_PREIMAGE)
+                .into_inner()
+                .to_vec(),
+            amount_msat,
+            expires_at: created_at + DEFAULT_EXPIRY_TIME,
+        })
+    }
+
+    async fn create_offer(
+        &self,
+        _request: CreateOfferRequest,
+    ) -> Result<CreateOfferResponse, GatewayError> {
+        Err(GatewayError::Other(anyhow::anyhow!(
+            "FakeLightningTest does not support BOLT12 offers"
+        )))
+    }
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        _events: ReceiverStream<InvoiceRequestResponse>,
+        _task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>, GatewayError> {
         Ok(Box::pin(stream::iter(vec![])))
     }
 }
@@ -12,11 +12,14 @@ use fedimint_core::task::TaskGroup;
 use fedimint_core::Amount;
 use lightning_invoice::Invoice;
 use ln_gateway::gatewaylnrpc::{
-    GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse, PayInvoiceRequest,
-    PayInvoiceResponse,
+    CreateOfferRequest, CreateOfferResponse, FetchInvoiceRequest, FetchInvoiceResponse,
+    GetChannelBalancesResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
+    InvoiceRequestResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 use ln_gateway::lnd::GatewayLndClient;
-use ln_gateway::lnrpc_client::{ILnRpcClient, NetworkLnRpcClient, RouteHtlcStream};
+use ln_gateway::lnrpc_client::{
+    ILnRpcClient, NetworkLnRpcClient, RouteHtlcStream, RouteInvoiceRequestStream,
+};
 use ln_gateway::GatewayError;
 use tokio::sync::{Mutex, RwLock};
 use tokio_stream::wrappers::ReceiverStream;
@@ -105,6 +108,10 @@ impl ILnRpcClient for ClnLightningTest {
         self.lnrpc.read().await.routehints().await
     }
 
+    async fn channel_balances(&self) -> Result<GetChannelBalancesResponse, GatewayError> {
+        self.lnrpc.read().await.channel_balances().await
+    }
+
     async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse, GatewayError> {
         self.lnrpc.read().await.pay(invoice).await
     }
@@ -120,6 +127,32 @@ impl ILnRpcClient for ClnLightningTest {
             .route_htlcs(events, task_group)
             .await
     }
+
+    async fn fetch_invoice(
+        &self,
+        request: FetchInvoiceRequest,
+    ) -> Result<FetchInvoiceResponse, GatewayError> {
+        self.lnrpc.read().await.fetch_invoice(request).await
+    }
+
+    async fn create_offer(
+        &self,
+        request: CreateOfferRequest,
+    ) -> Result<CreateOfferResponse, GatewayError> {
+        self.lnrpc.read().await.create_offer(request).await
+    }
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        events: ReceiverStream<InvoiceRequestResponse>,
+        task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>, GatewayError> {
+        self.lnrpc
+            .write()
+            .await
+            .route_invoice_requests(events, task_group)
+            .await
+    }
 }
 
 impl ClnLightningTest {
@@ -256,6 +289,10 @@ impl ILnRpcClient for LndLightningTest {
         self.lnrpc.read().await.routehints().await
     }
 
+    async fn channel_balances(&self) -> Result<GetChannelBalancesResponse, GatewayError> {
+        self.lnrpc.read().await.channel_balances().await
+    }
+
     async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse, GatewayError> {
         self.lnrpc.read().await.pay(invoice).await
     }
@@ -271,6 +308,32 @@ impl ILnRpcClient for LndLightningTest {
             .route_htlcs(events, task_group)
             .await
     }
+
+    async fn fetch_invoice(
+        &self,
+        request: FetchInvoiceRequest,
+    ) -> Result<FetchInvoiceResponse, GatewayError> {
+        self.lnrpc.read().await.fetch_invoice(request).await
+    }
+
+    async fn create_offer(
+        &self,
+        request: CreateOfferRequest,
+    ) -> Result<CreateOfferResponse, GatewayError> {
+        self.lnrpc.read().await.create_offer(request).await
+    }
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        events: ReceiverStream<InvoiceRequestResponse>,
+        task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>, GatewayError> {
+        self.lnrpc
+            .write()
+            .await
+            .route_invoice_requests(events, task_group)
+            .await
+    }
 }
 
 impl LndLightningTest {
@@ -11,7 +11,7 @@ async fn client(connect_info: &WsClientConnectInfo) -> Result<fedimint_client::C
     let cfg = client.download_client_config(connect_info).await?;
     let mut builder = fedimint_client::ClientBuilder::default();
     builder.with_module(LightningClientGen);
-    builder.with_module(MintClientGen);
+    builder.with_module(MintClientGen::default());
     builder.with_module(WalletClientGen::default());
     builder.with_primary_module(1);
     builder.with_config(cfg);
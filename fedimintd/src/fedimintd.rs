@@ -4,7 +4,7 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use clap::Parser;
-use fedimint_core::admin_client::ConfigGenParamsRequest;
+use fedimint_core::admin_client::{ConfigGenParamsRequest, ConsensusEngine};
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::config::{ServerModuleGenParamsRegistry, ServerModuleGenRegistry};
 use fedimint_core::db::Database;
@@ -18,6 +18,7 @@ use fedimint_mint_server::MintGen;
 use fedimint_server::config::api::ConfigGenSettings;
 use fedimint_server::config::io::{CODE_VERSION, DB_FILE, PLAINTEXT_PASSWORD};
 use fedimint_server::FedimintServer;
+use fedimint_wallet_server::common::config::PegInDescriptorType;
 use fedimint_wallet_server::WalletGen;
 use futures::FutureExt;
 use tokio::select;
@@ -55,6 +56,9 @@ pub struct ServerOpts {
     /// Address we bind to for exposing the API
     #[arg(long, env = "FM_BIND_API", default_value = "127.0.0.1:8174")]
     bind_api: SocketAddr,
+    /// Address we bind to for exposing Prometheus metrics, disabled if not set
+    #[arg(long, env = "FM_BIND_METRICS_API")]
+    bind_metrics_api: Option<SocketAddr>,
     /// Our API address for clients to connect to us
     #[arg(long, env = "FM_API_URL", default_value = "ws://127.0.0.1:8174")]
     api_url: Url,
@@ -68,6 +72,19 @@ pub struct ServerOpts {
     /// The bitcoin network that fedimint will be running on
     #[arg(long, env = "FM_FINALITY_DELAY", default_value = "10")]
     finality_delay: u32,
+    /// Pay out peg-outs in batches, collecting peg-outs for this many blocks
+    /// before creating a transaction (only used if we are the config gen
+    /// leader)
+    #[arg(long, env = "FM_PEG_OUT_BATCH_WINDOW")]
+    peg_out_batch_window: Option<u32>,
+    /// The kind of peg-in address the federation will use if we are the config
+    /// gen leader (`wsh` or `tr-script-only`)
+    #[arg(long, env = "FM_PEG_IN_DESCRIPTOR_TYPE", default_value = "wsh")]
+    peg_in_descriptor_type: PegInDescriptorType,
+    /// The consensus algorithm the federation will run if we are the config
+    /// gen leader (`hbbft` or `atomic_broadcast`)
+    #[arg(long, env = "FM_CONSENSUS_ENGINE", default_value = "hbbft")]
+    consensus_engine: ConsensusEngine,
 }
 
 /// `fedimintd` builder
@@ -212,7 +229,7 @@ impl Fedimintd {
 
 async fn run(
     opts: ServerOpts,
-    task_group: TaskGroup,
+    mut task_group: TaskGroup,
     module_gens: ServerModuleGenRegistry,
     mut module_gens_params: ServerModuleGenParamsRegistry,
 ) -> anyhow::Result<()> {
@@ -222,6 +239,8 @@ async fn run(
         opts.max_denomination,
         opts.network,
         opts.finality_delay,
+        opts.peg_out_batch_window,
+        opts.peg_in_descriptor_type,
     );
 
     let module_kinds = module_gens_params
@@ -233,6 +252,10 @@ async fn run(
         decoders.clone(),
     );
 
+    if let Some(bind_metrics_api) = opts.bind_metrics_api {
+        fedimint_metrics::run_api_server(bind_metrics_api, &mut task_group).await?;
+    }
+
     // TODO: Fedimintd should use the config gen API
     // on each run we want to pass the currently passed passsword, so we need to
     // overwrite
@@ -242,6 +265,7 @@ async fn run(
     let default_params = ConfigGenParamsRequest {
         meta: BTreeMap::new(),
         modules: module_gens_params,
+        consensus_engine: opts.consensus_engine,
     };
     let mut api = FedimintServer {
         data_dir: opts.data_dir,
@@ -12,7 +12,7 @@ use fedimint_ln_server::LightningGen;
 use fedimint_mint_server::common::config::{MintGenParams, MintGenParamsConsensus};
 use fedimint_mint_server::MintGen;
 use fedimint_wallet_server::common::config::{
-    WalletGenParams, WalletGenParamsConsensus, WalletGenParamsLocal,
+    PegInDescriptorType, WalletGenParams, WalletGenParamsConsensus, WalletGenParamsLocal,
 };
 use fedimint_wallet_server::WalletGen;
 
@@ -26,6 +26,8 @@ pub fn attach_default_module_gen_params(
     max_denomination: Amount,
     network: Network,
     finality_delay: u32,
+    peg_out_batch_window: Option<u32>,
+    peg_in_descriptor_type: PegInDescriptorType,
 ) {
     module_gen_params
         .attach_config_gen_params(
@@ -40,6 +42,8 @@ pub fn attach_default_module_gen_params(
                     // TODO this is not very elegant, but I'm planning to get rid of it in a next
                     // commit anyway
                     finality_delay,
+                    peg_out_batch_window,
+                    peg_in_descriptor_type,
                 },
             },
         )
@@ -53,6 +57,7 @@ pub fn attach_default_module_gen_params(
                         .tiers()
                         .cloned()
                         .collect(),
+                    fee_consensus: Default::default(),
                 },
             },
         )
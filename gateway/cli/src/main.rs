@@ -4,8 +4,8 @@ use fedimint_core::config::FederationId;
 use fedimint_logging::TracingSetup;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    WithdrawPayload,
+    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, LiquidityPayload,
+    MnemonicPayload, RestorePayload, SetFeesPayload, WithdrawPayload,
 };
 use serde::Serialize;
 use url::Url;
@@ -50,6 +50,22 @@ pub enum Commands {
         #[clap(long)]
         address: Address,
     },
+    /// Compare the ecash balance of every federation with the lightning
+    /// channel balances and the configured limits, and show how they are
+    /// rebalanced
+    Liquidity,
+    /// Change the routing fees charged for payments of a federation, the
+    /// federation is informed right away
+    SetFees {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// Flat fee in msat charged for every payment
+        #[clap(long)]
+        base_msat: u32,
+        /// Fee in millionths of the payment amount, 10000 is 1%
+        #[clap(long)]
+        proportional_millionths: u32,
+    },
     /// Register federation with the gateway
     ConnectFed {
         /// ConnectInfo code to connect to the federation
@@ -60,11 +76,14 @@ pub enum Commands {
         #[clap(long)]
         federation_id: FederationId,
     },
-    /// Restore ecash from last available snapshot or from scratch
+    /// Reconnect to a federation the gateway lost the data of, restoring its
+    /// ecash from the last available snapshot or from scratch
     Restore {
-        #[clap(long)]
-        federation_id: FederationId,
+        /// ConnectInfo code to connect to the federation
+        connect: String,
     },
+    /// Print the mnemonic the ecash of all federations can be recovered with
+    Mnemonic,
     Completion {
         shell: clap_complete::Shell,
     },
@@ -115,6 +134,26 @@ async fn main() -> anyhow::Result<()> {
 
             print_response(response).await;
         }
+        Commands::Liquidity => {
+            let response = client().get_liquidity(LiquidityPayload).await?;
+
+            print_response(response).await;
+        }
+        Commands::SetFees {
+            federation_id,
+            base_msat,
+            proportional_millionths,
+        } => {
+            let response = client()
+                .set_fees(SetFeesPayload {
+                    federation_id,
+                    base_msat,
+                    proportional_millionths,
+                })
+                .await?;
+
+            print_response(response).await;
+        }
         Commands::ConnectFed { connect } => {
             let response = client()
                 .connect_federation(ConnectFedPayload { connect })
@@ -125,8 +164,15 @@ async fn main() -> anyhow::Result<()> {
         Commands::Backup { federation_id } => {
             client().backup(BackupPayload { federation_id }).await?;
         }
-        Commands::Restore { federation_id } => {
-            client().restore(RestorePayload { federation_id }).await?;
+        Commands::Restore { connect } => {
+            let response = client().restore(RestorePayload { connect }).await?;
+
+            print_response(response).await;
+        }
+        Commands::Mnemonic => {
+            let response = client().get_mnemonic(MnemonicPayload).await?;
+
+            print_response(response).await;
         }
         Commands::Completion { shell } => {
             clap_complete::generate(
@@ -21,13 +21,16 @@ use ln_gateway::gatewaylnrpc::gateway_lightning_server::{
 use ln_gateway::gatewaylnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use ln_gateway::gatewaylnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use ln_gateway::gatewaylnrpc::{
-    EmptyRequest, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    CreateOfferRequest, CreateOfferResponse, EmptyRequest, FetchInvoiceRequest,
+    FetchInvoiceResponse, GetChannelBalancesResponse, GetNodeInfoResponse, GetRouteHintsResponse,
+    InterceptHtlcRequest, InterceptHtlcResponse, InterceptInvoiceRequest, InvoiceRequestResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
 };
 use secp256k1::PublicKey;
 use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
-use tokio::io::{stdin, stdout};
+use tokio::io::{stdin, stdout, AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
 use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Server;
@@ -221,6 +224,53 @@ impl ClnRpcService {
         })
     }
 
+    /// Calls a CLN RPC method that is not covered by `cln_rpc`'s typed
+    /// requests, such as the ones for BOLT12 offers
+    async fn call_raw(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ClnExtensionError> {
+        let mut stream = UnixStream::connect(&self.socket)
+            .await
+            .map_err(|e| anyhow!("Could not connect to CLN RPC socket: {e}"))?;
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": method,
+            "params": params,
+        });
+        stream
+            .write_all(&serde_json::to_vec(&request).map_err(anyhow::Error::from)?)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        // lightningd keeps the connection open, so read until we got a complete response
+        let mut buf = vec![];
+        let response = loop {
+            let mut chunk = [0u8; 4096];
+            let read = stream.read(&mut chunk).await.map_err(anyhow::Error::from)?;
+            if read == 0 {
+                return Err(anyhow!("CLN closed the RPC connection").into());
+            }
+            buf.extend_from_slice(&chunk[..read]);
+
+            if let Ok(response) = serde_json::from_slice::<serde_json::Value>(&buf) {
+                break response;
+            }
+        };
+
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("CLN {method} returned error: {error}").into());
+        }
+
+        response
+            .get("result")
+            .cloned()
+            .ok_or(ClnExtensionError::RpcWrongResponse)
+    }
+
     pub async fn info(&self) -> Result<(PublicKey, String), ClnExtensionError> {
         self.rpc_client()
             .await?
@@ -426,6 +476,52 @@ impl GatewayLightning for ClnRpcService {
         Ok(tonic::Response::new(GetRouteHintsResponse { route_hints }))
     }
 
+    async fn get_channel_balances(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<GetChannelBalancesResponse>, Status> {
+        let peers_response = self
+            .rpc_client()
+            .await
+            .map_err(|err| tonic::Status::internal(err.to_string()))?
+            .call(cln_rpc::Request::ListPeers(model::ListpeersRequest {
+                id: None,
+                level: None,
+            }))
+            .await
+            .map_err(|err| tonic::Status::internal(err.to_string()))?;
+
+        let peers = match peers_response {
+            cln_rpc::Response::ListPeers(peers) => Ok(peers.peers),
+            _ => Err(ClnExtensionError::RpcWrongResponse),
+        }
+        .map_err(|err| tonic::Status::internal(err.to_string()))?;
+
+        let mut local_balance_msat = 0;
+        let mut remote_balance_msat = 0;
+        for chan in peers
+            .into_iter()
+            .filter(|peer| peer.connected)
+            .flat_map(|peer| peer.channels.into_iter())
+            .filter(|chan| {
+                matches!(
+                    chan.state,
+                    model::ListpeersPeersChannelsState::CHANNELD_NORMAL
+                )
+            })
+        {
+            let to_us_msat = chan.to_us_msat.map(|amt| amt.msat()).unwrap_or_default();
+            let total_msat = chan.total_msat.map(|amt| amt.msat()).unwrap_or_default();
+            local_balance_msat += to_us_msat;
+            remote_balance_msat += total_msat.saturating_sub(to_us_msat);
+        }
+
+        Ok(tonic::Response::new(GetChannelBalancesResponse {
+            local_balance_msat,
+            remote_balance_msat,
+        }))
+    }
+
     async fn pay_invoice(
         &self,
         request: tonic::Request<PayInvoiceRequest>,
@@ -472,6 +568,79 @@ impl GatewayLightning for ClnRpcService {
         Ok(tonic::Response::new(outcome))
     }
 
+    async fn fetch_invoice(
+        &self,
+        request: tonic::Request<FetchInvoiceRequest>,
+    ) -> Result<tonic::Response<FetchInvoiceResponse>, Status> {
+        let FetchInvoiceRequest { offer, amount_msat } = request.into_inner();
+
+        let mut params = serde_json::json!({ "offer": offer });
+        if let Some(amount_msat) = amount_msat {
+            params["amount_msat"] = serde_json::json!(amount_msat);
+        }
+
+        let invoice = self
+            .call_raw("fetchinvoice", params)
+            .await
+            .and_then(|response| {
+                response["invoice"]
+                    .as_str()
+                    .map(ToString::to_string)
+                    .ok_or(ClnExtensionError::RpcWrongResponse)
+            })
+            .map_err(|e| {
+                error!("cln fetchinvoice returned error {:?}", e);
+                Status::internal(e.to_string())
+            })?;
+
+        let decoded = self
+            .call_raw("decode", serde_json::json!({ "string": invoice }))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let payment_hash = decoded["invoice_payment_hash"]
+            .as_str()
+            .and_then(|hash| bitcoin_hashes::sha256::Hash::from_str(hash).ok())
+            .ok_or_else(|| Status::internal("BOLT12 invoice has no valid payment hash"))?;
+        let amount_msat = msat_value(&decoded["invoice_amount_msat"])
+            .ok_or_else(|| Status::internal("BOLT12 invoice has no valid amount"))?;
+        let created_at = decoded["invoice_created_at"]
+            .as_u64()
+            .ok_or_else(|| Status::internal("BOLT12 invoice has no creation time"))?;
+        let relative_expiry = decoded["invoice_relative_expiry"]
+            .as_u64()
+            .unwrap_or(BOLT12_DEFAULT_RELATIVE_EXPIRY);
+
+        Ok(tonic::Response::new(FetchInvoiceResponse {
+            invoice,
+            payment_hash: payment_hash.to_vec(),
+            amount_msat,
+            expires_at: created_at + relative_expiry,
+        }))
+    }
+
+    async fn create_offer(
+        &self,
+        _request: tonic::Request<CreateOfferRequest>,
+    ) -> Result<tonic::Response<CreateOfferResponse>, Status> {
+        // CLN answers invoice requests for its offers itself, with invoices for
+        // preimages it knows, so we can't issue invoices for federation offers yet
+        Err(Status::unimplemented(
+            "CLN does not support answering invoice requests for offers externally",
+        ))
+    }
+
+    type RouteInvoiceRequestsStream = ReceiverStream<Result<InterceptInvoiceRequest, Status>>;
+
+    async fn route_invoice_requests(
+        &self,
+        _request: tonic::Request<tonic::Streaming<InvoiceRequestResponse>>,
+    ) -> Result<tonic::Response<Self::RouteInvoiceRequestsStream>, Status> {
+        Err(Status::unimplemented(
+            "CLN does not support answering invoice requests for offers externally",
+        ))
+    }
+
     type RouteHtlcsStream = ReceiverStream<Result<InterceptHtlcRequest, Status>>;
 
     async fn route_htlcs(
@@ -515,6 +684,19 @@ pub enum ClnExtensionError {
     RpcWrongResponse,
 }
 
+/// Seconds a BOLT12 invoice without explicit expiry stays valid
+const BOLT12_DEFAULT_RELATIVE_EXPIRY: u64 = 7200;
+
+/// CLN reports amounts either as a number or as a string with a "msat" suffix
+/// depending on its version
+fn msat_value(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::Number(msat) => msat.as_u64(),
+        serde_json::Value::String(msat) => msat.strip_suffix("msat")?.parse().ok(),
+        _ => None,
+    }
+}
+
 // TODO: upstream
 fn scid_to_u64(scid: ShortChannelId) -> u64 {
     let mut scid_num = scid.outnum() as u64;
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use std::process::exit;
 
 use clap::Parser;
+use fedimint_bip39::Mnemonic;
 use fedimint_client::module::gen::ClientModuleGenRegistry;
 use fedimint_core::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
@@ -17,7 +18,8 @@ use fedimint_ln_common::LightningModuleTypes;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::{MintClientGen, MintCommonGen, MintModuleTypes};
 use fedimint_wallet_client::{WalletClientGen, WalletCommonGen, WalletModuleTypes};
-use ln_gateway::client::StandardGatewayClientBuilder;
+use ln_gateway::client::{load_or_generate_mnemonic, StandardGatewayClientBuilder};
+use ln_gateway::liquidity::LiquidityConfig;
 use ln_gateway::{Gateway, GatewayError, LightningMode, DEFAULT_FEES};
 use tracing::info;
 use url::Url;
@@ -49,6 +51,14 @@ pub struct GatewayOpts {
     /// Format: <base_msat>,<proportional_millionths>
     #[arg(long = "fees", env = "FM_GATEWAY_FEES")]
     pub fees: Option<GatewayFee>,
+
+    /// Mnemonic to recover the gateway's ecash from, only used on first start.
+    /// A new mnemonic is generated if none is given.
+    #[arg(long = "mnemonic", env = "FM_GATEWAY_MNEMONIC")]
+    pub mnemonic: Option<Mnemonic>,
+
+    #[command(flatten)]
+    pub liquidity: LiquidityConfig,
 }
 
 /// Fedimint Gateway Binary
@@ -77,8 +87,15 @@ async fn main() -> Result<(), anyhow::Error> {
         api_addr,
         password,
         fees,
+        mnemonic,
+        liquidity,
     } = GatewayOpts::parse();
 
+    liquidity.validate().unwrap_or_else(|e| {
+        eprintln!("Invalid liquidity configuration: {e:?}");
+        exit(1)
+    });
+
     info!(
         "Starting gateway with these base configs \n data directory: {:?},\n listen: {},\n api address: {} ",
         data_dir, listen, api_addr
@@ -86,7 +103,7 @@ async fn main() -> Result<(), anyhow::Error> {
 
     // Create federation client builder
     let mut registry = ClientModuleGenRegistry::new();
-    registry.attach(MintClientGen);
+    registry.attach(MintClientGen::default());
     registry.attach(WalletClientGen::default());
     let client_builder = StandardGatewayClientBuilder::new(
         data_dir.clone(),
@@ -118,12 +135,19 @@ async fn main() -> Result<(), anyhow::Error> {
             .map_err(|_| GatewayError::DatabaseError)?,
         decoders.clone(),
     );
+    load_or_generate_mnemonic(&gatewayd_db, mnemonic)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to load gateway mnemonic: {e:?}");
+            exit(1)
+        });
 
     // Create gateway instance
     let gateway = Gateway::new(
         mode,
         client_builder,
         fees.unwrap_or(GatewayFee(DEFAULT_FEES)).0,
+        liquidity,
         gatewayd_db,
         api_addr,
     )
@@ -3,21 +3,68 @@ use std::fmt::Debug;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use fedimint_bip39::{Bip39RootSecretStrategy, Mnemonic};
+use fedimint_client::backup::{Metadata, MetadataSchemaVersion, RestoreSource, VersionedMetadata};
 use fedimint_client::module::gen::ClientModuleGenRegistry;
-use fedimint_client::secret::PlainRootSecretStrategy;
-use fedimint_client::ClientBuilder;
+use fedimint_client::secret::{
+    DeriveableSecretClientExt, PlainRootSecretStrategy, RootSecretStrategy,
+};
+use fedimint_client::{set_client_root_secret_if_missing, ClientBuilder, ClientSecret};
 use fedimint_core::api::{DynGlobalApi, GlobalFederationApi, WsClientConnectInfo, WsFederationApi};
+use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{Database, DatabaseTransaction};
 use fedimint_core::task::TaskGroup;
+use fedimint_ln_common::LightningGateway;
 use futures::StreamExt;
 use lightning::routing::gossip::RoutingFees;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-use crate::db::{FederationConfig, FederationIdKey, FederationIdKeyPrefix};
+use crate::db::{
+    Bolt12InvoiceStore, FederationConfig, FederationIdKey, FederationIdKeyPrefix,
+    GatewayMnemonicKey,
+};
 use crate::lnrpc_client::ILnRpcClient;
-use crate::ng::GatewayClientGen;
+use crate::ng::{GatewayClientGen, GatewayFederations};
 use crate::{GatewayError, Result};
 
+/// Settings of the gateway for a federation stored in the backups of its
+/// federation client, so they can be recovered together with the ecash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayBackupMetadata {
+    pub timelock_delta: u64,
+    /// Registration with the federation at the time of the backup, which
+    /// contains the short channel id and fees of the federation
+    pub registration: LightningGateway,
+}
+
+impl GatewayBackupMetadata {
+    /// Config of the federation with the backed up settings and the current
+    /// client config of the federation
+    pub fn federation_config(&self, config: ClientConfig) -> FederationConfig {
+        FederationConfig {
+            mint_channel_id: self.registration.mint_channel_id,
+            timelock_delta: self.timelock_delta,
+            fees: self.registration.fees,
+            config,
+        }
+    }
+}
+
+impl VersionedMetadata for GatewayBackupMetadata {
+    const SCHEMA_VERSION: MetadataSchemaVersion = MetadataSchemaVersion(1);
+
+    fn migrate(version: MetadataSchemaVersion, _value: serde_json::Value) -> anyhow::Result<Self> {
+        Err(anyhow::anyhow!(
+            "Unsupported gateway backup metadata version {}",
+            version.0
+        ))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct StandardGatewayClientBuilder {
     work_dir: PathBuf,
@@ -44,37 +91,143 @@ impl StandardGatewayClientBuilder {
         &self,
         config: FederationConfig,
         lnrpc: Arc<dyn ILnRpcClient>,
+        gatewayd_db: Database,
+        federations: GatewayFederations,
         tg: &mut TaskGroup,
     ) -> Result<fedimint_client::Client> {
         let federation_id = config.config.federation_id;
+        let db = self.open_client_db(federation_id)?;
 
-        let db_path = self.work_dir.join(format!("{federation_id}.db"));
+        // Clients of federations joined before the gateway had a mnemonic keep the
+        // random secret they were created with
+        let federation_secret = federation_secret(&gatewayd_db, federation_id).await?;
+        set_client_root_secret_if_missing(
+            &db,
+            &ClientSecret::<PlainRootSecretStrategy>::new(federation_secret),
+        )
+        .await
+        .map_err(|_| GatewayError::DatabaseError)?;
+
+        let mut client_builder = self.client_builder(config, lnrpc, gatewayd_db, federations);
+        client_builder.with_database(db);
+
+        let client = client_builder
+            .build::<PlainRootSecretStrategy>(tg)
+            .await
+            .map_err(|error| {
+                tracing::warn!("Error building client: {:?}", error);
+                GatewayError::ClientNgError
+            })?;
+
+        if client
+            .root_secret_encoding::<PlainRootSecretStrategy>()
+            .await
+            != federation_secret
+        {
+            warn!(%federation_id, "Federation client was created before the gateway had a mnemonic, its funds cannot be recovered from the mnemonic");
+        }
+
+        Ok(client)
+    }
+
+    /// Creates the client of a federation from the newest backup of the ecash
+    /// derived from the gateway's mnemonic the federation stores
+    pub async fn restore(
+        &self,
+        config: FederationConfig,
+        lnrpc: Arc<dyn ILnRpcClient>,
+        gatewayd_db: Database,
+        federations: GatewayFederations,
+        tg: &mut TaskGroup,
+    ) -> Result<fedimint_client::Client> {
+        let federation_id = config.config.federation_id;
+        let db = self.open_client_db(federation_id)?;
+        let federation_secret = federation_secret(&gatewayd_db, federation_id).await?;
 
-        let db =
-            fedimint_rocksdb::RocksDb::open(db_path).map_err(|_| GatewayError::DatabaseError)?;
+        let mut client_builder = self.client_builder(config, lnrpc, gatewayd_db, federations);
+        client_builder.with_database(db);
+
+        let (client, _) = client_builder
+            .build_restoring_from(
+                tg,
+                ClientSecret::<PlainRootSecretStrategy>::new(federation_secret),
+                RestoreSource::NewestFederationBackup,
+            )
+            .await
+            .map_err(|error| {
+                tracing::warn!("Error restoring client: {:?}", error);
+                GatewayError::ClientNgError
+            })?;
 
+        Ok(client)
+    }
+
+    /// Downloads the gateway settings stored in the newest backup of the
+    /// federation client, without creating the client yet
+    pub async fn download_backup_metadata(
+        &self,
+        config: FederationConfig,
+        lnrpc: Arc<dyn ILnRpcClient>,
+        gatewayd_db: Database,
+    ) -> Result<Option<GatewayBackupMetadata>> {
+        let federation_id = config.config.federation_id;
+        let db = MemDatabase::new();
+        let federation_secret = federation_secret(&gatewayd_db, federation_id).await?;
+        set_client_root_secret_if_missing(
+            &db,
+            &ClientSecret::<PlainRootSecretStrategy>::new(federation_secret),
+        )
+        .await
+        .map_err(|_| GatewayError::DatabaseError)?;
+
+        let mut client_builder =
+            self.client_builder(config, lnrpc, gatewayd_db, GatewayFederations::default());
+        client_builder.with_database(db);
+
+        // The client is only used to download the backup, so its executor is never
+        // started
+        let client = client_builder
+            .build_stopped::<PlainRootSecretStrategy>()
+            .await
+            .map_err(|error| {
+                tracing::warn!("Error building client: {:?}", error);
+                GatewayError::ClientNgError
+            })?;
+
+        let metadata = match client.download_backup_from_federation().await? {
+            Some(backup) if !backup.metadata().is_empty() => backup.metadata().clone(),
+            _ => return Ok(None),
+        };
+        Ok(Some(metadata.to_versioned()?))
+    }
+
+    fn open_client_db(&self, federation_id: FederationId) -> Result<fedimint_rocksdb::RocksDb> {
+        let db_path = self.work_dir.join(format!("{federation_id}.db"));
+        fedimint_rocksdb::RocksDb::open(db_path).map_err(|_| GatewayError::DatabaseError)
+    }
+
+    fn client_builder(
+        &self,
+        config: FederationConfig,
+        lnrpc: Arc<dyn ILnRpcClient>,
+        gatewayd_db: Database,
+        federations: GatewayFederations,
+    ) -> ClientBuilder {
         let mut registry = self.registry.clone();
         registry.attach(GatewayClientGen {
             lightning_client: lnrpc,
             fees: config.fees,
             timelock_delta: config.timelock_delta,
             mint_channel_id: config.mint_channel_id,
+            bolt12_invoices: Arc::new(Bolt12InvoiceStore::new(gatewayd_db)),
+            federations,
         });
 
         let mut client_builder = ClientBuilder::default();
         client_builder.with_module_gens(registry);
         client_builder.with_primary_module(self.primary_module);
         client_builder.with_config(config.config);
-        client_builder.with_database(db);
-
         client_builder
-            // TODO: make this configurable?
-            .build::<PlainRootSecretStrategy>(tg)
-            .await
-            .map_err(|error| {
-                tracing::warn!("Error building client: {:?}", error);
-                GatewayError::ClientNgError
-            })
     }
 
     pub async fn create_config(
@@ -120,3 +273,51 @@ impl StandardGatewayClientBuilder {
             .collect::<Vec<_>>())
     }
 }
+
+/// Secret of the client of a federation derived from the gateway's mnemonic
+async fn federation_secret(
+    gatewayd_db: &Database,
+    federation_id: FederationId,
+) -> Result<[u8; 64]> {
+    let mnemonic = load_or_generate_mnemonic(gatewayd_db, None).await?;
+    Ok(
+        <Bip39RootSecretStrategy as RootSecretStrategy>::to_root_secret(&mnemonic)
+            .derive_federation_secret(&federation_id)
+            .to_random_bytes::<64>(),
+    )
+}
+
+/// Loads the mnemonic the secrets of all federation clients are derived from.
+///
+/// If the database doesn't contain a mnemonic yet `restore` is stored, or a
+/// new one is generated if it is `None`. Fails if `restore` differs from the
+/// stored mnemonic.
+pub async fn load_or_generate_mnemonic(
+    gatewayd_db: &Database,
+    restore: Option<Mnemonic>,
+) -> Result<Mnemonic> {
+    let mut dbtx = gatewayd_db.begin_transaction().await;
+    let mnemonic = match dbtx.get_value(&GatewayMnemonicKey).await {
+        Some(entropy) => {
+            let mnemonic = Mnemonic::from_entropy(&entropy)
+                .map_err(|e| GatewayError::other(format!("Invalid stored mnemonic: {e}")))?;
+            if matches!(restore, Some(restore) if restore != mnemonic) {
+                return Err(GatewayError::other(
+                    "Gateway database already contains a different mnemonic".to_string(),
+                ));
+            }
+            mnemonic
+        }
+        None => {
+            let mnemonic =
+                restore.unwrap_or_else(|| Bip39RootSecretStrategy::<12>::random(&mut thread_rng()));
+            dbtx.insert_new_entry(&GatewayMnemonicKey, &mnemonic.to_entropy())
+                .await;
+            mnemonic
+        }
+    };
+    dbtx.commit_tx_result()
+        .await
+        .map_err(|_| GatewayError::DatabaseError)?;
+    Ok(mnemonic)
+}
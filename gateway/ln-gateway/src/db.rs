@@ -1,14 +1,31 @@
+use std::time::UNIX_EPOCH;
+
+use bitcoin_hashes::sha256;
+use fedimint_client::sm::OperationId;
 use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::db::Database;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{apply, async_trait_maybe_send, impl_db_lookup, impl_db_record, Amount};
 use fedimint_ln_common::LightningGateway;
 use lightning::routing::gossip::RoutingFees;
+use lightning_invoice::Invoice;
+use secp256k1::{PublicKey, XOnlyPublicKey};
+
+use crate::ng::IBolt12Invoices;
 
 #[repr(u8)]
 #[derive(Clone, Debug)]
 pub enum DbKeyPrefix {
     FederationConfig = 0x04,
     FederationRegistration = 0x05,
+    LnurlPay = 0x06,
+    LnurlOffer = 0x07,
+    Bolt12Invoice = 0x08,
+    Bolt12Offer = 0x09,
+    Bolt12OfferPayment = 0x0a,
+    GatewayMnemonic = 0x0b,
+    InFlightHtlc = 0x0c,
+    LnurlInvoice = 0x0d,
 }
 
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
@@ -45,3 +62,200 @@ impl_db_record!(
     value = LightningGateway,
     db_prefix = DbKeyPrefix::FederationRegistration,
 );
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct LnurlPayKey {
+    pub username: String,
+}
+
+/// LNURL-pay parameters a client registered for a username
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct LnurlPayConfig {
+    pub federation_id: FederationId,
+    pub description: String,
+    pub min_sendable: Amount,
+    pub max_sendable: Amount,
+    pub owner_key: XOnlyPublicKey,
+}
+
+impl_db_record!(
+    key = LnurlPayKey,
+    value = LnurlPayConfig,
+    db_prefix = DbKeyPrefix::LnurlPay,
+);
+
+/// An offer registered for a username that no invoice was issued for yet
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct LnurlOfferKey {
+    pub username: String,
+    pub payment_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnurlOfferKeyPrefix {
+    pub username: String,
+}
+
+impl_db_record!(
+    key = LnurlOfferKey,
+    value = (),
+    db_prefix = DbKeyPrefix::LnurlOffer,
+);
+
+impl_db_lookup!(key = LnurlOfferKey, query_prefix = LnurlOfferKeyPrefix);
+
+/// An invoice we issued for LNURL-pay, its HTLCs fund the incoming contract
+/// with the amount the payer chose instead of the offer's amount
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct LnurlInvoiceKey {
+    pub payment_hash: sha256::Hash,
+}
+
+impl_db_record!(
+    key = LnurlInvoiceKey,
+    value = (),
+    db_prefix = DbKeyPrefix::LnurlInvoice,
+);
+
+/// A BOLT12 invoice we fetched for a client, outgoing contracts for the proxy
+/// invoice we signed with `proxy_node_key` are paid by paying this invoice
+/// instead. Every proxy invoice is signed with a fresh key, so clients can't
+/// make us pay an invoice fetched for someone else.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct Bolt12InvoiceKey {
+    pub proxy_node_key: PublicKey,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct Bolt12InvoiceKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct Bolt12Invoice {
+    pub invoice: String,
+    /// Seconds since the UNIX epoch after which the invoice expires
+    pub expires_at: u64,
+}
+
+impl_db_record!(
+    key = Bolt12InvoiceKey,
+    value = Bolt12Invoice,
+    db_prefix = DbKeyPrefix::Bolt12Invoice,
+);
+
+impl_db_lookup!(
+    key = Bolt12InvoiceKey,
+    query_prefix = Bolt12InvoiceKeyPrefix
+);
+
+/// Gives the federation clients of the gateway access to the BOLT12 invoices
+/// we fetched without exposing the rest of the gateway database
+#[derive(Debug, Clone)]
+pub struct Bolt12InvoiceStore {
+    gatewayd_db: Database,
+}
+
+impl Bolt12InvoiceStore {
+    pub fn new(gatewayd_db: Database) -> Self {
+        Self { gatewayd_db }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IBolt12Invoices for Bolt12InvoiceStore {
+    async fn bolt12_invoice(&self, proxy_invoice: &Invoice) -> Option<String> {
+        let bolt12_invoice = self
+            .gatewayd_db
+            .begin_transaction()
+            .await
+            .get_value(&Bolt12InvoiceKey {
+                proxy_node_key: *proxy_invoice.payee_pub_key()?,
+            })
+            .await?;
+
+        let now = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        (now < bolt12_invoice.expires_at).then_some(bolt12_invoice.invoice)
+    }
+}
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct Bolt12OfferKey {
+    pub offer_id: Vec<u8>,
+}
+
+/// A BOLT12 offer we created on behalf of a client
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct Bolt12OfferConfig {
+    pub federation_id: FederationId,
+    pub offer: String,
+    pub min_amount: Amount,
+}
+
+impl_db_record!(
+    key = Bolt12OfferKey,
+    value = Bolt12OfferConfig,
+    db_prefix = DbKeyPrefix::Bolt12Offer,
+);
+
+/// An offer registered for a BOLT12 offer that no invoice was issued for yet
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct Bolt12OfferPaymentKey {
+    pub offer_id: Vec<u8>,
+    pub payment_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct Bolt12OfferPaymentKeyPrefix {
+    pub offer_id: Vec<u8>,
+}
+
+impl_db_record!(
+    key = Bolt12OfferPaymentKey,
+    value = (),
+    db_prefix = DbKeyPrefix::Bolt12OfferPayment,
+);
+
+impl_db_lookup!(
+    key = Bolt12OfferPaymentKey,
+    query_prefix = Bolt12OfferPaymentKeyPrefix
+);
+
+/// Entropy of the mnemonic the secrets of all federation clients of the gateway
+/// are derived from
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GatewayMnemonicKey;
+
+impl_db_record!(
+    key = GatewayMnemonicKey,
+    value = Vec<u8>,
+    db_prefix = DbKeyPrefix::GatewayMnemonic,
+);
+
+/// An intercepted HTLC that is being settled through a federation, kept until
+/// the lightning node received our response so it can be resumed after a
+/// restart
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct InFlightHtlcKey {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InFlightHtlcKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct InFlightHtlc {
+    pub federation_id: FederationId,
+    /// Operation of the federation client funding the incoming contract
+    pub operation_id: OperationId,
+}
+
+impl_db_record!(
+    key = InFlightHtlcKey,
+    value = InFlightHtlc,
+    db_prefix = DbKeyPrefix::InFlightHtlc,
+);
+
+impl_db_lookup!(key = InFlightHtlcKey, query_prefix = InFlightHtlcKeyPrefix);
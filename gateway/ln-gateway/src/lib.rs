@@ -1,5 +1,6 @@
 pub mod client;
 pub mod db;
+pub mod liquidity;
 pub mod lnd;
 pub mod lnrpc_client;
 pub mod ng;
@@ -12,46 +13,73 @@ pub mod gatewaylnrpc {
 }
 
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::anyhow;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use bitcoin::{Address, Txid};
 use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash};
 use clap::Subcommand;
-use client::StandardGatewayClientBuilder;
+use client::{load_or_generate_mnemonic, GatewayBackupMetadata, StandardGatewayClientBuilder};
+use db::{
+    Bolt12Invoice, Bolt12InvoiceKey, Bolt12InvoiceKeyPrefix, Bolt12OfferConfig, Bolt12OfferKey,
+    Bolt12OfferPaymentKey, Bolt12OfferPaymentKeyPrefix, FederationConfig, FederationIdKey,
+    InFlightHtlc, InFlightHtlcKey, InFlightHtlcKeyPrefix, LnurlInvoiceKey, LnurlOfferKey,
+    LnurlOfferKeyPrefix, LnurlPayConfig, LnurlPayKey,
+};
+use fedimint_client::backup::Metadata;
+use fedimint_client::sm::OperationId;
 use fedimint_core::api::{FederationError, WsClientConnectInfo};
 use fedimint_core::config::FederationId;
-use fedimint_core::db::Database;
+use fedimint_core::db::{Database, DatabaseTransaction};
 use fedimint_core::task::{sleep, RwLock, TaskGroup, TaskHandle};
 use fedimint_core::time::now;
 use fedimint_core::util::NextOrPending;
 use fedimint_core::Amount;
+use fedimint_ln_client::bolt12::{
+    Bolt12OfferRegistration, Bolt12OfferRegistrationResponse, FetchBolt12InvoicePayload,
+    FetchBolt12InvoiceResponse,
+};
 use fedimint_ln_client::contracts::Preimage;
+use fedimint_ln_client::lnurl::{lightning_address, SignedLnurlPayRegistration};
 use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::KIND;
+use fedimint_mint_client::MintClientExt;
 use fedimint_wallet_client::{WalletClientExt, WithdrawState};
-use futures::stream::StreamExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::{Future, FutureExt};
 use gatewaylnrpc::intercept_htlc_response::{Action, Cancel};
-use gatewaylnrpc::{GetNodeInfoResponse, InterceptHtlcResponse};
+use gatewaylnrpc::invoice_request_response::{Accept, Reject};
+use gatewaylnrpc::{
+    invoice_request_response, CreateOfferRequest, CreateOfferResponse, FetchInvoiceRequest,
+    FetchInvoiceResponse, GetNodeInfoResponse, InterceptHtlcResponse, InterceptInvoiceRequest,
+    InvoiceRequestResponse,
+};
 use lightning::routing::gossip::RoutingFees;
-use lnrpc_client::{ILnRpcClient, RouteHtlcStream};
-use ng::{GatewayClientExt, GatewayClientModule, GatewayExtRegisterStates};
+use lightning_invoice::{Invoice, DEFAULT_EXPIRY_TIME};
+use liquidity::{
+    FederationLiquidity, LiquidityConfig, LiquidityDecision, LiquidityReport,
+    LIQUIDITY_CHECK_INTERVAL,
+};
+use lnrpc_client::{ILnRpcClient, RouteHtlcStream, RouteInvoiceRequestStream};
+use ng::{GatewayClientExt, GatewayClientModule, GatewayExtRegisterStates, GatewayFederations};
 use rand::Rng;
 use rpc::FederationInfo;
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Semaphore};
 use tracing::{error, info};
 use url::Url;
 
@@ -62,7 +90,8 @@ use crate::ng::{GatewayExtPayStates, GatewayExtReceiveStates, Htlc};
 use crate::rpc::rpc_server::run_webserver;
 use crate::rpc::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, GatewayInfo,
-    InfoPayload, RestorePayload, WithdrawPayload,
+    InfoPayload, LiquidityPayload, LnurlPayResponse, MnemonicPayload, MnemonicResponse,
+    RestorePayload, SetFeesPayload, WithdrawPayload,
 };
 
 /// LND HTLC interceptor can't handle SCID of 0, so start from 1
@@ -71,6 +100,9 @@ const INITIAL_SCID: u64 = 1;
 /// How long a gateway announcement stays valid
 pub const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
 
+/// How many intercepted HTLCs of a single federation are processed concurrently
+const MAX_CONCURRENT_HTLCS_PER_FEDERATION: usize = 16;
+
 const ROUTE_HINT_RETRIES: usize = 10;
 const ROUTE_HINT_RETRY_SLEEP: Duration = Duration::from_secs(2);
 
@@ -142,14 +174,20 @@ impl IntoResponse for GatewayError {
 pub struct Gateway {
     lnrpc: Arc<dyn ILnRpcClient>,
     lightning_mode: Option<LightningMode>,
+    // TODO: manage the clients with `fedimint_client::multi::MultiFederationClient`. This needs
+    // per-federation module gens for `GatewayClientGen`, restoring clients from backups and a
+    // migration of the existing per-federation databases and random client secrets.
     clients: Arc<RwLock<BTreeMap<FederationId, Arc<fedimint_client::Client>>>>,
     scid_to_federation: Arc<RwLock<BTreeMap<u64, FederationId>>>,
     client_builder: StandardGatewayClientBuilder,
     channel_id_generator: Arc<Mutex<AtomicU64>>,
     fees: RoutingFees,
+    liquidity: LiquidityConfig,
     gatewayd_db: Database,
     api: Url,
     task_group: TaskGroup,
+    // Whether the lightning node passes us invoice requests for BOLT12 offers
+    answers_invoice_requests: Arc<AtomicBool>,
 }
 
 impl Gateway {
@@ -158,6 +196,7 @@ impl Gateway {
         lightning_mode: LightningMode,
         client_builder: StandardGatewayClientBuilder,
         fees: RoutingFees,
+        liquidity: LiquidityConfig,
         gatewayd_db: Database,
         api: Url,
     ) -> Result<Self> {
@@ -171,13 +210,17 @@ impl Gateway {
             channel_id_generator: Arc::new(Mutex::new(AtomicU64::new(INITIAL_SCID))),
             lightning_mode: Some(lightning_mode),
             fees,
+            liquidity,
             gatewayd_db,
             api,
             task_group: TaskGroup::new(),
+            answers_invoice_requests: Arc::new(AtomicBool::new(false)),
         };
 
         gw.load_clients().await?;
         gw.route_htlcs().await?;
+        gw.route_invoice_requests().await?;
+        gw.manage_liquidity().await;
 
         Ok(gw)
     }
@@ -187,6 +230,7 @@ impl Gateway {
         lnrpc: Arc<dyn ILnRpcClient>,
         client_builder: StandardGatewayClientBuilder,
         fees: RoutingFees,
+        liquidity: LiquidityConfig,
         gatewayd_db: Database,
         api: Url,
     ) -> Result<Self> {
@@ -198,13 +242,17 @@ impl Gateway {
             channel_id_generator: Arc::new(Mutex::new(AtomicU64::new(INITIAL_SCID))),
             lightning_mode: None,
             fees,
+            liquidity,
             gatewayd_db,
             api,
             task_group: TaskGroup::new(),
+            answers_invoice_requests: Arc::new(AtomicBool::new(false)),
         };
 
         gw.load_clients().await?;
         gw.route_htlcs().await?;
+        gw.route_invoice_requests().await?;
+        gw.manage_liquidity().await;
 
         Ok(gw)
     }
@@ -236,9 +284,25 @@ impl Gateway {
     }
 
     pub async fn route_htlcs(&mut self) -> Result<()> {
+        // Gateways created with a lightning connection only route HTLCs when
+        // asked to with `route_htlcs_from`
+        let Some(ln_mode) = self.lightning_mode.clone() else {
+            return Ok(());
+        };
+        self.route_htlcs_from(move || Self::create_boxxed_lightning_client(ln_mode.clone()))
+            .await
+    }
+
+    /// Routes the HTLCs intercepted by the lightning client `connect` creates,
+    /// a new client is created whenever the HTLC stream breaks
+    pub async fn route_htlcs_from<C, F>(&mut self, connect: C) -> Result<()>
+    where
+        C: Fn() -> F + Send + Sync + 'static,
+        F: Future<Output = Box<dyn ILnRpcClient>> + Send,
+    {
         let scid_to_federation = self.scid_to_federation.clone();
         let clients = self.clients.clone();
-        let ln_mode = self.lightning_mode.clone();
+        let gatewayd_db = self.gatewayd_db.clone();
         self.task_group
             .spawn(
                 "Subscribe to intercepted HTLCs in stream",
@@ -250,23 +314,295 @@ impl Gateway {
 
                         // Create a stream used to communicate with the Lightning implementation
                         let (sender, ln_receiver) = mpsc::channel::<InterceptHtlcResponse>(100);
+                        let mut lnrpc = connect().await;
+
+                        // Re-create the HTLC stream if the connection breaks
+                        match lnrpc
+                            .route_htlcs(ln_receiver.into(), &mut TaskGroup::new())
+                            .await
+                        {
+                            Ok(stream) => {
+                                // Blocks until the connection to the lightning node breaks
+                                info!("Established HTLC stream");
+                                Self::handle_htlc_stream(stream, sender, handle.clone(), scid_to_federation.clone(), clients.clone(), gatewayd_db.clone()).await;
+                                tracing::warn!("HTLC Stream Lightning connection broken");
+                            }
+                            Err(_) => {
+                                error!("route_htlcs failed to open HTLC stream. Waiting 5 seconds and trying again");
+                                sleep(Duration::from_secs(5)).await;
+                            }
+                        }
+                    }
+                },
+            )
+            .await;
+        Ok(())
+    }
+
+    async fn handle_htlc_stream(
+        mut stream: RouteHtlcStream<'_>,
+        sender: Sender<InterceptHtlcResponse>,
+        handle: TaskHandle,
+        scid_to_federation: Arc<RwLock<BTreeMap<u64, FederationId>>>,
+        clients: Arc<RwLock<BTreeMap<FederationId, Arc<fedimint_client::Client>>>>,
+        gatewayd_db: Database,
+    ) {
+        let mut htlc_limits = BTreeMap::<FederationId, Arc<Semaphore>>::new();
+        let mut active_htlcs = BTreeSet::<InFlightHtlcKey>::new();
+        let mut in_flight = FuturesUnordered::new();
+
+        // Resume the HTLCs we didn't respond to before the gateway restarted or the
+        // previous stream broke
+        let persisted_htlcs = gatewayd_db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&InFlightHtlcKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, htlc) in persisted_htlcs {
+            info!(?key, "Resuming in-flight HTLC");
+            let client = clients.read().await.get(&htlc.federation_id).cloned();
+            let limit = htlc_limits
+                .entry(htlc.federation_id)
+                .or_insert_with(|| Arc::new(Semaphore::new(MAX_CONCURRENT_HTLCS_PER_FEDERATION)))
+                .clone();
+            active_htlcs.insert(key.clone());
+            in_flight.push(Self::resume_htlc(client, limit, key, htlc.operation_id).boxed());
+        }
+
+        loop {
+            let htlc_request = tokio::select! {
+                htlc_request = stream.next() => match htlc_request {
+                    Some(Ok(htlc_request)) => htlc_request,
+                    _ => break,
+                },
+                Some((key, action)) = in_flight.next() => {
+                    active_htlcs.remove(&key);
+                    Self::send_htlc_response(&sender, &gatewayd_db, key, action).await;
+                    continue;
+                }
+            };
+
+            if handle.is_shutting_down() {
+                break;
+            }
+
+            let key = InFlightHtlcKey {
+                incoming_chan_id: htlc_request.incoming_chan_id,
+                htlc_id: htlc_request.htlc_id,
+            };
+            // The lightning node sends HTLCs we didn't respond to again after
+            // reconnecting, these are already being processed
+            if active_htlcs.contains(&key) {
+                continue;
+            }
+
+            let federation_id = scid_to_federation
+                .read()
+                .await
+                .get(&htlc_request.short_channel_id)
+                .copied();
+            let client = match federation_id {
+                Some(federation_id) => clients.read().await.get(&federation_id).cloned(),
+                None => None,
+            };
+            let htlc = Htlc::try_from(htlc_request).ok();
+
+            match (federation_id, client, htlc) {
+                (Some(federation_id), Some(client), Some(htlc)) => {
+                    let operation_id = OperationId(htlc.payment_hash.into_inner());
+                    let mut dbtx = gatewayd_db.begin_transaction().await;
+                    let is_lnurl = dbtx
+                        .get_value(&LnurlInvoiceKey {
+                            payment_hash: htlc.payment_hash,
+                        })
+                        .await
+                        .is_some();
+                    dbtx.insert_entry(
+                        &key,
+                        &InFlightHtlc {
+                            federation_id,
+                            operation_id,
+                        },
+                    )
+                    .await;
+                    if let Err(error) = dbtx.commit_tx_result().await {
+                        error!("Failed to persist in-flight HTLC: {error:?}");
+                    }
+
+                    let limit = htlc_limits
+                        .entry(federation_id)
+                        .or_insert_with(|| {
+                            Arc::new(Semaphore::new(MAX_CONCURRENT_HTLCS_PER_FEDERATION))
+                        })
+                        .clone();
+                    active_htlcs.insert(key.clone());
+                    in_flight.push(Self::process_htlc(client, limit, key, htlc, is_lnurl).boxed());
+                }
+                // Just forward the HTLC if we do not have a federation client that
+                // corresponds to the short channel id
+                _ => {
+                    Self::send_htlc_response(
+                        &sender,
+                        &gatewayd_db,
+                        key,
+                        Action::Forward(Forward {}),
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// Funds the incoming contract for an intercepted HTLC and waits for the
+    /// action the lightning node should take
+    async fn process_htlc(
+        client: Arc<fedimint_client::Client>,
+        limit: Arc<Semaphore>,
+        key: InFlightHtlcKey,
+        htlc: Htlc,
+        is_lnurl: bool,
+    ) -> (InFlightHtlcKey, Action) {
+        let _permit = limit.acquire().await.expect("Semaphore is never closed");
+
+        let funding = if is_lnurl {
+            client.gateway_handle_intercepted_lnurl_htlc(htlc).await
+        } else {
+            client.gateway_handle_intercepted_htlc(htlc).await
+        };
+        let action = match funding {
+            Ok(operation_id) => Self::await_htlc_action(&client, operation_id).await,
+            Err(error) => {
+                info!("Forwarding HTLC the federation can't handle: {error:?}");
+                Action::Forward(Forward {})
+            }
+        };
+        (key, action)
+    }
+
+    /// Waits for the action the lightning node should take for an HTLC whose
+    /// incoming contract was funded before, cancelling it if the federation
+    /// client is gone or funding never started
+    async fn resume_htlc(
+        client: Option<Arc<fedimint_client::Client>>,
+        limit: Arc<Semaphore>,
+        key: InFlightHtlcKey,
+        operation_id: OperationId,
+    ) -> (InFlightHtlcKey, Action) {
+        let _permit = limit.acquire().await.expect("Semaphore is never closed");
+
+        let action = match client {
+            Some(client) => Self::await_htlc_action(&client, operation_id).await,
+            None => Action::Cancel(Cancel {
+                reason: "Federation of the HTLC is not connected anymore".to_string(),
+            }),
+        };
+        (key, action)
+    }
+
+    async fn await_htlc_action(
+        client: &fedimint_client::Client,
+        operation_id: OperationId,
+    ) -> Action {
+        let mut updates = match client.gateway_subscribe_ln_receive(operation_id).await {
+            Ok(updates) => updates.into_stream(),
+            Err(error) => {
+                return Action::Cancel(Cancel {
+                    reason: format!("Failed to fund incoming contract: {error}"),
+                })
+            }
+        };
+
+        while let Some(state) = updates.next().await {
+            match state {
+                GatewayExtReceiveStates::Preimage(preimage) => {
+                    return Action::Settle(Settle {
+                        preimage: preimage.0.to_vec(),
+                    })
+                }
+                GatewayExtReceiveStates::FundingFailed(reason)
+                | GatewayExtReceiveStates::RefundError(reason) => {
+                    return Action::Cancel(Cancel { reason })
+                }
+                GatewayExtReceiveStates::RefundSuccess(_) => {
+                    return Action::Cancel(Cancel {
+                        reason: "Gateway is being refunded".to_string(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Action::Cancel(Cancel {
+            reason: "Incoming contract updates ended unexpectedly".to_string(),
+        })
+    }
+
+    /// Sends the response for an HTLC to the lightning node and forgets about
+    /// the HTLC once the node received it
+    async fn send_htlc_response(
+        sender: &Sender<InterceptHtlcResponse>,
+        gatewayd_db: &Database,
+        key: InFlightHtlcKey,
+        action: Action,
+    ) {
+        let response = InterceptHtlcResponse {
+            action: Some(action),
+            incoming_chan_id: key.incoming_chan_id,
+            htlc_id: key.htlc_id,
+        };
+
+        if let Err(error) = sender.send(response).await {
+            error!("Error sending HTLC response to lightning node: {error:?}");
+            return;
+        }
+
+        let mut dbtx = gatewayd_db.begin_transaction().await;
+        dbtx.remove_entry(&key).await;
+        if let Err(error) = dbtx.commit_tx_result().await {
+            error!("Failed to remove in-flight HTLC: {error:?}");
+        }
+    }
+
+    pub async fn route_invoice_requests(&mut self) -> Result<()> {
+        let gatewayd_db = self.gatewayd_db.clone();
+        let ln_mode = self.lightning_mode.clone();
+        let answers_invoice_requests = self.answers_invoice_requests.clone();
+        self.task_group
+            .spawn(
+                "Subscribe to BOLT12 invoice requests in stream",
+                move |handle| async move {
+                    loop {
+                        if handle.is_shutting_down() {
+                            break;
+                        }
+
+                        let (sender, ln_receiver) = mpsc::channel::<InvoiceRequestResponse>(100);
 
                         if let Some(ln_mode) = ln_mode.clone() {
                             let mut lnrpc = Self::create_boxxed_lightning_client(ln_mode).await;
 
-                            // Re-create the HTLC stream if the connection breaks
+                            // Re-create the invoice request stream if the connection breaks
                             match lnrpc
-                                .route_htlcs(ln_receiver.into(), &mut TaskGroup::new())
+                                .route_invoice_requests(ln_receiver.into(), &mut TaskGroup::new())
                                 .await
                             {
                                 Ok(stream) => {
-                                    // Blocks until the connection to the lightning node breaks
-                                    info!("Established HTLC stream");
-                                    Self::handle_htlc_stream(stream, sender, handle.clone(), scid_to_federation.clone(), clients.clone()).await;
-                                    tracing::warn!("HTLC Stream Lightning connection broken");
+                                    info!("Established invoice request stream");
+                                    answers_invoice_requests.store(true, Ordering::SeqCst);
+                                    Self::handle_invoice_request_stream(stream, sender, handle.clone(), gatewayd_db.clone()).await;
+                                    answers_invoice_requests.store(false, Ordering::SeqCst);
+                                    tracing::warn!("Invoice request stream Lightning connection broken");
+                                }
+                                Err(GatewayError::LnRpcError(status))
+                                    if status.code() == tonic::Code::Unimplemented =>
+                                {
+                                    info!("Lightning node can't answer invoice requests for BOLT12 offers");
+                                    break;
                                 }
                                 Err(_) => {
-                                    error!("route_htlcs failed to open HTLC stream. Waiting 5 seconds and trying again");
+                                    error!("route_invoice_requests failed to open invoice request stream. Waiting 5 seconds and trying again");
                                     sleep(Duration::from_secs(5)).await;
                                 }
                             }
@@ -280,110 +616,95 @@ impl Gateway {
         Ok(())
     }
 
-    async fn handle_htlc_stream(
-        mut stream: RouteHtlcStream<'_>,
-        sender: Sender<InterceptHtlcResponse>,
+    /// Whether we can currently serve BOLT12 offers registered by clients
+    pub fn answers_invoice_requests(&self) -> bool {
+        self.answers_invoice_requests.load(Ordering::SeqCst)
+    }
+
+    async fn handle_invoice_request_stream(
+        mut stream: RouteInvoiceRequestStream<'_>,
+        sender: Sender<InvoiceRequestResponse>,
         handle: TaskHandle,
-        scid_to_federation: Arc<RwLock<BTreeMap<u64, FederationId>>>,
-        clients: Arc<RwLock<BTreeMap<FederationId, Arc<fedimint_client::Client>>>>,
+        gatewayd_db: Database,
     ) {
-        while let Some(Ok(htlc_request)) = stream.next().await {
+        while let Some(Ok(invoice_request)) = stream.next().await {
             if handle.is_shutting_down() {
                 break;
             }
 
-            let scid_to_feds = scid_to_federation.read().await;
-            let federation_id = scid_to_feds.get(&htlc_request.short_channel_id);
-            // Just forward the HTLC if we do not have a federation that
-            // corresponds to the short channel id
-            if let Some(federation_id) = federation_id {
-                let clients = clients.read().await;
-                let client = clients.get(federation_id);
-                // Just forward the HTLC if we do not have a client that
-                // corresponds to the federation id
-                if let Some(client) = client {
-                    let htlc: Result<Htlc> = htlc_request
-                        .clone()
-                        .try_into()
-                        .map_err(|_| GatewayError::ClientNgError);
-                    if let Ok(htlc) = htlc {
-                        let intercept_op = client.gateway_handle_intercepted_htlc(htlc).await;
-                        // TODO: Refactor this into the state machine so we don't need to wait here
-                        if let Ok(intercept_op) = intercept_op {
-                            let intercept_sub =
-                                client.gateway_subscribe_ln_receive(intercept_op).await;
-                            if let Ok(intercept_sub) = intercept_sub {
-                                let mut intercept_sub = intercept_sub.into_stream();
-
-                                let outcome = loop {
-                                    if let Ok(state) = intercept_sub.ok().await {
-                                        match state {
-                                            GatewayExtReceiveStates::Preimage(preimage) => {
-                                                break InterceptHtlcResponse {
-                                                    action: Some(Action::Settle(Settle {
-                                                        preimage: preimage.0.to_vec(),
-                                                    })),
-                                                    incoming_chan_id: htlc_request.incoming_chan_id,
-                                                    htlc_id: htlc_request.htlc_id,
-                                                };
-                                            }
-                                            GatewayExtReceiveStates::FundingFailed(failed) => {
-                                                break InterceptHtlcResponse {
-                                                    action: Some(Action::Cancel(Cancel {
-                                                        reason: failed,
-                                                    })),
-                                                    incoming_chan_id: htlc_request.incoming_chan_id,
-                                                    htlc_id: htlc_request.htlc_id,
-                                                }
-                                            }
-                                            GatewayExtReceiveStates::RefundSuccess(_) => {
-                                                break InterceptHtlcResponse {
-                                                    action: Some(Action::Cancel(Cancel {
-                                                        reason: "Gateway is being refunded"
-                                                            .to_string(),
-                                                    })),
-                                                    incoming_chan_id: htlc_request.incoming_chan_id,
-                                                    htlc_id: htlc_request.htlc_id,
-                                                }
-                                            }
-                                            GatewayExtReceiveStates::RefundError(failed) => {
-                                                break InterceptHtlcResponse {
-                                                    action: Some(Action::Cancel(Cancel {
-                                                        reason: failed,
-                                                    })),
-                                                    incoming_chan_id: htlc_request.incoming_chan_id,
-                                                    htlc_id: htlc_request.htlc_id,
-                                                }
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                };
-
-                                if let Err(error) = sender.send(outcome).await {
-                                    error!(
-                                        "Error sending HTLC response to lightning node: {error:?}"
-                                    );
-                                }
-                                continue;
-                            }
-                        }
-                    }
-                }
-            }
-
-            let outcome = InterceptHtlcResponse {
-                action: Some(Action::Forward(Forward {})),
-                incoming_chan_id: htlc_request.incoming_chan_id,
-                htlc_id: htlc_request.htlc_id,
+            let action = match Self::accept_invoice_request(&gatewayd_db, &invoice_request).await {
+                Ok(accept) => invoice_request_response::Action::Accept(accept),
+                Err(error) => invoice_request_response::Action::Reject(Reject {
+                    reason: error.to_string(),
+                }),
+            };
+            let outcome = InvoiceRequestResponse {
+                action: Some(action),
+                request_id: invoice_request.request_id,
             };
 
             if let Err(error) = sender.send(outcome).await {
-                error!("Error sending HTLC response to lightning node: {error:?}");
+                error!("Error sending invoice request response to lightning node: {error:?}");
             }
         }
     }
 
+    /// Picks one of the offers registered for a BOLT12 offer to be paid by the
+    /// requested invoice, each offer is only ever used once
+    async fn accept_invoice_request(
+        gatewayd_db: &Database,
+        invoice_request: &InterceptInvoiceRequest,
+    ) -> Result<Accept> {
+        let mut dbtx = gatewayd_db.begin_transaction().await;
+        let config = dbtx
+            .get_value(&Bolt12OfferKey {
+                offer_id: invoice_request.offer_id.clone(),
+            })
+            .await
+            .ok_or_else(|| GatewayError::other("Unknown BOLT12 offer".to_string()))?;
+
+        let amount = Amount::from_msats(invoice_request.amount_msat);
+        if amount < config.min_amount {
+            return Err(GatewayError::other(format!(
+                "Amount has to be at least {}",
+                config.min_amount
+            )));
+        }
+
+        let payment_key = dbtx
+            .find_by_prefix(&Bolt12OfferPaymentKeyPrefix {
+                offer_id: invoice_request.offer_id.clone(),
+            })
+            .await
+            .next()
+            .await
+            .map(|(key, ())| key)
+            .ok_or_else(|| {
+                GatewayError::other("BOLT12 offer can't receive payments right now".to_string())
+            })?;
+        dbtx.remove_entry(&payment_key).await;
+
+        let mint_channel_id = dbtx
+            .get_value(&FederationIdKey {
+                id: config.federation_id,
+            })
+            .await
+            .ok_or_else(|| {
+                GatewayError::other(format!("No federation with id {}", config.federation_id))
+            })?
+            .mint_channel_id;
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)?;
+
+        Ok(Accept {
+            payment_hash: payment_key.payment_hash.into_inner().to_vec(),
+            short_channel_id: mint_channel_id,
+            relative_expiry: DEFAULT_EXPIRY_TIME,
+        })
+    }
+
     async fn fetch_lightning_route_info(&self) -> Result<(Vec<RouteHint>, PublicKey, String)> {
         let mut num_retries = 0;
         let (route_hints, node_pub_key, alias) = loop {
@@ -419,11 +740,18 @@ impl Gateway {
         if let Ok(configs) = self.client_builder.load_configs(dbtx).await {
             let channel_id_generator = self.channel_id_generator.lock().await;
             let mut next_channel_id = channel_id_generator.load(Ordering::SeqCst);
+            let federations = self.federations();
 
             for config in configs {
                 let client = Arc::new(
                     self.client_builder
-                        .build(config.clone(), self.lnrpc.clone(), &mut self.task_group)
+                        .build(
+                            config.clone(),
+                            self.lnrpc.clone(),
+                            self.gatewayd_db.clone(),
+                            federations.clone(),
+                            &mut self.task_group,
+                        )
                         .await?,
                 );
 
@@ -447,6 +775,16 @@ impl Gateway {
         Ok(())
     }
 
+    /// Handle to our federation clients for the state machines paying
+    /// invoices of one federation directly from another
+    fn federations(&self) -> GatewayFederations {
+        GatewayFederations::new(
+            &self.clients,
+            self.scid_to_federation.clone(),
+            self.gatewayd_db.clone(),
+        )
+    }
+
     pub async fn register_client(
         &mut self,
         client: fedimint_client::Client,
@@ -454,24 +792,7 @@ impl Gateway {
         scid: u64,
         route_hints: Vec<RouteHint>,
     ) -> Result<()> {
-        let register_op = client
-            .register_with_federation(self.api.clone(), route_hints, GW_ANNOUNCEMENT_TTL)
-            .await?;
-        // TODO: Move this inside of the state machine
-        {
-            let mut register_sub = client
-                .gateway_subscribe_register(register_op)
-                .await?
-                .into_stream();
-            loop {
-                let state = register_sub.ok().await?;
-                match state {
-                    GatewayExtRegisterStates::Success => break,
-                    GatewayExtRegisterStates::Done => break,
-                    _ => {}
-                }
-            }
-        }
+        self.register_with_federation(&client, route_hints).await?;
 
         self.clients
             .write()
@@ -484,6 +805,31 @@ impl Gateway {
         Ok(())
     }
 
+    /// Registers our current routing info with the federation of `client`
+    async fn register_with_federation(
+        &self,
+        client: &fedimint_client::Client,
+        route_hints: Vec<RouteHint>,
+    ) -> Result<()> {
+        let register_op = client
+            .register_with_federation(self.api.clone(), route_hints, GW_ANNOUNCEMENT_TTL)
+            .await?;
+        // TODO: Move this inside of the state machine
+        let mut register_sub = client
+            .gateway_subscribe_register(register_op)
+            .await?
+            .into_stream();
+        loop {
+            let state = register_sub.ok().await?;
+            match state {
+                GatewayExtRegisterStates::Success => break,
+                GatewayExtRegisterStates::Done => break,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     pub async fn remove_client(
         &self,
         federation_id: FederationId,
@@ -530,9 +876,32 @@ impl Gateway {
             .await
             .fetch_add(1, Ordering::SeqCst);
 
+        let gw_client_cfg = self.download_federation_config(connect, channel_id).await;
+        let (route_hints, _, _) = self.fetch_lightning_route_info().await?;
+
+        let client = self
+            .client_builder
+            .build(
+                gw_client_cfg.clone(),
+                self.lnrpc.clone(),
+                self.gatewayd_db.clone(),
+                self.federations(),
+                &mut self.task_group,
+            )
+            .await?;
+
+        self.add_federation(client, gw_client_cfg, route_hints)
+            .await
+    }
+
+    async fn download_federation_config(
+        &self,
+        connect: WsClientConnectInfo,
+        channel_id: u64,
+    ) -> FederationConfig {
         // Downloading the config can fail if another user tries to download at the same
         // time. Just retry after a small delay
-        let gw_client_cfg = loop {
+        loop {
             match self
                 .client_builder
                 .create_config(connect.clone(), channel_id, self.fees)
@@ -547,20 +916,19 @@ impl Gateway {
                     sleep(Duration::from_secs_f64(random_delay)).await;
                 }
             }
-        };
+        }
+    }
 
+    /// Registers with the federation of a newly created client and persists
+    /// its config, so the client is loaded again when the gateway restarts
+    async fn add_federation(
+        &mut self,
+        client: fedimint_client::Client,
+        gw_client_cfg: FederationConfig,
+        route_hints: Vec<RouteHint>,
+    ) -> Result<FederationInfo> {
         let federation_id = gw_client_cfg.config.federation_id;
-        let (route_hints, _, _) = self.fetch_lightning_route_info().await?;
-
-        let client = self
-            .client_builder
-            .build(
-                gw_client_cfg.clone(),
-                self.lnrpc.clone(),
-                &mut self.task_group,
-            )
-            .await?;
-
+        let channel_id = gw_client_cfg.mint_channel_id;
         let (gateway, _) = client.get_first_module::<GatewayClientModule>(&KIND);
 
         let registration = gateway.to_gateway_registration_info(
@@ -655,6 +1023,13 @@ impl Gateway {
             .await)
     }
 
+    pub async fn handle_mnemonic_msg(&self, _payload: MnemonicPayload) -> Result<MnemonicResponse> {
+        let mnemonic = load_or_generate_mnemonic(&self.gatewayd_db, None).await?;
+        Ok(MnemonicResponse {
+            mnemonic: mnemonic.to_string(),
+        })
+    }
+
     pub async fn handle_address_msg(&self, payload: DepositAddressPayload) -> Result<Address> {
         let (_, address) = self
             .select_client(payload.federation_id)
@@ -704,18 +1079,628 @@ impl Gateway {
         )));
     }
 
+    pub async fn handle_liquidity_msg(
+        &self,
+        _payload: LiquidityPayload,
+    ) -> Result<LiquidityReport> {
+        let balances = self.lnrpc.channel_balances().await?;
+
+        let clients = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(federation_id, client)| (*federation_id, client.clone()))
+            .collect::<Vec<_>>();
+        let lightning_inbound = Amount::from_msats(balances.remote_balance_msat);
+        let mut federations = vec![];
+        for (federation_id, client) in clients {
+            let ecash_balance = client.get_balance().await;
+            federations.push(FederationLiquidity {
+                federation_id,
+                ecash_balance,
+                decision: self.liquidity.decide(ecash_balance, lightning_inbound),
+            });
+        }
+
+        Ok(LiquidityReport {
+            lightning_outbound: Amount::from_msats(balances.local_balance_msat),
+            lightning_inbound,
+            federations,
+        })
+    }
+
+    /// Periodically checks the ecash balances of all federations against the
+    /// configured liquidity limits, alerting the operator if they are crossed
+    /// and pegging out excess ecash if a peg-out address is configured
+    async fn manage_liquidity(&mut self) {
+        if self.liquidity.min_ecash.is_none() && self.liquidity.max_ecash.is_none() {
+            return;
+        }
+
+        let gateway = self.clone();
+        self.task_group
+            .spawn("Manage federation liquidity", move |handle| async move {
+                while !handle.is_shutting_down() {
+                    match gateway.handle_liquidity_msg(LiquidityPayload).await {
+                        Ok(report) => gateway.rebalance(report).await,
+                        Err(e) => error!("Failed to check liquidity: {e:?}"),
+                    }
+                    sleep(LIQUIDITY_CHECK_INTERVAL).await;
+                }
+            })
+            .await;
+    }
+
+    async fn rebalance(&self, report: LiquidityReport) {
+        for FederationLiquidity {
+            federation_id,
+            ecash_balance,
+            decision,
+        } in report.federations
+        {
+            match (decision, self.liquidity.peg_out_address.clone()) {
+                (LiquidityDecision::Balanced, _) => {}
+                (LiquidityDecision::PegIn { amount }, _) => {
+                    tracing::warn!(
+                        ?federation_id,
+                        %ecash_balance,
+                        "Ecash balance below minimum, peg in {amount} to keep receiving payments"
+                    );
+                }
+                (LiquidityDecision::PegOut { amount }, None) => {
+                    tracing::warn!(
+                        ?federation_id,
+                        %ecash_balance,
+                        "Ecash balance above maximum, peg out {amount} to rebalance"
+                    );
+                }
+                (LiquidityDecision::PegOut { amount }, Some(address)) => {
+                    info!(?federation_id, %ecash_balance, "Pegging out {amount} of excess ecash");
+                    match self.peg_out_excess(federation_id, address, amount).await {
+                        Ok(txid) => info!(?federation_id, "Pegged out excess ecash in {txid}"),
+                        Err(e) => error!(?federation_id, "Failed to peg out excess ecash: {e:?}"),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn peg_out_excess(
+        &self,
+        federation_id: FederationId,
+        address: Address,
+        excess: Amount,
+    ) -> Result<Txid> {
+        let excess = bitcoin::Amount::from_sat(excess.msats / 1000);
+        let fees = self
+            .select_client(federation_id)
+            .await?
+            .get_withdraw_fee(address.clone(), excess)
+            .await?;
+
+        // The peg-out fees are paid from the ecash balance as well
+        let Some(amount) = excess.checked_sub(fees.amount()) else {
+            return Err(GatewayError::other(format!(
+                "Excess ecash of {excess} does not cover the peg-out fees"
+            )));
+        };
+
+        self.handle_withdraw_msg(WithdrawPayload {
+            federation_id,
+            amount,
+            address,
+        })
+        .await
+    }
+
+    /// Changes the routing fees of a federation and registers them with it
+    /// right away, so its clients pay the new fees without gatewayd restarting
+    pub async fn handle_set_fees_msg(
+        &self,
+        SetFeesPayload {
+            federation_id,
+            base_msat,
+            proportional_millionths,
+        }: SetFeesPayload,
+    ) -> Result<FederationInfo> {
+        // A proportional fee above 100% can never be paid by the routed amount
+        if proportional_millionths > 1_000_000 {
+            return Err(GatewayError::other(format!(
+                "Proportional fee of {proportional_millionths} millionths exceeds 100%"
+            )));
+        }
+
+        let fees = RoutingFees {
+            base_msat,
+            proportional_millionths,
+        };
+        let client = self.select_client(federation_id).await?;
+
+        // Persist the fees first, so they are kept if gatewayd restarts before the
+        // registration succeeded
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        let mut config = dbtx
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .ok_or_else(|| {
+                GatewayError::other(format!("No config for federation {federation_id}"))
+            })?;
+        config.fees = fees;
+        dbtx.insert_entry(&FederationIdKey { id: federation_id }, &config)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)?;
+
+        let (gateway, _) = client.get_first_module::<GatewayClientModule>(&KIND);
+        gateway.set_fees(fees);
+        info!(?federation_id, ?fees, "Changed routing fees");
+
+        let (route_hints, _, _) = self.fetch_lightning_route_info().await?;
+        let registration = gateway.to_gateway_registration_info(
+            route_hints.clone(),
+            GW_ANNOUNCEMENT_TTL,
+            self.api.clone(),
+        );
+        self.register_with_federation(&client, route_hints).await?;
+
+        Ok(FederationInfo {
+            federation_id,
+            registration,
+        })
+    }
+
+    /// Backs up the ecash of a federation to the federation, together with the
+    /// gateway's settings for it, so it can be recovered from the gateway's
+    /// mnemonic with [`Self::handle_restore_msg`]
     pub async fn handle_backup_msg(
         &self,
-        BackupPayload { federation_id: _ }: BackupPayload,
+        BackupPayload { federation_id }: BackupPayload,
     ) -> Result<()> {
-        unimplemented!("Backup is not currently supported");
+        let client = self.select_client(federation_id).await?;
+        let config = self
+            .gatewayd_db
+            .begin_transaction()
+            .await
+            .get_value(&FederationIdKey { id: federation_id })
+            .await
+            .ok_or_else(|| {
+                GatewayError::other(format!("No config for federation {federation_id}"))
+            })?;
+        let (route_hints, _, _) = self.fetch_lightning_route_info().await?;
+
+        let (gateway, _) = client.get_first_module::<GatewayClientModule>(&KIND);
+        let metadata = GatewayBackupMetadata {
+            timelock_delta: config.timelock_delta,
+            registration: gateway.to_gateway_registration_info(
+                route_hints,
+                GW_ANNOUNCEMENT_TTL,
+                self.api.clone(),
+            ),
+        };
+        client
+            .backup_to_federation(Metadata::from_versioned(&metadata))
+            .await?;
+        Ok(())
     }
 
+    /// Connects to a federation the gateway lost the data of, restoring its
+    /// ecash and settings from the newest backup made with
+    /// [`Self::handle_backup_msg`]
     pub async fn handle_restore_msg(
+        &mut self,
+        RestorePayload { connect }: RestorePayload,
+    ) -> Result<FederationInfo> {
+        let connect = WsClientConnectInfo::from_str(&connect).map_err(|e| {
+            GatewayError::Other(anyhow::anyhow!("Invalid federation member string {}", e))
+        })?;
+        if self.clients.read().await.contains_key(&connect.id) {
+            return Err(GatewayError::other(format!(
+                "Federation {} is already connected",
+                connect.id
+            )));
+        }
+
+        let channel_id = self
+            .channel_id_generator
+            .lock()
+            .await
+            .fetch_add(1, Ordering::SeqCst);
+        let gw_client_cfg = self.download_federation_config(connect, channel_id).await;
+        let federation_id = gw_client_cfg.config.federation_id;
+        let (route_hints, node_pub_key, _) = self.fetch_lightning_route_info().await?;
+
+        // Keep the short channel id the federation had before, so invoices created
+        // by its clients can still be paid through us
+        let metadata = self
+            .client_builder
+            .download_backup_metadata(
+                gw_client_cfg.clone(),
+                self.lnrpc.clone(),
+                self.gatewayd_db.clone(),
+            )
+            .await?;
+        let scid_to_federation = self.scid_to_federation.read().await.clone();
+        let gw_client_cfg = match metadata {
+            Some(metadata)
+                if !scid_to_federation.contains_key(&metadata.registration.mint_channel_id) =>
+            {
+                if metadata.registration.node_pub_key != node_pub_key {
+                    tracing::warn!(%federation_id, "Backup was made with another lightning node, invoices created before can't be paid through us");
+                }
+                let channel_id_generator = self.channel_id_generator.lock().await;
+                let next_channel_id = metadata.registration.mint_channel_id + 1;
+                if next_channel_id > channel_id_generator.load(Ordering::SeqCst) {
+                    channel_id_generator.store(next_channel_id, Ordering::SeqCst);
+                }
+                metadata.federation_config(gw_client_cfg.config)
+            }
+            Some(_) => {
+                tracing::warn!(%federation_id, "Channel id of the backup is used by another federation, invoices created before can't be paid through us");
+                gw_client_cfg
+            }
+            None => {
+                tracing::warn!(%federation_id, "Federation has no gateway backup, restoring ecash from scratch");
+                gw_client_cfg
+            }
+        };
+
+        let client = self
+            .client_builder
+            .restore(
+                gw_client_cfg.clone(),
+                self.lnrpc.clone(),
+                self.gatewayd_db.clone(),
+                self.federations(),
+                &mut self.task_group,
+            )
+            .await?;
+        // Only announce ourselves once we know our balance again
+        if client
+            .get_first_instance(&fedimint_mint_client::KIND)
+            .is_some()
+        {
+            info!(%federation_id, "Waiting for restore to complete");
+            client.await_restore_finished().await?;
+        }
+
+        self.add_federation(client, gw_client_cfg, route_hints)
+            .await
+    }
+
+    /// Stores offers a client created so we can serve LNURL-pay requests for
+    /// its username while it is offline
+    pub async fn handle_register_lnurl_pay_msg(
         &self,
-        RestorePayload { federation_id: _ }: RestorePayload,
+        payload: SignedLnurlPayRegistration,
     ) -> Result<()> {
-        unimplemented!("Restore is not currently supported");
+        let registration = payload
+            .verify_valid(&secp256k1_zkp::Secp256k1::verification_only())
+            .map_err(|e| GatewayError::other(format!("Invalid registration signature: {e}")))?;
+
+        if !is_valid_lnurl_username(&registration.username) {
+            return Err(GatewayError::other(format!(
+                "Invalid username {}",
+                registration.username
+            )));
+        }
+
+        if registration.min_sendable > registration.max_sendable {
+            return Err(GatewayError::other(
+                "Minimum sendable amount exceeds the maximum".to_string(),
+            ));
+        }
+
+        // Invoices for less than the offer's amount could not be funded
+        let client = self.select_client(registration.federation_id).await?;
+        let (_, instance) = client.get_first_module::<GatewayClientModule>(&KIND);
+        for payment_hash in &registration.payment_hashes {
+            let offer = instance.api.fetch_offer(*payment_hash).await?;
+            if offer.amount > registration.min_sendable {
+                return Err(GatewayError::other(format!(
+                    "Offer {payment_hash} requires more than the minimum sendable amount"
+                )));
+            }
+        }
+
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        let key = LnurlPayKey {
+            username: registration.username.clone(),
+        };
+        if let Some(config) = dbtx.get_value(&key).await {
+            if config.owner_key != registration.owner_key {
+                return Err(GatewayError::other(format!(
+                    "Username {} is already taken",
+                    registration.username
+                )));
+            }
+        }
+
+        dbtx.insert_entry(
+            &key,
+            &LnurlPayConfig {
+                federation_id: registration.federation_id,
+                description: registration.description.clone(),
+                min_sendable: registration.min_sendable,
+                max_sendable: registration.max_sendable,
+                owner_key: registration.owner_key,
+            },
+        )
+        .await;
+        for payment_hash in &registration.payment_hashes {
+            dbtx.insert_entry(
+                &LnurlOfferKey {
+                    username: registration.username.clone(),
+                    payment_hash: *payment_hash,
+                },
+                &(),
+            )
+            .await;
+        }
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)
+    }
+
+    /// Answers the first request of LNURL-pay, telling the payer which amounts
+    /// `username` accepts and where to request an invoice
+    pub async fn handle_lnurl_pay_request(&self, username: String) -> Result<LnurlPayResponse> {
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        let config = self.get_lnurl_pay_config(&mut dbtx, &username).await?;
+
+        let has_offers = dbtx
+            .find_by_prefix(&LnurlOfferKeyPrefix {
+                username: username.clone(),
+            })
+            .await
+            .next()
+            .await
+            .is_some();
+        if !has_offers {
+            return Err(GatewayError::other(format!(
+                "{username} can't receive payments right now"
+            )));
+        }
+
+        let callback = self
+            .api
+            .join(&format!("lnurlp/{username}/callback"))
+            .map_err(|e| GatewayError::other(format!("Invalid callback URL: {e}")))?;
+
+        Ok(LnurlPayResponse {
+            callback,
+            max_sendable: config.max_sendable.msats,
+            min_sendable: config.min_sendable.msats,
+            metadata: self.lnurl_metadata(&username, &config)?,
+            tag: "payRequest".to_string(),
+        })
+    }
+
+    /// Issues an invoice for `amount` that pays to one of the offers
+    /// registered for `username`, each offer is only ever used once
+    pub async fn handle_lnurl_pay_callback(
+        &self,
+        username: String,
+        amount: Amount,
+    ) -> Result<Invoice> {
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        let config = self.get_lnurl_pay_config(&mut dbtx, &username).await?;
+
+        if amount < config.min_sendable || config.max_sendable < amount {
+            return Err(GatewayError::other(format!(
+                "Amount has to be between {} and {}",
+                config.min_sendable, config.max_sendable
+            )));
+        }
+
+        let offer_key = dbtx
+            .find_by_prefix(&LnurlOfferKeyPrefix {
+                username: username.clone(),
+            })
+            .await
+            .next()
+            .await
+            .map(|(key, ())| key)
+            .ok_or_else(|| {
+                GatewayError::other(format!("{username} can't receive payments right now"))
+            })?;
+        dbtx.remove_entry(&offer_key).await;
+
+        let client = self.select_client(config.federation_id).await?;
+        let (gateway, _) = client.get_first_module::<GatewayClientModule>(&KIND);
+        let route_hints: Vec<RouteHint> = self.lnrpc.routehints().await?.try_into()?;
+        let description_hash =
+            sha256::Hash::hash(self.lnurl_metadata(&username, &config)?.as_bytes());
+        let invoice = gateway.create_lnurl_invoice(
+            offer_key.payment_hash,
+            amount,
+            description_hash,
+            route_hints,
+        )?;
+        dbtx.insert_entry(
+            &LnurlInvoiceKey {
+                payment_hash: offer_key.payment_hash,
+            },
+            &(),
+        )
+        .await;
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)?;
+
+        Ok(invoice)
+    }
+
+    /// Fetches an invoice for a BOLT12 offer, clients pay it by creating an
+    /// outgoing contract for the proxy invoice we return alongside it
+    pub async fn handle_fetch_bolt12_invoice_msg(
+        &self,
+        payload: FetchBolt12InvoicePayload,
+    ) -> Result<FetchBolt12InvoiceResponse> {
+        let client = self.select_client(payload.federation_id).await?;
+        let FetchInvoiceResponse {
+            invoice,
+            payment_hash,
+            amount_msat,
+            expires_at,
+        } = self
+            .lnrpc
+            .fetch_invoice(FetchInvoiceRequest {
+                offer: payload.offer,
+                amount_msat: payload.amount.map(|amount| amount.msats),
+            })
+            .await?;
+
+        let amount = Amount::from_msats(amount_msat);
+        if payload
+            .amount
+            .map_or(false, |requested| requested != amount)
+        {
+            return Err(GatewayError::other(format!(
+                "Recipient issued an invoice for {amount} instead of the requested amount"
+            )));
+        }
+
+        let payment_hash = sha256::Hash::from_slice(&payment_hash)
+            .map_err(|e| GatewayError::other(format!("Invalid payment hash: {e}")))?;
+        let (gateway, _) = client.get_first_module::<GatewayClientModule>(&KIND);
+        let proxy_invoice = gateway.create_bolt12_proxy_invoice(
+            payment_hash,
+            amount,
+            UNIX_EPOCH + Duration::from_secs(expires_at),
+        )?;
+
+        let proxy_node_key = *proxy_invoice
+            .payee_pub_key()
+            .expect("Proxy invoices contain their payee key");
+
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        // Anyone can ask us to fetch invoices, so we don't keep them after they expired
+        let now_secs = now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let expired = dbtx
+            .find_by_prefix(&Bolt12InvoiceKeyPrefix)
+            .await
+            .filter_map(|(key, bolt12_invoice)| async move {
+                (bolt12_invoice.expires_at <= now_secs).then_some(key)
+            })
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired {
+            dbtx.remove_entry(&key).await;
+        }
+        dbtx.insert_new_entry(
+            &Bolt12InvoiceKey { proxy_node_key },
+            &Bolt12Invoice {
+                invoice: invoice.clone(),
+                expires_at,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)?;
+
+        Ok(FetchBolt12InvoiceResponse {
+            invoice,
+            proxy_invoice,
+        })
+    }
+
+    /// Creates a BOLT12 offer on behalf of a client, invoice requests for it
+    /// are answered with the offers the client created with the federation
+    pub async fn handle_register_bolt12_offer_msg(
+        &self,
+        registration: Bolt12OfferRegistration,
+    ) -> Result<Bolt12OfferRegistrationResponse> {
+        if !self.answers_invoice_requests() {
+            return Err(GatewayError::other(
+                "Lightning node can't answer invoice requests for BOLT12 offers".to_string(),
+            ));
+        }
+
+        if registration.payment_hashes.is_empty() {
+            return Err(GatewayError::other(
+                "At least one offer has to be registered".to_string(),
+            ));
+        }
+
+        // Invoices for less than the offer's amount could not be funded
+        let client = self.select_client(registration.federation_id).await?;
+        let (_, instance) = client.get_first_module::<GatewayClientModule>(&KIND);
+        for payment_hash in &registration.payment_hashes {
+            let offer = instance.api.fetch_offer(*payment_hash).await?;
+            if offer.amount > registration.min_amount {
+                return Err(GatewayError::other(format!(
+                    "Offer {payment_hash} requires more than the minimum amount"
+                )));
+            }
+        }
+
+        let CreateOfferResponse { offer, offer_id } = self
+            .lnrpc
+            .create_offer(CreateOfferRequest {
+                description: registration.description.clone(),
+                min_amount_msat: registration.min_amount.msats,
+            })
+            .await?;
+
+        let mut dbtx = self.gatewayd_db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &Bolt12OfferKey {
+                offer_id: offer_id.clone(),
+            },
+            &Bolt12OfferConfig {
+                federation_id: registration.federation_id,
+                offer: offer.clone(),
+                min_amount: registration.min_amount,
+            },
+        )
+        .await;
+        for payment_hash in &registration.payment_hashes {
+            dbtx.insert_entry(
+                &Bolt12OfferPaymentKey {
+                    offer_id: offer_id.clone(),
+                    payment_hash: *payment_hash,
+                },
+                &(),
+            )
+            .await;
+        }
+
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|_| GatewayError::DatabaseError)?;
+
+        Ok(Bolt12OfferRegistrationResponse { offer })
+    }
+
+    async fn get_lnurl_pay_config(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        username: &str,
+    ) -> Result<LnurlPayConfig> {
+        dbtx.get_value(&LnurlPayKey {
+            username: username.to_string(),
+        })
+        .await
+        .ok_or_else(|| GatewayError::other(format!("Unknown username {username}")))
+    }
+
+    /// The LUD-06 metadata the invoices of `username` commit to
+    fn lnurl_metadata(&self, username: &str, config: &LnurlPayConfig) -> Result<String> {
+        let lightning_address = lightning_address(username, &self.api)
+            .map_err(|e| GatewayError::other(e.to_string()))?;
+        Ok(serde_json::json!([
+            ["text/plain", config.description],
+            ["text/identifier", lightning_address],
+        ])
+        .to_string())
     }
 
     pub async fn spawn_blocking_webserver(self, listen: SocketAddr, password: String) {
@@ -729,3 +1714,12 @@ impl Gateway {
         }
     }
 }
+
+/// Usernames of Lightning Addresses may only contain the characters allowed by
+/// LUD-16
+fn is_valid_lnurl_username(username: &str) -> bool {
+    !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c))
+}
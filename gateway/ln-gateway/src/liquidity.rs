@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use anyhow::ensure;
+use bitcoin::Address;
+use clap::Args;
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+/// How often the gateway checks the ecash balances of its federations against
+/// the configured limits
+pub const LIQUIDITY_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Limits the ecash balance of every federation the gateway is connected to
+/// should stay within
+#[derive(Debug, Clone, Default, Args)]
+pub struct LiquidityConfig {
+    /// Ecash balance in msat a federation should at least hold to fund incoming
+    /// payments. Falling below it alerts the operator to peg in. Capped at
+    /// the lightning node's inbound balance, which limits the incoming
+    /// payments the ecash can fund.
+    #[arg(
+        long = "liquidity-min-ecash-msat",
+        env = "FM_GATEWAY_LIQUIDITY_MIN_ECASH_MSAT"
+    )]
+    pub min_ecash: Option<Amount>,
+
+    /// Ecash balance in msat a federation should at most hold. Exceeding it
+    /// alerts the operator to peg out, or pegs out the excess if a peg-out
+    /// address is configured.
+    #[arg(
+        long = "liquidity-max-ecash-msat",
+        env = "FM_GATEWAY_LIQUIDITY_MAX_ECASH_MSAT"
+    )]
+    pub max_ecash: Option<Amount>,
+
+    /// Address ecash above the maximum is automatically pegged out to
+    #[arg(
+        long = "liquidity-peg-out-address",
+        env = "FM_GATEWAY_LIQUIDITY_PEG_OUT_ADDRESS"
+    )]
+    pub peg_out_address: Option<Address>,
+}
+
+impl LiquidityConfig {
+    /// Checks that the configured limits can be satisfied
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let (Some(min), Some(max)) = (self.min_ecash, self.max_ecash) {
+            ensure!(
+                min <= max,
+                "Minimum ecash balance {min} exceeds the maximum {max}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Decides what needs to be done to bring an ecash balance within the
+    /// configured limits.
+    ///
+    /// Ecash is only spent when the lightning node receives payments for
+    /// federation users, so the minimum is capped at the node's inbound
+    /// balance: ecash beyond it can't be spent until the node has more
+    /// inbound liquidity and isn't worth pegging in.
+    pub fn decide(&self, ecash_balance: Amount, lightning_inbound: Amount) -> LiquidityDecision {
+        let min_ecash = self.min_ecash.map(|min| min.min(lightning_inbound));
+        match (min_ecash, self.max_ecash) {
+            (Some(min), _) if ecash_balance < min => LiquidityDecision::PegIn {
+                amount: min - ecash_balance,
+            },
+            (_, Some(max)) if max < ecash_balance => LiquidityDecision::PegOut {
+                amount: ecash_balance - max,
+            },
+            _ => LiquidityDecision::Balanced,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityDecision {
+    /// The ecash balance is within the configured limits
+    Balanced,
+    /// The ecash balance is below the configured minimum, `amount` has to be
+    /// pegged in to reach it
+    PegIn { amount: Amount },
+    /// The ecash balance is above the configured maximum by `amount`, which is
+    /// pegged out if a peg-out address is configured
+    PegOut { amount: Amount },
+}
+
+/// Liquidity of one of the federations the gateway is connected to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationLiquidity {
+    pub federation_id: FederationId,
+    pub ecash_balance: Amount,
+    pub decision: LiquidityDecision,
+}
+
+/// Liquidity of the gateway's lightning node and of all its federations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidityReport {
+    /// Our balance in the lightning node's active channels, spent when paying
+    /// invoices for federation users
+    pub lightning_outbound: Amount,
+    /// Our peers' balance in the lightning node's active channels, spent when
+    /// receiving payments for federation users
+    pub lightning_inbound: Amount,
+    pub federations: Vec<FederationLiquidity>,
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{LiquidityConfig, LiquidityDecision};
+
+    const INBOUND: Amount = Amount::from_sats(100_000);
+
+    #[test]
+    fn balance_within_limits_is_balanced() {
+        let config = LiquidityConfig {
+            min_ecash: Some(Amount::from_sats(1_000)),
+            max_ecash: Some(Amount::from_sats(10_000)),
+            peg_out_address: None,
+        };
+
+        assert_eq!(
+            config.decide(Amount::from_sats(1_000), INBOUND),
+            LiquidityDecision::Balanced
+        );
+        assert_eq!(
+            config.decide(Amount::from_sats(10_000), INBOUND),
+            LiquidityDecision::Balanced
+        );
+        assert_eq!(
+            LiquidityConfig::default().decide(Amount::ZERO, INBOUND),
+            LiquidityDecision::Balanced
+        );
+    }
+
+    #[test]
+    fn balance_outside_limits_is_rebalanced() {
+        let config = LiquidityConfig {
+            min_ecash: Some(Amount::from_sats(1_000)),
+            max_ecash: Some(Amount::from_sats(10_000)),
+            peg_out_address: None,
+        };
+
+        assert_eq!(
+            config.decide(Amount::from_sats(400), INBOUND),
+            LiquidityDecision::PegIn {
+                amount: Amount::from_sats(600)
+            }
+        );
+        assert_eq!(
+            config.decide(Amount::from_sats(12_000), INBOUND),
+            LiquidityDecision::PegOut {
+                amount: Amount::from_sats(2_000)
+            }
+        );
+    }
+
+    #[test]
+    fn minimum_is_capped_at_inbound_liquidity() {
+        let config = LiquidityConfig {
+            min_ecash: Some(Amount::from_sats(1_000)),
+            max_ecash: Some(Amount::from_sats(10_000)),
+            peg_out_address: None,
+        };
+
+        // Only as much ecash as the lightning node can receive is needed
+        assert_eq!(
+            config.decide(Amount::from_sats(400), Amount::from_sats(400)),
+            LiquidityDecision::Balanced
+        );
+        assert_eq!(
+            config.decide(Amount::from_sats(400), Amount::from_sats(700)),
+            LiquidityDecision::PegIn {
+                amount: Amount::from_sats(300)
+            }
+        );
+    }
+
+    #[test]
+    fn minimum_above_maximum_is_rejected() {
+        let config = LiquidityConfig {
+            min_ecash: Some(Amount::from_sats(10_000)),
+            max_ecash: Some(Amount::from_sats(1_000)),
+            peg_out_address: None,
+        };
+        assert!(config.validate().is_err());
+
+        assert!(LiquidityConfig {
+            max_ecash: None,
+            ..config
+        }
+        .validate()
+        .is_ok());
+    }
+}
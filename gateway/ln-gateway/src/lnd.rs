@@ -18,10 +18,13 @@ use tracing::{error, info, trace};
 use crate::gatewaylnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use crate::gatewaylnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use crate::gatewaylnrpc::{
-    GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest, InterceptHtlcResponse,
-    PayInvoiceRequest, PayInvoiceResponse,
+    CreateOfferRequest, CreateOfferResponse, FetchInvoiceRequest, FetchInvoiceResponse,
+    GetChannelBalancesResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
+    InterceptHtlcResponse, InvoiceRequestResponse, PayInvoiceRequest, PayInvoiceResponse,
+};
+use crate::lnrpc_client::{
+    ILnRpcClient, RouteHtlcStream, RouteInvoiceRequestStream, MAX_LIGHTNING_RETRIES,
 };
-use crate::lnrpc_client::{ILnRpcClient, RouteHtlcStream, MAX_LIGHTNING_RETRIES};
 use crate::GatewayError;
 
 type HtlcSubscriptionSender = mpsc::Sender<Result<InterceptHtlcRequest, Status>>;
@@ -318,6 +321,49 @@ impl ILnRpcClient for GatewayLndClient {
         Ok(GetRouteHintsResponse { route_hints })
     }
 
+    async fn channel_balances(&self) -> crate::Result<GetChannelBalancesResponse> {
+        let mut client = Self::connect(
+            self.address.clone(),
+            self.tls_cert.clone(),
+            self.macaroon.clone(),
+        )
+        .await?;
+        let channels = client
+            .lightning()
+            .list_channels(ListChannelsRequest {
+                active_only: true,
+                inactive_only: false,
+                public_only: false,
+                private_only: false,
+                peer: vec![],
+            })
+            .await
+            .map_err(|e| {
+                GatewayError::LnRpcError(tonic::Status::new(
+                    tonic::Code::Internal,
+                    format!("LND error: {e:?}"),
+                ))
+            })?
+            .into_inner();
+
+        // LND reports channel balances in sats
+        let (local_balance_msat, remote_balance_msat) =
+            channels
+                .channels
+                .iter()
+                .fold((0, 0), |(local, remote), chan| {
+                    (
+                        local + chan.local_balance as u64 * 1000,
+                        remote + chan.remote_balance as u64 * 1000,
+                    )
+                });
+
+        Ok(GetChannelBalancesResponse {
+            local_balance_msat,
+            remote_balance_msat,
+        })
+    }
+
     async fn pay(&self, invoice: PayInvoiceRequest) -> crate::Result<PayInvoiceResponse> {
         let mut client = Self::connect(
             self.address.clone(),
@@ -404,4 +450,32 @@ impl ILnRpcClient for GatewayLndClient {
 
         Ok(Box::pin(ReceiverStream::new(actor_receiver)))
     }
+
+    async fn fetch_invoice(
+        &self,
+        _request: FetchInvoiceRequest,
+    ) -> crate::Result<FetchInvoiceResponse> {
+        Err(bolt12_unsupported())
+    }
+
+    async fn create_offer(
+        &self,
+        _request: CreateOfferRequest,
+    ) -> crate::Result<CreateOfferResponse> {
+        Err(bolt12_unsupported())
+    }
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        _events: ReceiverStream<InvoiceRequestResponse>,
+        _task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>, GatewayError> {
+        Err(bolt12_unsupported())
+    }
+}
+
+fn bolt12_unsupported() -> GatewayError {
+    GatewayError::LnRpcError(tonic::Status::unimplemented(
+        "LND does not support BOLT12 offers",
+    ))
 }
@@ -12,14 +12,19 @@ use url::Url;
 
 use crate::gatewaylnrpc::gateway_lightning_client::GatewayLightningClient;
 use crate::gatewaylnrpc::{
-    EmptyRequest, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    CreateOfferRequest, CreateOfferResponse, EmptyRequest, FetchInvoiceRequest,
+    FetchInvoiceResponse, GetChannelBalancesResponse, GetNodeInfoResponse, GetRouteHintsResponse,
+    InterceptHtlcRequest, InterceptHtlcResponse, InterceptInvoiceRequest, InvoiceRequestResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
 };
 use crate::{GatewayError, Result};
 
 pub type RouteHtlcStream<'a> =
     BoxStream<'a, std::result::Result<InterceptHtlcRequest, tonic::Status>>;
 
+pub type RouteInvoiceRequestStream<'a> =
+    BoxStream<'a, std::result::Result<InterceptInvoiceRequest, tonic::Status>>;
+
 pub const MAX_LIGHTNING_RETRIES: u32 = 10;
 
 #[async_trait]
@@ -30,6 +35,10 @@ pub trait ILnRpcClient: Debug + Send + Sync {
     /// Get route hints to the lightning node
     async fn routehints(&self) -> Result<GetRouteHintsResponse>;
 
+    /// Get our and our peers' share of the capacity of the lightning node's
+    /// active channels
+    async fn channel_balances(&self) -> Result<GetChannelBalancesResponse>;
+
     /// Attempt to pay an invoice using the lightning node
     async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse>;
 
@@ -38,6 +47,18 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         events: ReceiverStream<InterceptHtlcResponse>,
         task_group: &mut TaskGroup,
     ) -> Result<RouteHtlcStream<'a>>;
+
+    /// Request a BOLT12 invoice for an offer
+    async fn fetch_invoice(&self, request: FetchInvoiceRequest) -> Result<FetchInvoiceResponse>;
+
+    /// Create a BOLT12 offer whose invoice requests are answered by us
+    async fn create_offer(&self, request: CreateOfferRequest) -> Result<CreateOfferResponse>;
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        events: ReceiverStream<InvoiceRequestResponse>,
+        task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>>;
 }
 
 /// An `ILnRpcClient` that wraps around `GatewayLightningClient` for
@@ -101,6 +122,13 @@ impl ILnRpcClient for NetworkLnRpcClient {
         Ok(res.into_inner())
     }
 
+    async fn channel_balances(&self) -> Result<GetChannelBalancesResponse> {
+        let req = Request::new(EmptyRequest {});
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.get_channel_balances(req).await?;
+        Ok(res.into_inner())
+    }
+
     async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse> {
         let req = Request::new(invoice);
         let mut client = Self::connect(self.connection_url.clone()).await?;
@@ -117,4 +145,28 @@ impl ILnRpcClient for NetworkLnRpcClient {
         let res = client.route_htlcs(events).await?;
         Ok(Box::pin(res.into_inner()))
     }
+
+    async fn fetch_invoice(&self, request: FetchInvoiceRequest) -> Result<FetchInvoiceResponse> {
+        let req = Request::new(request);
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.fetch_invoice(req).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn create_offer(&self, request: CreateOfferRequest) -> Result<CreateOfferResponse> {
+        let req = Request::new(request);
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.create_offer(req).await?;
+        Ok(res.into_inner())
+    }
+
+    async fn route_invoice_requests<'a>(
+        &mut self,
+        events: ReceiverStream<InvoiceRequestResponse>,
+        _task_group: &mut TaskGroup,
+    ) -> Result<RouteInvoiceRequestStream<'a>> {
+        let mut client = Self::connect(self.connection_url.clone()).await?;
+        let res = client.route_invoice_requests(events).await?;
+        Ok(Box::pin(res.into_inner()))
+    }
 }
@@ -1,8 +1,11 @@
 pub mod pay;
 pub mod register;
 
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::iter::once;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
 
 use async_stream::stream;
 use bitcoin_hashes::{sha256, Hash};
@@ -15,14 +18,17 @@ use fedimint_client::sm::{Context, DynState, ModuleNotifier, OperationId, State}
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
 use fedimint_client::{sm_enum_variant_translation, Client, DynGlobalClientContext};
 use fedimint_core::api::{DynGlobalApi, DynModuleApi};
+use fedimint_core::config::FederationId;
 use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId};
 use fedimint_core::db::{AutocommitError, Database};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{
     ApiVersion, ExtendsCommonModuleGen, MultiApiVersion, TransactionItemAmount,
 };
+use fedimint_core::task::{MaybeSend, MaybeSync, RwLock};
 use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint, TransactionId};
 use fedimint_ln_client::contracts::ContractId;
+use fedimint_ln_client::network_to_currency;
 use fedimint_ln_common::config::LightningClientConfig;
 use fedimint_ln_common::contracts::Preimage;
 use fedimint_ln_common::incoming::{
@@ -30,11 +36,16 @@ use fedimint_ln_common::incoming::{
 };
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::{
-    create_incoming_contract_output, ln_operation, LightningClientContext, LightningCommonGen,
-    LightningGateway, LightningModuleTypes, LightningOutput, KIND,
+    create_incoming_contract_output, create_paid_incoming_contract_output, ln_operation,
+    LightningClientContext, LightningCommonGen, LightningGateway, LightningModuleTypes,
+    LightningOutput, KIND,
 };
 use futures::StreamExt;
+use lightning::ln::PaymentSecret;
 use lightning::routing::gossip::RoutingFees;
+use lightning::routing::router::RouteHintHop;
+use lightning_invoice::{Invoice, InvoiceBuilder, DEFAULT_EXPIRY_TIME};
+use rand::Rng;
 use secp256k1::{KeyPair, PublicKey, Secp256k1};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -42,6 +53,7 @@ use url::Url;
 
 use self::pay::{GatewayPayCommon, GatewayPayInvoice, GatewayPayStateMachine, GatewayPayStates};
 use self::register::RegisterWithFederationStateMachine;
+use crate::db::LnurlInvoiceKey;
 use crate::gatewaylnrpc::{GetNodeInfoResponse, InterceptHtlcRequest};
 use crate::lnrpc_client::ILnRpcClient;
 use crate::ng::register::{
@@ -122,6 +134,13 @@ pub trait GatewayClientExt {
     /// Attempt fulfill HTLC by buying preimage from the federation
     async fn gateway_handle_intercepted_htlc(&self, htlc: Htlc) -> anyhow::Result<OperationId>;
 
+    /// Attempt to fulfill an HTLC paying an invoice we issued for LNURL-pay,
+    /// buying the preimage for the paid amount instead of the offer's
+    async fn gateway_handle_intercepted_lnurl_htlc(
+        &self,
+        htlc: Htlc,
+    ) -> anyhow::Result<OperationId>;
+
     /// Subscribe to updates when the gateway is handling an intercepted HTLC
     async fn gateway_subscribe_ln_receive(
         &self,
@@ -297,15 +316,14 @@ impl GatewayClientExt for Client {
 
     /// Handles an intercepted HTLC by buying a preimage from the federation
     async fn gateway_handle_intercepted_htlc(&self, htlc: Htlc) -> anyhow::Result<OperationId> {
-        let (gateway, instance) = self.get_first_module::<GatewayClientModule>(&KIND);
-        let (operation_id, output) = gateway
-            .create_funding_incoming_contract_output(htlc)
-            .await?;
-        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
-        let operation_meta_gen = |_: TransactionId, _: Option<OutPoint>| GatewayMeta::Receive;
-        self.finalize_and_submit_transaction(operation_id, KIND.as_str(), operation_meta_gen, tx)
-            .await?;
-        Ok(operation_id)
+        fund_incoming_contract(self, htlc, false).await
+    }
+
+    async fn gateway_handle_intercepted_lnurl_htlc(
+        &self,
+        htlc: Htlc,
+    ) -> anyhow::Result<OperationId> {
+        fund_incoming_contract(self, htlc, true).await
     }
 
     async fn gateway_subscribe_ln_receive(
@@ -369,12 +387,33 @@ impl GatewayClientExt for Client {
     }
 }
 
+/// Funds the incoming contract for an intercepted HTLC with either the offer's
+/// or the paid amount
+async fn fund_incoming_contract(
+    client: &Client,
+    htlc: Htlc,
+    fund_paid_amount: bool,
+) -> anyhow::Result<OperationId> {
+    let (gateway, instance) = client.get_first_module::<GatewayClientModule>(&KIND);
+    let (operation_id, output) = gateway
+        .create_funding_incoming_contract_output(htlc, fund_paid_amount)
+        .await?;
+    let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+    let operation_meta_gen = |_: TransactionId, _: Option<OutPoint>| GatewayMeta::Receive;
+    client
+        .finalize_and_submit_transaction(operation_id, KIND.as_str(), operation_meta_gen, tx)
+        .await?;
+    Ok(operation_id)
+}
+
 #[derive(Debug, Clone)]
 pub struct GatewayClientGen {
     pub lightning_client: Arc<dyn ILnRpcClient>,
     pub timelock_delta: u64,
     pub mint_channel_id: u64,
     pub fees: RoutingFees,
+    pub bolt12_invoices: Arc<dyn IBolt12Invoices>,
+    pub federations: GatewayFederations,
 }
 
 impl ExtendsCommonModuleGen for GatewayClientGen {
@@ -408,19 +447,108 @@ impl ClientModuleGen for GatewayClientGen {
             lightning_client: self.lightning_client.clone(),
             timelock_delta: self.timelock_delta,
             mint_channel_id: self.mint_channel_id,
-            fees: self.fees,
+            fees: Arc::new(Mutex::new(self.fees)),
             module_api,
+            bolt12_invoices: self.bolt12_invoices.clone(),
+            federations: self.federations.clone(),
         })
     }
 }
 
+/// Looks up the BOLT12 invoices the gateway fetched for the proxy invoices
+/// clients create outgoing contracts for
+#[apply(async_trait_maybe_send!)]
+pub trait IBolt12Invoices: Debug + MaybeSend + MaybeSync {
+    /// Returns the BOLT12 invoice to pay instead of `proxy_invoice` if we
+    /// created the latter for it and it hasn't expired yet
+    async fn bolt12_invoice(&self, proxy_invoice: &Invoice) -> Option<String>;
+}
+
+/// Gives the state machines of one federation access to the clients of the
+/// other federations the gateway is connected to, so payments between them
+/// can be swapped directly instead of being routed over Lightning.
+#[derive(Clone, Default)]
+pub struct GatewayFederations {
+    // Weak since the clients hold this handle themselves
+    clients: Weak<RwLock<BTreeMap<FederationId, Arc<Client>>>>,
+    scid_to_federation: Arc<RwLock<BTreeMap<u64, FederationId>>>,
+    gatewayd_db: Option<Database>,
+}
+
+impl GatewayFederations {
+    pub fn new(
+        clients: &Arc<RwLock<BTreeMap<FederationId, Arc<Client>>>>,
+        scid_to_federation: Arc<RwLock<BTreeMap<u64, FederationId>>>,
+        gatewayd_db: Database,
+    ) -> Self {
+        Self {
+            clients: Arc::downgrade(clients),
+            scid_to_federation,
+            gatewayd_db: Some(gatewayd_db),
+        }
+    }
+
+    /// Whether the gateway issued the invoice with `payment_hash` for
+    /// LNURL-pay, so paying it funds the incoming contract with the amount
+    /// paid instead of the offer's amount
+    pub async fn is_lnurl_invoice(&self, payment_hash: sha256::Hash) -> bool {
+        let Some(gatewayd_db) = &self.gatewayd_db else {
+            return false;
+        };
+        gatewayd_db
+            .begin_transaction()
+            .await
+            .get_value(&LnurlInvoiceKey { payment_hash })
+            .await
+            .is_some()
+    }
+
+    /// Returns the short channel id and client of the federation an invoice
+    /// pays to if its last hop is a federation channel of the gateway with the
+    /// given node key
+    pub async fn client_for_invoice(
+        &self,
+        invoice: &Invoice,
+        node_pub_key: PublicKey,
+    ) -> Option<(u64, Arc<Client>)> {
+        let short_channel_id = invoice
+            .route_hints()
+            .first()
+            .and_then(|rh| rh.0.last())
+            .filter(|hop| hop.src_node_id == node_pub_key)
+            .map(|hop| hop.short_channel_id)?;
+        let federation_id = *self
+            .scid_to_federation
+            .read()
+            .await
+            .get(&short_channel_id)?;
+        let client = self
+            .clients
+            .upgrade()?
+            .read()
+            .await
+            .get(&federation_id)
+            .cloned()?;
+        Some((short_channel_id, client))
+    }
+}
+
+impl Debug for GatewayFederations {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GatewayFederations").finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GatewayClientContext {
     lnrpc: Arc<dyn ILnRpcClient>,
     redeem_key: bitcoin::KeyPair,
+    node_pub_key: PublicKey,
     timelock_delta: u64,
     secp: secp256k1_zkp::Secp256k1<secp256k1_zkp::All>,
     pub ln_decoder: Decoder,
+    bolt12_invoices: Arc<dyn IBolt12Invoices>,
+    federations: GatewayFederations,
 }
 
 impl Context for GatewayClientContext {}
@@ -452,9 +580,12 @@ pub struct GatewayClientModule {
     node_pub_key: PublicKey,
     timelock_delta: u64,
     mint_channel_id: u64,
-    fees: RoutingFees,
+    // Can be changed by the operator while the client is running
+    fees: Arc<Mutex<RoutingFees>>,
     lightning_client: Arc<dyn ILnRpcClient>,
     module_api: DynModuleApi,
+    bolt12_invoices: Arc<dyn IBolt12Invoices>,
+    federations: GatewayFederations,
 }
 
 impl ClientModule for GatewayClientModule {
@@ -466,9 +597,12 @@ impl ClientModule for GatewayClientModule {
         Self::ModuleStateMachineContext {
             lnrpc: self.lightning_client.clone(),
             redeem_key: self.redeem_key,
+            node_pub_key: self.node_pub_key,
             timelock_delta: self.timelock_delta,
             secp: secp256k1_zkp::Secp256k1::new(),
             ln_decoder: self.decoder(),
+            bolt12_invoices: self.bolt12_invoices.clone(),
+            federations: self.federations.clone(),
         }
     }
 
@@ -496,12 +630,13 @@ impl ClientModule for GatewayClientModule {
                 amount: account_output.amount,
                 fee: self.cfg.fee_consensus.contract_output,
             },
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
-                TransactionItemAmount {
-                    amount: Amount::ZERO,
-                    fee: Amount::ZERO,
-                }
-            }
+            LightningOutput::Offer(_)
+            | LightningOutput::CancelOutgoing { .. }
+            | LightningOutput::HoldOffer { .. }
+            | LightningOutput::SettleHeld { .. } => TransactionItemAmount {
+                amount: Amount::ZERO,
+                fee: Amount::ZERO,
+            },
         }
     }
 }
@@ -520,8 +655,116 @@ impl GatewayClientModule {
             api,
             route_hints,
             valid_until: fedimint_core::time::now() + time_to_live,
-            fees: self.fees,
+            fees: *self.fees.lock().expect("fees lock poisoned"),
+        }
+    }
+
+    /// Changes the fees announced by registrations from now on, clients only
+    /// see them once we register again with
+    /// [`GatewayClientExt::register_with_federation`]
+    pub fn set_fees(&self, fees: RoutingFees) {
+        *self.fees.lock().expect("fees lock poisoned") = fees;
+    }
+
+    /// Creates an invoice paying to an offer a client registered with us for
+    /// LNURL-pay. The invoice is signed by a temporary node key just like the
+    /// ones created by clients, the last hop of its route hints is the
+    /// federation's short channel id so we intercept the HTLCs paying it.
+    pub fn create_lnurl_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+        amount: Amount,
+        description_hash: sha256::Hash,
+        route_hints: Vec<RouteHint>,
+    ) -> anyhow::Result<Invoice> {
+        let secp = secp256k1_zkp::Secp256k1::new();
+        let mut rng = rand::rngs::OsRng;
+        let (node_secret_key, node_public_key) = secp.generate_keypair(&mut rng);
+
+        let route_hint_last_hop = RouteHintHop {
+            src_node_id: self.node_pub_key,
+            short_channel_id: self.mint_channel_id,
+            fees: RoutingFees {
+                base_msat: 0,
+                proportional_millionths: 0,
+            },
+            cltv_expiry_delta: 30,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        };
+        let route_hints = if route_hints.is_empty() {
+            vec![lightning::routing::router::RouteHint(vec![
+                route_hint_last_hop,
+            ])]
+        } else {
+            route_hints
+                .iter()
+                .map(|rh| {
+                    lightning::routing::router::RouteHint(
+                        rh.to_ldk_route_hint()
+                            .0
+                            .into_iter()
+                            .chain(once(route_hint_last_hop.clone()))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+
+        let duration_since_epoch = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Time went backwards");
+
+        let mut invoice_builder = InvoiceBuilder::new(network_to_currency(self.cfg.network))
+            .amount_milli_satoshis(amount.msats)
+            .description_hash(description_hash)
+            .payment_hash(payment_hash)
+            .payment_secret(PaymentSecret(rng.gen()))
+            .duration_since_epoch(duration_since_epoch)
+            .min_final_cltv_expiry(18)
+            .payee_pub_key(node_public_key)
+            .expiry_time(Duration::from_secs(DEFAULT_EXPIRY_TIME));
+
+        for rh in route_hints {
+            invoice_builder = invoice_builder.private_route(rh);
         }
+
+        Ok(invoice_builder
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &node_secret_key))?)
+    }
+
+    /// Creates a BOLT11 invoice standing in for a BOLT12 invoice we fetched,
+    /// since outgoing contracts can only commit to the former. It has the
+    /// same payment hash and amount, is signed by a temporary node key and
+    /// has no route hints so it is never mistaken for an internal payment.
+    pub fn create_bolt12_proxy_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+        amount: Amount,
+        expires_at: SystemTime,
+    ) -> anyhow::Result<Invoice> {
+        let secp = secp256k1_zkp::Secp256k1::new();
+        let mut rng = rand::rngs::OsRng;
+        let (node_secret_key, node_public_key) = secp.generate_keypair(&mut rng);
+
+        let now = fedimint_core::time::now();
+        let duration_since_epoch = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Time went backwards");
+        let expiry_time = expires_at
+            .duration_since(now)
+            .map_err(|_| anyhow::anyhow!("BOLT12 invoice already expired"))?;
+
+        Ok(InvoiceBuilder::new(network_to_currency(self.cfg.network))
+            .amount_milli_satoshis(amount.msats)
+            .description("BOLT12 invoice".to_string())
+            .payment_hash(payment_hash)
+            .payment_secret(PaymentSecret(rng.gen()))
+            .duration_since_epoch(duration_since_epoch)
+            .min_final_cltv_expiry(18)
+            .payee_pub_key(node_public_key)
+            .expiry_time(expiry_time)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &node_secret_key))?)
     }
 
     async fn await_paid_invoice(
@@ -551,6 +794,7 @@ impl GatewayClientModule {
     async fn create_funding_incoming_contract_output(
         &self,
         htlc: Htlc,
+        fund_paid_amount: bool,
     ) -> Result<
         (
             OperationId,
@@ -559,13 +803,23 @@ impl GatewayClientModule {
         IncomingSmError,
     > {
         let operation_id = OperationId(htlc.payment_hash.into_inner());
-        let (incoming_output, contract_id) = create_incoming_contract_output(
-            &self.module_api,
-            htlc.payment_hash,
-            htlc.outgoing_amount_msat,
-            self.redeem_key,
-        )
-        .await?;
+        let (incoming_output, contract_id) = if fund_paid_amount {
+            create_paid_incoming_contract_output(
+                &self.module_api,
+                htlc.payment_hash,
+                htlc.outgoing_amount_msat,
+                self.redeem_key,
+            )
+            .await?
+        } else {
+            create_incoming_contract_output(
+                &self.module_api,
+                htlc.payment_hash,
+                htlc.outgoing_amount_msat,
+                self.redeem_key,
+            )
+            .await?
+        };
 
         let client_output = ClientOutput::<LightningOutput, GatewayClientStateMachines> {
             output: incoming_output,
@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
+use bitcoin_hashes::Hash;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput};
-use fedimint_client::DynGlobalClientContext;
+use fedimint_client::{Client, DynGlobalClientContext};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{Amount, OutPoint, TransactionId};
 use fedimint_ln_client::contracts::IdentifiableContract;
@@ -10,11 +11,14 @@ use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::contracts::outgoing::OutgoingContractAccount;
 use fedimint_ln_common::contracts::{ContractId, FundedContract, Preimage};
 use fedimint_ln_common::{LightningInput, LightningOutput};
-use futures::future;
+use futures::{future, StreamExt};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use super::{GatewayClientContext, GatewayClientStateMachines};
+use super::{
+    GatewayClientContext, GatewayClientExt, GatewayClientStateMachines, GatewayExtReceiveStates,
+    Htlc,
+};
 use crate::gatewaylnrpc::{PayInvoiceRequest, PayInvoiceResponse};
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -29,6 +33,8 @@ use crate::gatewaylnrpc::{PayInvoiceRequest, PayInvoiceResponse};
 ///    PayInvoice -- validate contract failed --> CancelContract
 ///    PayInvoice -- pay invoice unsuccessful --> CancelContract
 ///    PayInvoice -- pay invoice successful --> ClaimOutgoingContract
+///    PayInvoice -- direct swap unsuccessful --> CancelContract
+///    PayInvoice -- direct swap successful --> ClaimOutgoingContract
 ///    ClaimOutgoingContract -- claim tx submission --> Preimage
 ///    CancelContract -- cancel tx submission successful --> Canceled
 ///    CancelContract -- cancel tx submission unsuccessful --> Failed
@@ -118,6 +124,8 @@ pub enum OutgoingPaymentError {
     OutgoingContractDoesNotExist { contract_id: ContractId },
     #[error("An error occurred while paying the lightning invoice.")]
     LightningPayError { contract: OutgoingContractAccount },
+    #[error("An error occurred while swapping the payment to another federation.")]
+    DirectSwapError { contract: OutgoingContractAccount },
     #[error("An invalid contract was specified.")]
     InvalidOutgoingContract {
         error: OutgoingContractError,
@@ -189,12 +197,36 @@ impl GatewayPayInvoice {
                 error: e,
                 contract: outgoing_contract_account.clone(),
             })?;
-            let preimage = Self::await_buy_preimage_over_lightning(
-                context,
-                payment_parameters,
-                outgoing_contract_account.clone(),
-            )
-            .await?;
+            // Invoices of federations we are connected to as well don't need to take
+            // a detour over the lightning network
+            let preimage = match context
+                .federations
+                .client_for_invoice(&payment_parameters.invoice, context.node_pub_key)
+                .await
+            {
+                Some((short_channel_id, client)) => {
+                    let is_lnurl = context
+                        .federations
+                        .is_lnurl_invoice(*payment_parameters.invoice.payment_hash())
+                        .await;
+                    Self::await_buy_preimage_over_direct_swap(
+                        client,
+                        short_channel_id,
+                        payment_parameters,
+                        outgoing_contract_account.clone(),
+                        is_lnurl,
+                    )
+                    .await?
+                }
+                None => {
+                    Self::await_buy_preimage_over_lightning(
+                        context,
+                        payment_parameters,
+                        outgoing_contract_account.clone(),
+                    )
+                    .await?
+                }
+            };
             return Ok((outgoing_contract_account, preimage));
         }
 
@@ -209,10 +241,14 @@ impl GatewayPayInvoice {
         let invoice = buy_preimage.invoice.clone();
         let max_delay = buy_preimage.max_delay;
         let max_fee_percent = buy_preimage.max_fee_percent();
+
+        // Proxy invoices we created for a BOLT12 invoice are paid by paying the latter
+        let bolt12_invoice = context.bolt12_invoices.bolt12_invoice(&invoice).await;
+
         match context
             .lnrpc
             .pay(PayInvoiceRequest {
-                invoice: invoice.to_string(),
+                invoice: bolt12_invoice.unwrap_or_else(|| invoice.to_string()),
                 max_delay,
                 max_fee_percent,
             })
@@ -227,6 +263,64 @@ impl GatewayPayInvoice {
         }
     }
 
+    /// Buys the preimage from the federation that issued the invoice by funding
+    /// the incoming contract there with our own ecash, just like we would for
+    /// an intercepted HTLC paying the invoice. Invoices issued for LNURL-pay
+    /// fund the contract with the invoice amount instead of the offer's amount.
+    async fn await_buy_preimage_over_direct_swap(
+        client: Arc<Client>,
+        short_channel_id: u64,
+        buy_preimage: PaymentParameters,
+        contract: OutgoingContractAccount,
+        is_lnurl: bool,
+    ) -> Result<Preimage, OutgoingPaymentError> {
+        let payment_hash = *buy_preimage.invoice.payment_hash();
+        let operation_id = OperationId(payment_hash.into_inner());
+
+        // The incoming contract might have been funded before the gateway restarted
+        if client
+            .operation_log()
+            .get_operation(operation_id)
+            .await
+            .is_none()
+        {
+            let htlc = Htlc {
+                payment_hash,
+                incoming_amount_msat: buy_preimage.max_send_amount,
+                outgoing_amount_msat: buy_preimage.invoice_amount,
+                incoming_expiry: contract.contract.timelock,
+                short_channel_id,
+                incoming_chan_id: 0,
+                htlc_id: 0,
+            };
+            let funding = if is_lnurl {
+                client.gateway_handle_intercepted_lnurl_htlc(htlc).await
+            } else {
+                client.gateway_handle_intercepted_htlc(htlc).await
+            };
+            funding.map_err(|_| OutgoingPaymentError::DirectSwapError {
+                contract: contract.clone(),
+            })?;
+        }
+
+        let mut updates = client
+            .gateway_subscribe_ln_receive(operation_id)
+            .await
+            .map_err(|_| OutgoingPaymentError::DirectSwapError {
+                contract: contract.clone(),
+            })?
+            .into_stream();
+        while let Some(state) = updates.next().await {
+            match state {
+                GatewayExtReceiveStates::Preimage(preimage) => return Ok(preimage),
+                GatewayExtReceiveStates::Funding => {}
+                _ => break,
+            }
+        }
+
+        Err(OutgoingPaymentError::DirectSwapError { contract })
+    }
+
     async fn transition_bought_preimage(
         result: Result<(OutgoingContractAccount, Preimage), OutgoingPaymentError>,
         common: GatewayPayCommon,
@@ -247,7 +341,8 @@ impl GatewayPayInvoice {
                         )),
                     }
                 }
-                OutgoingPaymentError::LightningPayError { contract } => GatewayPayStateMachine {
+                OutgoingPaymentError::LightningPayError { contract }
+                | OutgoingPaymentError::DirectSwapError { contract } => GatewayPayStateMachine {
                     common,
                     state: GatewayPayStates::CancelContract(Box::new(GatewayPayCancelContract {
                         contract,
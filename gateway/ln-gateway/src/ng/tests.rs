@@ -1,12 +1,15 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use assert_matches::assert_matches;
+use bitcoin_hashes::hex::ToHex;
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_client::sm::OperationId;
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use fedimint_client::Client;
+use fedimint_core::config::FederationId;
 use fedimint_core::core::IntoDynInstance;
 use fedimint_core::util::NextOrPending;
 use fedimint_core::{sats, Amount, OutPoint, TransactionId};
@@ -15,7 +18,7 @@ use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
 use fedimint_ln_client::{
     LightningClientExt, LightningClientGen, LightningClientModule, LightningClientStateMachines,
-    LightningMeta, LnPayState, PayType,
+    LightningMeta, LnPayState, LnReceiveState, PayType,
 };
 use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::config::LightningGenParams;
@@ -27,8 +30,13 @@ use fedimint_ln_server::LightningGen;
 use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::Fixtures;
 use fedimint_testing::gateway::GatewayTest;
+use fedimint_testing::ln::mock::{FakeLightningTest, FAKE_BOLT12_PREIMAGE};
 use fedimint_testing::ln::LightningTest;
 use futures::Future;
+use lightning_invoice::Invoice;
+use ln_gateway::db::{InFlightHtlc, InFlightHtlcKey};
+use ln_gateway::gatewaylnrpc::intercept_htlc_response::Action;
+use ln_gateway::gatewaylnrpc::InterceptHtlcRequest;
 use ln_gateway::ng::{
     GatewayClientExt, GatewayClientModule, GatewayClientStateMachines, GatewayExtPayStates,
     GatewayExtReceiveStates, GatewayExtRegisterStates, GatewayMeta, Htlc, GW_ANNOUNCEMENT_TTL,
@@ -229,6 +237,208 @@ async fn test_gateway_client_pay_invalid_invoice() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_client_pay_invoice_of_other_federation_directly() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_fed().await;
+    let fed2 = fixtures.new_fed().await;
+    let user_client1 = fed1.new_client().await;
+    let user_client2 = fed2.new_client().await;
+    let mut gateway = fixtures.new_gateway(fixtures.lnd().await).await;
+    gateway.connect_fed(&fed1).await;
+    gateway.connect_fed(&fed2).await;
+    let gateway1 = gateway.select_client(&fed1).await;
+    let gateway2 = gateway.select_client(&fed2).await;
+
+    // Print money for the paying user and the gateway in the receiving federation
+    let (_, outpoint) = user_client1.print_money(sats(1000)).await?;
+    user_client1.receive_money(outpoint).await?;
+    let (_, outpoint) = gateway2.print_money(sats(1000)).await?;
+    gateway2.receive_money(outpoint).await?;
+
+    // User of the second federation creates an invoice routed through the gateway
+    let (receive_op, invoice) = user_client2
+        .create_bolt11_invoice(sats(250), "description".into(), None)
+        .await?;
+    let mut receive_sub = user_client2
+        .subscribe_ln_receive(receive_op)
+        .await?
+        .into_stream();
+    assert_eq!(receive_sub.ok().await?, LnReceiveState::Created);
+    assert_matches!(
+        receive_sub.ok().await?,
+        LnReceiveState::WaitingForPayment { .. }
+    );
+
+    // User of the first federation pays it, the gateway swaps the payment
+    let (pay_type, contract_id) = user_client1.pay_bolt11_invoice(invoice).await?;
+    match pay_type {
+        PayType::Lightning(pay_op) => {
+            let mut pay_sub = user_client1.subscribe_ln_pay(pay_op).await?.into_stream();
+            assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+            assert_eq!(pay_sub.ok().await?, LnPayState::Funded);
+            loop {
+                match pay_sub.ok().await? {
+                    LnPayState::AwaitingChange => {}
+                    LnPayState::Success { .. } => break,
+                    state => panic!("Unexpected payment state {state:?}"),
+                }
+            }
+        }
+        _ => panic!("Expected Lightning payment!"),
+    }
+    assert_eq!(receive_sub.ok().await?, LnReceiveState::Funded);
+    assert_eq!(receive_sub.ok().await?, LnReceiveState::AwaitingFunds);
+    assert_eq!(receive_sub.ok().await?, LnReceiveState::Claimed);
+
+    let mut gw_pay_sub = gateway1
+        .gateway_subscribe_ln_pay(OperationId(contract_id.into_inner()))
+        .await?
+        .into_stream();
+    assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+    assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Preimage { .. });
+    match gw_pay_sub.ok().await? {
+        GatewayExtPayStates::Success { outpoint, .. } => gateway1.receive_money(outpoint).await?,
+        _ => panic!("Gateway pay state machine was not successful"),
+    }
+
+    assert_eq!(user_client1.get_balance().await, sats(1000 - 250));
+    assert_eq!(user_client2.get_balance().await, sats(250));
+    assert_eq!(gateway1.get_balance().await, sats(250));
+    assert_eq!(gateway2.get_balance().await, sats(1000 - 250));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_client_pay_lnurl_invoice_of_other_federation_directly() -> anyhow::Result<()>
+{
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_fed().await;
+    let fed2 = fixtures.new_fed().await;
+    let user_client1 = fed1.new_client().await;
+    let user_client2 = fed2.new_client().await;
+    let mut gateway = fixtures.new_gateway(fixtures.lnd().await).await;
+    gateway.connect_fed(&fed1).await;
+    gateway.connect_fed(&fed2).await;
+    let gateway1 = gateway.select_client(&fed1).await;
+    let gateway2 = gateway.select_client(&fed2).await;
+
+    // Print money for the paying user and the gateway in the receiving federation
+    let (_, outpoint) = user_client1.print_money(sats(1000)).await?;
+    user_client1.receive_money(outpoint).await?;
+    let (_, outpoint) = gateway2.print_money(sats(1000)).await?;
+    gateway2.receive_money(outpoint).await?;
+
+    // User of the second federation receives over LNURL-pay with an offer of
+    // 100 sats, the payer chooses to pay more
+    user_client2
+        .register_lnurl_pay(
+            "alice".to_string(),
+            "description".to_string(),
+            sats(100),
+            sats(500),
+            1,
+        )
+        .await?;
+    let invoice = gateway.lnurl_pay_invoice("alice", sats(250)).await;
+
+    // User of the first federation pays it, the gateway swaps the payment
+    let (pay_type, contract_id) = user_client1.pay_bolt11_invoice(invoice).await?;
+    match pay_type {
+        PayType::Lightning(pay_op) => {
+            let mut pay_sub = user_client1.subscribe_ln_pay(pay_op).await?.into_stream();
+            assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+            assert_eq!(pay_sub.ok().await?, LnPayState::Funded);
+            loop {
+                match pay_sub.ok().await? {
+                    LnPayState::AwaitingChange => {}
+                    LnPayState::Success { .. } => break,
+                    state => panic!("Unexpected payment state {state:?}"),
+                }
+            }
+        }
+        _ => panic!("Expected Lightning payment!"),
+    }
+
+    let mut gw_pay_sub = gateway1
+        .gateway_subscribe_ln_pay(OperationId(contract_id.into_inner()))
+        .await?
+        .into_stream();
+    assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+    assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Preimage { .. });
+    match gw_pay_sub.ok().await? {
+        GatewayExtPayStates::Success { outpoint, .. } => gateway1.receive_money(outpoint).await?,
+        _ => panic!("Gateway pay state machine was not successful"),
+    }
+
+    // The incoming contract was funded with the amount paid, not the offer's
+    assert_eq!(user_client1.get_balance().await, sats(1000 - 250));
+    assert_eq!(gateway1.get_balance().await, sats(250));
+    assert_eq!(gateway2.get_balance().await, sats(1000 - 250));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_client_pay_bolt12_offer() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let user_client = fed.new_client().await;
+    // Only the fake lightning node fetches BOLT12 invoices
+    let mut gateway = fixtures
+        .new_gateway(Arc::new(FakeLightningTest::new()))
+        .await;
+    gateway.connect_fed(&fed).await;
+    let gateway_client = gateway.select_client(&fed).await;
+
+    // Print money for user_client
+    let (_, outpoint) = user_client.print_money(sats(1000)).await?;
+    user_client.receive_money(outpoint).await?;
+
+    // The contract commits to the proxy invoice, the preimage can only be bought by
+    // paying the BOLT12 invoice the gateway fetched for it
+    let (pay_type, contract_id) = user_client
+        .pay_bolt12_offer("lno1fake".to_string(), Some(sats(250)))
+        .await?;
+    match pay_type {
+        PayType::Lightning(pay_op) => {
+            let mut pay_sub = user_client.subscribe_ln_pay(pay_op).await?.into_stream();
+            assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+            assert_eq!(pay_sub.ok().await?, LnPayState::Funded);
+            loop {
+                match pay_sub.ok().await? {
+                    LnPayState::AwaitingChange => {}
+                    LnPayState::Success { preimage } => {
+                        assert_eq!(preimage, FAKE_BOLT12_PREIMAGE.to_hex());
+                        break;
+                    }
+                    state => panic!("Unexpected payment state {state:?}"),
+                }
+            }
+        }
+        _ => panic!("Expected Lightning payment!"),
+    }
+
+    let mut gw_pay_sub = gateway_client
+        .gateway_subscribe_ln_pay(OperationId(contract_id.into_inner()))
+        .await?
+        .into_stream();
+    assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+    assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Preimage { .. });
+    match gw_pay_sub.ok().await? {
+        GatewayExtPayStates::Success { outpoint, .. } => {
+            gateway_client.receive_money(outpoint).await?
+        }
+        _ => panic!("Gateway pay state machine was not successful"),
+    }
+
+    assert_eq!(user_client.get_balance().await, sats(1000 - 250));
+    assert_eq!(gateway_client.get_balance().await, sats(250));
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_intercept_valid_htlc() -> anyhow::Result<()> {
     gateway_test(|gateway, _, fed, user_client| async move {
@@ -275,6 +485,71 @@ async fn test_gateway_client_intercept_valid_htlc() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_client_intercept_overpaying_htlc() -> anyhow::Result<()> {
+    gateway_test(|gateway, _, fed, user_client| async move {
+        let gateway = gateway.remove_client(&fed).await;
+        // Print money for gateway client
+        let initial_gateway_balance = sats(1000);
+        let (_, outpoint) = gateway.print_money(initial_gateway_balance).await?;
+        gateway.receive_money(outpoint).await?;
+
+        // HTLCs paying more than the offer only fund the offer's amount...
+        let (_invoice_op, invoice) = user_client
+            .create_bolt11_invoice(sats(100), "description".into(), None)
+            .await?;
+        let htlc = Htlc {
+            payment_hash: *invoice.payment_hash(),
+            incoming_amount_msat: sats(150),
+            outgoing_amount_msat: sats(150),
+            incoming_expiry: u32::MAX,
+            short_channel_id: 1,
+            incoming_chan_id: 2,
+            htlc_id: 1,
+        };
+        let intercept_op = gateway.gateway_handle_intercepted_htlc(htlc).await?;
+        let mut intercept_sub = gateway
+            .gateway_subscribe_ln_receive(intercept_op)
+            .await?
+            .into_stream();
+        assert_eq!(intercept_sub.ok().await?, GatewayExtReceiveStates::Funding);
+        assert_matches!(
+            intercept_sub.ok().await?,
+            GatewayExtReceiveStates::Preimage { .. }
+        );
+        assert_eq!(gateway.get_balance().await, sats(1000 - 100));
+
+        // ...unless they pay an invoice issued for LNURL-pay, which the payer
+        // chose the amount of
+        let (_invoice_op, invoice) = user_client
+            .create_bolt11_invoice(sats(100), "description".into(), None)
+            .await?;
+        let htlc = Htlc {
+            payment_hash: *invoice.payment_hash(),
+            incoming_amount_msat: sats(150),
+            outgoing_amount_msat: sats(150),
+            incoming_expiry: u32::MAX,
+            short_channel_id: 1,
+            incoming_chan_id: 2,
+            htlc_id: 2,
+        };
+        let intercept_op = gateway.gateway_handle_intercepted_lnurl_htlc(htlc).await?;
+        let mut intercept_sub = gateway
+            .gateway_subscribe_ln_receive(intercept_op)
+            .await?
+            .into_stream();
+        assert_eq!(intercept_sub.ok().await?, GatewayExtReceiveStates::Funding);
+        assert_matches!(
+            intercept_sub.ok().await?,
+            GatewayExtReceiveStates::Preimage { .. }
+        );
+        assert_eq!(gateway.get_balance().await, sats(1000 - 100 - 150));
+
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_intercept_offer_does_not_exist() -> anyhow::Result<()> {
     gateway_test(|gateway, _, fed, _| async move {
@@ -432,6 +707,179 @@ async fn test_gateway_client_intercept_htlc_invalid_offer() -> anyhow::Result<()
     .await
 }
 
+/// HTLC for `invoice` as the lightning node intercepts it on the channel
+/// `short_channel_id` of a federation
+fn intercepted_htlc(
+    invoice: &Invoice,
+    short_channel_id: u64,
+    htlc_id: u64,
+) -> InterceptHtlcRequest {
+    InterceptHtlcRequest {
+        payment_hash: invoice.payment_hash().into_inner().to_vec(),
+        incoming_amount_msat: invoice.amount_milli_satoshis().unwrap(),
+        outgoing_amount_msat: invoice.amount_milli_satoshis().unwrap(),
+        incoming_expiry: u32::MAX,
+        short_channel_id,
+        incoming_chan_id: 2,
+        htlc_id,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_slow_federation_does_not_block_other_federation() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_fed().await;
+    let fed2 = fixtures.new_fed().await;
+    let user_client2 = fed2.new_client().await;
+    let lightning = FakeLightningTest::new();
+    let mut gateway = fixtures.new_gateway(Arc::new(lightning.clone())).await;
+    gateway.connect_fed(&fed1).await;
+    let info2 = gateway.connect_fed(&fed2).await;
+    let gateway1 = gateway.select_client(&fed1).await;
+    let gateway2 = gateway.select_client(&fed2).await;
+
+    let (_, outpoint) = gateway2.print_money(sats(1000)).await?;
+    gateway2.receive_money(outpoint).await?;
+
+    // More HTLCs than the gateway handles at once wait for an operation of the
+    // first federation that never makes progress
+    let stuck_op = OperationId::new_random();
+    let mut dbtx = gateway1.db().begin_transaction().await;
+    gateway1
+        .operation_log()
+        .add_operation_log_entry(&mut dbtx, stuck_op, "ln", GatewayMeta::Receive)
+        .await;
+    dbtx.commit_tx().await;
+    let mut dbtx = gateway.gatewayd_db().begin_transaction().await;
+    for htlc_id in 0..20 {
+        dbtx.insert_entry(
+            &InFlightHtlcKey {
+                incoming_chan_id: 1,
+                htlc_id,
+            },
+            &InFlightHtlc {
+                federation_id: fed1.id(),
+                operation_id: stuck_op,
+            },
+        )
+        .await;
+    }
+    dbtx.commit_tx().await;
+    gateway.route_htlcs(&lightning).await;
+
+    // An HTLC for the second federation is still settled
+    let (_invoice_op, invoice) = user_client2
+        .create_bolt11_invoice(sats(100), "description".into(), None)
+        .await?;
+    lightning.intercept_htlc(intercepted_htlc(
+        &invoice,
+        info2.registration.mint_channel_id,
+        0,
+    ));
+    let response = lightning.next_htlc_response().await;
+    assert_eq!((response.incoming_chan_id, response.htlc_id), (2, 0));
+    assert_matches!(response.action, Some(Action::Settle(_)));
+    assert_eq!(gateway2.get_balance().await, sats(1000 - 100));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_resumes_persisted_htlcs() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let user_client = fed.new_client().await;
+    let lightning = FakeLightningTest::new();
+    let mut gateway = fixtures.new_gateway(Arc::new(lightning.clone())).await;
+    let info = gateway.connect_fed(&fed).await;
+    let gateway_client = gateway.select_client(&fed).await;
+
+    let (_, outpoint) = gateway_client.print_money(sats(1000)).await?;
+    gateway_client.receive_money(outpoint).await?;
+
+    // The gateway funded the incoming contract of an HTLC before it restarted
+    let (_invoice_op, invoice) = user_client
+        .create_bolt11_invoice(sats(100), "description".into(), None)
+        .await?;
+    let htlc = intercepted_htlc(&invoice, info.registration.mint_channel_id, 1);
+    let operation_id = gateway_client
+        .gateway_handle_intercepted_htlc(Htlc::try_from(htlc)?)
+        .await?;
+
+    // One HTLC belongs to a federation the gateway isn't connected to anymore
+    let mut dbtx = gateway.gatewayd_db().begin_transaction().await;
+    dbtx.insert_entry(
+        &InFlightHtlcKey {
+            incoming_chan_id: 2,
+            htlc_id: 1,
+        },
+        &InFlightHtlc {
+            federation_id: fed.id(),
+            operation_id,
+        },
+    )
+    .await;
+    dbtx.insert_entry(
+        &InFlightHtlcKey {
+            incoming_chan_id: 2,
+            htlc_id: 2,
+        },
+        &InFlightHtlc {
+            federation_id: FederationId::dummy(),
+            operation_id: OperationId::new_random(),
+        },
+    )
+    .await;
+    dbtx.commit_tx().await;
+    gateway.route_htlcs(&lightning).await;
+
+    let mut actions = BTreeMap::new();
+    for _ in 0..2 {
+        let response = lightning.next_htlc_response().await;
+        actions.insert(response.htlc_id, response.action);
+    }
+    assert_matches!(actions[&1], Some(Action::Settle(_)));
+    assert_matches!(actions[&2], Some(Action::Cancel(_)));
+    assert_eq!(gateway_client.get_balance().await, sats(1000 - 100));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_funds_resent_htlc_once() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let user_client = fed.new_client().await;
+    let lightning = FakeLightningTest::new();
+    let mut gateway = fixtures.new_gateway(Arc::new(lightning.clone())).await;
+    let info = gateway.connect_fed(&fed).await;
+    let gateway_client = gateway.select_client(&fed).await;
+
+    let (_, outpoint) = gateway_client.print_money(sats(1000)).await?;
+    gateway_client.receive_money(outpoint).await?;
+
+    // The lightning node sends the HTLC again before the gateway responded
+    let (_invoice_op, invoice) = user_client
+        .create_bolt11_invoice(sats(100), "description".into(), None)
+        .await?;
+    let htlc = intercepted_htlc(&invoice, info.registration.mint_channel_id, 1);
+    lightning.intercept_htlc(htlc.clone());
+    lightning.intercept_htlc(htlc);
+    gateway.route_htlcs(&lightning).await;
+
+    let response = lightning.next_htlc_response().await;
+    assert_eq!((response.incoming_chan_id, response.htlc_id), (2, 1));
+    assert_matches!(response.action, Some(Action::Settle(_)));
+    assert!(
+        tokio::time::timeout(Duration::from_secs(1), lightning.next_htlc_response())
+            .await
+            .is_err()
+    );
+    assert_eq!(gateway_client.get_balance().await, sats(1000 - 100));
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_register_with_federation() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -16,7 +16,9 @@ use futures::Future;
 use lightning::routing::gossip::RoutingFees;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::sync::oneshot;
+use url::Url;
 
+use crate::liquidity::LiquidityReport;
 use crate::{Gateway, Result};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,6 +29,29 @@ pub struct ConnectFedPayload {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InfoPayload;
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MnemonicPayload;
+
+/// Mnemonic the secrets of all federation clients of the gateway are derived
+/// from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MnemonicResponse {
+    pub mnemonic: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiquidityPayload;
+
+/// New routing fees of one of the federations the gateway is connected to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFeesPayload {
+    pub federation_id: FederationId,
+    /// Flat fee in msat charged for every payment
+    pub base_msat: u32,
+    /// Fee in millionths of the amount of every payment
+    pub proportional_millionths: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BackupPayload {
     pub federation_id: FederationId,
@@ -34,7 +59,8 @@ pub struct BackupPayload {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RestorePayload {
-    pub federation_id: FederationId,
+    /// Connect info of the federation to restore the ecash of
+    pub connect: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -74,6 +100,26 @@ pub struct GatewayInfo {
     pub fees: RoutingFees,
 }
 
+/// First response of LNURL-pay (LUD-06), describing how to pay a Lightning
+/// Address
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayResponse {
+    pub callback: Url,
+    /// Maximum amount in millisatoshi
+    pub max_sendable: u64,
+    /// Minimum amount in millisatoshi
+    pub min_sendable: u64,
+    pub metadata: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LnurlPayCallbackParams {
+    /// Amount in millisatoshi
+    pub amount: u64,
+}
+
 #[derive(Debug)]
 pub enum GatewayRequest {
     Info(GatewayRequestInner<InfoPayload>),
@@ -84,6 +130,8 @@ pub enum GatewayRequest {
     Withdraw(GatewayRequestInner<WithdrawPayload>),
     Backup(GatewayRequestInner<BackupPayload>),
     Restore(GatewayRequestInner<RestorePayload>),
+    Liquidity(GatewayRequestInner<LiquidityPayload>),
+    SetFees(GatewayRequestInner<SetFeesPayload>),
     Shutdown,
 }
 
@@ -128,7 +176,9 @@ impl_gateway_request_trait!(
 );
 impl_gateway_request_trait!(WithdrawPayload, Txid, GatewayRequest::Withdraw);
 impl_gateway_request_trait!(BackupPayload, (), GatewayRequest::Backup);
-impl_gateway_request_trait!(RestorePayload, (), GatewayRequest::Restore);
+impl_gateway_request_trait!(RestorePayload, FederationInfo, GatewayRequest::Restore);
+impl_gateway_request_trait!(LiquidityPayload, LiquidityReport, GatewayRequest::Liquidity);
+impl_gateway_request_trait!(SetFeesPayload, FederationInfo, GatewayRequest::SetFees);
 
 impl<T> GatewayRequestInner<T>
 where
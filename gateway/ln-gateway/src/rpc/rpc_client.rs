@@ -10,9 +10,10 @@ use thiserror::Error;
 use url::Url;
 
 use super::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    WithdrawPayload,
+    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, LiquidityPayload,
+    MnemonicPayload, MnemonicResponse, RestorePayload, SetFeesPayload, WithdrawPayload,
 };
+use crate::liquidity::LiquidityReport;
 use crate::rpc::{FederationInfo, GatewayInfo};
 
 pub struct GatewayRpcClient {
@@ -76,11 +77,32 @@ impl GatewayRpcClient {
         self.call(url, payload).await
     }
 
-    pub async fn restore(&self, payload: RestorePayload) -> GatewayRpcResult<()> {
+    pub async fn get_liquidity(
+        &self,
+        payload: LiquidityPayload,
+    ) -> GatewayRpcResult<LiquidityReport> {
+        let url = self.base_url.join("/liquidity").expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn set_fees(&self, payload: SetFeesPayload) -> GatewayRpcResult<FederationInfo> {
+        let url = self.base_url.join("/set-fees").expect("invalid base url");
+        self.call(url, payload).await
+    }
+
+    pub async fn restore(&self, payload: RestorePayload) -> GatewayRpcResult<FederationInfo> {
         let url = self.base_url.join("/restore").expect("invalid base url");
         self.call(url, payload).await
     }
 
+    pub async fn get_mnemonic(
+        &self,
+        payload: MnemonicPayload,
+    ) -> GatewayRpcResult<MnemonicResponse> {
+        let url = self.base_url.join("/mnemonic").expect("invalid base url");
+        self.call(url, payload).await
+    }
+
     async fn call<P, T: DeserializeOwned>(&self, url: Url, payload: P) -> Result<T, GatewayRpcError>
     where
         P: Serialize,
@@ -1,10 +1,14 @@
 use std::net::SocketAddr;
 
+use axum::extract::{Path, Query};
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
 use axum_macros::debug_handler;
 use bitcoin_hashes::hex::ToHex;
+use fedimint_core::Amount;
+use fedimint_ln_client::bolt12::{Bolt12OfferRegistration, FetchBolt12InvoicePayload};
+use fedimint_ln_client::lnurl::SignedLnurlPayRegistration;
 use fedimint_ln_client::pay::PayInvoicePayload;
 use serde_json::json;
 use tokio::sync::oneshot;
@@ -14,7 +18,8 @@ use tracing::{error, instrument};
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, InfoPayload,
-    RestorePayload, WithdrawPayload,
+    LiquidityPayload, LnurlPayCallbackParams, MnemonicPayload, RestorePayload, SetFeesPayload,
+    WithdrawPayload,
 };
 use crate::{Gateway, GatewayError};
 
@@ -24,7 +29,14 @@ pub async fn run_webserver(
     mut gateway: Gateway,
 ) -> axum::response::Result<oneshot::Receiver<()>> {
     // Public routes on gateway webserver
-    let routes = Router::new().route("/pay_invoice", post(pay_invoice));
+    let routes = Router::new()
+        .route("/pay_invoice", post(pay_invoice))
+        .route("/lnurl/register", post(register_lnurl_pay))
+        .route("/.well-known/lnurlp/:username", get(lnurl_pay_request))
+        .route("/lnurlp/:username/callback", get(lnurl_pay_callback))
+        .route("/bolt12/fetch_invoice", post(fetch_bolt12_invoice))
+        .route("/bolt12/supported", get(bolt12_offers_supported))
+        .route("/bolt12/register", post(register_bolt12_offer));
 
     // Authenticated, public routes used for gateway administration
     let admin_routes = Router::new()
@@ -32,9 +44,12 @@ pub async fn run_webserver(
         .route("/balance", post(balance))
         .route("/address", post(address))
         .route("/withdraw", post(withdraw))
+        .route("/liquidity", post(liquidity))
+        .route("/set-fees", post(set_fees))
         .route("/connect-fed", post(connect_fed))
         .route("/backup", post(backup))
         .route("/restore", post(restore))
+        .route("/mnemonic", post(mnemonic))
         .layer(RequireAuthorizationLayer::bearer(&authkey));
 
     let app = Router::new()
@@ -97,6 +112,17 @@ async fn balance(
     Ok(Json(json!(amount)))
 }
 
+/// Display the mnemonic the gateway's ecash can be recovered with
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn mnemonic(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<MnemonicPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let mnemonic = gateway.handle_mnemonic_msg(payload).await?;
+    Ok(Json(json!(mnemonic)))
+}
+
 /// Generate deposit address
 #[debug_handler]
 #[instrument(skip_all, err)]
@@ -119,6 +145,28 @@ async fn withdraw(
     Ok(Json(json!(txid)))
 }
 
+/// Report the liquidity of the gateway and how it would be rebalanced
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn liquidity(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<LiquidityPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let report = gateway.handle_liquidity_msg(payload).await?;
+    Ok(Json(json!(report)))
+}
+
+/// Change the routing fees of a gateway federation
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn set_fees(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFeesPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let fed = gateway.handle_set_fees_msg(payload).await?;
+    Ok(Json(json!(fed)))
+}
+
 #[instrument(skip_all, err)]
 async fn pay_invoice(
     Extension(gateway): Extension<Gateway>,
@@ -128,6 +176,77 @@ async fn pay_invoice(
     Ok(Json(json!(preimage.0.to_hex())))
 }
 
+/// Register offers to be served to LNURL-pay requests for a username
+#[instrument(skip_all, err)]
+async fn register_lnurl_pay(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SignedLnurlPayRegistration>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_register_lnurl_pay_msg(payload).await?;
+    Ok(())
+}
+
+/// Resolve a Lightning Address served by this gateway
+///
+/// LNURL wallets expect errors to be reported in the response body.
+#[instrument(skip_all)]
+async fn lnurl_pay_request(
+    Extension(gateway): Extension<Gateway>,
+    Path(username): Path<String>,
+) -> impl IntoResponse {
+    match gateway.handle_lnurl_pay_request(username).await {
+        Ok(response) => Json(json!(response)),
+        Err(e) => lnurl_error(e),
+    }
+}
+
+/// Issue an invoice for a Lightning Address served by this gateway
+#[instrument(skip_all)]
+async fn lnurl_pay_callback(
+    Extension(gateway): Extension<Gateway>,
+    Path(username): Path<String>,
+    Query(params): Query<LnurlPayCallbackParams>,
+) -> impl IntoResponse {
+    match gateway
+        .handle_lnurl_pay_callback(username, Amount::from_msats(params.amount))
+        .await
+    {
+        Ok(invoice) => Json(json!({ "pr": invoice.to_string(), "routes": [] })),
+        Err(e) => lnurl_error(e),
+    }
+}
+
+fn lnurl_error(error: GatewayError) -> Json<serde_json::Value> {
+    Json(json!({ "status": "ERROR", "reason": error.to_string() }))
+}
+
+/// Fetch an invoice for a BOLT12 offer that clients can pay through us
+#[instrument(skip_all, err)]
+async fn fetch_bolt12_invoice(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<FetchBolt12InvoicePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let response = gateway.handle_fetch_bolt12_invoice_msg(payload).await?;
+    Ok(Json(json!(response)))
+}
+
+/// Whether clients can register BOLT12 offers with us
+#[instrument(skip_all)]
+async fn bolt12_offers_supported(Extension(gateway): Extension<Gateway>) -> impl IntoResponse {
+    Json(json!(gateway.answers_invoice_requests()))
+}
+
+/// Create a BOLT12 offer whose invoice requests are answered with a client's
+/// offers
+#[instrument(skip_all, err)]
+async fn register_bolt12_offer(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<Bolt12OfferRegistration>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let response = gateway.handle_register_bolt12_offer_msg(payload).await?;
+    Ok(Json(json!(response)))
+}
+
 /// Connect a new federation
 #[instrument(skip_all, err)]
 async fn connect_fed(
@@ -151,9 +270,9 @@ async fn backup(
 // Restore a gateway actor state
 #[instrument(skip_all, err)]
 async fn restore(
-    Extension(gateway): Extension<Gateway>,
+    Extension(mut gateway): Extension<Gateway>,
     Json(payload): Json<RestorePayload>,
 ) -> Result<impl IntoResponse, GatewayError> {
-    gateway.handle_restore_msg(payload).await?;
-    Ok(())
+    let fed = gateway.handle_restore_msg(payload).await?;
+    Ok(Json(json!(fed)))
 }
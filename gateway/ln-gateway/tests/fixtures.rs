@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use fedimint_bip39::Mnemonic;
 use fedimint_dummy_client::DummyClientGen;
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
@@ -12,6 +13,12 @@ use fedimint_testing::fixtures::Fixtures;
 use fedimint_testing::gateway::GatewayTest;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 
+fn gateway_fixtures() -> Fixtures {
+    let fixtures = Fixtures::new_primary(DummyClientGen, DummyGen, DummyGenParams::default());
+    let ln_params = LightningGenParams::regtest(fixtures.bitcoin_server());
+    fixtures.with_module(LightningClientGen, LightningGen, ln_params)
+}
+
 /// Constructs a gateway connected to 2 federations for RPC tests
 pub async fn fixtures() -> (
     GatewayTest,
@@ -20,9 +27,7 @@ pub async fn fixtures() -> (
     FederationTest,
     Arc<dyn BitcoinTest>,
 ) {
-    let mut fixtures = Fixtures::new_primary(DummyClientGen, DummyGen, DummyGenParams::default());
-    let ln_params = LightningGenParams::regtest(fixtures.bitcoin_server());
-    fixtures = fixtures.with_module(LightningClientGen, LightningGen, ln_params);
+    let fixtures = gateway_fixtures();
 
     let lnd = fixtures.lnd().await;
     let gateway = fixtures.new_gateway(lnd).await;
@@ -33,3 +38,13 @@ pub async fn fixtures() -> (
 
     (gateway, client, fed1, fed2, fixtures.bitcoin())
 }
+
+/// Constructs a gateway without any data whose ecash is derived from
+/// `mnemonic`
+pub async fn gateway_with_mnemonic(mnemonic: Mnemonic) -> GatewayTest {
+    let fixtures = gateway_fixtures();
+    let lnd = fixtures.lnd().await;
+    fixtures
+        .new_gateway_with_mnemonic(lnd, Some(mnemonic))
+        .await
+}
@@ -4,9 +4,17 @@
 //! and business logic.
 mod fixtures;
 
+use std::str::FromStr;
+
+use fedimint_bip39::Mnemonic;
+use fedimint_core::Amount;
 use fedimint_testing::federation::FederationTest;
+use ln_gateway::liquidity::LiquidityDecision;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
-use ln_gateway::rpc::ConnectFedPayload;
+use ln_gateway::rpc::{
+    BackupPayload, ConnectFedPayload, LiquidityPayload, MnemonicPayload, RestorePayload,
+    SetFeesPayload,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn gatewayd_supports_connecting_multiple_federations() {
@@ -65,6 +73,25 @@ async fn gatewayd_shows_balance_for_any_connected_federation() -> anyhow::Result
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn gatewayd_reports_liquidity_of_all_connected_federations() -> anyhow::Result<()> {
+    let (_, rpc, fed1, fed2, _) = fixtures::fixtures().await;
+
+    let ids = [fed1.connection_code().id, fed2.connection_code().id];
+    connect_federations(&rpc, &[fed1, fed2]).await?;
+
+    let report = rpc.get_liquidity(LiquidityPayload).await?;
+    assert_eq!(report.federations.len(), 2);
+    for federation in report.federations {
+        assert!(ids.contains(&federation.federation_id));
+        assert_eq!(federation.ecash_balance, Amount::ZERO);
+        // The gateway is started without liquidity limits
+        assert_eq!(federation.decision, LiquidityDecision::Balanced);
+    }
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn gatewayd_allows_deposit_to_any_connected_federation() -> anyhow::Result<()> {
     // todo: implement test case
@@ -79,16 +106,96 @@ async fn gatewayd_allows_withdrawal_from_any_connected_federation() -> anyhow::R
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn gatewayd_changes_fees_of_a_connected_federation_at_runtime() -> anyhow::Result<()> {
+    let (_, rpc, fed1, fed2, _) = fixtures::fixtures().await;
+
+    let id1 = fed1.connection_code().id;
+    connect_federations(&rpc, &[fed1, fed2]).await?;
+
+    let info = rpc
+        .set_fees(SetFeesPayload {
+            federation_id: id1,
+            base_msat: 1_000,
+            proportional_millionths: 2_000,
+        })
+        .await?;
+    assert_eq!(info.federation_id, id1);
+    assert_eq!(info.registration.fees.base_msat, 1_000);
+    assert_eq!(info.registration.fees.proportional_millionths, 2_000);
+
+    // Proportional fees above 100% are rejected
+    assert!(rpc
+        .set_fees(SetFeesPayload {
+            federation_id: id1,
+            base_msat: 1_000,
+            proportional_millionths: 1_000_001,
+        })
+        .await
+        .is_err());
+
+    // Only the registration with the chosen federation changes
+    for info in rpc.get_info().await?.federations {
+        let expected_base_msat = if info.federation_id == id1 { 1_000 } else { 0 };
+        assert_eq!(info.registration.fees.base_msat, expected_base_msat);
+    }
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn gatewayd_supports_backup_of_any_connected_federation() -> anyhow::Result<()> {
-    // todo: implement test case
+    let (_, rpc, fed1, fed2, _) = fixtures::fixtures().await;
+
+    for fed in [&fed1, &fed2] {
+        let connect = fed.connection_code().to_string();
+        let info = rpc
+            .connect_federation(ConnectFedPayload { connect })
+            .await?;
+        rpc.backup(BackupPayload {
+            federation_id: info.federation_id,
+        })
+        .await?;
+    }
 
     Ok(())
 }
 
 #[tokio::test(flavor = "multi_thread")]
 async fn gatewayd_supports_restore_of_any_connected_federation() -> anyhow::Result<()> {
-    // todo: implement test case
+    let (_, rpc, fed1, fed2, _) = fixtures::fixtures().await;
+
+    let mut infos = vec![];
+    for fed in [&fed1, &fed2] {
+        let connect = fed.connection_code().to_string();
+        let info = rpc
+            .connect_federation(ConnectFedPayload { connect })
+            .await?;
+        rpc.backup(BackupPayload {
+            federation_id: info.federation_id,
+        })
+        .await?;
+        infos.push(info);
+    }
+
+    // A gateway that lost its data recovers the federations from the mnemonic
+    let mnemonic = Mnemonic::from_str(&rpc.get_mnemonic(MnemonicPayload).await?.mnemonic)?;
+    let restored_gateway = fixtures::gateway_with_mnemonic(mnemonic).await;
+    let restored_rpc = restored_gateway.get_rpc().await;
+
+    for (fed, info) in [&fed1, &fed2].into_iter().zip(infos) {
+        let connect = fed.connection_code().to_string();
+        let restored_info = restored_rpc.restore(RestorePayload { connect }).await?;
+        assert_eq!(restored_info.federation_id, info.federation_id);
+        assert_eq!(
+            restored_info.registration.mint_channel_id,
+            info.registration.mint_channel_id
+        );
+        assert_eq!(
+            restored_info.registration.gateway_pub_key,
+            info.registration.gateway_pub_key
+        );
+    }
 
     Ok(())
 }
@@ -56,7 +56,7 @@ use fedimint_testing::btc::mock::FakeBitcoinFactory;
 use fedimint_testing::btc::real::RealBitcoinTest;
 use fedimint_testing::btc::BitcoinTest;
 use fedimint_wallet_client::{WalletClientGen, WalletConsensusItem};
-use fedimint_wallet_server::common::config::WalletConfig;
+use fedimint_wallet_server::common::config::{PegInDescriptorType, WalletConfig};
 use fedimint_wallet_server::common::db::UTXOKey;
 use fedimint_wallet_server::common::SpendableUTXO;
 use fedimint_wallet_server::{Wallet, WalletGen};
@@ -145,7 +145,7 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
 
     let client_module_inits = ClientModuleGenRegistry::from(vec![
         DynClientModuleGen::from(WalletClientGen::default()),
-        DynClientModuleGen::from(MintClientGen),
+        DynClientModuleGen::from(MintClientGen::default()),
         DynClientModuleGen::from(LightningClientGen),
     ]);
 
@@ -165,6 +165,8 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
                 msats(MAX_MSAT_DENOMINATION),
                 bitcoin::network::constants::Network::Regtest,
                 10,
+                None,
+                PegInDescriptorType::Wsh,
             );
             let params = gen_local(&peers, base_port, "test", module_gens_params).unwrap();
 
@@ -243,6 +245,8 @@ pub async fn fixtures(num_peers: u16) -> anyhow::Result<Fixtures> {
                 msats(MAX_MSAT_DENOMINATION),
                 bitcoin::network::constants::Network::Regtest,
                 10,
+                None,
+                PegInDescriptorType::Wsh,
             );
             let bitcoin_rpc = || factory.bitcoin.clone().into();
             let params = gen_local(&peers, base_port, "test", module_gens_params).unwrap();
@@ -357,6 +361,7 @@ pub fn gen_local(
                         federation_name.to_string(),
                     )]),
                     modules: modules.clone(),
+                    consensus_engine: Default::default(),
                 },
             };
             Ok((*peer, params))
@@ -273,6 +273,38 @@ impl ClientModule for DummyClientModule {
         }
     }
 
+    async fn preview_sufficient_input(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        if get_funds(dbtx).await < amount {
+            return Err(format_err!("Insufficient funds"));
+        }
+
+        Ok(<Self as ClientModule>::input_amount(
+            self,
+            &DummyInput {
+                amount,
+                account: self.key.x_only_public_key().0,
+            },
+        ))
+    }
+
+    async fn preview_exact_output(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        Ok(<Self as ClientModule>::output_amount(
+            self,
+            &DummyOutput {
+                amount,
+                account: self.key.x_only_public_key().0,
+            },
+        ))
+    }
+
     async fn await_primary_module_output(
         &self,
         operation_id: OperationId,
@@ -1,9 +1,21 @@
+use std::sync::Arc;
+
+use fedimint_client::module::gen::ClientModuleGenRegistry;
+use fedimint_client::multi::MultiFederationClient;
+use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::sm::DynState;
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::{ClientSecret, DynGlobalClientContext};
 use fedimint_core::config::ClientModuleConfig;
-use fedimint_core::core::ModuleKind;
+use fedimint_core::core::{DynOutput, ModuleKind};
+use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::module::ModuleConsensusVersion;
-use fedimint_core::sats;
-use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::util::NextOrPending;
+use fedimint_core::{sats, Amount};
+use fedimint_dummy_client::{DummyClientExt, DummyClientGen, DummyClientModule};
 use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_common::{DummyOutput, KIND};
 use fedimint_dummy_server::DummyGen;
 use fedimint_testing::fixtures::Fixtures;
 
@@ -27,6 +39,112 @@ async fn can_print_and_send_money() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn can_preview_transaction_without_spending() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    let (_, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+
+    let (_, instance) = client1.get_first_module::<DummyClientModule>(&KIND);
+    let output = ClientOutput {
+        output: DynOutput::from_typed(
+            instance.id,
+            DummyOutput {
+                amount: sats(250),
+                account: client2.account(),
+            },
+        ),
+        state_machines: Arc::new(|_, _| Vec::<DynState<DynGlobalClientContext>>::new()),
+    };
+    let tx = TransactionBuilder::new().with_output(output);
+
+    let preview = client1.preview_transaction(&tx).await?;
+    assert!(preview.funding_input.is_some());
+    assert!(preview.change_output.is_none());
+    assert_eq!(preview.total_input, sats(250));
+    assert_eq!(preview.total_output, sats(250));
+    assert_eq!(preview.total_fee, Amount::ZERO);
+
+    // Funding the preview must not spend anything
+    assert_eq!(client1.get_balance().await, sats(1000));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multi_federation_client_aggregates_federations() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_fed().await;
+    let fed2 = fixtures.new_fed().await;
+    let mut module_gens = ClientModuleGenRegistry::new();
+    module_gens.attach(DummyClientGen);
+
+    let multi_client = MultiFederationClient::new::<PlainRootSecretStrategy>(
+        MemDatabase::new(),
+        module_gens.clone(),
+        0,
+        TaskGroup::new(),
+    )
+    .await?;
+    let client1 = multi_client.join_federation(fed1.client_config()).await?;
+    let client2 = multi_client.join_federation(fed2.client_config()).await?;
+    assert!(multi_client
+        .join_federation(fed1.client_config())
+        .await
+        .is_err());
+    let mut operation_updates = multi_client.subscribe_operation_updates().await;
+
+    let (operation_id, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+    let update = operation_updates.next_or_pending().await;
+    assert_eq!(update.federation_id, fed1.id());
+    assert_eq!(update.operation_id, operation_id);
+
+    let (_, outpoint) = client2.print_money(sats(250)).await?;
+    client2.receive_money(outpoint).await?;
+
+    assert_eq!(multi_client.get_total_balance().await, sats(1250));
+    assert_eq!(multi_client.get_balances().await[&fed2.id()], sats(250));
+    let operations = multi_client
+        .query_operations(&Default::default(), 10, None)
+        .await;
+    assert_eq!(operations.len(), 2);
+    assert_eq!(operations[0].federation_id, fed2.id());
+
+    // Federation secrets are derived from the shared one, so restoring the shared
+    // secret restores the secrets of all federations
+    let restored = MultiFederationClient::new_with_secret(
+        MemDatabase::new(),
+        ClientSecret::<PlainRootSecretStrategy>::new(
+            multi_client
+                .root_secret_encoding::<PlainRootSecretStrategy>()
+                .await,
+        ),
+        module_gens,
+        0,
+        TaskGroup::new(),
+    )
+    .await?;
+    let restored_client1 = restored.join_federation(fed1.client_config()).await?;
+    let secret1 = client1
+        .root_secret_encoding::<PlainRootSecretStrategy>()
+        .await;
+    assert_eq!(
+        secret1,
+        restored_client1
+            .root_secret_encoding::<PlainRootSecretStrategy>()
+            .await
+    );
+    assert_ne!(
+        secret1,
+        client2
+            .root_secret_encoding::<PlainRootSecretStrategy>()
+            .await
+    );
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn can_threshold_sign_message() {
     let fed = fixtures().new_fed().await;
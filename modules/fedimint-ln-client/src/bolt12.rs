@@ -0,0 +1,98 @@
+use bitcoin_hashes::sha256;
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use fedimint_ln_common::LightningGateway;
+use lightning_invoice::Invoice;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Asks a gateway to fetch an invoice for a BOLT12 offer so the federation can
+/// pay it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FetchBolt12InvoicePayload {
+    pub federation_id: FederationId,
+    pub offer: String,
+    /// Only required if the offer doesn't specify an amount itself
+    pub amount: Option<Amount>,
+}
+
+/// A BOLT12 invoice fetched by a gateway
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FetchBolt12InvoiceResponse {
+    /// The invoice issued by the recipient of the offer
+    pub invoice: String,
+    /// BOLT11 invoice with the same payment hash and amount as `invoice`, the
+    /// gateway pays `invoice` when asked to pay an outgoing contract for it
+    pub proxy_invoice: Invoice,
+}
+
+/// Asks a gateway to create a BOLT12 offer whose invoice requests are answered
+/// with offers the client created with the federation beforehand
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Bolt12OfferRegistration {
+    pub federation_id: FederationId,
+    /// Shown to the payer as part of the offer
+    pub description: String,
+    pub min_amount: Amount,
+    /// Payment hashes of the offers, each of them can only be paid once
+    pub payment_hashes: Vec<sha256::Hash>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Bolt12OfferRegistrationResponse {
+    /// The BOLT12 offer the gateway created
+    pub offer: String,
+}
+
+pub(crate) async fn fetch_invoice_from_gateway(
+    gateway: &LightningGateway,
+    payload: &FetchBolt12InvoicePayload,
+) -> anyhow::Result<FetchBolt12InvoiceResponse> {
+    post_to_gateway(gateway, "bolt12/fetch_invoice", payload).await
+}
+
+/// Whether the gateway's lightning node lets it answer invoice requests for
+/// offers registered with it
+pub(crate) async fn gateway_supports_offers(gateway: &LightningGateway) -> anyhow::Result<bool> {
+    let response = reqwest::Client::new()
+        .get(gateway.api.join("bolt12/supported")?.as_str())
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Gateway rejected bolt12/supported request: {}",
+            response.status()
+        );
+    }
+
+    Ok(response.json().await?)
+}
+
+pub(crate) async fn register_with_gateway(
+    gateway: &LightningGateway,
+    registration: &Bolt12OfferRegistration,
+) -> anyhow::Result<Bolt12OfferRegistrationResponse> {
+    post_to_gateway(gateway, "bolt12/register", registration).await
+}
+
+async fn post_to_gateway<P: Serialize, R: DeserializeOwned>(
+    gateway: &LightningGateway,
+    route: &str,
+    payload: &P,
+) -> anyhow::Result<R> {
+    let response = reqwest::Client::new()
+        .post(gateway.api.join(route)?.as_str())
+        .json(payload)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Gateway rejected {route} request: {} {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        );
+    }
+
+    Ok(response.json().await?)
+}
@@ -1,6 +1,7 @@
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{impl_db_lookup, impl_db_record};
 use fedimint_ln_common::LightningGateway;
+use secp256k1::XOnlyPublicKey;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
@@ -8,6 +9,7 @@ use strum_macros::EnumIter;
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     LightningGateway = 0x28,
+    GatewayStats = 0x29,
 }
 
 #[derive(Debug, Encodable, Decodable, Serialize)]
@@ -25,3 +27,23 @@ impl_db_lookup!(
     key = LightningGatewayKey,
     query_prefix = LightningGatewayKeyPrefix
 );
+
+/// Outcomes of the payments past gateways attempted for us
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct GatewayStatsKey(pub XOnlyPublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayStatsKeyPrefix;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct GatewayStats {
+    pub successes: u64,
+    pub failures: u64,
+}
+
+impl_db_record!(
+    key = GatewayStatsKey,
+    value = GatewayStats,
+    db_prefix = DbKeyPrefix::GatewayStats,
+);
+impl_db_lookup!(key = GatewayStatsKey, query_prefix = GatewayStatsKeyPrefix);
@@ -0,0 +1,175 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use fedimint_core::Amount;
+use fedimint_ln_common::LightningGateway;
+use lightning::routing::gossip::RoutingFees;
+use secp256k1::XOnlyPublicKey;
+
+use crate::db::GatewayStats;
+
+/// Highest proportional fee we accept from a gateway, charging more than the
+/// paid amount itself is never reasonable
+const MAX_PROPORTIONAL_MILLIONTHS: u32 = 1_000_000;
+
+/// Fee charged by a gateway for paying an invoice of `amount`
+pub(crate) fn gateway_fee(fees: &RoutingFees, amount: Amount) -> Amount {
+    let base_fee = fees.base_msat as u64;
+    let margin_fee =
+        u128::from(amount.msats) * u128::from(fees.proportional_millionths) / 1_000_000;
+
+    Amount::from_msats(base_fee.saturating_add(margin_fee.try_into().unwrap_or(u64::MAX)))
+}
+
+impl GatewayStats {
+    /// Share of successful payments in parts per million, gateways we haven't
+    /// used yet are assumed to succeed half of the time
+    fn success_rate_ppm(&self) -> u64 {
+        (self.successes + 1) * 1_000_000 / (self.successes + self.failures + 2)
+    }
+}
+
+/// Orders the gateways that are still valid at `now` and don't charge more
+/// than the paid amount by how well they are suited to pay an invoice of
+/// `amount`
+///
+/// Gateways that reliably paid our invoices in the past come first since a
+/// failed payment locks up our funds until the gateway cancels the contract.
+/// Among equally reliable gateways the cheaper one is preferred, then the one
+/// whose announcement stays valid longer.
+pub(crate) fn rank_gateways(
+    gateways: Vec<LightningGateway>,
+    stats: &BTreeMap<XOnlyPublicKey, GatewayStats>,
+    amount: Amount,
+    now: SystemTime,
+) -> Vec<LightningGateway> {
+    let mut gateways = gateways
+        .into_iter()
+        .filter(|gateway| gateway.valid_until > now)
+        .filter(|gateway| gateway.fees.proportional_millionths <= MAX_PROPORTIONAL_MILLIONTHS)
+        .collect::<Vec<_>>();
+
+    gateways.sort_by_key(|gateway| {
+        let stats = stats
+            .get(&gateway.gateway_pub_key)
+            .copied()
+            .unwrap_or_default();
+        (
+            Reverse(stats.success_rate_ppm()),
+            gateway_fee(&gateway.fees, amount),
+            Reverse(gateway.valid_until),
+        )
+    });
+
+    gateways
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use secp256k1::{KeyPair, Secp256k1};
+    use url::Url;
+
+    use super::*;
+
+    fn gateway(
+        proportional_millionths: u32,
+        valid_for: Duration,
+        now: SystemTime,
+    ) -> LightningGateway {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        LightningGateway {
+            mint_channel_id: 1,
+            gateway_pub_key: keypair.x_only_public_key().0,
+            node_pub_key: keypair.public_key(),
+            api: Url::parse("http://127.0.0.1:8175").unwrap(),
+            route_hints: vec![],
+            valid_until: now + valid_for,
+            fees: RoutingFees {
+                base_msat: 0,
+                proportional_millionths,
+            },
+        }
+    }
+
+    fn ranked_fees(ranked: &[LightningGateway]) -> Vec<u32> {
+        ranked
+            .iter()
+            .map(|gateway| gateway.fees.proportional_millionths)
+            .collect()
+    }
+
+    #[test]
+    fn prefers_cheaper_gateways_and_drops_expired_ones() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let gateways = vec![
+            gateway(10_000, Duration::from_secs(600), now),
+            gateway(1_000, Duration::from_secs(600), now),
+            gateway(100, Duration::ZERO, now),
+        ];
+
+        let ranked = rank_gateways(gateways, &BTreeMap::new(), Amount::from_sats(1000), now);
+
+        assert_eq!(ranked_fees(&ranked), vec![1_000, 10_000]);
+    }
+
+    #[test]
+    fn prefers_reliable_gateways() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let cheap = gateway(1_000, Duration::from_secs(600), now);
+        let expensive = gateway(10_000, Duration::from_secs(600), now);
+        let stats = BTreeMap::from([(
+            cheap.gateway_pub_key,
+            GatewayStats {
+                successes: 1,
+                failures: 3,
+            },
+        )]);
+
+        let ranked = rank_gateways(vec![cheap, expensive], &stats, Amount::from_sats(1000), now);
+
+        assert_eq!(ranked_fees(&ranked), vec![10_000, 1_000]);
+    }
+
+    #[test]
+    fn gateway_fee_is_exact_and_never_overflows() {
+        let fees = |base_msat, proportional_millionths| RoutingFees {
+            base_msat,
+            proportional_millionths,
+        };
+
+        // 1_000_000 / 300_000 used to round to a fee of a third instead of 30%
+        assert_eq!(
+            gateway_fee(&fees(0, 300_000), Amount::from_msats(1_000)),
+            Amount::from_msats(300)
+        );
+        assert_eq!(
+            gateway_fee(&fees(10, 1_000), Amount::from_msats(1_999)),
+            Amount::from_msats(11)
+        );
+        assert_eq!(
+            gateway_fee(&fees(0, 2_000_000), Amount::from_msats(1_000)),
+            Amount::from_msats(2_000)
+        );
+        assert_eq!(
+            gateway_fee(&fees(u32::MAX, u32::MAX), Amount::from_msats(u64::MAX)),
+            Amount::from_msats(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn drops_gateways_charging_more_than_the_amount() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let gateways = vec![
+            gateway(1_000_001, Duration::from_secs(600), now),
+            gateway(1_000_000, Duration::from_secs(600), now),
+        ];
+
+        let ranked = rank_gateways(gateways, &BTreeMap::new(), Amount::from_sats(1000), now);
+
+        assert_eq!(ranked_fees(&ranked), vec![1_000_000]);
+    }
+}
@@ -1,7 +1,11 @@
+pub mod bolt12;
 mod db;
+mod gateways;
+pub mod lnurl;
 pub mod pay;
 pub mod receive;
 
+use std::collections::BTreeMap;
 use std::iter::once;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
@@ -9,12 +13,12 @@ use std::time::{Duration, SystemTime};
 use anyhow::{bail, ensure, format_err};
 use async_stream::stream;
 use bitcoin::{KeyPair, Network};
-use bitcoin_hashes::Hash;
-use db::LightningGatewayKey;
+use bitcoin_hashes::{sha256, Hash};
+use db::{GatewayStatsKeyPrefix, LightningGatewayKey};
 use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationAmount, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{DynState, ModuleNotifier, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -31,9 +35,8 @@ use fedimint_core::module::{
 use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint, TransactionId};
 use fedimint_ln_common::api::LnFederationApi;
 use fedimint_ln_common::config::LightningClientConfig;
-use fedimint_ln_common::contracts::incoming::IncomingContractOffer;
-use fedimint_ln_common::contracts::outgoing::{
-    OutgoingContract, OutgoingContractAccount, OutgoingContractData,
+use fedimint_ln_common::contracts::incoming::{
+    hold_settlement_message, HoldConditions, IncomingContractOffer, MAX_HOLD_TIMEOUT_BLOCKS,
 };
 use fedimint_ln_common::contracts::{
     Contract, ContractId, EncryptedPreimage, IdentifiableContract, Preimage,
@@ -43,6 +46,7 @@ use fedimint_ln_common::incoming::{
 };
 pub use fedimint_ln_common::*;
 use futures::StreamExt;
+use gateways::{gateway_fee, rank_gateways};
 use lightning::ln::PaymentSecret;
 use lightning::routing::gossip::RoutingFees;
 use lightning::routing::router::{RouteHint, RouteHintHop};
@@ -55,19 +59,28 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::{debug, error};
 
+use crate::bolt12::{
+    fetch_invoice_from_gateway, Bolt12OfferRegistration, FetchBolt12InvoicePayload,
+    FetchBolt12InvoiceResponse,
+};
+use crate::lnurl::{lightning_address, register_with_gateway, LnurlPayRegistration};
 use crate::pay::{
-    GatewayPayError, LightningPayCommon, LightningPayCreatedOutgoingLnContract,
-    LightningPayStateMachine, LightningPayStates,
+    create_outgoing_contract, GatewayPayError, LightningPayCommon,
+    LightningPayCreatedOutgoingLnContract, LightningPayStateMachine, LightningPayStateMachineV0,
+    LightningPayStates,
 };
 use crate::receive::{
     LightningReceiveError, LightningReceiveStateMachine, LightningReceiveStates,
-    LightningReceiveSubmittedOffer,
+    LightningReceiveSubmittedOffer, LightningReceiveSubmittedStaticOffer,
 };
 
 /// Number of blocks until outgoing lightning contracts times out and user
 /// client can get refund
 const OUTGOING_LN_CONTRACT_TIMELOCK: u64 = 500;
 
+/// Maximum number of gateways a single payment is attempted with
+const MAX_PAY_ATTEMPTS: usize = 3;
+
 #[apply(async_trait_maybe_send!)]
 pub trait LightningClientExt {
     /// The set active gateway, or a random one if none has been set
@@ -79,9 +92,25 @@ pub trait LightningClientExt {
     /// Gateways actively registered with the fed
     async fn fetch_registered_gateways(&self) -> anyhow::Result<Vec<LightningGateway>>;
 
+    /// Gateways to pay an invoice of `amount` with, in the order they are
+    /// tried
+    ///
+    /// The set active gateway always comes first, the remaining gateways are
+    /// ranked by their past success rate, fees and validity.
+    async fn select_pay_gateways(&self, amount: Amount) -> anyhow::Result<Vec<LightningGateway>>;
+
     /// Pays a LN invoice with our available funds
     async fn pay_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<(PayType, ContractId)>;
 
+    /// Pays a BOLT12 offer through the active gateway, which fetches the
+    /// invoice from the recipient. `amount` is only required if the offer
+    /// doesn't specify one.
+    async fn pay_bolt12_offer(
+        &self,
+        offer: String,
+        amount: Option<Amount>,
+    ) -> anyhow::Result<(PayType, ContractId)>;
+
     async fn subscribe_internal_pay(
         &self,
         operation_id: OperationId,
@@ -104,6 +133,56 @@ pub trait LightningClientExt {
         &self,
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<'_, LnReceiveState>>;
+
+    /// Receive over LN with a new hold invoice, the payment is only settled
+    /// once it was accepted with [`LightningClientExt::accept_held_payment`]
+    ///
+    /// A payment that is neither accepted nor cancelled within
+    /// `timeout_blocks` is cancelled by the federation.
+    async fn create_bolt11_hold_invoice(
+        &self,
+        amount: Amount,
+        description: String,
+        expiry_time: Option<u64>,
+        timeout_blocks: u64,
+    ) -> anyhow::Result<(OperationId, Invoice)>;
+
+    /// Accepts the held payment of a hold invoice, which is then claimed by
+    /// the receive operation
+    async fn accept_held_payment(&self, operation_id: OperationId) -> anyhow::Result<()>;
+
+    /// Cancels the held payment of a hold invoice, refunding the payer
+    async fn cancel_held_payment(&self, operation_id: OperationId) -> anyhow::Result<()>;
+
+    /// Receive over LN while offline by letting the active gateway serve
+    /// LNURL-pay requests for `username`, returns the resulting Lightning
+    /// Address.
+    ///
+    /// Creates `num_offers` offers for payments between `min_sendable` and
+    /// `max_sendable`, each of which can be paid once. Registering the same
+    /// username again adds further offers. Incoming payments are claimed in
+    /// the background.
+    async fn register_lnurl_pay(
+        &self,
+        username: String,
+        description: String,
+        min_sendable: Amount,
+        max_sendable: Amount,
+        num_offers: usize,
+    ) -> anyhow::Result<(OperationId, String)>;
+
+    /// Receive over LN while offline with a BOLT12 offer the active gateway
+    /// answers invoice requests for, returns the offer.
+    ///
+    /// Creates `num_offers` offers for payments of at least `min_amount`, each
+    /// of which can be paid once. Incoming payments are claimed in the
+    /// background.
+    async fn register_bolt12_offer(
+        &self,
+        description: String,
+        min_amount: Amount,
+        num_offers: usize,
+    ) -> anyhow::Result<(OperationId, String)>;
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -137,6 +216,13 @@ pub enum LnPayState {
         block_height: u32,
         gateway_error: GatewayPayError,
     },
+    /// The gateway cancelled the contract of the previous attempt, the payment
+    /// is retried with the next gateway
+    Retrying {
+        attempt: u32,
+        gateway_error: GatewayPayError,
+        next_gateway: XOnlyPublicKey,
+    },
     AwaitingChange,
     Success {
         preimage: String,
@@ -148,12 +234,21 @@ pub enum LnPayState {
 }
 
 /// The high-level state of a reissue operation started with
-/// [`LightningClientExt::create_bolt11_invoice`].
+/// [`LightningClientExt::create_bolt11_invoice`] or
+/// [`LightningClientExt::create_bolt11_hold_invoice`].
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum LnReceiveState {
     Created,
-    WaitingForPayment { invoice: String, timeout: Duration },
-    Canceled { reason: LightningReceiveError },
+    WaitingForPayment {
+        invoice: String,
+        timeout: Duration,
+    },
+    Canceled {
+        reason: LightningReceiveError,
+    },
+    /// The hold invoice was paid, the payment waits to be accepted or
+    /// cancelled
+    Held,
     Funded,
     AwaitingFunds,
     Claimed,
@@ -234,6 +329,33 @@ impl LightningClientExt for Client {
         Ok(instance.api.fetch_gateways().await?)
     }
 
+    async fn select_pay_gateways(&self, amount: Amount) -> anyhow::Result<Vec<LightningGateway>> {
+        let (_lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let mut dbtx = instance.db.begin_transaction().await;
+        let active_gateway = dbtx.get_value(&LightningGatewayKey).await;
+        let stats = dbtx
+            .find_by_prefix(&GatewayStatsKeyPrefix)
+            .await
+            .map(|(key, stats)| (key.0, stats))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        let mut gateways = rank_gateways(
+            self.fetch_registered_gateways().await?,
+            &stats,
+            amount,
+            fedimint_core::time::now(),
+        );
+        if let Some(active_gateway) = active_gateway {
+            gateways.retain(|gw| gw.gateway_pub_key != active_gateway.gateway_pub_key);
+            gateways.insert(0, active_gateway);
+        }
+        gateways.truncate(MAX_PAY_ATTEMPTS);
+
+        ensure!(!gateways.is_empty(), "Could not find any gateways");
+        Ok(gateways)
+    }
+
     async fn pay_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<(PayType, ContractId)> {
         let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
         let payment_hash = invoice.payment_hash();
@@ -254,13 +376,20 @@ impl LightningClientExt for Client {
                 .await?;
             (PayType::Internal(operation_id), output, contract_id)
         } else {
-            let active_gateway = self.select_active_gateway().await?;
+            let invoice_amount = Amount::from_msats(
+                invoice
+                    .amount_milli_satoshis()
+                    .ok_or(anyhow::anyhow!("MissingInvoiceAmount"))?,
+            );
+            let mut gateways = self.select_pay_gateways(invoice_amount).await?;
+            let gateway = gateways.remove(0);
             let (output, contract_id) = lightning
                 .create_outgoing_output(
                     operation_id,
                     instance.api,
                     invoice.clone(),
-                    active_gateway,
+                    gateway,
+                    gateways,
                     self.get_config().await.federation_id,
                     rand::rngs::OsRng,
                 )
@@ -268,22 +397,57 @@ impl LightningClientExt for Client {
             (PayType::Lightning(operation_id), output, contract_id)
         };
 
-        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
-        let operation_meta_gen = |txid, change_outpoint| LightningMeta::Pay {
-            out_point: OutPoint { txid, out_idx: 0 },
-            invoice: invoice.clone(),
-            change_outpoint,
-        };
+        submit_pay_transaction(self, operation_id, invoice, output).await?;
 
-        self.finalize_and_submit_transaction(
-            operation_id,
-            LightningCommonGen::KIND.as_str(),
-            operation_meta_gen,
-            tx,
+        Ok((pay_type, contract_id))
+    }
+
+    async fn pay_bolt12_offer(
+        &self,
+        offer: String,
+        amount: Option<Amount>,
+    ) -> anyhow::Result<(PayType, ContractId)> {
+        let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let federation_id = self.get_config().await.federation_id;
+
+        // Only the gateway that fetched the invoice knows which BOLT12 invoice to pay
+        // for the proxy invoice, so we can't fall back to other gateways
+        let gateway = self.select_active_gateway().await?;
+        let FetchBolt12InvoiceResponse {
+            invoice: _,
+            proxy_invoice,
+        } = fetch_invoice_from_gateway(
+            &gateway,
+            &FetchBolt12InvoicePayload {
+                federation_id,
+                offer,
+                amount,
+            },
         )
         .await?;
+        if let Some(amount) = amount {
+            ensure!(
+                proxy_invoice.amount_milli_satoshis() == Some(amount.msats),
+                "Gateway fetched an invoice for a different amount"
+            );
+        }
 
-        Ok((pay_type, contract_id))
+        let operation_id = OperationId(proxy_invoice.payment_hash().into_inner());
+        let (output, contract_id) = lightning
+            .create_outgoing_output(
+                operation_id,
+                instance.api,
+                proxy_invoice.clone(),
+                gateway,
+                vec![],
+                federation_id,
+                rand::rngs::OsRng,
+            )
+            .await?;
+
+        submit_pay_transaction(self, operation_id, proxy_invoice, output).await?;
+
+        Ok((PayType::Lightning(operation_id), contract_id))
     }
 
     async fn create_bolt11_invoice(
@@ -292,36 +456,125 @@ impl LightningClientExt for Client {
         description: String,
         expiry_time: Option<u64>,
     ) -> anyhow::Result<(OperationId, Invoice)> {
+        create_receive_invoice(self, amount, description, expiry_time, None).await
+    }
+
+    async fn create_bolt11_hold_invoice(
+        &self,
+        amount: Amount,
+        description: String,
+        expiry_time: Option<u64>,
+        timeout_blocks: u64,
+    ) -> anyhow::Result<(OperationId, Invoice)> {
+        ensure!(
+            timeout_blocks <= MAX_HOLD_TIMEOUT_BLOCKS,
+            "Hold timeout must not exceed {MAX_HOLD_TIMEOUT_BLOCKS} blocks"
+        );
+        create_receive_invoice(self, amount, description, expiry_time, Some(timeout_blocks)).await
+    }
+
+    async fn accept_held_payment(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        settle_held_payment(self, operation_id, true).await
+    }
+
+    async fn cancel_held_payment(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        settle_held_payment(self, operation_id, false).await
+    }
+
+    async fn register_lnurl_pay(
+        &self,
+        username: String,
+        description: String,
+        min_sendable: Amount,
+        max_sendable: Amount,
+        num_offers: usize,
+    ) -> anyhow::Result<(OperationId, String)> {
+        ensure!(num_offers > 0, "At least one offer has to be registered");
+        ensure!(
+            min_sendable <= max_sendable,
+            "Minimum sendable amount exceeds the maximum"
+        );
+
         let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
-        let (src_node_id, short_channel_id, route_hints) = match self.select_active_gateway().await
-        {
-            Ok(active_gateway) => (
-                active_gateway.node_pub_key,
-                active_gateway.mint_channel_id,
-                active_gateway.route_hints,
-            ),
-            Err(_) => {
-                let markers = self.get_internal_payment_markers()?;
-                (markers.0, markers.1, vec![])
-            }
-        };
+        let gateway = self.select_active_gateway().await?;
+        let lightning_address = lightning_address(&username, &gateway.api)?;
+
+        let operation_id = OperationId::new_random();
+        let mut tx = TransactionBuilder::new();
+        let mut payment_hashes = vec![];
+        for _ in 0..num_offers {
+            let (payment_hash, output) =
+                lightning.create_static_offer_output(operation_id, min_sendable, rand::rngs::OsRng);
+            tx = tx.with_output(output.into_dyn(instance.id));
+            payment_hashes.push(payment_hash);
+        }
 
-        let (operation_id, invoice, output) = lightning
-            .create_lightning_receive_output(
-                amount,
-                description,
-                rand::rngs::OsRng,
-                expiry_time,
-                src_node_id,
-                short_channel_id,
-                route_hints,
-                lightning.cfg.network,
+        let operation_meta_gen = |txid, _| LightningMeta::LnurlPay {
+            offer_txid: txid,
+            username: username.clone(),
+            payment_hashes: payment_hashes.clone(),
+        };
+        let txid = self
+            .finalize_and_submit_transaction(
+                operation_id,
+                LightningCommonGen::KIND.as_str(),
+                operation_meta_gen,
+                tx,
             )
             .await?;
-        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
-        let operation_meta_gen = |txid, _| LightningMeta::Receive {
-            out_point: OutPoint { txid, out_idx: 0 },
-            invoice: invoice.clone(),
+
+        // The gateway can only hand out invoices for offers the federation knows about
+        self.transaction_updates(operation_id)
+            .await
+            .await_tx_accepted(txid)
+            .await
+            .map_err(|e| anyhow::anyhow!("Offer transaction was not accepted: {e:?}"))?;
+
+        let registration = LnurlPayRegistration {
+            federation_id: self.get_config().await.federation_id,
+            username: username.clone(),
+            description,
+            min_sendable,
+            max_sendable,
+            payment_hashes,
+            owner_key: lightning.lnurl_key.x_only_public_key().0,
+        }
+        .sign(&lightning.secp, &lightning.lnurl_key);
+        register_with_gateway(&gateway, &registration).await?;
+
+        Ok((operation_id, lightning_address))
+    }
+
+    async fn register_bolt12_offer(
+        &self,
+        description: String,
+        min_amount: Amount,
+        num_offers: usize,
+    ) -> anyhow::Result<(OperationId, String)> {
+        ensure!(num_offers > 0, "At least one offer has to be registered");
+
+        let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let gateway = self.select_active_gateway().await?;
+        // Don't pay for offers the gateway can't serve
+        ensure!(
+            bolt12::gateway_supports_offers(&gateway).await?,
+            "Gateway can't answer invoice requests for BOLT12 offers"
+        );
+
+        let operation_id = OperationId::new_random();
+        let mut tx = TransactionBuilder::new();
+        let mut payment_hashes = vec![];
+        for _ in 0..num_offers {
+            let (payment_hash, output) =
+                lightning.create_static_offer_output(operation_id, min_amount, rand::rngs::OsRng);
+            tx = tx.with_output(output.into_dyn(instance.id));
+            payment_hashes.push(payment_hash);
+        }
+
+        let operation_meta_gen = |txid, _| LightningMeta::Bolt12Offer {
+            offer_txid: txid,
+            description: description.clone(),
+            payment_hashes: payment_hashes.clone(),
         };
         let txid = self
             .finalize_and_submit_transaction(
@@ -332,15 +585,23 @@ impl LightningClientExt for Client {
             )
             .await?;
 
-        // Wait for the transaction to be accepted by the federation, otherwise the
-        // invoice will not be able to be paid
+        // The gateway can only answer invoice requests with offers the federation
+        // knows about
         self.transaction_updates(operation_id)
             .await
             .await_tx_accepted(txid)
             .await
             .map_err(|e| anyhow::anyhow!("Offer transaction was not accepted: {e:?}"))?;
 
-        Ok((operation_id, invoice))
+        let registration = Bolt12OfferRegistration {
+            federation_id: self.get_config().await.federation_id,
+            description,
+            min_amount,
+            payment_hashes,
+        };
+        let response = bolt12::register_with_gateway(&gateway, &registration).await?;
+
+        Ok((operation_id, response.offer))
     }
 
     async fn subscribe_ln_receive(
@@ -350,8 +611,9 @@ impl LightningClientExt for Client {
         let (lightning, _instance) = self.get_first_module::<LightningClientModule>(&KIND);
 
         let operation = ln_operation(self, operation_id).await?;
-        let (out_point, invoice) = match operation.meta::<LightningMeta>() {
-            LightningMeta::Receive { out_point, invoice } => (out_point, invoice),
+        let (out_point, invoice, hold) = match operation.meta::<LightningMeta>() {
+            LightningMeta::Receive { out_point, invoice } => (out_point, invoice, false),
+            LightningMeta::HoldReceive { out_point, invoice } => (out_point, invoice, true),
             _ => bail!("Operation is not a lightning payment"),
         };
 
@@ -360,6 +622,7 @@ impl LightningClientExt for Client {
             .await
             .await_tx_accepted(out_point.txid);
 
+        let held = lightning.await_held(operation_id);
         let receive_success = lightning.await_receive_success(operation_id);
         let claim_acceptance = lightning.await_claim_acceptance(operation_id);
 
@@ -373,6 +636,16 @@ impl LightningClientExt for Client {
                 }
                             yield LnReceiveState::WaitingForPayment { invoice: invoice.to_string(), timeout: invoice.expiry_time() };
 
+                            if hold {
+                                match held.await {
+                                    Ok(()) => yield LnReceiveState::Held,
+                                    Err(e) => {
+                                        yield LnReceiveState::Canceled { reason: e };
+                                        return;
+                                    }
+                                }
+                            }
+
                             match receive_success.await {
                                 Ok(()) => {
                                     yield LnReceiveState::Funded;
@@ -416,51 +689,75 @@ impl LightningClientExt for Client {
             .transaction_updates(operation_id)
             .await
             .await_tx_accepted(out_point.txid);
-        let payment_success = lightning.await_lightning_payment_success(operation_id);
-
-        let refund_success = lightning.await_refund(operation_id);
 
         Ok(operation.outcome_or_updates(self.db(), operation_id, || {
             stream! {
-                    yield LnPayState::Created;
+                yield LnPayState::Created;
 
-                    if tx_accepted_future.await.is_err() {
+                if tx_accepted_future.await.is_err() {
                     yield LnPayState::Canceled;
-                        return;
+                    return;
                 }
-                            yield LnPayState::Funded;
-
-                match payment_success.await {
-                    Ok(preimage) => {
-                        if let Some(change) = change_outpoint {
-                            yield LnPayState::AwaitingChange;
-                            match self.await_primary_module_output(operation_id, change).await {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    yield LnPayState::Failed;
-                                    return;
+                yield LnPayState::Funded;
+
+                let mut attempt = 0;
+                let mut last_error = None;
+                loop {
+                    match lightning.await_lightning_payment_success(operation_id, attempt).await {
+                        Ok(preimage) => {
+                            if let Some(change) = change_outpoint {
+                                yield LnPayState::AwaitingChange;
+                                match self.await_primary_module_output(operation_id, change).await {
+                                    Ok(_) => {}
+                                    Err(_) => {
+                                        yield LnPayState::Failed;
+                                        return;
+                                    }
                                 }
                             }
-                        }
-
-                        yield LnPayState::Success {preimage};
-                        return;
-                    }
-                    Err(PayError::Refundable(block_height, error)) => {
-                        yield LnPayState::WaitingForRefund{ block_height, gateway_error: error.clone() };
 
-                        if let Ok(refund_txid) = refund_success.await {
-                            // need to await primary module to get refund
-                            if self.await_primary_module_output(operation_id, OutPoint{ txid: refund_txid, out_idx: 0}).await.is_ok() {
-                                yield LnPayState::Refunded { gateway_error: error };
-                                return;
+                            yield LnPayState::Success {preimage};
+                            return;
+                        }
+                        Err(PayError::Refundable(block_height, error)) => {
+                            yield LnPayState::WaitingForRefund{ block_height, gateway_error: error.clone() };
+
+                            match lightning.await_refund(operation_id, attempt).await {
+                                Ok(RefundOutcome::Retried(next_gateway)) => {
+                                    attempt += 1;
+                                    last_error = Some(error.clone());
+                                    yield LnPayState::Retrying {
+                                        attempt,
+                                        gateway_error: error,
+                                        next_gateway,
+                                    };
+                                    continue;
+                                }
+                                Ok(RefundOutcome::Refunded(refund_txid)) => {
+                                    // need to await primary module to get refund
+                                    if self.await_primary_module_output(operation_id, OutPoint{ txid: refund_txid, out_idx: 0}).await.is_ok() {
+                                        yield LnPayState::Refunded { gateway_error: error };
+                                        return;
+                                    }
+                                }
+                                Err(_) => {}
                             }
                         }
+                        Err(PayError::Refunded(refund_txid)) => {
+                            // the retry could not be funded, the previous contract was refunded instead
+                            if let Some(gateway_error) = last_error.take() {
+                                if self.await_primary_module_output(operation_id, OutPoint{ txid: refund_txid, out_idx: 0}).await.is_ok() {
+                                    yield LnPayState::Refunded { gateway_error };
+                                    return;
+                                }
+                            }
+                        }
+                        _ => {}
                     }
-                    _ => {}
-                }
 
-                yield LnPayState::Failed;
+                    yield LnPayState::Failed;
+                    return;
+                }
             }
         }))
     }
@@ -501,6 +798,157 @@ impl LightningClientExt for Client {
     }
 }
 
+/// Submits a transaction funding the contract of a payment of `invoice`
+async fn submit_pay_transaction(
+    client: &Client,
+    operation_id: OperationId,
+    invoice: Invoice,
+    output: ClientOutput<LightningOutput, LightningClientStateMachines>,
+) -> anyhow::Result<()> {
+    let (_lightning, instance) = client.get_first_module::<LightningClientModule>(&KIND);
+    let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+    let operation_meta_gen = |txid, change_outpoint| LightningMeta::Pay {
+        out_point: OutPoint { txid, out_idx: 0 },
+        invoice: invoice.clone(),
+        change_outpoint,
+    };
+
+    client
+        .finalize_and_submit_transaction(
+            operation_id,
+            LightningCommonGen::KIND.as_str(),
+            operation_meta_gen,
+            tx,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Creates an invoice paying to a new offer, which is held until accepted if
+/// `hold_timeout_blocks` is set
+async fn create_receive_invoice(
+    client: &Client,
+    amount: Amount,
+    description: String,
+    expiry_time: Option<u64>,
+    hold_timeout_blocks: Option<u64>,
+) -> anyhow::Result<(OperationId, Invoice)> {
+    let (lightning, instance) = client.get_first_module::<LightningClientModule>(&KIND);
+    let (src_node_id, short_channel_id, route_hints) = match client.select_active_gateway().await {
+        Ok(active_gateway) => (
+            active_gateway.node_pub_key,
+            active_gateway.mint_channel_id,
+            active_gateway.route_hints,
+        ),
+        Err(_) => {
+            let markers = client.get_internal_payment_markers()?;
+            (markers.0, markers.1, vec![])
+        }
+    };
+
+    let (operation_id, invoice, output) = lightning
+        .create_lightning_receive_output(
+            amount,
+            description,
+            rand::rngs::OsRng,
+            expiry_time,
+            src_node_id,
+            short_channel_id,
+            route_hints,
+            lightning.cfg.network,
+            hold_timeout_blocks,
+        )
+        .await?;
+    let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+    let operation_meta_gen = |txid, _| {
+        let out_point = OutPoint { txid, out_idx: 0 };
+        let invoice = invoice.clone();
+        if hold_timeout_blocks.is_some() {
+            LightningMeta::HoldReceive { out_point, invoice }
+        } else {
+            LightningMeta::Receive { out_point, invoice }
+        }
+    };
+    let txid = client
+        .finalize_and_submit_transaction(
+            operation_id,
+            LightningCommonGen::KIND.as_str(),
+            operation_meta_gen,
+            tx,
+        )
+        .await?;
+
+    // Wait for the transaction to be accepted by the federation, otherwise the
+    // invoice will not be able to be paid
+    client
+        .transaction_updates(operation_id)
+        .await
+        .await_tx_accepted(txid)
+        .await
+        .map_err(|e| anyhow::anyhow!("Offer transaction was not accepted: {e:?}"))?;
+
+    Ok((operation_id, invoice))
+}
+
+/// Accepts or cancels the held payment of the hold invoice created by
+/// `operation_id`
+async fn settle_held_payment(
+    client: &Client,
+    operation_id: OperationId,
+    accept: bool,
+) -> anyhow::Result<()> {
+    let (lightning, instance) = client.get_first_module::<LightningClientModule>(&KIND);
+
+    let operation = ln_operation(client, operation_id).await?;
+    let invoice = match operation.meta::<LightningMeta>() {
+        LightningMeta::HoldReceive { invoice, .. } => invoice,
+        _ => bail!("Operation is not a hold invoice"),
+    };
+
+    let contract_id: ContractId = (*invoice.payment_hash()).into();
+    let release_key = lightning.hold_release_key(invoice.payment_hash());
+    let signature = lightning.secp.sign_schnorr(
+        &hold_settlement_message(contract_id, accept).into(),
+        &release_key,
+    );
+
+    let output = ClientOutput::<LightningOutput, LightningClientStateMachines> {
+        output: LightningOutput::SettleHeld {
+            contract: contract_id,
+            accept,
+            signature,
+        },
+        // The receive operation already awaits the outcome of the held contract
+        state_machines: Arc::new(|_, _| vec![]),
+    };
+
+    let settle_operation_id = OperationId::new_random();
+    let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+    let operation_meta_gen = |txid, _| LightningMeta::SettleHeld {
+        out_point: OutPoint { txid, out_idx: 0 },
+        receive_operation_id: operation_id,
+        accept,
+    };
+    let txid = client
+        .finalize_and_submit_transaction(
+            settle_operation_id,
+            LightningCommonGen::KIND.as_str(),
+            operation_meta_gen,
+            tx,
+        )
+        .await?;
+
+    client
+        .transaction_updates(settle_operation_id)
+        .await
+        .await_tx_accepted(txid)
+        .await
+        .map_err(|e| anyhow::anyhow!("Settlement transaction was not accepted: {e:?}"))?;
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LightningMeta {
     Pay {
@@ -512,6 +960,25 @@ pub enum LightningMeta {
         out_point: OutPoint,
         invoice: Invoice,
     },
+    HoldReceive {
+        out_point: OutPoint,
+        invoice: Invoice,
+    },
+    SettleHeld {
+        out_point: OutPoint,
+        receive_operation_id: OperationId,
+        accept: bool,
+    },
+    LnurlPay {
+        offer_txid: TransactionId,
+        username: String,
+        payment_hashes: Vec<sha256::Hash>,
+    },
+    Bolt12Offer {
+        offer_txid: TransactionId,
+        description: String,
+        payment_hashes: Vec<sha256::Hash>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -540,6 +1007,8 @@ impl ClientModuleGen for LightningClientGen {
             cfg,
             notifier,
             redeem_key: module_root_secret.child_key(ChildId(0)).to_secp_key(&secp),
+            lnurl_key: module_root_secret.child_key(ChildId(1)).to_secp_key(&secp),
+            hold_secret: module_root_secret.child_key(ChildId(2)),
             secp,
             module_api,
         })
@@ -551,6 +1020,10 @@ pub struct LightningClientModule {
     pub cfg: LightningClientConfig,
     notifier: ModuleNotifier<DynGlobalClientContext, LightningClientStateMachines>,
     redeem_key: KeyPair,
+    /// Owns the usernames we registered with gateways for LNURL-pay
+    lnurl_key: KeyPair,
+    /// Derives the keys releasing the payments of our hold invoices
+    hold_secret: DerivableSecret,
     secp: Secp256k1<All>,
     module_api: DynModuleApi,
 }
@@ -588,16 +1061,42 @@ impl ClientModule for LightningClientModule {
                 amount: account_output.amount,
                 fee: self.cfg.fee_consensus.contract_output,
             },
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
-                TransactionItemAmount {
-                    amount: Amount::ZERO,
-                    fee: Amount::ZERO,
-                }
+            LightningOutput::Offer(_)
+            | LightningOutput::CancelOutgoing { .. }
+            | LightningOutput::HoldOffer { .. }
+            | LightningOutput::SettleHeld { .. } => TransactionItemAmount {
+                amount: Amount::ZERO,
+                fee: Amount::ZERO,
+            },
+        }
+    }
+
+    fn operation_amount(&self, meta: &serde_json::Value) -> Option<OperationAmount> {
+        let invoice_amount =
+            |invoice: &Invoice| invoice.amount_milli_satoshis().map(Amount::from_msats);
+
+        match serde_json::from_value::<LightningMeta>(meta.clone()).ok()? {
+            LightningMeta::Pay { invoice, .. } => {
+                invoice_amount(&invoice).map(OperationAmount::Outgoing)
             }
+            LightningMeta::Receive { invoice, .. } | LightningMeta::HoldReceive { invoice, .. } => {
+                invoice_amount(&invoice).map(OperationAmount::Incoming)
+            }
+            LightningMeta::SettleHeld { .. }
+            | LightningMeta::LnurlPay { .. }
+            | LightningMeta::Bolt12Offer { .. } => None,
         }
     }
 }
 
+/// How the contract of a payment attempt that is waiting for a refund was
+/// resolved
+enum RefundOutcome {
+    Refunded(TransactionId),
+    /// The refund funded a new contract with the contained gateway
+    Retried(XOnlyPublicKey),
+}
+
 #[derive(Error, Debug, Serialize, Deserialize)]
 pub enum PayError {
     #[error("Lightning payment was canceled")]
@@ -613,13 +1112,17 @@ pub enum PayError {
 impl LightningClientModule {
     /// Create an output that incentivizes a Lightning gateway to pay an invoice
     /// for us. It has time till the block height defined by `timelock`,
-    /// after that we can claim our money back.
+    /// after that we can claim our money back. If the gateway cancels the
+    /// contract the payment is retried with the `fallback_gateways` in order,
+    /// skipping those that charge a higher fee than `gateway`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_outgoing_output<'a, 'b>(
         &'a self,
         operation_id: OperationId,
         api: DynModuleApi,
         invoice: Invoice,
         gateway: LightningGateway,
+        fallback_gateways: Vec<LightningGateway>,
         fed_id: FederationId,
         mut rng: impl RngCore + CryptoRng + 'a,
     ) -> anyhow::Result<(
@@ -641,41 +1144,25 @@ impl LightningClientModule {
             .ok_or(format_err!("Cannot get consensus block height"))?;
         let absolute_timelock = consensus_height + OUTGOING_LN_CONTRACT_TIMELOCK;
 
-        // Compute amount to lock in the outgoing contract
-        let invoice_amount_msat = invoice
-            .amount_milli_satoshis()
-            .ok_or(anyhow::anyhow!("MissingInvoiceAmount"))?;
-
-        let fees = gateway.fees;
-        let base_fee = fees.base_msat as u64;
-        let margin_fee: u64 = if fees.proportional_millionths > 0 {
-            let fee_percent = 1000000 / fees.proportional_millionths as u64;
-            invoice_amount_msat / fee_percent
-        } else {
-            0
-        };
-
-        let contract_amount_msat = invoice_amount_msat + base_fee + margin_fee;
-        let contract_amount = Amount::from_msats(contract_amount_msat);
-
         let user_sk = bitcoin::KeyPair::new(&self.secp, &mut rng);
-
-        let contract = OutgoingContract {
-            hash: *invoice.payment_hash(),
-            gateway_key: gateway.gateway_pub_key,
-            timelock: absolute_timelock as u32,
-            user_key: user_sk.x_only_public_key().0,
-            invoice,
-            cancelled: false,
-        };
-
-        let outgoing_payment = OutgoingContractData {
-            recovery_key: user_sk,
-            contract_account: OutgoingContractAccount {
-                amount: contract_amount,
-                contract: contract.clone(),
-            },
-        };
+        let outgoing_payment =
+            create_outgoing_contract(invoice, &gateway, absolute_timelock as u32, user_sk)?;
+        let contract = outgoing_payment.contract_account.contract.clone();
+        let contract_amount = outgoing_payment.contract_account.amount;
+
+        // Retrying with another gateway must not cost the user more than the fee
+        // they agreed to when paying
+        let invoice_amount = Amount::from_msats(
+            contract
+                .invoice
+                .amount_milli_satoshis()
+                .ok_or(anyhow::anyhow!("MissingInvoiceAmount"))?,
+        );
+        let max_fee = contract_amount - invoice_amount;
+        let fallback_gateways = fallback_gateways
+            .into_iter()
+            .filter(|gw| gateway_fee(&gw.fees, invoice_amount) <= max_fee)
+            .collect::<Vec<_>>();
 
         let contract_id = contract.contract_id();
         let sm_gen = Arc::new(move |funding_txid: TransactionId, _input_idx: u64| {
@@ -685,6 +1172,9 @@ impl LightningClientModule {
                         operation_id,
                         federation_id: fed_id,
                         contract: outgoing_payment.clone(),
+                        fallback_gateways: fallback_gateways.clone(),
+                        attempt: 0,
+                        max_fee,
                     },
                     state: LightningPayStates::CreatedOutgoingLnContract(
                         LightningPayCreatedOutgoingLnContract {
@@ -775,6 +1265,38 @@ impl LightningClientModule {
         }
     }
 
+    async fn await_held(&self, operation_id: OperationId) -> Result<(), LightningReceiveError> {
+        let mut stream = self.notifier.subscribe(operation_id).await;
+        loop {
+            match stream.next().await {
+                Some(LightningClientStateMachines::Receive(state)) => match state.state {
+                    LightningReceiveStates::Held(_) => return Ok(()),
+                    LightningReceiveStates::Canceled(e) => {
+                        return Err(e);
+                    }
+                    _ => {}
+                },
+                Some(_) => {}
+                None => return Err(LightningReceiveError::UpdateStreamEnded),
+            }
+        }
+    }
+
+    /// Key accepting or cancelling the payment of our hold invoice with
+    /// `payment_hash`, derived so it doesn't have to be stored. Every 8 bytes
+    /// of the hash select one level of the derivation path, so distinct hashes
+    /// never share a key.
+    fn hold_release_key(&self, payment_hash: &sha256::Hash) -> KeyPair {
+        payment_hash
+            .into_inner()
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes long")))
+            .fold(self.hold_secret.clone(), |secret, index| {
+                secret.child_key(ChildId(index))
+            })
+            .to_secp_key(&self.secp)
+    }
+
     async fn await_claim_acceptance(
         &self,
         operation_id: OperationId,
@@ -796,40 +1318,58 @@ impl LightningClientModule {
     }
 
     // Wait for the Lightning invoice to be paid successfully or waiting for refund
+    // in the given attempt
     async fn await_lightning_payment_success(
         &self,
         operation_id: OperationId,
+        attempt: u32,
     ) -> Result<String, PayError> {
         let mut stream = self.notifier.subscribe(operation_id).await;
         loop {
-            match stream.next().await {
-                Some(LightningClientStateMachines::LightningPay(state)) => match state.state {
+            match stream
+                .next()
+                .await
+                .map(LightningClientStateMachines::into_lightning_pay)
+            {
+                Some(Some(state)) if state.common.attempt == attempt => match state.state {
                     LightningPayStates::Success(preimage) => {
                         return Ok(preimage);
                     }
-                    LightningPayStates::Refundable(refundable) => {
-                        return Err(PayError::Refundable(
-                            refundable.block_timelock,
-                            refundable.error,
-                        ));
-                    }
-                    _ => {}
-                },
+                }
                 Some(_) => {}
                 None => {}
             }
         }
     }
 
-    async fn await_refund(&self, operation_id: OperationId) -> Result<TransactionId, PayError> {
+    // Wait for the contract of the given attempt to be refunded or to be
+    // replaced by a contract with the next gateway
+    async fn await_refund(
+        &self,
+        operation_id: OperationId,
+        attempt: u32,
+    ) -> Result<RefundOutcome, PayError> {
         let mut stream = self.notifier.subscribe(operation_id).await;
         loop {
-            match stream.next().await {
-                Some(LightningClientStateMachines::LightningPay(state)) => match state.state {
-                    LightningPayStates::Refunded(refund_txid) => {
-                        return Ok(refund_txid);
+            match stream
+                .next()
+                .await
+                .map(LightningClientStateMachines::into_lightning_pay)
+            {
+                Some(Some(state)) => match state.state {
+                    LightningPayStates::Retrying(retrying)
+                        if state.common.attempt == attempt + 1 =>
+                    {
+                        return Ok(RefundOutcome::Retried(retrying.gateway.gateway_pub_key));
+                    }
+                    LightningPayStates::Refunded(refund_txid)
+                        if state.common.attempt == attempt =>
+                    {
+                        return Ok(RefundOutcome::Refunded(refund_txid));
+                    }
+                    LightningPayStates::Failure(reason) if state.common.attempt == attempt => {
+                        return Err(PayError::Failed(reason))
                     }
-                    LightningPayStates::Failure(reason) => return Err(PayError::Failed(reason)),
                     _ => {}
                 },
                 Some(_) => {}
@@ -849,6 +1389,7 @@ impl LightningClientModule {
         short_channel_id: u64,
         route_hints: Vec<fedimint_ln_common::route_hints::RouteHint>,
         network: Network,
+        hold_timeout_blocks: Option<u64>,
     ) -> anyhow::Result<(
         OperationId,
         Invoice,
@@ -917,20 +1458,26 @@ impl LightningClientModule {
         let operation_id = OperationId(invoice.payment_hash().into_inner());
 
         let sm_invoice = invoice.clone();
+        let hold = hold_timeout_blocks.is_some();
         let sm_gen = Arc::new(move |txid: TransactionId, _input_idx: u64| {
+            let submitted_offer = LightningReceiveSubmittedOffer {
+                offer_txid: txid,
+                invoice: sm_invoice.clone(),
+                payment_keypair,
+            };
             vec![LightningClientStateMachines::Receive(
                 LightningReceiveStateMachine {
                     operation_id,
-                    state: LightningReceiveStates::SubmittedOffer(LightningReceiveSubmittedOffer {
-                        offer_txid: txid,
-                        invoice: sm_invoice.clone(),
-                        payment_keypair,
-                    }),
+                    state: if hold {
+                        LightningReceiveStates::SubmittedHoldOffer(submitted_offer)
+                    } else {
+                        LightningReceiveStates::SubmittedOffer(submitted_offer)
+                    },
                 },
             )]
         });
 
-        let ln_output = LightningOutput::Offer(IncomingContractOffer {
+        let offer = IncomingContractOffer {
             amount,
             hash: payment_hash,
             encrypted_preimage: EncryptedPreimage::new(
@@ -938,7 +1485,17 @@ impl LightningClientModule {
                 &self.cfg.threshold_pub_key,
             ),
             expiry_time,
-        });
+        };
+        let ln_output = match hold_timeout_blocks {
+            Some(timeout_blocks) => LightningOutput::HoldOffer {
+                offer,
+                conditions: HoldConditions {
+                    release_key: self.hold_release_key(&payment_hash).x_only_public_key().0,
+                    timeout_blocks,
+                },
+            },
+            None => LightningOutput::Offer(offer),
+        };
 
         Ok((
             operation_id,
@@ -949,14 +1506,78 @@ impl LightningClientModule {
             },
         ))
     }
+
+    /// Create an offer that a gateway issues an invoice for later on, it
+    /// never expires and can be paid with any amount of at least `min_amount`
+    pub fn create_static_offer_output(
+        &self,
+        operation_id: OperationId,
+        min_amount: Amount,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> (
+        sha256::Hash,
+        ClientOutput<LightningOutput, LightningClientStateMachines>,
+    ) {
+        let payment_keypair = KeyPair::new(&self.secp, &mut rng);
+        let preimage: [u8; 32] = payment_keypair.x_only_public_key().0.serialize();
+        let payment_hash = sha256::Hash::hash(&preimage);
+
+        let sm_gen = Arc::new(move |txid: TransactionId, _input_idx: u64| {
+            vec![LightningClientStateMachines::Receive(
+                LightningReceiveStateMachine {
+                    operation_id,
+                    state: LightningReceiveStates::SubmittedStaticOffer(
+                        LightningReceiveSubmittedStaticOffer {
+                            offer_txid: txid,
+                            payment_hash,
+                            payment_keypair,
+                        },
+                    ),
+                },
+            )]
+        });
+
+        let ln_output = LightningOutput::Offer(IncomingContractOffer {
+            amount: min_amount,
+            hash: payment_hash,
+            encrypted_preimage: EncryptedPreimage::new(
+                Preimage(preimage),
+                &self.cfg.threshold_pub_key,
+            ),
+            expiry_time: None,
+        });
+
+        (
+            payment_hash,
+            ClientOutput {
+                output: ln_output,
+                state_machines: sm_gen,
+            },
+        )
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum LightningClientStateMachines {
     InternalPay(IncomingStateMachine),
-    LightningPay(LightningPayStateMachine),
+    /// Payments persisted before they could fall back to other gateways, kept
+    /// at its index to decode them
+    LightningPayV0(LightningPayStateMachineV0),
     Receive(LightningReceiveStateMachine),
+    LightningPay(LightningPayStateMachine),
+}
+
+impl LightningClientStateMachines {
+    /// Returns the state of a Lightning payment, upgrading it if it was
+    /// persisted as [`LightningPayStateMachineV0`]
+    fn into_lightning_pay(self) -> Option<LightningPayStateMachine> {
+        match self {
+            LightningClientStateMachines::LightningPay(state) => Some(state),
+            LightningClientStateMachines::LightningPayV0(state) => Some(state.into()),
+            _ => None,
+        }
+    }
 }
 
 impl IntoDynInstance for LightningClientStateMachines {
@@ -983,6 +1604,15 @@ impl State for LightningClientStateMachines {
                     LightningClientStateMachines::InternalPay
                 )
             }
+            LightningClientStateMachines::LightningPayV0(lightning_pay_state) => {
+                // Legacy payments transition into the current state machine
+                LightningPayStateMachine::from(lightning_pay_state.clone())
+                    .transitions(context, global_context)
+                    .map(LightningClientStateMachines::LightningPay, |sm| {
+                        sm.into_lightning_pay()
+                            .expect("Incorrectly dispatched state")
+                    })
+            }
             LightningClientStateMachines::LightningPay(lightning_pay_state) => {
                 sm_enum_variant_translation!(
                     lightning_pay_state.transitions(context, global_context),
@@ -1003,6 +1633,9 @@ impl State for LightningClientStateMachines {
             LightningClientStateMachines::InternalPay(internal_pay_state) => {
                 internal_pay_state.operation_id()
             }
+            LightningClientStateMachines::LightningPayV0(lightning_pay_state) => {
+                lightning_pay_state.common.operation_id
+            }
             LightningClientStateMachines::LightningPay(lightning_pay_state) => {
                 lightning_pay_state.operation_id()
             }
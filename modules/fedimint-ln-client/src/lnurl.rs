@@ -0,0 +1,143 @@
+use bitcoin_hashes::sha256;
+use fedimint_core::config::FederationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use fedimint_ln_common::LightningGateway;
+use secp256k1_zkp::{schnorr, KeyPair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Asks a gateway to answer LNURL-pay requests for `username` with invoices
+/// paying to offers the client created with the federation beforehand
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct LnurlPayRegistration {
+    pub federation_id: FederationId,
+    pub username: String,
+    /// Shown to the payer, the invoices commit to it via their description
+    /// hash
+    pub description: String,
+    pub min_sendable: Amount,
+    pub max_sendable: Amount,
+    /// Payment hashes of the offers, each of them can only be paid once
+    pub payment_hashes: Vec<sha256::Hash>,
+    /// Key owning `username`, registrations for a username that is already
+    /// taken have to be signed by the same key
+    pub owner_key: XOnlyPublicKey,
+}
+
+impl LnurlPayRegistration {
+    fn hash(&self) -> sha256::Hash {
+        self.consensus_hash()
+    }
+
+    pub fn sign<C: Signing>(
+        self,
+        secp: &Secp256k1<C>,
+        keypair: &KeyPair,
+    ) -> SignedLnurlPayRegistration {
+        let signature = secp.sign_schnorr(&Message::from(self.hash()), keypair);
+
+        SignedLnurlPayRegistration {
+            registration: self,
+            signature,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedLnurlPayRegistration {
+    #[serde(flatten)]
+    registration: LnurlPayRegistration,
+    pub signature: schnorr::Signature,
+}
+
+impl SignedLnurlPayRegistration {
+    pub fn verify_valid<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<&LnurlPayRegistration, secp256k1_zkp::Error> {
+        secp.verify_schnorr(
+            &self.signature,
+            &Message::from(self.registration.hash()),
+            &self.registration.owner_key,
+        )?;
+
+        Ok(&self.registration)
+    }
+}
+
+/// The Lightning Address of `username` at a gateway serving LNURL-pay under
+/// `api`. Lightning Addresses resolve to
+/// `https://<domain>/.well-known/lnurlp/<username>`, so a non-default port is
+/// kept as part of the domain while an API served under a path can't be
+/// addressed at all.
+pub fn lightning_address(username: &str, api: &Url) -> anyhow::Result<String> {
+    let host = api
+        .host_str()
+        .ok_or(anyhow::anyhow!("Gateway API has no host"))?;
+    anyhow::ensure!(
+        api.path() == "/",
+        "Lightning Addresses can't reach a gateway API served under {}",
+        api.path()
+    );
+
+    Ok(match api.port() {
+        Some(port) => format!("{username}@{host}:{port}"),
+        None => format!("{username}@{host}"),
+    })
+}
+
+pub(crate) async fn register_with_gateway(
+    gateway: &LightningGateway,
+    registration: &SignedLnurlPayRegistration,
+) -> anyhow::Result<()> {
+    let response = reqwest::Client::new()
+        .post(
+            gateway
+                .api
+                .join("lnurl/register")
+                .expect("'lnurl/register' contains no invalid characters for a URL")
+                .as_str(),
+        )
+        .json(registration)
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Gateway rejected LNURL-pay registration: {} {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::lightning_address;
+
+    #[test]
+    fn lightning_address_keeps_non_default_port() {
+        let api = Url::parse("https://gateway.example.com/").unwrap();
+        assert_eq!(
+            lightning_address("alice", &api).unwrap(),
+            "alice@gateway.example.com"
+        );
+
+        let api = Url::parse("http://127.0.0.1:8175").unwrap();
+        assert_eq!(
+            lightning_address("alice", &api).unwrap(),
+            "alice@127.0.0.1:8175"
+        );
+    }
+
+    #[test]
+    fn lightning_address_requires_api_at_root() {
+        let api = Url::parse("https://example.com/gateway/").unwrap();
+        assert!(lightning_address("alice", &api).is_err());
+    }
+}
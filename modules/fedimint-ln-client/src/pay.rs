@@ -1,25 +1,35 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::{bail, ensure, format_err};
+use bitcoin::KeyPair;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
-use fedimint_client::transaction::{ClientInput, TxSubmissionError};
+use fedimint_client::transaction::{ClientInput, ClientOutput, TxSubmissionError};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::config::FederationId;
 use fedimint_core::core::Decoder;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
-use fedimint_core::{OutPoint, TransactionId};
-use fedimint_ln_common::contracts::outgoing::OutgoingContractData;
-use fedimint_ln_common::contracts::ContractId;
-use fedimint_ln_common::{LightningGateway, LightningInput, LightningOutputOutcome};
+use fedimint_core::{Amount, OutPoint, TransactionId};
+use fedimint_ln_common::contracts::outgoing::{
+    OutgoingContract, OutgoingContractAccount, OutgoingContractData,
+};
+use fedimint_ln_common::contracts::{Contract, ContractId, IdentifiableContract};
+use fedimint_ln_common::{
+    ContractOutput, LightningGateway, LightningInput, LightningOutput, LightningOutputOutcome,
+};
 use futures::future;
+use lightning_invoice::Invoice;
+use secp256k1_zkp::Secp256k1;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::api::LnFederationApi;
-use crate::{LightningClientContext, LightningClientStateMachines};
+use crate::db::{GatewayStats, GatewayStatsKey};
+use crate::gateways::gateway_fee;
+use crate::{LightningClientContext, LightningClientStateMachines, OUTGOING_LN_CONTRACT_TIMELOCK};
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
 /// State machine that requests the lightning gateway to pay an invoice on
@@ -34,10 +44,18 @@ use crate::{LightningClientContext, LightningClientStateMachines};
 ///  Funded -- await gateway payment success  --> Success
 ///  Funded -- await gateway payment failed --> Refundable
 ///  Refundable -- gateway issued refunded --> Refund
+///  Refundable -- gateway issued refunded, fallback gateway left --> Retrying
 ///  Refundable -- transaction timeout --> Refund
+///  Retrying -- await transaction acceptance --> Funded
+///  Retrying -- await transaction rejected --> Refund
 ///  Refund -- await transaction acceptance --> Refunded
 ///  Refund -- await transaction rejected --> Failure
 /// ```
+///
+/// When retrying, the refund of the cancelled contract funds a new contract
+/// for the next fallback gateway within the same transaction. This only
+/// happens once a gateway cancelled its contract, otherwise it could still
+/// claim it with the preimage revealed by another gateway.
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum LightningPayStates {
     CreatedOutgoingLnContract(LightningPayCreatedOutgoingLnContract),
@@ -48,13 +66,22 @@ pub enum LightningPayStates {
     Refund(LightningPayRefund),
     Refunded(TransactionId),
     Failure(String),
+    Retrying(LightningPayRetrying),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct LightningPayCommon {
     pub operation_id: OperationId,
     pub federation_id: FederationId,
+    /// Contract of the current attempt
     pub contract: OutgoingContractData,
+    /// Gateways to try next if the current one cancels the contract
+    pub fallback_gateways: Vec<LightningGateway>,
+    /// Number of gateways that cancelled their contract before the current
+    /// attempt
+    pub attempt: u32,
+    /// Highest fee we pay a fallback gateway
+    pub max_fee: Amount,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -63,6 +90,39 @@ pub struct LightningPayStateMachine {
     pub state: LightningPayStates,
 }
 
+/// [`LightningPayCommon`] as persisted before payments could fall back to
+/// other gateways
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningPayCommonV0 {
+    pub operation_id: OperationId,
+    pub federation_id: FederationId,
+    pub contract: OutgoingContractData,
+}
+
+/// [`LightningPayStateMachine`] as persisted before payments could fall back
+/// to other gateways, these payments continue without fallback gateways
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningPayStateMachineV0 {
+    pub common: LightningPayCommonV0,
+    pub state: LightningPayStates,
+}
+
+impl From<LightningPayStateMachineV0> for LightningPayStateMachine {
+    fn from(state: LightningPayStateMachineV0) -> Self {
+        LightningPayStateMachine {
+            common: LightningPayCommon {
+                operation_id: state.common.operation_id,
+                federation_id: state.common.federation_id,
+                contract: state.common.contract,
+                fallback_gateways: vec![],
+                attempt: 0,
+                max_fee: Amount::ZERO,
+            },
+            state: state.state,
+        }
+    }
+}
+
 impl State for LightningPayStateMachine {
     type ModuleContext = LightningClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -93,6 +153,9 @@ impl State for LightningPayStateMachine {
             LightningPayStates::Failure(_) => {
                 vec![]
             }
+            LightningPayStates::Retrying(retrying) => {
+                retrying.transitions(context, global_context.clone())
+            }
         }
     }
 
@@ -213,9 +276,10 @@ impl LightningPayFunded {
         vec![StateTransition::new(
             // Immediately try to pay the invoice by contacting the gateway
             future::ready(()),
-            move |_dbtx, (), old_state| {
+            move |dbtx, (), old_state| {
                 Box::pin(Self::transition_outgoing_contract_execution(
                     old_state,
+                    dbtx,
                     global_context.clone(),
                     contract_id,
                     gateway.clone(),
@@ -266,12 +330,27 @@ impl LightningPayFunded {
 
     async fn transition_outgoing_contract_execution(
         old_state: LightningPayStateMachine,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         global_context: DynGlobalClientContext,
         contract_id: ContractId,
         gateway: LightningGateway,
         payload: PayInvoicePayload,
     ) -> LightningPayStateMachine {
-        match Self::gateway_pay_invoice(gateway, payload).await {
+        let result = Self::gateway_pay_invoice(gateway.clone(), payload).await;
+
+        let stats_key = GatewayStatsKey(gateway.gateway_pub_key);
+        let mut stats: GatewayStats = dbtx
+            .module_tx()
+            .get_value(&stats_key)
+            .await
+            .unwrap_or_default();
+        match result {
+            Ok(_) => stats.successes += 1,
+            Err(_) => stats.failures += 1,
+        }
+        dbtx.module_tx().insert_entry(&stats_key, &stats).await;
+
+        match result {
             Ok(preimage) => LightningPayStateMachine {
                 common: old_state.common,
                 state: LightningPayStates::Success(preimage),
@@ -326,10 +405,11 @@ impl LightningPayRefundable {
         vec![
             StateTransition::new(
                 Self::await_contract_cancellable(contract_id, global_context.clone()),
-                move |dbtx, (), old_state| {
-                    Box::pin(Self::try_refund_outgoing_contract(
+                move |dbtx, consensus_height, old_state| {
+                    Box::pin(Self::try_retry_or_refund(
                         old_state,
                         common.clone(),
+                        consensus_height,
                         dbtx,
                         global_context.clone(),
                     ))
@@ -340,7 +420,7 @@ impl LightningPayRefundable {
                 move |dbtx, (), old_state| {
                     Box::pin(Self::try_refund_outgoing_contract(
                         old_state,
-                        timeout_common.clone(),
+                        timeout_common.contract.clone(),
                         dbtx,
                         timeout_global_context.clone(),
                     ))
@@ -349,6 +429,97 @@ impl LightningPayRefundable {
         ]
     }
 
+    /// Pays the invoice via the next fallback gateway after the current one
+    /// cancelled its contract, falls back to claiming a refund if that isn't
+    /// possible
+    async fn try_retry_or_refund(
+        old_state: LightningPayStateMachine,
+        common: LightningPayCommon,
+        consensus_height: u64,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: DynGlobalClientContext,
+    ) -> LightningPayStateMachine {
+        match Self::try_retry(&common, consensus_height, dbtx, &global_context).await {
+            Ok(new_state) => new_state,
+            Err(e) => {
+                if !common.fallback_gateways.is_empty() {
+                    warn!("Could not retry payment with another gateway: {e:?}");
+                }
+                Self::try_refund_outgoing_contract(old_state, common.contract, dbtx, global_context)
+                    .await
+            }
+        }
+    }
+
+    async fn try_retry(
+        common: &LightningPayCommon,
+        consensus_height: u64,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: &DynGlobalClientContext,
+    ) -> anyhow::Result<LightningPayStateMachine> {
+        let Some((gateway, fallback_gateways)) = common.fallback_gateways.split_first() else {
+            bail!("No fallback gateway left");
+        };
+
+        let invoice = common.contract.contract_account.contract.invoice.clone();
+        ensure!(!invoice.is_expired(), "Invoice expired");
+
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(format_err!("MissingInvoiceAmount"))?,
+        );
+        let fee = gateway_fee(&gateway.fees, invoice_amount);
+        ensure!(
+            fee <= common.max_fee,
+            "Fallback gateway charges {fee}, more than the maximum fee of {}",
+            common.max_fee
+        );
+
+        let contract = create_outgoing_contract(
+            invoice,
+            gateway,
+            (consensus_height + OUTGOING_LN_CONTRACT_TIMELOCK) as u32,
+            KeyPair::new(&Secp256k1::new(), &mut rand::rngs::OsRng),
+        )?;
+        let contract_id = contract.contract_account.contract.contract_id();
+
+        let previous_contract = common.contract.clone();
+        let refund_input = ClientInput::<LightningInput, LightningClientStateMachines> {
+            input: previous_contract.contract_account.refund(),
+            keys: vec![previous_contract.recovery_key],
+            // Both the input and the output are managed by this state machine
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let contract_output = ClientOutput::<LightningOutput, LightningClientStateMachines> {
+            output: LightningOutput::Contract(ContractOutput {
+                amount: contract.contract_account.amount,
+                contract: Contract::Outgoing(contract.contract_account.contract.clone()),
+            }),
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        let (funding_txid, _) = global_context
+            .fund_output_with_input(dbtx, refund_input, contract_output)
+            .await?;
+
+        Ok(LightningPayStateMachine {
+            common: LightningPayCommon {
+                operation_id: common.operation_id,
+                federation_id: common.federation_id,
+                contract,
+                fallback_gateways: fallback_gateways.to_vec(),
+                attempt: common.attempt + 1,
+                max_fee: common.max_fee,
+            },
+            state: LightningPayStates::Retrying(LightningPayRetrying {
+                funding_txid,
+                contract_id,
+                gateway: gateway.clone(),
+                previous_contract,
+            }),
+        })
+    }
+
     /// Claims a refund for an expired or cancelled outgoing contract
     ///
     /// This can be necessary when the Lightning gateway cannot route the
@@ -356,11 +527,10 @@ impl LightningPayRefundable {
     /// of the e-cash output generated as change.
     async fn try_refund_outgoing_contract(
         old_state: LightningPayStateMachine,
-        common: LightningPayCommon,
+        contract_data: OutgoingContractData,
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         global_context: DynGlobalClientContext,
     ) -> LightningPayStateMachine {
-        let contract_data = common.contract;
         let (refund_key, refund_input) = (
             contract_data.recovery_key,
             contract_data.contract_account.refund(),
@@ -382,10 +552,12 @@ impl LightningPayRefundable {
         }
     }
 
+    /// Waits for the gateway to cancel the contract, returns the consensus
+    /// block height afterwards to set the timelock of a retry
     async fn await_contract_cancellable(
         contract_id: ContractId,
         global_context: DynGlobalClientContext,
-    ) {
+    ) -> u64 {
         // TODO: Remove polling
         loop {
             let contract = global_context
@@ -394,12 +566,24 @@ impl LightningPayRefundable {
                 .await;
             if let Ok(contract) = contract {
                 if contract.contract.cancelled {
-                    return;
+                    break;
                 }
             }
 
             sleep(Duration::from_secs(5)).await;
         }
+
+        loop {
+            if let Ok(Some(consensus_height)) = global_context
+                .module_api()
+                .fetch_consensus_block_height()
+                .await
+            {
+                return consensus_height;
+            }
+
+            sleep(Duration::from_secs(5)).await;
+        }
     }
 
     async fn await_contract_timeout(global_context: DynGlobalClientContext, timelock: u32) {
@@ -422,6 +606,76 @@ impl LightningPayRefundable {
     }
 }
 
+/// A fallback gateway was chosen after the previous one cancelled its contract
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningPayRetrying {
+    pub funding_txid: TransactionId,
+    pub contract_id: ContractId,
+    pub gateway: LightningGateway,
+    /// The cancelled contract refunded by the funding transaction
+    previous_contract: OutgoingContractData,
+}
+
+impl LightningPayRetrying {
+    fn transitions(
+        &self,
+        context: &LightningClientContext,
+        global_context: DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningPayStateMachine>> {
+        let contract_id = self.contract_id;
+        let gateway = self.gateway.clone();
+        let previous_contract = self.previous_contract.clone();
+        vec![StateTransition::new(
+            LightningPayCreatedOutgoingLnContract::await_outgoing_contract_funded(
+                context.ln_decoder.clone(),
+                global_context.clone(),
+                self.funding_txid,
+            ),
+            move |dbtx, result, old_state| {
+                Box::pin(Self::transition_outgoing_contract_funded(
+                    result,
+                    old_state,
+                    dbtx,
+                    global_context.clone(),
+                    contract_id,
+                    gateway.clone(),
+                    previous_contract.clone(),
+                ))
+            },
+        )]
+    }
+
+    async fn transition_outgoing_contract_funded(
+        result: Result<(), GatewayPayError>,
+        old_state: LightningPayStateMachine,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: DynGlobalClientContext,
+        contract_id: ContractId,
+        gateway: LightningGateway,
+        previous_contract: OutgoingContractData,
+    ) -> LightningPayStateMachine {
+        match result {
+            Ok(_) => {
+                let payload = PayInvoicePayload::new(old_state.common.federation_id, contract_id);
+                LightningPayStateMachine {
+                    common: old_state.common,
+                    state: LightningPayStates::Funded(LightningPayFunded { payload, gateway }),
+                }
+            }
+            Err(_) => {
+                // The rejected transaction also contained the refund of the previous contract
+                LightningPayRefundable::try_refund_outgoing_contract(
+                    old_state,
+                    previous_contract,
+                    dbtx,
+                    global_context,
+                )
+                .await
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct LightningPayRefund {
     refund_txid: TransactionId,
@@ -496,3 +750,35 @@ impl PayInvoicePayload {
         }
     }
 }
+
+/// Creates an outgoing contract that incentivizes `gateway` to pay `invoice`,
+/// after the block height `timelock` the funds can be refunded to
+/// `recovery_key`
+pub(crate) fn create_outgoing_contract(
+    invoice: Invoice,
+    gateway: &LightningGateway,
+    timelock: u32,
+    recovery_key: KeyPair,
+) -> anyhow::Result<OutgoingContractData> {
+    let invoice_amount = Amount::from_msats(
+        invoice
+            .amount_milli_satoshis()
+            .ok_or(anyhow::anyhow!("MissingInvoiceAmount"))?,
+    );
+    let contract_amount = invoice_amount + gateway_fee(&gateway.fees, invoice_amount);
+
+    Ok(OutgoingContractData {
+        recovery_key,
+        contract_account: OutgoingContractAccount {
+            amount: contract_amount,
+            contract: OutgoingContract {
+                hash: *invoice.payment_hash(),
+                gateway_key: gateway.gateway_pub_key,
+                timelock,
+                user_key: recovery_key.x_only_public_key().0,
+                invoice,
+                cancelled: false,
+            },
+        },
+    })
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use bitcoin::util::key::KeyPair;
+use bitcoin_hashes::sha256;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, TxSubmissionError};
 use fedimint_client::DynGlobalClientContext;
@@ -10,7 +11,7 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
 use fedimint_core::{OutPoint, TransactionId};
 use fedimint_ln_common::contracts::incoming::IncomingContractAccount;
-use fedimint_ln_common::contracts::DecryptedPreimage;
+use fedimint_ln_common::contracts::{ContractId, DecryptedPreimage};
 use fedimint_ln_common::LightningInput;
 use lightning_invoice::Invoice;
 use serde::{Deserialize, Serialize};
@@ -32,7 +33,20 @@ use crate::{LightningClientContext, LightningClientStateMachines};
 ///     ConfirmedInvoice -- await offer timeout --> Canceled
 ///     Funded -- await claim tx acceptance --> Success
 ///     Funded -- await claim tx rejection --> Canceled
+///     SubmittedStaticOffer -- await transaction rejection --> Canceled
+///     SubmittedStaticOffer -- await offer confirmation --> ConfirmedStaticOffer
+///     ConfirmedStaticOffer -- await contract creation + decryption --> Funded
+///     SubmittedHoldOffer -- await transaction rejection --> Canceled
+///     SubmittedHoldOffer -- await invoice confirmation --> ConfirmedHoldInvoice
+///     ConfirmedHoldInvoice -- await contract creation --> Held
+///     ConfirmedHoldInvoice -- await offer timeout --> Canceled
+///     Held -- await acceptance + decryption --> Funded
+///     Held -- await cancellation --> Canceled
 /// ```
+///
+/// Static offers are created for invoices that a gateway issues on our behalf
+/// later on, so they don't time out. Contracts funded for hold invoices are
+/// only decrypted once the payment was accepted.
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum LightningReceiveStates {
     SubmittedOffer(LightningReceiveSubmittedOffer),
@@ -40,6 +54,11 @@ pub enum LightningReceiveStates {
     ConfirmedInvoice(LightningReceiveConfirmedInvoice),
     Funded(LightningReceiveFunded),
     Success(TransactionId),
+    SubmittedStaticOffer(LightningReceiveSubmittedStaticOffer),
+    ConfirmedStaticOffer(LightningReceiveConfirmedStaticOffer),
+    SubmittedHoldOffer(LightningReceiveSubmittedOffer),
+    ConfirmedHoldInvoice(LightningReceiveConfirmedInvoice),
+    Held(LightningReceiveHeld),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -73,6 +92,19 @@ impl State for LightningReceiveStateMachine {
             LightningReceiveStates::Success(_) => {
                 vec![]
             }
+            LightningReceiveStates::SubmittedStaticOffer(submitted_offer) => {
+                submitted_offer.transitions(self.operation_id, global_context)
+            }
+            LightningReceiveStates::ConfirmedStaticOffer(confirmed_offer) => {
+                confirmed_offer.transitions(global_context)
+            }
+            LightningReceiveStates::SubmittedHoldOffer(submitted_offer) => {
+                submitted_offer.hold_transitions(self.operation_id, global_context)
+            }
+            LightningReceiveStates::ConfirmedHoldInvoice(confirmed_invoice) => {
+                confirmed_invoice.hold_transitions(global_context)
+            }
+            LightningReceiveStates::Held(held) => held.transitions(global_context),
         }
     }
 
@@ -98,6 +130,10 @@ pub enum LightningReceiveError {
     ClaimRejected,
     #[error("The decrypted preimage was invalid")]
     InvalidPreimage,
+    #[error("The held payment was cancelled")]
+    HoldCanceled,
+    #[error("The state machine stopped sending updates")]
+    UpdateStreamEnded,
 }
 
 impl LightningReceiveSubmittedOffer {
@@ -123,6 +159,35 @@ impl LightningReceiveSubmittedOffer {
         )]
     }
 
+    fn hold_transitions(
+        &self,
+        operation_id: OperationId,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningReceiveStateMachine>> {
+        let invoice = self.invoice.clone();
+        let keypair = self.payment_keypair;
+        vec![StateTransition::new(
+            Self::await_invoice_confirmation(global_context.clone(), operation_id, self.offer_txid),
+            move |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => LightningReceiveStates::ConfirmedHoldInvoice(
+                        LightningReceiveConfirmedInvoice {
+                            invoice: invoice.clone(),
+                            keypair,
+                        },
+                    ),
+                    Err(_) => LightningReceiveStates::Canceled(LightningReceiveError::Rejected),
+                };
+                Box::pin(async move {
+                    LightningReceiveStateMachine {
+                        operation_id: old_state.operation_id,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+
     async fn await_invoice_confirmation(
         global_context: DynGlobalClientContext,
         operation_id: OperationId,
@@ -170,7 +235,10 @@ impl LightningReceiveConfirmedInvoice {
         let global_context = global_context.clone();
         vec![
             StateTransition::new(
-                Self::await_incoming_contract_account(invoice, global_context.clone()),
+                Self::await_incoming_contract_account(
+                    (*invoice.payment_hash()).into(),
+                    global_context.clone(),
+                ),
                 move |dbtx, contract, old_state| {
                     Box::pin(Self::transition_funded(
                         old_state,
@@ -188,13 +256,55 @@ impl LightningReceiveConfirmedInvoice {
         ]
     }
 
+    fn hold_transitions(
+        &self,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningReceiveStateMachine>> {
+        let contract_id = (*self.invoice.payment_hash()).into();
+        let keypair = self.keypair;
+        vec![
+            StateTransition::new(
+                Self::await_contract_created(contract_id, global_context.clone()),
+                move |_dbtx, (), old_state| {
+                    Box::pin(async move {
+                        LightningReceiveStateMachine {
+                            operation_id: old_state.operation_id,
+                            state: LightningReceiveStates::Held(LightningReceiveHeld {
+                                contract_id,
+                                keypair,
+                            }),
+                        }
+                    })
+                },
+            ),
+            StateTransition::new(
+                Self::await_payment_timeout(self.invoice.expiry_time()),
+                |_dbtx, (), old_state| Box::pin(Self::transition_timeout(old_state)),
+            ),
+        ]
+    }
+
+    async fn await_contract_created(
+        contract_id: ContractId,
+        global_context: DynGlobalClientContext,
+    ) {
+        // TODO: Get rid of polling
+        while global_context
+            .module_api()
+            .get_incoming_contract(contract_id)
+            .await
+            .is_err()
+        {
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
     async fn await_incoming_contract_account(
-        invoice: Invoice,
+        contract_id: ContractId,
         global_context: DynGlobalClientContext,
     ) -> Result<IncomingContractAccount, LightningReceiveError> {
         // TODO: Get rid of polling
         loop {
-            let contract_id = (*invoice.payment_hash()).into();
             let contract = global_context
                 .module_api()
                 .get_incoming_contract(contract_id)
@@ -276,6 +386,114 @@ impl LightningReceiveConfirmedInvoice {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningReceiveSubmittedStaticOffer {
+    pub offer_txid: TransactionId,
+    pub payment_hash: sha256::Hash,
+    pub payment_keypair: KeyPair,
+}
+
+impl LightningReceiveSubmittedStaticOffer {
+    fn transitions(
+        &self,
+        operation_id: OperationId,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningReceiveStateMachine>> {
+        let payment_hash = self.payment_hash;
+        let keypair = self.payment_keypair;
+        vec![StateTransition::new(
+            LightningReceiveSubmittedOffer::await_invoice_confirmation(
+                global_context.clone(),
+                operation_id,
+                self.offer_txid,
+            ),
+            move |_dbtx, result, old_state| {
+                let state = match result {
+                    Ok(()) => LightningReceiveStates::ConfirmedStaticOffer(
+                        LightningReceiveConfirmedStaticOffer {
+                            payment_hash,
+                            keypair,
+                        },
+                    ),
+                    Err(_) => LightningReceiveStates::Canceled(LightningReceiveError::Rejected),
+                };
+                Box::pin(async move {
+                    LightningReceiveStateMachine {
+                        operation_id: old_state.operation_id,
+                        state,
+                    }
+                })
+            },
+        )]
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningReceiveConfirmedStaticOffer {
+    payment_hash: sha256::Hash,
+    keypair: KeyPair,
+}
+
+impl LightningReceiveConfirmedStaticOffer {
+    fn transitions(
+        &self,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningReceiveStateMachine>> {
+        let keypair = self.keypair;
+        let global_context = global_context.clone();
+        vec![StateTransition::new(
+            LightningReceiveConfirmedInvoice::await_incoming_contract_account(
+                self.payment_hash.into(),
+                global_context.clone(),
+            ),
+            move |dbtx, contract, old_state| {
+                Box::pin(LightningReceiveConfirmedInvoice::transition_funded(
+                    old_state,
+                    keypair,
+                    contract,
+                    dbtx,
+                    global_context.clone(),
+                ))
+            },
+        )]
+    }
+}
+
+/// The contract for a hold invoice was funded, its preimage is only decrypted
+/// once we accept the payment
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct LightningReceiveHeld {
+    contract_id: ContractId,
+    keypair: KeyPair,
+}
+
+impl LightningReceiveHeld {
+    fn transitions(
+        &self,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningReceiveStateMachine>> {
+        let keypair = self.keypair;
+        let global_context = global_context.clone();
+        vec![StateTransition::new(
+            LightningReceiveConfirmedInvoice::await_incoming_contract_account(
+                self.contract_id,
+                global_context.clone(),
+            ),
+            move |dbtx, contract, old_state| {
+                // Cancelled contracts look like ones with an invalid preimage
+                let contract = contract.map_err(|_| LightningReceiveError::HoldCanceled);
+                Box::pin(LightningReceiveConfirmedInvoice::transition_funded(
+                    old_state,
+                    keypair,
+                    contract,
+                    dbtx,
+                    global_context.clone(),
+                ))
+            },
+        )]
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct LightningReceiveFunded {
     outpoint: OutPoint,
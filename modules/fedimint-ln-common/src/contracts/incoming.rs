@@ -10,6 +10,13 @@ use serde::{Deserialize, Serialize};
 use crate::contracts::{ContractId, DecryptedPreimage, EncryptedPreimage, IdentifiableContract};
 use crate::LightningInput;
 
+const HOLD_SETTLEMENT_TAG: &str = "held incoming contract settlement";
+
+/// Maximum number of blocks an incoming contract may be held, this has to stay
+/// well below the CLTV delta of the gateway's route hint so the gateway can
+/// still fail the HTLC if the payment is cancelled on timeout
+pub const MAX_HOLD_TIMEOUT_BLOCKS: u64 = 12;
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct IncomingContractOffer {
     /// Amount for which the user is willing to sell the preimage
@@ -25,6 +32,40 @@ impl IncomingContractOffer {
     }
 }
 
+/// Turns an offer into a hold offer: once a contract is funded for it the
+/// preimage is only decrypted after the offer creator accepted the payment
+///
+/// Until then the contract is held and can either be accepted or cancelled
+/// with a signature of `release_key`, see [`hold_settlement_message`]. If
+/// neither happens within `timeout_blocks` the federation cancels the
+/// contract. A cancelled contract is treated like one with an invalid
+/// preimage, allowing the gateway to claim back its funds.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct HoldConditions {
+    pub release_key: secp256k1::XOnlyPublicKey,
+    pub timeout_blocks: u64,
+}
+
+/// An incoming contract funded for a hold offer that is waiting to be accepted
+/// or cancelled
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct HeldIncomingContract {
+    pub release_key: secp256k1::XOnlyPublicKey,
+    /// Consensus block height at which the contract is cancelled
+    pub timeout: u64,
+}
+
+/// Message that has to be signed by the release key of a held contract to
+/// accept (`accept = true`) or cancel the payment
+pub fn hold_settlement_message(contract_id: ContractId, accept: bool) -> Sha256 {
+    let mut engine = Sha256::engine();
+    Encodable::consensus_encode(&HOLD_SETTLEMENT_TAG.as_bytes(), &mut engine)
+        .expect("Hashing never fails");
+    Encodable::consensus_encode(&contract_id, &mut engine).expect("Hashing never fails");
+    Encodable::consensus_encode(&accept, &mut engine).expect("Hashing never fails");
+    Sha256::from_engine(engine)
+}
+
 // FIXME: the protocol currently envisions the use of a pub key as preimage.
 // This is bad for privacy though since pub keys are distinguishable from
 // randomness and the payer would learn the recipient is using a federated mint.
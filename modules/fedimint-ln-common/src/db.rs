@@ -4,7 +4,7 @@ use secp256k1::PublicKey;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-use crate::contracts::incoming::IncomingContractOffer;
+use crate::contracts::incoming::{HeldIncomingContract, HoldConditions, IncomingContractOffer};
 use crate::contracts::{ContractId, PreimageDecryptionShare};
 use crate::{ContractAccount, LightningGateway, LightningOutputOutcome};
 
@@ -18,6 +18,8 @@ pub enum DbKeyPrefix {
     ContractUpdate = 0x44,
     LightningGateway = 0x45,
     BlockHeightVote = 0x46,
+    HoldOffer = 0x47,
+    HeldContract = 0x48,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -142,3 +144,31 @@ impl_db_lookup!(
     key = BlockHeightVoteKey,
     query_prefix = BlockHeightVotePrefix
 );
+
+/// Hold conditions of offers that were created as hold offers
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct HoldOfferKey(pub bitcoin_hashes::sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct HoldOfferKeyPrefix;
+
+impl_db_record!(
+    key = HoldOfferKey,
+    value = HoldConditions,
+    db_prefix = DbKeyPrefix::HoldOffer,
+);
+impl_db_lookup!(key = HoldOfferKey, query_prefix = HoldOfferKeyPrefix);
+
+/// Funded incoming contracts waiting to be accepted or cancelled
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct HeldContractKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct HeldContractKeyPrefix;
+
+impl_db_record!(
+    key = HeldContractKey,
+    value = HeldIncomingContract,
+    db_prefix = DbKeyPrefix::HeldContract,
+);
+impl_db_lookup!(key = HeldContractKey, query_prefix = HeldContractKeyPrefix);
@@ -43,7 +43,9 @@ use crate::contracts::{
 use crate::incoming::IncomingSmError;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("ln");
-const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+// Version 1 adds hold offers that are settled or cancelled by consensus and
+// rejects duplicate offers
+const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(1);
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct LightningInput {
@@ -69,10 +71,13 @@ impl std::fmt::Display for LightningInput {
 
 /// Represents an output of the Lightning module.
 ///
-/// There are three sub-types:
+/// There are five sub-types:
 ///   * Normal contracts users may lock funds in
 ///   * Offers to buy preimages (see `contracts::incoming` docs)
 ///   * Early cancellation of outgoing contracts before their timeout
+///   * Offers to buy preimages that are only released once the offer creator
+///     accepts the payment
+///   * Accepting or cancelling a held incoming contract
 ///
 /// The offer type exists to register `IncomingContractOffer`s. Instead of
 /// patching in a second way of letting clients submit consensus items outside
@@ -92,6 +97,21 @@ pub enum LightningOutput {
         /// Signature of gateway
         gateway_signature: secp256k1::schnorr::Signature,
     },
+    /// Create incoming contract offer whose contract is held until the offer
+    /// creator decides on it
+    HoldOffer {
+        offer: contracts::incoming::IncomingContractOffer,
+        conditions: contracts::incoming::HoldConditions,
+    },
+    /// Accept or cancel a held incoming contract
+    SettleHeld {
+        /// Contract to settle
+        contract: ContractId,
+        /// Release the preimage if `true`, cancel the contract otherwise
+        accept: bool,
+        /// Signature of the hold offer's release key
+        signature: secp256k1::schnorr::Signature,
+    },
 }
 
 impl std::fmt::Display for LightningOutput {
@@ -119,6 +139,22 @@ impl std::fmt::Display for LightningOutput {
             LightningOutput::CancelOutgoing { contract, .. } => {
                 write!(f, "LN outgoing contract cancellation {contract}")
             }
+            LightningOutput::HoldOffer { offer, .. } => {
+                write!(
+                    f,
+                    "LN hold offer for {} with hash {}",
+                    offer.amount, offer.hash
+                )
+            }
+            LightningOutput::SettleHeld {
+                contract, accept, ..
+            } => {
+                if *accept {
+                    write!(f, "LN held contract acceptance {contract}")
+                } else {
+                    write!(f, "LN held contract cancellation {contract}")
+                }
+            }
         }
     }
 }
@@ -147,6 +183,10 @@ pub enum LightningOutputOutcome {
     CancelOutgoingContract {
         id: ContractId,
     },
+    SettleHeldContract {
+        id: ContractId,
+        accepted: bool,
+    },
 }
 
 impl LightningOutputOutcome {
@@ -155,6 +195,7 @@ impl LightningOutputOutcome {
             LightningOutputOutcome::Contract { id: _, outcome } => outcome.is_permanent(),
             LightningOutputOutcome::Offer { .. } => true,
             LightningOutputOutcome::CancelOutgoingContract { .. } => true,
+            LightningOutputOutcome::SettleHeldContract { .. } => true,
         }
     }
 }
@@ -171,6 +212,9 @@ impl std::fmt::Display for LightningOutputOutcome {
             LightningOutputOutcome::CancelOutgoingContract { id: contract_id } => {
                 write!(f, "LN Outgoing Contract Cancellation {contract_id}")
             }
+            LightningOutputOutcome::SettleHeldContract { id, accepted } => {
+                write!(f, "LN Held Contract Settlement {id} (accepted: {accepted})")
+            }
         }
     }
 }
@@ -369,6 +413,14 @@ pub enum LightningError {
     NotOutgoingContract,
     #[error("Cancellation request wasn't properly signed")]
     InvalidCancellationSignature,
+    #[error("Hold timeout of {0} blocks exceeds the maximum of {1} blocks")]
+    HoldTimeoutTooLong(u64, u64),
+    #[error("The contract {0} is not held")]
+    NotHeldContract(ContractId),
+    #[error("Settlement of held contract wasn't properly signed")]
+    InvalidSettlementSignature,
+    #[error("An offer for payment hash {0} already exists")]
+    DuplicateOffer(secp256k1::hashes::sha256::Hash),
 }
 
 pub async fn ln_operation(
@@ -407,6 +459,9 @@ async fn fetch_and_validate_offer(
     Ok(offer)
 }
 
+/// Creates an output funding the incoming contract of the offer for
+/// `payment_hash` with the offer's amount, anything paid above it is kept by
+/// whoever funds the contract
 pub async fn create_incoming_contract_output(
     module_api: &DynModuleApi,
     payment_hash: sha256::Hash,
@@ -414,6 +469,29 @@ pub async fn create_incoming_contract_output(
     redeem_key: secp256k1::KeyPair,
 ) -> Result<(LightningOutput, ContractId), IncomingSmError> {
     let offer = fetch_and_validate_offer(module_api, payment_hash, amount_msat).await?;
+    let amount = offer.amount;
+    Ok(incoming_contract_output(offer, amount, redeem_key))
+}
+
+/// Creates an output funding the incoming contract of the offer for
+/// `payment_hash` with the paid `amount_msat`. Offers only define the minimum
+/// amount, offers served via LNURL-pay are paid with whatever amount the payer
+/// chose.
+pub async fn create_paid_incoming_contract_output(
+    module_api: &DynModuleApi,
+    payment_hash: sha256::Hash,
+    amount_msat: Amount,
+    redeem_key: secp256k1::KeyPair,
+) -> Result<(LightningOutput, ContractId), IncomingSmError> {
+    let offer = fetch_and_validate_offer(module_api, payment_hash, amount_msat).await?;
+    Ok(incoming_contract_output(offer, amount_msat, redeem_key))
+}
+
+fn incoming_contract_output(
+    offer: IncomingContractOffer,
+    amount: Amount,
+    redeem_key: secp256k1::KeyPair,
+) -> (LightningOutput, ContractId) {
     let our_pub_key = secp256k1::XOnlyPublicKey::from_keypair(&redeem_key).0;
     let contract = IncomingContract {
         hash: offer.hash,
@@ -423,9 +501,9 @@ pub async fn create_incoming_contract_output(
     };
     let contract_id = contract.contract_id();
     let incoming_output = LightningOutput::Contract(ContractOutput {
-        amount: offer.amount,
+        amount,
         contract: Contract::Incoming(contract),
     });
 
-    Ok((incoming_output, contract_id))
+    (incoming_output, contract_id)
 }
@@ -27,7 +27,10 @@ use fedimint_ln_common::config::{
     FeeConsensus, LightningClientConfig, LightningConfig, LightningConfigConsensus,
     LightningConfigLocal, LightningConfigPrivate, LightningGenParams,
 };
-use fedimint_ln_common::contracts::incoming::IncomingContractOffer;
+use fedimint_ln_common::contracts::incoming::{
+    hold_settlement_message, HeldIncomingContract, HoldConditions, IncomingContractOffer,
+    MAX_HOLD_TIMEOUT_BLOCKS,
+};
 use fedimint_ln_common::contracts::{
     Contract, ContractId, ContractOutcome, DecryptedPreimage, EncryptedPreimage, FundedContract,
     IdentifiableContract, Preimage, PreimageDecryptionShare,
@@ -35,9 +38,10 @@ use fedimint_ln_common::contracts::{
 use fedimint_ln_common::db::{
     AgreedDecryptionShareContractIdPrefix, AgreedDecryptionShareKey,
     AgreedDecryptionShareKeyPrefix, BlockHeightVoteKey, BlockHeightVotePrefix, ContractKey,
-    ContractKeyPrefix, ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix,
-    LightningGatewayKey, LightningGatewayKeyPrefix, OfferKey, OfferKeyPrefix,
-    ProposeDecryptionShareKey, ProposeDecryptionShareKeyPrefix,
+    ContractKeyPrefix, ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix, HeldContractKey,
+    HeldContractKeyPrefix, HoldOfferKey, HoldOfferKeyPrefix, LightningGatewayKey,
+    LightningGatewayKeyPrefix, OfferKey, OfferKeyPrefix, ProposeDecryptionShareKey,
+    ProposeDecryptionShareKeyPrefix,
 };
 use fedimint_ln_common::{
     ContractAccount, LightningCommonGen, LightningConsensusItem, LightningError, LightningGateway,
@@ -63,7 +67,7 @@ impl ServerModuleGen for LightningGen {
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+        &[ModuleConsensusVersion(1)]
     }
 
     async fn init(
@@ -251,6 +255,26 @@ impl ServerModuleGen for LightningGen {
                         "Block Height Votes"
                     );
                 }
+                DbKeyPrefix::HoldOffer => {
+                    push_db_pair_items!(
+                        dbtx,
+                        HoldOfferKeyPrefix,
+                        HoldOfferKey,
+                        HoldConditions,
+                        lightning,
+                        "Hold Offers"
+                    );
+                }
+                DbKeyPrefix::HeldContract => {
+                    push_db_pair_items!(
+                        dbtx,
+                        HeldContractKeyPrefix,
+                        HeldContractKey,
+                        HeldIncomingContract,
+                        lightning,
+                        "Held Contracts"
+                    );
+                }
             }
         }
 
@@ -352,10 +376,8 @@ impl ServerModule for Lightning {
                         }
                     };
 
-                    let (contract, out_point) = match account.contract {
-                        FundedContract::Incoming(contract) => {
-                            (contract.contract, contract.out_point)
-                        }
+                    let contract = match account.contract {
+                        FundedContract::Incoming(contract) => contract.contract,
                         FundedContract::Outgoing(..) => {
                             warn!("Received decryption share for outgoing contract");
                             continue;
@@ -436,35 +458,7 @@ impl ServerModule for Lightning {
 
                     debug!(?decrypted_preimage);
 
-                    // TODO: maybe define update helper fn
-                    // Update contract
-                    let contract_db_key = ContractKey(contract_id);
-                    let mut contract_account = dbtx
-                        .get_value(&contract_db_key)
-                        .await
-                        .expect("checked before that it exists");
-                    let mut incoming = match &mut contract_account.contract {
-                        FundedContract::Incoming(incoming) => incoming,
-                        _ => unreachable!("previously checked that it's an incoming contrac"),
-                    };
-                    incoming.contract.decrypted_preimage = decrypted_preimage.clone();
-                    trace!(?contract_account, "Updating contract account");
-                    dbtx.insert_entry(&contract_db_key, &contract_account).await;
-
-                    // Update output outcome
-                    let mut outcome = dbtx
-                        .get_value(&ContractUpdateKey(out_point))
-                        .await
-                        .expect("outcome was created on funding");
-                    let incoming_contract_outcome_preimage = match &mut outcome {
-                        LightningOutputOutcome::Contract {
-                            outcome: ContractOutcome::Incoming(decryption_outcome),
-                            ..
-                        } => decryption_outcome,
-                        _ => panic!("We are expeccting an incoming contract"),
-                    };
-                    *incoming_contract_outcome_preimage = decrypted_preimage.clone();
-                    dbtx.insert_entry(&ContractUpdateKey(out_point), &outcome)
+                    self.update_decrypted_preimage(dbtx, contract_id, decrypted_preimage)
                         .await;
                 }
                 LightningConsensusItem::BlockHeight(block_height) => {
@@ -618,12 +612,60 @@ impl ServerModule for Lightning {
                 }
             }
             LightningOutput::Offer(offer) => {
+                // Replacing a hold offer would leave its hold conditions behind
+                if dbtx.get_value(&HoldOfferKey(offer.hash)).await.is_some() {
+                    return Err(LightningError::DuplicateOffer(offer.hash))
+                        .into_module_error_other();
+                }
+
                 if !offer.encrypted_preimage.0.verify() {
                     Err(LightningError::InvalidEncryptedPreimage).into_module_error_other()
                 } else {
                     Ok(TransactionItemAmount::ZERO)
                 }
             }
+            LightningOutput::HoldOffer { offer, conditions } => {
+                if conditions.timeout_blocks > MAX_HOLD_TIMEOUT_BLOCKS {
+                    return Err(LightningError::HoldTimeoutTooLong(
+                        conditions.timeout_blocks,
+                        MAX_HOLD_TIMEOUT_BLOCKS,
+                    ))
+                    .into_module_error_other();
+                }
+
+                if dbtx.get_value(&OfferKey(offer.hash)).await.is_some() {
+                    return Err(LightningError::DuplicateOffer(offer.hash))
+                        .into_module_error_other();
+                }
+
+                if !offer.encrypted_preimage.0.verify() {
+                    Err(LightningError::InvalidEncryptedPreimage).into_module_error_other()
+                } else {
+                    Ok(TransactionItemAmount::ZERO)
+                }
+            }
+            LightningOutput::SettleHeld {
+                contract,
+                accept,
+                signature,
+            } => {
+                let held_contract = dbtx
+                    .get_value(&HeldContractKey(*contract))
+                    .await
+                    .ok_or(LightningError::NotHeldContract(*contract))
+                    .into_module_error_other()?;
+
+                secp256k1::global::SECP256K1
+                    .verify_schnorr(
+                        signature,
+                        &hold_settlement_message(*contract, *accept).into(),
+                        &held_contract.release_key,
+                    )
+                    .map_err(|_| LightningError::InvalidSettlementSignature)
+                    .into_module_error_other()?;
+
+                Ok(TransactionItemAmount::ZERO)
+            }
             LightningOutput::CancelOutgoing {
                 contract,
                 gateway_signature,
@@ -695,17 +737,29 @@ impl ServerModule for Lightning {
                         .await
                         .expect("offer exists if output is valid");
 
-                    let decryption_share = self
-                        .cfg
-                        .private
-                        .threshold_sec_key
-                        .decrypt_share(&incoming.encrypted_preimage.0)
-                        .expect("We checked for decryption share validity on contract creation");
-                    dbtx.insert_new_entry(
-                        &ProposeDecryptionShareKey(contract.contract.contract_id()),
-                        &PreimageDecryptionShare(decryption_share),
-                    )
-                    .await;
+                    // Contracts for hold offers are only decrypted once accepted
+                    match dbtx.remove_entry(&HoldOfferKey(offer.hash)).await {
+                        Some(conditions) => {
+                            let timeout =
+                                self.consensus_block_height(dbtx).await + conditions.timeout_blocks;
+                            dbtx.insert_new_entry(
+                                &HeldContractKey(contract.contract.contract_id()),
+                                &HeldIncomingContract {
+                                    release_key: conditions.release_key,
+                                    timeout,
+                                },
+                            )
+                            .await;
+                        }
+                        None => {
+                            self.propose_decryption_share(
+                                dbtx,
+                                contract.contract.contract_id(),
+                                &incoming.encrypted_preimage,
+                            )
+                            .await;
+                        }
+                    }
                     dbtx.remove_entry(&OfferKey(offer.hash)).await;
                 }
             }
@@ -719,6 +773,51 @@ impl ServerModule for Lightning {
                 dbtx.insert_new_entry(&OfferKey(offer.hash), &(*offer).clone())
                     .await;
             }
+            LightningOutput::HoldOffer { offer, conditions } => {
+                dbtx.insert_new_entry(
+                    &ContractUpdateKey(out_point),
+                    &LightningOutputOutcome::Offer { id: offer.id() },
+                )
+                .await;
+                dbtx.insert_new_entry(&OfferKey(offer.hash), offer).await;
+                dbtx.insert_new_entry(&HoldOfferKey(offer.hash), conditions)
+                    .await;
+            }
+            LightningOutput::SettleHeld {
+                contract, accept, ..
+            } => {
+                dbtx.remove_entry(&HeldContractKey(*contract))
+                    .await
+                    .expect("Contract is held if output is valid");
+
+                if *accept {
+                    let encrypted_preimage = match self
+                        .get_contract_account(dbtx, *contract)
+                        .await
+                        .expect("Held contracts exist")
+                        .contract
+                    {
+                        FundedContract::Incoming(incoming) => incoming.contract.encrypted_preimage,
+                        FundedContract::Outgoing(_) => {
+                            panic!("Only incoming contracts are held")
+                        }
+                    };
+                    self.propose_decryption_share(dbtx, *contract, &encrypted_preimage)
+                        .await;
+                } else {
+                    self.update_decrypted_preimage(dbtx, *contract, DecryptedPreimage::Invalid)
+                        .await;
+                }
+
+                dbtx.insert_new_entry(
+                    &ContractUpdateKey(out_point),
+                    &LightningOutputOutcome::SettleHeldContract {
+                        id: *contract,
+                        accepted: *accept,
+                    },
+                )
+                .await;
+            }
             LightningOutput::CancelOutgoing { contract, .. } => {
                 let updated_contract_account = {
                     let mut contract_account = dbtx
@@ -756,8 +855,26 @@ impl ServerModule for Lightning {
     async fn end_consensus_epoch<'a, 'b>(
         &'a self,
         _consensus_peers: &BTreeSet<PeerId>,
-        _dbtx: &mut ModuleDatabaseTransaction<'b>,
+        dbtx: &mut ModuleDatabaseTransaction<'b>,
     ) -> Vec<PeerId> {
+        // Cancel held contracts that were neither accepted nor cancelled in time
+        let consensus_height = self.consensus_block_height(dbtx).await;
+        let timed_out_contracts = dbtx
+            .find_by_prefix(&HeldContractKeyPrefix)
+            .await
+            .filter_map(|(HeldContractKey(contract_id), held)| async move {
+                (held.timeout <= consensus_height).then_some(contract_id)
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        for contract_id in timed_out_contracts {
+            debug!(%contract_id, "Cancelling held contract after timeout");
+            dbtx.remove_entry(&HeldContractKey(contract_id)).await;
+            self.update_decrypted_preimage(dbtx, contract_id, DecryptedPreimage::Invalid)
+                .await;
+        }
+
         vec![]
     }
 
@@ -866,6 +983,66 @@ impl Lightning {
         heights[peer_count / 2]
     }
 
+    /// Stores our decryption share for the preimage of a funded incoming
+    /// contract, starting the decryption process
+    async fn propose_decryption_share(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        contract_id: ContractId,
+        encrypted_preimage: &EncryptedPreimage,
+    ) {
+        let decryption_share = self
+            .cfg
+            .private
+            .threshold_sec_key
+            .decrypt_share(&encrypted_preimage.0)
+            .expect("We checked for decryption share validity on contract creation");
+        dbtx.insert_new_entry(
+            &ProposeDecryptionShareKey(contract_id),
+            &PreimageDecryptionShare(decryption_share),
+        )
+        .await;
+    }
+
+    /// Updates the decryption status of an incoming contract and the outcome
+    /// of the output that funded it
+    async fn update_decrypted_preimage(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        contract_id: ContractId,
+        decrypted_preimage: DecryptedPreimage,
+    ) {
+        let contract_db_key = ContractKey(contract_id);
+        let mut contract_account = dbtx
+            .get_value(&contract_db_key)
+            .await
+            .expect("checked before that it exists");
+        let incoming = match &mut contract_account.contract {
+            FundedContract::Incoming(incoming) => incoming,
+            _ => unreachable!("previously checked that it's an incoming contrac"),
+        };
+        incoming.contract.decrypted_preimage = decrypted_preimage.clone();
+        let out_point = incoming.out_point;
+        trace!(?contract_account, "Updating contract account");
+        dbtx.insert_entry(&contract_db_key, &contract_account).await;
+
+        // Update output outcome
+        let mut outcome = dbtx
+            .get_value(&ContractUpdateKey(out_point))
+            .await
+            .expect("outcome was created on funding");
+        let incoming_contract_outcome_preimage = match &mut outcome {
+            LightningOutputOutcome::Contract {
+                outcome: ContractOutcome::Incoming(decryption_outcome),
+                ..
+            } => decryption_outcome,
+            _ => panic!("We are expeccting an incoming contract"),
+        };
+        *incoming_contract_outcome_preimage = decrypted_preimage;
+        dbtx.insert_entry(&ContractUpdateKey(out_point), &outcome)
+            .await;
+    }
+
     fn validate_decryption_share(
         &self,
         peer: PeerId,
@@ -1211,7 +1388,9 @@ mod fedimint_migration_tests {
                             "validate_migrations was not able to read any ProposeDecryptionShares"
                         );
                         }
-                        DbKeyPrefix::BlockHeightVote => {}
+                        DbKeyPrefix::BlockHeightVote
+                        | DbKeyPrefix::HoldOffer
+                        | DbKeyPrefix::HeldContract => {}
                     }
                 }
             },
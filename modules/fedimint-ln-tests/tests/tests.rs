@@ -6,6 +6,7 @@ use fedimint_core::util::NextOrPending;
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
+use fedimint_ln_client::receive::LightningReceiveError;
 use fedimint_ln_client::{
     InternalPayState, LightningClientExt, LightningClientGen, LnReceiveState, PayType,
 };
@@ -112,6 +113,72 @@ async fn makes_internal_payments_within_federation() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn settles_hold_invoices_only_when_accepted() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    // Print money for client2
+    let (op, outpoint) = client2.print_money(sats(1000)).await?;
+    client2.await_primary_module_output(op, outpoint).await?;
+
+    // TEST accepted hold payment is claimed by the receiver
+    let (receive_op, invoice) = client1
+        .create_bolt11_hold_invoice(sats(250), "accepted".to_string(), None, 6)
+        .await?;
+    let mut sub1 = client1
+        .subscribe_ln_receive(receive_op)
+        .await?
+        .into_stream();
+    assert_eq!(sub1.ok().await?, LnReceiveState::Created);
+    assert_matches!(sub1.ok().await?, LnReceiveState::WaitingForPayment { .. });
+
+    let (pay_type, _) = client2.pay_bolt11_invoice(invoice).await?;
+    let PayType::Internal(pay_op) = pay_type else {
+        panic!("Expected internal payment!");
+    };
+    let mut sub2 = client2.subscribe_internal_pay(pay_op).await?.into_stream();
+    assert_eq!(sub2.ok().await?, InternalPayState::Funding);
+    assert_eq!(sub1.ok().await?, LnReceiveState::Held);
+
+    client1.accept_held_payment(receive_op).await?;
+    assert_matches!(sub2.ok().await?, InternalPayState::Preimage { .. });
+    assert_eq!(sub1.ok().await?, LnReceiveState::Funded);
+    assert_eq!(sub1.ok().await?, LnReceiveState::AwaitingFunds);
+    assert_eq!(sub1.ok().await?, LnReceiveState::Claimed);
+
+    // TEST cancelled hold payment is refunded to the payer
+    let (receive_op, invoice) = client1
+        .create_bolt11_hold_invoice(sats(250), "cancelled".to_string(), None, 6)
+        .await?;
+    let mut sub1 = client1
+        .subscribe_ln_receive(receive_op)
+        .await?
+        .into_stream();
+    assert_eq!(sub1.ok().await?, LnReceiveState::Created);
+    assert_matches!(sub1.ok().await?, LnReceiveState::WaitingForPayment { .. });
+
+    let (pay_type, _) = client2.pay_bolt11_invoice(invoice).await?;
+    let PayType::Internal(pay_op) = pay_type else {
+        panic!("Expected internal payment!");
+    };
+    let mut sub2 = client2.subscribe_internal_pay(pay_op).await?.into_stream();
+    assert_eq!(sub2.ok().await?, InternalPayState::Funding);
+    assert_eq!(sub1.ok().await?, LnReceiveState::Held);
+
+    client1.cancel_held_payment(receive_op).await?;
+    assert_matches!(sub2.ok().await?, InternalPayState::RefundSuccess(_));
+    assert_eq!(
+        sub1.ok().await?,
+        LnReceiveState::Canceled {
+            reason: LightningReceiveError::HoldCanceled
+        }
+    );
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn rejects_wrong_network_invoice() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -0,0 +1,26 @@
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send};
+use fedimint_mint_common::Nonce;
+
+#[apply(async_trait_maybe_send!)]
+pub trait MintFederationApi {
+    /// Returns for every nonce whether the note with that nonce was already
+    /// spent, in the order the nonces were passed in
+    async fn fetch_note_spent_status(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<bool>>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> MintFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn fetch_note_spent_status(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<bool>> {
+        self.request_current_consensus(
+            "note_spent_status".to_string(),
+            ApiRequestErased::new(nonces),
+        )
+        .await
+    }
+}
@@ -1,3 +1,5 @@
+/// Client side of the mint module's API endpoints
+pub mod api;
 // Backup and restore logic
 pub(crate) mod backup;
 /// Database keys used throughout the mint client module
@@ -6,12 +8,20 @@ mod db;
 mod input;
 /// State machines for out-of-band transmitted e-cash notes
 mod oob;
+/// Versioned string encoding of out-of-band transmitted e-cash notes
+mod oob_notes;
 /// State machines for mint outputs
 mod output;
+/// Background reissuance of notes to keep a target denomination distribution
+mod rebalance;
+/// Strategies for selecting the e-cash notes to spend
+mod select;
 
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi;
 use std::fmt::Formatter;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -21,7 +31,7 @@ use backup::recovery::{MintRestoreStateMachine, MintRestoreStates};
 use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine as BitcoinHashEngine};
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::oplog::{OperationAmount, OperationLogEntry, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{
     Context, DynState, Executor, ModuleNotifier, OperationId, State, StateTransition,
@@ -34,11 +44,11 @@ use fedimint_core::db::{
     AutocommitError, Database, DatabaseTransaction, ModuleDatabaseTransaction,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
     ApiVersion, CommonModuleGen, ExtendsCommonModuleGen, ModuleCommon, MultiApiVersion,
     TransactionItemAmount,
 };
+use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::util::{BoxStream, NextOrPending};
 use fedimint_core::{
     apply, async_trait_maybe_send, Amount, OutPoint, Tiered, TieredMulti, TieredSummary,
@@ -55,6 +65,7 @@ use tbs::AggregatePublicKey;
 use thiserror::Error;
 use tracing::{debug, info, warn};
 
+use crate::api::MintFederationApi;
 use crate::backup::recovery::MintRestoreInProgressState;
 use crate::backup::EcashBackup;
 use crate::db::{NextECashNoteIndexKey, NoteKey, NoteKeyPrefix};
@@ -62,10 +73,17 @@ use crate::input::{
     MintInputCommon, MintInputStateCreated, MintInputStateMachine, MintInputStates,
 };
 use crate::oob::{MintOOBStateMachine, MintOOBStates, MintOOBStatesCreated};
+pub use crate::oob_notes::OOBNotes;
 use crate::output::{
     MintOutputCommon, MintOutputStateMachine, MintOutputStates, MintOutputStatesCreated,
     MultiNoteIssuanceRequest, NoteIssuanceRequest,
 };
+use crate::rebalance::{plan_rebalance, RebalancePlan};
+pub use crate::rebalance::{DenominationTarget, RebalanceConfig};
+pub use crate::select::{
+    GreedyNoteSelector, MinimizeChangeNoteSelector, NoteSelector, PrivacyNoteSelector,
+    TargetDistributionNoteSelector,
+};
 
 const MINT_E_CASH_TYPE_CHILD_ID: ChildId = ChildId(0);
 
@@ -78,9 +96,12 @@ pub trait MintClientExt {
     /// Try to reissue e-cash notes received from a third party to receive them
     /// in our wallet. The progress and outcome can be observed using
     /// [`MintClientExt::subscribe_reissue_external_notes`].
+    ///
+    /// Fails if the notes were issued by a different federation. Notes in the
+    /// legacy format don't carry a federation id and are assumed to be ours.
     async fn reissue_external_notes<M: Serialize + Send>(
         &self,
-        notes: TieredMulti<SpendableNote>,
+        oob_notes: OOBNotes,
         extra_meta: M,
     ) -> anyhow::Result<OperationId>;
 
@@ -91,6 +112,14 @@ pub trait MintClientExt {
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<'_, ReissueExternalNotesState>>;
 
+    /// Checks that e-cash notes received from a third party were issued by our
+    /// federation and haven't been spent yet without reissuing them. Returns
+    /// the total amount of the notes if they are valid.
+    ///
+    /// Notes can still be double spent until they are reissued, so this check
+    /// only shortens the time a recipient has to trust the sender.
+    async fn validate_notes(&self, oob_notes: &OOBNotes) -> anyhow::Result<Amount>;
+
     /// Fetches and removes notes of *at least* amount `min_amount` from the
     /// wallet to be sent to the recipient out of band. These spends can be
     /// canceled by calling [`MintClientExt::try_cancel_spend_notes`] as long as
@@ -102,12 +131,15 @@ pub trait MintClientExt {
     /// should be chosen such that the recipient (who is potentially offline at
     /// the time of receiving the e-cash notes) had a reasonable timeframe to
     /// come online and reissue the notes themselves.
+    ///
+    /// The returned [`OOBNotes`] identify our federation, a memo or connect
+    /// info for the recipient can be attached before passing them on.
     async fn spend_notes<M: Serialize + Send>(
         &self,
         min_amount: Amount,
         try_cancel_after: Duration,
         extra_meta: M,
-    ) -> anyhow::Result<(OperationId, TieredMulti<SpendableNote>)>;
+    ) -> anyhow::Result<(OperationId, OOBNotes)>;
 
     /// Try to cancel a spend operation started with
     /// [`MintClientExt::spend_notes`]. If the e-cash notes have already been
@@ -124,6 +156,12 @@ pub trait MintClientExt {
 
     /// Awaits the backup restoration to complete
     async fn await_restore_finished(&self) -> anyhow::Result<()>;
+
+    /// Reissues notes to ourselves if the held denominations drifted from the
+    /// target configured with [`MintClientGen::with_rebalancing`]. Returns the
+    /// operation id of the reissuance or `None` if no rebalancing was
+    /// necessary.
+    async fn rebalance_denominations(&self) -> anyhow::Result<Option<OperationId>>;
 }
 
 /// The high-level state of a reissue operation started with
@@ -171,11 +209,18 @@ pub enum SpendOOBState {
 impl MintClientExt for Client {
     async fn reissue_external_notes<M: Serialize + Send>(
         &self,
-        notes: TieredMulti<SpendableNote>,
+        oob_notes: OOBNotes,
         extra_meta: M,
     ) -> anyhow::Result<OperationId> {
         let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
 
+        if let Some(federation_id) = oob_notes.federation_id() {
+            if federation_id != self.federation_id() {
+                bail!("Notes were issued by federation {federation_id}, not by ours");
+            }
+        }
+
+        let notes = oob_notes.into_notes();
         let operation_id = OperationId(
             notes
                 .consensus_hash::<sha256t::Hash<OOBReissueTag>>()
@@ -261,13 +306,26 @@ impl MintClientExt for Client {
         ))
     }
 
+    async fn validate_notes(&self, oob_notes: &OOBNotes) -> anyhow::Result<Amount> {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+
+        if let Some(federation_id) = oob_notes.federation_id() {
+            if federation_id != self.federation_id() {
+                bail!("Notes were issued by federation {federation_id}, not by ours");
+            }
+        }
+
+        mint.validate_notes(oob_notes.notes()).await
+    }
+
     async fn spend_notes<M: Serialize + Send>(
         &self,
         min_amount: Amount,
         try_cancel_after: Duration,
         extra_meta: M,
-    ) -> anyhow::Result<(OperationId, TieredMulti<SpendableNote>)> {
+    ) -> anyhow::Result<(OperationId, OOBNotes)> {
         let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        let federation_id = self.federation_id();
         let extra_meta = serde_json::to_value(extra_meta)
             .expect("MintClientExt::spend_notes extra_meta is serializable");
 
@@ -305,7 +363,7 @@ impl MintClientExt for Client {
                             )
                             .await;
 
-                        Ok((operation_id, notes))
+                        Ok((operation_id, OOBNotes::new(federation_id, notes)))
                     })
                 },
                 Some(100),
@@ -377,6 +435,84 @@ impl MintClientExt for Client {
         let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
         mint.await_restore_finished().await
     }
+
+    async fn rebalance_denominations(&self) -> anyhow::Result<Option<OperationId>> {
+        let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        let Some(config) = mint.rebalance.as_ref() else {
+            bail!("Denomination rebalancing is not configured");
+        };
+
+        let operation_id = OperationId::new_random();
+
+        self.db()
+            .autocommit(
+                |dbtx| {
+                    Box::pin(async move {
+                        let held = mint
+                            .get_wallet_summary(&mut dbtx.with_module_prefix(instance.id))
+                            .await;
+                        let Some(plan) = plan_rebalance(
+                            &held,
+                            config,
+                            &mint.cfg.tbs_pks,
+                            &mint.cfg.fee_consensus,
+                            mint.cfg.max_notes_per_denomination,
+                        ) else {
+                            return Ok(None);
+                        };
+
+                        let (input, output) = mint
+                            .create_rebalance_transaction(
+                                &mut dbtx.with_module_prefix(instance.id),
+                                operation_id,
+                                &plan,
+                            )
+                            .await?;
+
+                        let tx = TransactionBuilder::new()
+                            .with_input(input.into_dyn(instance.id))
+                            .with_output(output.into_dyn(instance.id));
+
+                        let (txid, _change) = self
+                            .finalize_and_submit_transaction_inner(dbtx, operation_id, tx)
+                            .await?;
+
+                        self.operation_log()
+                            .add_operation_log_entry(
+                                dbtx,
+                                operation_id,
+                                MintCommonGen::KIND.as_str(),
+                                MintMeta {
+                                    variant: MintMetaVariants::Rebalance {
+                                        out_point: OutPoint { txid, out_idx: 0 },
+                                    },
+                                    amount: plan.issue.total_amount(),
+                                    extra_meta: serde_json::Value::Null,
+                                },
+                            )
+                            .await;
+
+                        debug!(
+                            target: LOG_TARGET,
+                            %operation_id,
+                            spent = ?plan.spend,
+                            issued = ?plan.issue,
+                            "Rebalancing denominations"
+                        );
+
+                        Ok(Some(operation_id))
+                    })
+                },
+                Some(100),
+            )
+            .await
+            .map_err(|e| match e {
+                AutocommitError::ClosureError { error, .. } => error,
+                AutocommitError::CommitFailed { last_error, .. } => {
+                    anyhow!("Commit to DB failed: {last_error}")
+                }
+            })
+    }
 }
 
 async fn mint_operation(
@@ -407,10 +543,42 @@ pub struct MintMeta {
 enum MintMetaVariants {
     Reissuance { out_point: OutPoint },
     SpendOOB { requested_amount: Amount },
+    Rebalance { out_point: OutPoint },
 }
 
 #[derive(Debug, Clone)]
-pub struct MintClientGen;
+pub struct MintClientGen {
+    /// Decides which notes are spent, see [`NoteSelector`]
+    note_selector: Arc<dyn NoteSelector>,
+    /// Target denomination distribution maintained in the background, see
+    /// [`MintClientGen::with_rebalancing`]
+    rebalance: Option<RebalanceConfig>,
+}
+
+impl MintClientGen {
+    /// Configures the mint client to select the notes it spends with
+    /// `note_selector`
+    pub fn new(note_selector: Arc<dyn NoteSelector>) -> Self {
+        Self {
+            note_selector,
+            rebalance: None,
+        }
+    }
+
+    /// Enables reissuing notes to keep the denomination distribution
+    /// described by `config`. The module checks the held denominations
+    /// periodically once the executor of the built client was started.
+    pub fn with_rebalancing(mut self, config: RebalanceConfig) -> Self {
+        self.rebalance = Some(config);
+        self
+    }
+}
+
+impl Default for MintClientGen {
+    fn default() -> Self {
+        Self::new(Arc::new(GreedyNoteSelector))
+    }
+}
 
 impl ExtendsCommonModuleGen for MintClientGen {
     type Common = MintCommonGen;
@@ -428,15 +596,18 @@ impl ClientModuleGen for MintClientGen {
         module_root_secret: DerivableSecret,
         notifier: ModuleNotifier<DynGlobalClientContext, <Self::Module as ClientModule>::States>,
         _api: DynGlobalApi,
-        _module_api: DynModuleApi,
+        module_api: DynModuleApi,
     ) -> anyhow::Result<Self::Module> {
         let (cancel_oob_payment_bc, _) = tokio::sync::broadcast::channel(16);
         Ok(MintClientModule {
             cfg,
+            module_api,
             secret: module_root_secret,
             secp: Secp256k1::new(),
             notifier,
             cancel_oob_payment_bc,
+            note_selector: self.note_selector.clone(),
+            rebalance: self.rebalance.clone(),
         })
     }
 }
@@ -444,10 +615,13 @@ impl ClientModuleGen for MintClientGen {
 #[derive(Debug)]
 pub struct MintClientModule {
     cfg: MintClientConfig,
+    module_api: DynModuleApi,
     secret: DerivableSecret,
     secp: Secp256k1<All>,
     notifier: ModuleNotifier<DynGlobalClientContext, MintClientStateMachines>,
     cancel_oob_payment_bc: tokio::sync::broadcast::Sender<OperationId>,
+    note_selector: Arc<dyn NoteSelector>,
+    rebalance: Option<RebalanceConfig>,
 }
 
 // TODO: wrap in Arc
@@ -487,11 +661,46 @@ impl ClientModule for MintClientModule {
             .expect("no version conficts")
     }
 
+    async fn start(&self, client: &Client, task_group: &mut TaskGroup) {
+        let Some(interval) = self.rebalance.as_ref().map(|config| config.interval) else {
+            return;
+        };
+
+        let client = client.clone();
+        task_group
+            .spawn("mint denomination rebalancing", move |handle| async move {
+                let mut pending_rebalance = None;
+
+                while !handle.is_shutting_down() {
+                    // wait for the previous rebalancing to finish, otherwise we would plan with
+                    // an outdated view of our notes
+                    let is_pending = match pending_rebalance {
+                        Some(operation_id) => {
+                            client.get_active_operations().await.contains(&operation_id)
+                        }
+                        None => false,
+                    };
+
+                    if !is_pending {
+                        match client.rebalance_denominations().await {
+                            Ok(operation_id) => pending_rebalance = operation_id,
+                            Err(e) => {
+                                warn!(target: LOG_TARGET, "Failed to rebalance denominations: {e}")
+                            }
+                        }
+                    }
+
+                    sleep(interval).await;
+                }
+            })
+            .await;
+    }
+
     fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: input.0.total_amount(),
             // FIXME: prevent overflows
-            fee: self.cfg.fee_consensus.note_spend_abs * (input.0.count_items() as u64),
+            fee: self.cfg.fee_consensus.spend_fee(&input.0),
         }
     }
 
@@ -501,7 +710,17 @@ impl ClientModule for MintClientModule {
     ) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: output.0.total_amount(),
-            fee: self.cfg.fee_consensus.note_issuance_abs * (output.0.count_items() as u64),
+            fee: self.cfg.fee_consensus.issuance_fee(&output.0),
+        }
+    }
+
+    fn operation_amount(&self, meta: &serde_json::Value) -> Option<OperationAmount> {
+        let meta: MintMeta = serde_json::from_value(meta.clone()).ok()?;
+        match meta.variant {
+            MintMetaVariants::Reissuance { .. } => Some(OperationAmount::Incoming(meta.amount)),
+            MintMetaVariants::SpendOOB { .. } => Some(OperationAmount::Outgoing(meta.amount)),
+            // Rebalancing only changes the denominations of our notes
+            MintMetaVariants::Rebalance { .. } => None,
         }
     }
 
@@ -527,7 +746,7 @@ impl ClientModule for MintClientModule {
                     ));
                 }
 
-                let notes = parse_ecash(args[1].to_string_lossy().as_ref())
+                let notes = OOBNotes::from_str(args[1].to_string_lossy().as_ref())
                     .map_err(|e| anyhow::format_err!("invalid notes format: {e}"))?;
 
                 let amount = notes.total_amount();
@@ -671,7 +890,32 @@ impl ClientModule for MintClientModule {
         amount: Amount,
     ) -> ClientOutput<MintOutput, MintClientStateMachines> {
         // FIXME: don't hardcode notes per denomination
-        self.create_output(dbtx, operation_id, 2, amount).await
+        self.create_output_after_fees(dbtx, operation_id, 2, amount)
+            .await
+    }
+
+    async fn preview_sufficient_input(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        min_amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        let notes = self.select_notes_covering_fees(dbtx, min_amount).await?;
+        Ok(TransactionItemAmount {
+            amount: notes.total_amount(),
+            fee: self.cfg.fee_consensus.spend_fee(&notes),
+        })
+    }
+
+    async fn preview_exact_output(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<TransactionItemAmount> {
+        let denominations = self.output_denominations_after_fees(dbtx, 2, amount).await;
+        Ok(TransactionItemAmount {
+            amount: denominations.total_amount(),
+            fee: self.issuance_fee(&denominations),
+        })
     }
 
     async fn await_primary_module_output(
@@ -750,14 +994,92 @@ impl MintClientModule {
         notes_per_denomination: u16,
         amount: Amount,
     ) -> ClientOutput<MintOutput, MintClientStateMachines> {
-        let mut amount_requests: Vec<((Amount, NoteIssuanceRequest), (Amount, BlindNonce))> =
-            Vec::new();
         let denominations = TieredSummary::represent_amount(
             amount,
             &self.get_wallet_summary(dbtx).await,
             &self.cfg.tbs_pks,
             notes_per_denomination,
         );
+
+        self.create_output_with_denominations(dbtx, operation_id, denominations)
+            .await
+    }
+
+    /// Creates a mint output of notes worth `amount` minus the fees for
+    /// issuing them. The notes are padded with small denominations until
+    /// their value plus fees matches `amount`, which is always possible if
+    /// issuing the smallest denomination is free.
+    pub async fn create_output_after_fees(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        notes_per_denomination: u16,
+        amount: Amount,
+    ) -> ClientOutput<MintOutput, MintClientStateMachines> {
+        let denominations = self
+            .output_denominations_after_fees(dbtx, notes_per_denomination, amount)
+            .await;
+
+        self.create_output_with_denominations(dbtx, operation_id, denominations)
+            .await
+    }
+
+    /// Fees for issuing the notes given by `denominations`
+    fn issuance_fee(&self, denominations: &TieredSummary) -> Amount {
+        denominations
+            .iter()
+            .map(|(tier, notes)| self.cfg.fee_consensus.note_issuance_fee(tier) * notes as u64)
+            .sum()
+    }
+
+    /// Denominations of the notes [`Self::create_output_after_fees`] issues
+    /// for `amount`
+    async fn output_denominations_after_fees(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        notes_per_denomination: u16,
+        amount: Amount,
+    ) -> TieredSummary {
+        let fees = &self.cfg.fee_consensus;
+        let held = self.get_wallet_summary(dbtx).await;
+        let mut notes_amount = amount;
+        let mut denominations = loop {
+            let denominations = TieredSummary::represent_amount(
+                notes_amount,
+                &held,
+                &self.cfg.tbs_pks,
+                notes_per_denomination,
+            );
+            let total = denominations.total_amount() + self.issuance_fee(&denominations);
+            if total <= amount {
+                break denominations;
+            }
+            notes_amount = notes_amount.saturating_sub(total - amount);
+        };
+
+        let mut remaining =
+            amount - denominations.total_amount() - self.issuance_fee(&denominations);
+        for tier in self.cfg.tbs_pks.tiers().rev() {
+            let cost = *tier + fees.note_issuance_fee(*tier);
+            while cost <= remaining {
+                denominations.inc(*tier, 1);
+                remaining -= cost;
+            }
+        }
+
+        denominations
+    }
+
+    /// Creates a mint output issuing exactly the e-cash notes given by
+    /// `denominations`
+    pub async fn create_output_with_denominations(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        denominations: TieredSummary,
+    ) -> ClientOutput<MintOutput, MintClientStateMachines> {
+        let mut amount_requests: Vec<((Amount, NoteIssuanceRequest), (Amount, BlindNonce))> =
+            Vec::new();
         for (amt, num) in denominations.iter() {
             for _ in 0..num {
                 let (request, blind_nonce) = self.new_ecash_note(amt, dbtx).await;
@@ -780,7 +1102,7 @@ impl MintClientModule {
         });
 
         debug!(
-            %amount,
+            amount = %sig_req.0.total_amount(),
             notes = %sig_req.0.count_items(),
             tiers = ?sig_req.0.iter_tiers().collect::<Vec<_>>(),
             "Generated issuance request"
@@ -827,14 +1149,15 @@ impl MintClientModule {
     }
 
     // FIXME: use lazy e-cash note loading implemented in #2183
-    /// Creates a mint input of at least `min_amount`.
+    /// Creates a mint input worth at least `min_amount` after subtracting the
+    /// fees for spending its notes
     pub async fn create_input(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
         operation_id: OperationId,
         min_amount: Amount,
     ) -> anyhow::Result<ClientInput<MintInput, MintClientStateMachines>> {
-        let spendable_selected_notes = Self::select_notes(dbtx, min_amount).await?;
+        let spendable_selected_notes = self.select_notes_covering_fees(dbtx, min_amount).await?;
 
         for (amount, note) in spendable_selected_notes.iter_items() {
             dbtx.remove_entry(&NoteKey {
@@ -848,6 +1171,26 @@ impl MintClientModule {
             .await
     }
 
+    /// Selects notes worth at least `min_amount` after subtracting the fees
+    /// for spending them
+    async fn select_notes_covering_fees(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        min_amount: Amount,
+    ) -> anyhow::Result<TieredMulti<SpendableNote>> {
+        let mut amount = min_amount;
+        // spending more notes raises the fee, so we select again until the fee of the
+        // selected notes is covered
+        loop {
+            let notes = self.select_notes(dbtx, amount).await?;
+            let fee = self.cfg.fee_consensus.spend_fee(&notes);
+            if min_amount + fee <= notes.total_amount() {
+                return Ok(notes);
+            }
+            amount = min_amount + fee;
+        }
+    }
+
     /// Create a mint input from external, potentially untrusted notes
     pub async fn create_input_from_notes(
         &self,
@@ -890,6 +1233,49 @@ impl MintClientModule {
         })
     }
 
+    /// Verifies the signatures of external notes and asks the federation if
+    /// any of them were spent already, returns their total amount
+    pub async fn validate_notes(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+    ) -> anyhow::Result<Amount> {
+        if let Some((amt, invalid_note)) = notes.iter_items().find(|(amt, note)| {
+            let Some(mint_key) = self.cfg.tbs_pks.get(*amt) else {
+                return true;
+            };
+            !note.note.verify(*mint_key)
+        }) {
+            bail!("Invalid note: amt={amt} note={invalid_note:?}");
+        }
+
+        let nonces = notes
+            .iter_items()
+            .map(|(_, note)| note.note.0)
+            .collect::<Vec<_>>();
+        if nonces.iter().collect::<BTreeSet<_>>().len() != nonces.len() {
+            bail!("Notes contain the same note more than once");
+        }
+
+        let mut spent_status = Vec::with_capacity(nonces.len());
+        for chunk in nonces.chunks(MAX_NOTE_SPENT_STATUS_NONCES) {
+            let chunk_status = self
+                .module_api
+                .fetch_note_spent_status(chunk.to_vec())
+                .await?;
+            if chunk_status.len() != chunk.len() {
+                bail!("Federation returned an invalid spent status");
+            }
+            spent_status.extend(chunk_status);
+        }
+
+        let spent = spent_status.into_iter().filter(|spent| *spent).count();
+        if spent != 0 {
+            bail!("{spent} of {} notes were already spent", nonces.len());
+        }
+
+        Ok(notes.total_amount())
+    }
+
     async fn spend_notes_oob(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
@@ -900,7 +1286,7 @@ impl MintClientModule {
         Vec<MintClientStateMachines>,
         TieredMulti<SpendableNote>,
     )> {
-        let spendable_selected_notes = Self::select_notes(dbtx, min_amount).await?;
+        let spendable_selected_notes = self.select_notes(dbtx, min_amount).await?;
 
         let operation_id = OperationId(
             spendable_selected_notes
@@ -978,20 +1364,79 @@ impl MintClientModule {
         Err(anyhow!("Restore stream closed without success or failure"))
     }
 
-    /// Select notes with total amount of *at least* `amount`. If more than
-    /// requested amount of notes are returned it was because exact change
-    /// couldn't be made, and the next smallest amount will be returned.
+    /// Select notes with total amount of *at least* `amount` using the
+    /// configured [`NoteSelector`]. If more than requested amount of notes are
+    /// returned it was because exact change couldn't be made.
     ///
     /// The caller can request change from the federation.
     async fn select_notes(
+        &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
         amount: Amount,
-    ) -> Result<TieredMulti<SpendableNote>, InsufficientBalanceError> {
-        let note_stream = dbtx
+    ) -> anyhow::Result<TieredMulti<SpendableNote>> {
+        let available = self.get_wallet_summary(dbtx).await;
+        let selected = self.note_selector.select_notes(&available, amount).await?;
+
+        Self::load_notes(dbtx, &selected).await
+    }
+
+    /// Loads notes from the database matching the number of notes per
+    /// denomination given by `selected`
+    async fn load_notes(
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        selected: &TieredSummary,
+    ) -> anyhow::Result<TieredMulti<SpendableNote>> {
+        let mut missing: BTreeMap<Amount, usize> = selected.iter().collect();
+        let notes = dbtx
             .find_by_prefix_sorted_descending(&NoteKeyPrefix)
             .await
-            .map(|(key, note)| (key.amount, note));
-        select_notes_from_stream(note_stream, amount).await
+            .filter_map(|(key, note)| {
+                let take = missing
+                    .get_mut(&key.amount)
+                    .filter(|count| **count > 0)
+                    .map(|count| *count -= 1)
+                    .is_some();
+                futures::future::ready(take.then_some((key.amount, note)))
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        // Note selectors are pluggable, so we can't rely on them only selecting notes we
+        // hold
+        if notes.len() != selected.count_items() {
+            bail!("Note selector selected notes we do not hold");
+        }
+
+        Ok(notes.into_iter().collect())
+    }
+
+    /// Removes the notes to be spent according to `plan` from our wallet and
+    /// creates the input spending them and the output issuing the new notes
+    async fn create_rebalance_transaction(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        plan: &RebalancePlan,
+    ) -> anyhow::Result<(
+        ClientInput<MintInput, MintClientStateMachines>,
+        ClientOutput<MintOutput, MintClientStateMachines>,
+    )> {
+        let notes = Self::load_notes(dbtx, &plan.spend).await?;
+
+        for (amount, note) in notes.iter_items() {
+            dbtx.remove_entry(&NoteKey {
+                amount,
+                nonce: note.note.0,
+            })
+            .await;
+        }
+
+        let input = self.create_input_from_notes(operation_id, notes).await?;
+        let output = self
+            .create_output_with_denominations(dbtx, operation_id, plan.issue.clone())
+            .await;
+
+        Ok((input, output))
     }
 
     async fn get_all_spendable_notes(
@@ -1381,14 +1826,6 @@ mod tests {
     }
 }
 
-pub fn parse_ecash(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
-    let bytes = base64::decode(s)?;
-    Ok(Decodable::consensus_decode(
-        &mut std::io::Cursor::new(bytes),
-        &ModuleDecoderRegistry::default(),
-    )?)
-}
-
 struct OOBSpendTag;
 
 impl sha256t::Tag for OOBSpendTag {
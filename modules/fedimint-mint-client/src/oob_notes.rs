@@ -0,0 +1,258 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use fedimint_core::api::WsClientConnectInfo;
+use fedimint_core::config::FederationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::{Amount, TieredMulti};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::SpendableNote;
+
+/// Prefix of all versioned out-of-band e-cash tokens, legacy tokens are plain
+/// base64 and always start with the encoded number of tiers (`AAAA…`)
+const OOB_NOTES_PREFIX: &str = "fedimint";
+
+/// The only token version known so far
+const OOB_NOTES_V0: u8 = 0;
+
+/// E-cash notes that are sent out of band, together with the information the
+/// recipient needs to redeem them
+///
+/// The string encoding is `fedimint` followed by the base64 encoded version
+/// byte and token. Tokens in the legacy format, which consisted of the
+/// base64 encoded notes only, can still be parsed but carry no federation id.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OOBNotes {
+    federation_id: Option<FederationId>,
+    connect_info: Option<WsClientConnectInfo>,
+    memo: Option<String>,
+    notes: TieredMulti<SpendableNote>,
+}
+
+/// Wire format of version 0 tokens
+#[derive(Debug, Clone, Encodable, Decodable)]
+struct OOBNotesV0 {
+    federation_id: FederationId,
+    connect_info: Option<String>,
+    memo: Option<String>,
+    notes: TieredMulti<SpendableNote>,
+}
+
+impl OOBNotes {
+    /// Creates a token for notes issued by the federation `federation_id`
+    pub fn new(federation_id: FederationId, notes: TieredMulti<SpendableNote>) -> Self {
+        OOBNotes {
+            federation_id: Some(federation_id),
+            connect_info: None,
+            memo: None,
+            notes,
+        }
+    }
+
+    /// Wraps notes that were received in the legacy format which doesn't
+    /// identify the issuing federation
+    pub fn legacy(notes: TieredMulti<SpendableNote>) -> Self {
+        OOBNotes {
+            federation_id: None,
+            connect_info: None,
+            memo: None,
+            notes,
+        }
+    }
+
+    /// Attaches the info a recipient that hasn't joined the federation yet
+    /// needs to download its config
+    pub fn with_connect_info(mut self, connect_info: WsClientConnectInfo) -> Self {
+        self.connect_info = Some(connect_info);
+        self
+    }
+
+    /// Attaches a human readable note for the recipient
+    pub fn with_memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+
+    /// The federation that issued the notes, `None` for legacy tokens
+    pub fn federation_id(&self) -> Option<FederationId> {
+        self.federation_id
+    }
+
+    pub fn connect_info(&self) -> Option<&WsClientConnectInfo> {
+        self.connect_info.as_ref()
+    }
+
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    pub fn notes(&self) -> &TieredMulti<SpendableNote> {
+        &self.notes
+    }
+
+    pub fn into_notes(self) -> TieredMulti<SpendableNote> {
+        self.notes
+    }
+
+    pub fn total_amount(&self) -> Amount {
+        self.notes.total_amount()
+    }
+}
+
+impl FromStr for OOBNotes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let Some(token) = s.strip_prefix(OOB_NOTES_PREFIX) else {
+            return Ok(OOBNotes::legacy(decode_base64(s)?));
+        };
+
+        let bytes = base64::decode(token)?;
+        let Some((version, token)) = bytes.split_first() else {
+            bail!("Empty e-cash token");
+        };
+
+        if *version != OOB_NOTES_V0 {
+            bail!("Unknown e-cash token version {version}");
+        }
+
+        let token = OOBNotesV0::consensus_decode(
+            &mut std::io::Cursor::new(token),
+            &ModuleDecoderRegistry::default(),
+        )?;
+        let connect_info = token
+            .connect_info
+            .map(|connect_info| WsClientConnectInfo::from_str(&connect_info))
+            .transpose()
+            .context("Invalid connect info in e-cash token")?;
+
+        Ok(OOBNotes {
+            federation_id: Some(token.federation_id),
+            connect_info,
+            memo: token.memo,
+            notes: token.notes,
+        })
+    }
+}
+
+impl Display for OOBNotes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // legacy tokens are passed on in the format they were received in
+        let Some(federation_id) = self.federation_id else {
+            return f.write_str(&encode_base64(&self.notes));
+        };
+
+        let mut bytes = vec![OOB_NOTES_V0];
+        OOBNotesV0 {
+            federation_id,
+            connect_info: self.connect_info.as_ref().map(ToString::to_string),
+            memo: self.memo.clone(),
+            notes: self.notes.clone(),
+        }
+        .consensus_encode(&mut bytes)
+        .expect("Writing to a vec can't fail");
+
+        write!(f, "{OOB_NOTES_PREFIX}{}", base64::encode(bytes))
+    }
+}
+
+impl Serialize for OOBNotes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for OOBNotes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        OOBNotes::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+fn decode_base64(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
+    let bytes = base64::decode(s)?;
+    Ok(Decodable::consensus_decode(
+        &mut std::io::Cursor::new(bytes),
+        &ModuleDecoderRegistry::default(),
+    )?)
+}
+
+fn encode_base64(notes: &TieredMulti<SpendableNote>) -> String {
+    let mut bytes = vec![];
+    notes
+        .consensus_encode(&mut bytes)
+        .expect("Writing to a vec can't fail");
+    base64::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{KeyPair, Secp256k1};
+    use tbs::{Message, Signature};
+
+    use super::*;
+    use crate::{Nonce, Note};
+
+    fn notes() -> TieredMulti<SpendableNote> {
+        let secp = Secp256k1::new();
+        (1..=3)
+            .map(|msats| {
+                let spend_key = KeyPair::new(&secp, &mut rand::thread_rng());
+                let note = SpendableNote {
+                    note: Note(
+                        Nonce(spend_key.x_only_public_key().0),
+                        Signature(Message::from_bytes(&[msats as u8]).0),
+                    ),
+                    spend_key,
+                };
+                (Amount::from_msats(msats), note)
+            })
+            .collect()
+    }
+
+    fn federation_id() -> FederationId {
+        FederationId(threshold_crypto::SecretKey::random().public_key())
+    }
+
+    #[test]
+    fn roundtrip_v0() {
+        let oob_notes = OOBNotes::new(federation_id(), notes()).with_memo("coffee");
+        let encoded = oob_notes.to_string();
+
+        assert!(encoded.starts_with(OOB_NOTES_PREFIX));
+        assert_eq!(OOBNotes::from_str(&encoded).unwrap(), oob_notes);
+    }
+
+    #[test]
+    fn parses_legacy_format() {
+        let notes = notes();
+        let parsed = OOBNotes::from_str(&encode_base64(&notes)).unwrap();
+
+        assert_eq!(parsed.federation_id(), None);
+        assert_eq!(parsed.memo(), None);
+        assert_eq!(parsed.notes(), &notes);
+        assert_eq!(parsed.to_string(), encode_base64(&notes));
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut bytes = vec![OOB_NOTES_V0 + 1];
+        federation_id().consensus_encode(&mut bytes).unwrap();
+        let encoded = format!("{OOB_NOTES_PREFIX}{}", base64::encode(bytes));
+
+        assert!(OOBNotes::from_str(&encoded).is_err());
+    }
+
+    #[test]
+    fn serde_uses_string_encoding() {
+        let oob_notes = OOBNotes::new(federation_id(), notes());
+        let json = serde_json::to_value(&oob_notes).unwrap();
+
+        assert_eq!(json, serde_json::Value::String(oob_notes.to_string()));
+        assert_eq!(serde_json::from_value::<OOBNotes>(json).unwrap(), oob_notes);
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use fedimint_core::{Amount, Tiered, TieredSummary};
+use fedimint_mint_common::config::FeeConsensus;
+
+/// How often the wallet's denominations are checked by default
+const DEFAULT_REBALANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of notes the wallet wants to hold per denomination
+#[derive(Debug, Clone)]
+pub enum DenominationTarget {
+    /// The same number of notes for every denomination of the federation
+    Uniform(usize),
+    /// A custom number of notes per denomination, denominations that are not
+    /// listed are targeted to hold no notes
+    PerTier(TieredSummary),
+}
+
+impl DenominationTarget {
+    fn notes<K>(&self, tiers: &Tiered<K>) -> BTreeMap<Amount, usize> {
+        match self {
+            DenominationTarget::Uniform(notes) => {
+                tiers.tiers().map(|tier| (*tier, *notes)).collect()
+            }
+            DenominationTarget::PerTier(target) => target
+                .iter()
+                .filter(|(tier, _)| tiers.get(*tier).is_some())
+                .collect(),
+        }
+    }
+}
+
+/// Configures the background task that reissues e-cash notes to ourselves
+/// whenever the held denominations drift from a target distribution
+#[derive(Debug, Clone)]
+pub struct RebalanceConfig {
+    /// Number of notes the wallet wants to hold per denomination
+    pub target: DenominationTarget,
+    /// Number of notes above the target a denomination may hold before the
+    /// surplus is consolidated into larger notes
+    pub max_surplus: usize,
+    /// Maximum fees paid for a single rebalancing transaction
+    pub max_fee: Amount,
+    /// How often the held denominations are checked
+    pub interval: Duration,
+}
+
+impl RebalanceConfig {
+    /// Targets `notes_per_denomination` notes of every denomination, paying at
+    /// most `max_fee` for a single rebalancing transaction
+    pub fn uniform(notes_per_denomination: usize, max_fee: Amount) -> Self {
+        RebalanceConfig {
+            target: DenominationTarget::Uniform(notes_per_denomination),
+            max_surplus: notes_per_denomination.max(1),
+            max_fee,
+            interval: DEFAULT_REBALANCE_INTERVAL,
+        }
+    }
+}
+
+/// The notes spent and issued by a rebalancing transaction
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RebalancePlan {
+    pub spend: TieredSummary,
+    pub issue: TieredSummary,
+}
+
+/// Decides which of the `held` notes to reissue to get closer to the target
+/// of `config`, returns `None` if no progress can be made within the fee
+/// budget
+///
+/// Surplus notes of denominations exceeding the target by more than
+/// `max_surplus` are always spent. Further notes held above the target are
+/// only spent to issue missing notes, starting with the smallest denomination.
+/// Whatever is left after fees is reissued in the largest possible notes.
+pub(crate) fn plan_rebalance<K>(
+    held: &TieredSummary,
+    config: &RebalanceConfig,
+    tiers: &Tiered<K>,
+    fees: &FeeConsensus,
+    max_notes_per_denomination: u16,
+) -> Option<RebalancePlan> {
+    let held: BTreeMap<Amount, usize> = held.iter().collect();
+    let target = config.target.notes(tiers);
+    let count = |notes: &BTreeMap<Amount, usize>, tier: Amount| -> usize {
+        notes.get(&tier).copied().unwrap_or(0)
+    };
+
+    let spend_fee = |tier: Amount| fees.note_spend_fee(tier).msats;
+    let issue_fee = |tier: Amount| fees.note_issuance_fee(tier).msats;
+    let max_notes = max_notes_per_denomination as usize;
+
+    let mut spend = BTreeMap::new();
+    let mut issue = BTreeMap::new();
+    // the value of the spent notes after fees that has not been issued yet
+    let mut value = 0u64;
+
+    // notes that are worth less than the fee to spend them are never touched
+    let spendable_tiers = held
+        .keys()
+        .copied()
+        .filter(|tier| tier.msats > spend_fee(*tier))
+        .collect::<Vec<_>>();
+
+    for tier in &spendable_tiers {
+        let surplus = count(&held, *tier).saturating_sub(count(&target, *tier));
+        if surplus > config.max_surplus {
+            spend.insert(*tier, surplus);
+            value += (tier.msats - spend_fee(*tier)) * surplus as u64;
+        }
+    }
+
+    let spare = |spend: &BTreeMap<Amount, usize>, tier: Amount| -> usize {
+        count(&held, tier)
+            .saturating_sub(count(&target, tier))
+            .saturating_sub(count(spend, tier))
+    };
+
+    'deficits: for tier in tiers.tiers() {
+        let missing = count(&target, *tier)
+            .saturating_sub(count(&held, *tier))
+            .min(max_notes);
+
+        for _ in 0..missing {
+            let cost = tier.msats + issue_fee(*tier);
+
+            if value < cost {
+                let shortfall = cost - value;
+                let spare_value: u64 = spendable_tiers
+                    .iter()
+                    .map(|spare_tier| {
+                        (spare_tier.msats - spend_fee(*spare_tier))
+                            * spare(&spend, *spare_tier) as u64
+                    })
+                    .sum();
+
+                if spare_value < shortfall {
+                    break 'deficits;
+                }
+
+                // prefer splitting the smallest single note that covers the shortfall
+                let covering_tier = spendable_tiers.iter().find(|spare_tier| {
+                    spare(&spend, **spare_tier) > 0
+                        && spare_tier.msats - spend_fee(**spare_tier) >= shortfall
+                });
+
+                let spare_tiers = match covering_tier {
+                    Some(spare_tier) => vec![*spare_tier],
+                    None => spendable_tiers.iter().rev().copied().collect(),
+                };
+
+                for spare_tier in spare_tiers {
+                    while spare(&spend, spare_tier) > 0 && value < cost {
+                        *spend.entry(spare_tier).or_default() += 1;
+                        value += spare_tier.msats - spend_fee(spare_tier);
+                    }
+                }
+            }
+
+            *issue.entry(*tier).or_default() += 1;
+            value -= cost;
+        }
+    }
+
+    for tier in tiers.tiers().rev() {
+        while value >= tier.msats + issue_fee(*tier) && count(&issue, *tier) < max_notes {
+            *issue.entry(*tier).or_default() += 1;
+            value -= tier.msats + issue_fee(*tier);
+        }
+    }
+
+    let spent_notes: usize = spend.values().sum();
+    let issued_notes: usize = issue.values().sum();
+    let fee = Amount::from_msats(
+        spend
+            .iter()
+            .map(|(tier, notes)| spend_fee(*tier) * *notes as u64)
+            .chain(
+                issue
+                    .iter()
+                    .map(|(tier, notes)| issue_fee(*tier) * *notes as u64),
+            )
+            .sum(),
+    );
+
+    if spent_notes == 0 || issued_notes == 0 || fee > config.max_fee || spend == issue {
+        return None;
+    }
+
+    Some(RebalancePlan {
+        spend: spend.into_iter().collect(),
+        issue: issue.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{Amount, Tiered, TieredSummary};
+    use fedimint_mint_common::config::FeeConsensus;
+
+    use super::*;
+
+    fn summary(notes: Vec<(u64, usize)>) -> TieredSummary {
+        notes
+            .into_iter()
+            .map(|(msats, count)| (Amount::from_msats(msats), count))
+            .collect()
+    }
+
+    fn plan(held: Vec<(u64, usize)>, config: &RebalanceConfig) -> Option<RebalancePlan> {
+        plan_rebalance(
+            &summary(held),
+            config,
+            &Tiered::gen_denominations(Amount::from_msats(8)),
+            &FeeConsensus::default(),
+            10,
+        )
+    }
+
+    #[test]
+    fn consolidates_surplus_notes() {
+        let config = RebalanceConfig::uniform(1, Amount::ZERO);
+        assert_eq!(
+            plan(vec![(1, 5), (2, 1), (4, 1), (8, 1)], &config),
+            Some(RebalancePlan {
+                spend: summary(vec![(1, 4)]),
+                issue: summary(vec![(4, 1)]),
+            })
+        );
+    }
+
+    #[test]
+    fn splits_notes_to_issue_missing_denominations() {
+        let config = RebalanceConfig::uniform(1, Amount::ZERO);
+        assert_eq!(
+            plan(vec![(2, 1), (8, 2)], &config),
+            Some(RebalancePlan {
+                spend: summary(vec![(8, 1)]),
+                issue: summary(vec![(1, 2), (2, 1), (4, 1)]),
+            })
+        );
+    }
+
+    #[test]
+    fn does_nothing_if_distribution_is_on_target() {
+        let config = RebalanceConfig::uniform(2, Amount::ZERO);
+        assert_eq!(plan(vec![(1, 2), (2, 3), (4, 2), (8, 1)], &config), None);
+    }
+
+    #[test]
+    fn respects_fee_budget() {
+        let config = RebalanceConfig::uniform(1, Amount::ZERO);
+        let fees = FeeConsensus {
+            note_issuance_abs: Amount::from_msats(1),
+            note_spend_abs: Amount::ZERO,
+            note_issuance_ppm: 0,
+            note_spend_ppm: 0,
+        };
+        assert_eq!(
+            plan_rebalance(
+                &summary(vec![(1, 5), (2, 1), (4, 1), (8, 1)]),
+                &config,
+                &Tiered::gen_denominations(Amount::from_msats(8)),
+                &fees,
+                10,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn never_spends_notes_eaten_up_by_proportional_fees() {
+        let config = RebalanceConfig::uniform(1, Amount::from_msats(1000));
+        let fees = FeeConsensus {
+            note_spend_ppm: 1_000_000,
+            ..FeeConsensus::default()
+        };
+        assert_eq!(
+            plan_rebalance(
+                &summary(vec![(1, 5), (2, 1), (4, 1), (8, 1)]),
+                &config,
+                &Tiered::gen_denominations(Amount::from_msats(8)),
+                &fees,
+                10,
+            ),
+            None
+        );
+    }
+}
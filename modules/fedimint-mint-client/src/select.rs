@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use fedimint_core::{apply, async_trait_maybe_send, Amount, TieredSummary};
+
+use crate::{select_notes_from_stream, InsufficientBalanceError};
+
+/// Decides which of the held e-cash notes are spent to cover an amount
+///
+/// Selectors only see how many notes of each denomination are available, the
+/// [`crate::MintClientModule`] then loads the selected notes from its
+/// database. If the returned notes are worth more than the requested amount
+/// the caller requests change from the federation, so selectors trade off the
+/// number of spent notes, the amount of change and the denominations left in
+/// the wallet.
+#[apply(async_trait_maybe_send!)]
+pub trait NoteSelector: Debug + Send + Sync {
+    /// Select notes with a total amount of *at least* `requested_amount` from
+    /// the `available` notes
+    async fn select_notes(
+        &self,
+        available: &TieredSummary,
+        requested_amount: Amount,
+    ) -> Result<TieredSummary, InsufficientBalanceError>;
+}
+
+/// Spends the largest notes first, falling back to the smallest note that
+/// covers the remainder if exact change cannot be made
+///
+/// See [`select_notes_from_stream`] for the algorithm.
+#[derive(Debug, Clone, Default)]
+pub struct GreedyNoteSelector;
+
+#[apply(async_trait_maybe_send!)]
+impl NoteSelector for GreedyNoteSelector {
+    async fn select_notes(
+        &self,
+        available: &TieredSummary,
+        requested_amount: Amount,
+    ) -> Result<TieredSummary, InsufficientBalanceError> {
+        let notes = available
+            .iter()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .flat_map(|(amount, count)| std::iter::repeat((amount, ())).take(count));
+
+        Ok(
+            select_notes_from_stream(futures::stream::iter(notes), requested_amount)
+                .await?
+                .summary(),
+        )
+    }
+}
+
+/// Minimizes the change the federation has to issue, spending more notes if
+/// necessary
+///
+/// Useful if the wallet wants to avoid waiting for change to be issued before
+/// the funds become spendable again.
+#[derive(Debug, Clone, Default)]
+pub struct MinimizeChangeNoteSelector;
+
+#[apply(async_trait_maybe_send!)]
+impl NoteSelector for MinimizeChangeNoteSelector {
+    async fn select_notes(
+        &self,
+        available: &TieredSummary,
+        requested_amount: Amount,
+    ) -> Result<TieredSummary, InsufficientBalanceError> {
+        if requested_amount == Amount::ZERO {
+            return Ok(TieredSummary::default());
+        }
+
+        let selection = Selection::new(available, requested_amount);
+        let tiers = selection.tiers();
+
+        // Starting with large notes can rule out exact combinations of smaller ones,
+        // so we try every denomination as the largest one to spend first and keep the
+        // selection with the least change, using fewer notes as a tie breaker.
+        (1..=tiers.len())
+            .map(|max_tier| {
+                let mut candidate = selection.clone();
+                let descending = tiers[..max_tier].iter().rev().copied().collect::<Vec<_>>();
+
+                candidate.fill(&descending);
+
+                // the smallest single note that covers the rest creates the least change
+                let covering_note = tiers
+                    .iter()
+                    .find(|tier| candidate.remaining(**tier) > 0 && **tier >= candidate.pending());
+
+                match covering_note {
+                    _ if candidate.pending() == Amount::ZERO => {}
+                    Some(tier) => candidate.take(*tier, 1),
+                    None => candidate.cover(&tiers),
+                }
+
+                let descending = tiers.iter().rev().copied().collect::<Vec<_>>();
+                candidate.prune(&descending)
+            })
+            .filter_map(Result::ok)
+            .min_by_key(|selected| (selected.total_amount(), selected.count_items()))
+            .ok_or(InsufficientBalanceError {
+                requested_amount,
+                total_amount: available.total_amount(),
+            })
+    }
+}
+
+/// Avoids spending denominations the wallet only holds few notes of
+///
+/// Spending a rare denomination makes a transaction easier to link to the
+/// wallet, so notes are spent from the denominations with the most notes
+/// first.
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyNoteSelector;
+
+#[apply(async_trait_maybe_send!)]
+impl NoteSelector for PrivacyNoteSelector {
+    async fn select_notes(
+        &self,
+        available: &TieredSummary,
+        requested_amount: Amount,
+    ) -> Result<TieredSummary, InsufficientBalanceError> {
+        let mut preference = available.iter().collect::<Vec<_>>();
+        // most common denominations first, larger ones first among equally common
+        preference.sort_by(|(a_tier, a_count), (b_tier, b_count)| {
+            b_count.cmp(a_count).then(b_tier.cmp(a_tier))
+        });
+
+        let preference = preference
+            .into_iter()
+            .map(|(tier, _)| tier)
+            .collect::<Vec<_>>();
+
+        Selection::new(available, requested_amount).select_by_preference(&preference)
+    }
+}
+
+/// Spends notes such that the wallet stays as close as possible to a target
+/// number of notes per denomination
+///
+/// Notes of denominations the wallet holds more of than targeted are spent
+/// first, which reduces how often the wallet has to reissue notes to be able
+/// to make exact payments.
+#[derive(Debug, Clone, Default)]
+pub struct TargetDistributionNoteSelector {
+    /// Number of notes the wallet wants to hold of each denomination
+    pub target: TieredSummary,
+}
+
+impl TargetDistributionNoteSelector {
+    pub fn new(target: TieredSummary) -> Self {
+        Self { target }
+    }
+
+    fn target(&self, tier: Amount) -> usize {
+        self.target
+            .iter()
+            .find(|(target_tier, _)| *target_tier == tier)
+            .map_or(0, |(_, count)| count)
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl NoteSelector for TargetDistributionNoteSelector {
+    async fn select_notes(
+        &self,
+        available: &TieredSummary,
+        requested_amount: Amount,
+    ) -> Result<TieredSummary, InsufficientBalanceError> {
+        let mut preference = available
+            .iter()
+            .map(|(tier, count)| (tier, count as i64 - self.target(tier) as i64))
+            .collect::<Vec<_>>();
+        // largest surplus over the target first, larger denominations first among
+        // equal surplus
+        preference.sort_by(|(a_tier, a_surplus), (b_tier, b_surplus)| {
+            b_surplus.cmp(a_surplus).then(b_tier.cmp(a_tier))
+        });
+
+        let preference = preference
+            .into_iter()
+            .map(|(tier, _)| tier)
+            .collect::<Vec<_>>();
+
+        Selection::new(available, requested_amount).select_by_preference(&preference)
+    }
+}
+
+/// Tracks which of the available notes have been selected so far
+#[derive(Clone)]
+struct Selection {
+    available: BTreeMap<Amount, usize>,
+    selected: BTreeMap<Amount, usize>,
+    requested_amount: Amount,
+}
+
+impl Selection {
+    fn new(available: &TieredSummary, requested_amount: Amount) -> Self {
+        Self {
+            available: available.iter().filter(|(_, count)| *count > 0).collect(),
+            selected: BTreeMap::new(),
+            requested_amount,
+        }
+    }
+
+    /// All available denominations in ascending order
+    fn tiers(&self) -> Vec<Amount> {
+        self.available.keys().copied().collect()
+    }
+
+    fn total_amount(&self) -> Amount {
+        self.selected
+            .iter()
+            .map(|(tier, count)| *tier * (*count as u64))
+            .sum()
+    }
+
+    /// Total amount of all available notes, selected or not
+    fn available_amount(&self) -> Amount {
+        self.available
+            .iter()
+            .map(|(tier, count)| *tier * (*count as u64))
+            .sum()
+    }
+
+    /// The amount still missing to cover the requested amount
+    fn pending(&self) -> Amount {
+        self.requested_amount.saturating_sub(self.total_amount())
+    }
+
+    /// Number of notes of `tier` that have not been selected yet
+    fn remaining(&self, tier: Amount) -> usize {
+        self.available.get(&tier).copied().unwrap_or(0)
+            - self.selected.get(&tier).copied().unwrap_or(0)
+    }
+
+    fn take(&mut self, tier: Amount, count: usize) {
+        *self.selected.entry(tier).or_default() += count;
+    }
+
+    /// Selects as many notes in the order of `tiers` as possible without
+    /// exceeding the requested amount
+    fn fill(&mut self, tiers: &[Amount]) {
+        for tier in tiers {
+            let count = (self.pending().msats / tier.msats).min(self.remaining(*tier) as u64);
+            self.take(*tier, count as usize);
+        }
+    }
+
+    /// Selects notes in the order of `tiers` until the requested amount is
+    /// covered
+    fn cover(&mut self, tiers: &[Amount]) {
+        for tier in tiers {
+            while self.pending() > Amount::ZERO && self.remaining(*tier) > 0 {
+                self.take(*tier, 1);
+            }
+        }
+    }
+
+    /// Spends the preferred denominations first, covers the rest with the
+    /// most preferred single note if possible and finally drops notes of the
+    /// least preferred denominations that are not needed
+    fn select_by_preference(
+        mut self,
+        preference: &[Amount],
+    ) -> Result<TieredSummary, InsufficientBalanceError> {
+        self.fill(preference);
+
+        let covering_note = preference
+            .iter()
+            .find(|tier| self.remaining(**tier) > 0 && **tier >= self.pending());
+
+        match covering_note {
+            _ if self.pending() == Amount::ZERO => {}
+            Some(tier) => self.take(*tier, 1),
+            None => self.cover(preference),
+        }
+
+        let least_preferred_first = preference.iter().rev().copied().collect::<Vec<_>>();
+        self.prune(&least_preferred_first)
+    }
+
+    /// Drops selected notes in the order of `tiers` as long as the requested
+    /// amount stays covered
+    fn prune(mut self, tiers: &[Amount]) -> Result<TieredSummary, InsufficientBalanceError> {
+        if self.pending() > Amount::ZERO {
+            return Err(InsufficientBalanceError {
+                requested_amount: self.requested_amount,
+                total_amount: self.available_amount(),
+            });
+        }
+
+        for tier in tiers {
+            while self.selected.get(tier).copied().unwrap_or(0) > 0
+                && self.total_amount() - *tier >= self.requested_amount
+            {
+                *self.selected.entry(*tier).or_default() -= 1;
+            }
+        }
+
+        Ok(self
+            .selected
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{Amount, TieredSummary};
+
+    use super::*;
+
+    fn summary(notes: Vec<(u64, usize)>) -> TieredSummary {
+        notes
+            .into_iter()
+            .map(|(sats, count)| (Amount::from_sats(sats), count))
+            .collect()
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn greedy_selector_matches_stream_selection() {
+        let available = summary(vec![(1, 1), (5, 5), (20, 5)]);
+        assert_eq!(
+            GreedyNoteSelector
+                .select_notes(&available, Amount::from_sats(7))
+                .await
+                .unwrap(),
+            summary(vec![(5, 2)])
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn minimize_change_selector_prefers_exact_amounts() {
+        let available = summary(vec![(2, 3), (5, 1)]);
+        // the greedy selector spends the notes of 5 and 2 sats, creating change
+        assert_eq!(
+            GreedyNoteSelector
+                .select_notes(&available, Amount::from_sats(6))
+                .await
+                .unwrap(),
+            summary(vec![(2, 1), (5, 1)])
+        );
+        assert_eq!(
+            MinimizeChangeNoteSelector
+                .select_notes(&available, Amount::from_sats(6))
+                .await
+                .unwrap(),
+            summary(vec![(2, 3)])
+        );
+
+        let available = summary(vec![(1, 10), (5, 1), (20, 1)]);
+        assert_eq!(
+            MinimizeChangeNoteSelector
+                .select_notes(&available, Amount::from_sats(13))
+                .await
+                .unwrap(),
+            summary(vec![(1, 8), (5, 1)])
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn privacy_selector_avoids_rare_denominations() {
+        let available = summary(vec![(1, 2), (5, 10), (20, 1)]);
+        assert_eq!(
+            PrivacyNoteSelector
+                .select_notes(&available, Amount::from_sats(20))
+                .await
+                .unwrap(),
+            summary(vec![(5, 4)])
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn target_distribution_selector_spends_surplus_first() {
+        let available = summary(vec![(1, 4), (2, 4), (4, 8)]);
+        let selector = TargetDistributionNoteSelector::new(summary(vec![(1, 4), (2, 4), (4, 4)]));
+        assert_eq!(
+            selector
+                .select_notes(&available, Amount::from_sats(8))
+                .await
+                .unwrap(),
+            summary(vec![(4, 2)])
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn selectors_return_error_if_amount_is_too_large() {
+        let available = summary(vec![(10, 1)]);
+        let selectors: Vec<Box<dyn NoteSelector>> = vec![
+            Box::new(GreedyNoteSelector),
+            Box::new(MinimizeChangeNoteSelector),
+            Box::new(PrivacyNoteSelector),
+            Box::new(TargetDistributionNoteSelector::default()),
+        ];
+
+        for selector in selectors {
+            let error = selector
+                .select_notes(&available, Amount::from_sats(100))
+                .await
+                .unwrap_err();
+            assert_eq!(error.total_amount, Amount::from_sats(10));
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn selectors_report_available_balance_if_amount_is_too_large() {
+        let available = summary(vec![(10, 1), (1, 2)]);
+        let selectors: Vec<Box<dyn NoteSelector>> = vec![
+            Box::new(GreedyNoteSelector),
+            Box::new(MinimizeChangeNoteSelector),
+            Box::new(PrivacyNoteSelector),
+            Box::new(TargetDistributionNoteSelector::default()),
+        ];
+
+        for selector in selectors {
+            let error = selector
+                .select_notes(&available, Amount::from_sats(100))
+                .await
+                .unwrap_err();
+            assert_eq!(error.requested_amount, Amount::from_sats(100));
+            assert_eq!(error.total_amount, Amount::from_sats(12));
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use std::collections::BTreeMap;
 
+use anyhow::ensure;
 use fedimint_core::config::EmptyGenParams;
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId, Tiered};
+use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId, Tiered, TieredMulti};
 use serde::{Deserialize, Serialize};
 use tbs::{AggregatePublicKey, PublicKeyShare};
 
@@ -18,6 +19,8 @@ pub struct MintGenParams {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MintGenParamsConsensus {
     pub mint_amounts: Vec<Amount>,
+    #[serde(default)]
+    pub fee_consensus: FeeConsensus,
 }
 
 const TEN_BTC_IN_SATS: u64 = 10 * 100_000_000;
@@ -30,6 +33,7 @@ impl Default for MintGenParams {
                     .tiers()
                     .cloned()
                     .collect(),
+                fee_consensus: FeeConsensus::default(),
             },
             local: EmptyGenParams {},
         }
@@ -84,10 +88,68 @@ plugin_types_trait_impl_config!(
     MintClientConfig
 );
 
+/// Fees charged per note, each consisting of a flat part and a part
+/// proportional to the note's denomination
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct FeeConsensus {
     pub note_issuance_abs: fedimint_core::Amount,
     pub note_spend_abs: fedimint_core::Amount,
+    /// Issuance fee in parts per million of the note's denomination
+    #[serde(default)]
+    pub note_issuance_ppm: u64,
+    /// Spend fee in parts per million of the note's denomination
+    #[serde(default)]
+    pub note_spend_ppm: u64,
+}
+
+impl FeeConsensus {
+    /// Fails if a proportional fee takes the whole denomination of a note or
+    /// more, which would make the notes worthless
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.note_issuance_ppm < 1_000_000,
+            "Note issuance fee of {} ppm must be below 100%",
+            self.note_issuance_ppm
+        );
+        ensure!(
+            self.note_spend_ppm < 1_000_000,
+            "Note spend fee of {} ppm must be below 100%",
+            self.note_spend_ppm
+        );
+        Ok(())
+    }
+
+    /// Fee for issuing a single note of denomination `amount`
+    pub fn note_issuance_fee(&self, amount: Amount) -> Amount {
+        self.note_issuance_abs + proportional_fee(amount, self.note_issuance_ppm)
+    }
+
+    /// Fee for spending a single note of denomination `amount`
+    pub fn note_spend_fee(&self, amount: Amount) -> Amount {
+        self.note_spend_abs + proportional_fee(amount, self.note_spend_ppm)
+    }
+
+    /// Fee for issuing all notes of `notes`
+    pub fn issuance_fee<T>(&self, notes: &TieredMulti<T>) -> Amount {
+        notes
+            .iter_items()
+            .map(|(amount, _)| self.note_issuance_fee(amount))
+            .sum()
+    }
+
+    /// Fee for spending all notes of `notes`
+    pub fn spend_fee<T>(&self, notes: &TieredMulti<T>) -> Amount {
+        notes
+            .iter_items()
+            .map(|(amount, _)| self.note_spend_fee(amount))
+            .sum()
+    }
+}
+
+/// The proportional part of a fee, rounded down to whole msats
+fn proportional_fee(amount: Amount, ppm: u64) -> Amount {
+    let msats = u128::from(amount.msats) * u128::from(ppm) / 1_000_000;
+    Amount::from_msats(msats.try_into().unwrap_or(u64::MAX))
 }
 
 impl Default for FeeConsensus {
@@ -95,6 +157,81 @@ impl Default for FeeConsensus {
         Self {
             note_issuance_abs: fedimint_core::Amount::ZERO,
             note_spend_abs: fedimint_core::Amount::ZERO,
+            note_issuance_ppm: 0,
+            note_spend_ppm: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{proportional_fee, FeeConsensus};
+
+    #[test]
+    fn proportional_fee_rounds_down_to_whole_msats() {
+        assert_eq!(
+            proportional_fee(Amount::from_msats(1_000_000), 1_000),
+            Amount::from_msats(1_000)
+        );
+        assert_eq!(
+            proportional_fee(Amount::from_msats(1_999), 1_000),
+            Amount::from_msats(1)
+        );
+        assert_eq!(
+            proportional_fee(Amount::from_msats(999), 1_000),
+            Amount::ZERO
+        );
+        assert_eq!(
+            proportional_fee(Amount::from_msats(u64::MAX), 1_000_000),
+            Amount::from_msats(u64::MAX)
+        );
+        assert_eq!(
+            proportional_fee(Amount::from_msats(u64::MAX), 2_000_000),
+            Amount::from_msats(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn note_fees_add_flat_and_proportional_part() {
+        let fees = FeeConsensus {
+            note_issuance_abs: Amount::from_msats(10),
+            note_spend_abs: Amount::from_msats(20),
+            note_issuance_ppm: 1_000,
+            note_spend_ppm: 2_000,
+        };
+
+        assert_eq!(
+            fees.note_issuance_fee(Amount::from_sats(1)),
+            Amount::from_msats(11)
+        );
+        assert_eq!(
+            fees.note_spend_fee(Amount::from_sats(1)),
+            Amount::from_msats(22)
+        );
+    }
+
+    #[test]
+    fn rejects_proportional_fees_of_whole_notes() {
+        assert!(FeeConsensus {
+            note_issuance_ppm: 999_999,
+            note_spend_ppm: 999_999,
+            ..FeeConsensus::default()
+        }
+        .validate()
+        .is_ok());
+        assert!(FeeConsensus {
+            note_issuance_ppm: 1_000_000,
+            ..FeeConsensus::default()
+        }
+        .validate()
+        .is_err());
+        assert!(FeeConsensus {
+            note_spend_ppm: u64::MAX,
+            ..FeeConsensus::default()
         }
+        .validate()
+        .is_err());
     }
 }
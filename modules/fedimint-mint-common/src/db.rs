@@ -16,6 +16,8 @@ pub enum DbKeyPrefix {
     OutputOutcome = 0x13,
     MintAuditItem = 0x14,
     EcashBackup = 0x15,
+    EcashBackupCount = 0x16,
+    EcashBackupReceived = 0x17,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -127,6 +129,35 @@ impl_db_record!(
 );
 impl_db_lookup!(key = EcashBackupKey, query_prefix = EcashBackupKeyPrefix);
 
+/// Number of stored [`EcashBackupKey`]s, missing if the backups were not
+/// counted yet
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct EcashBackupCountKey;
+
+impl_db_record!(
+    key = EcashBackupCountKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::EcashBackupCount,
+);
+
+/// Time the guardian received the latest upload of the [`EcashBackupKey`]
+/// with the same id
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct EcashBackupReceivedKey(pub secp256k1_zkp::XOnlyPublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EcashBackupReceivedKeyPrefix;
+
+impl_db_record!(
+    key = EcashBackupReceivedKey,
+    value = SystemTime,
+    db_prefix = DbKeyPrefix::EcashBackupReceived,
+);
+impl_db_lookup!(
+    key = EcashBackupReceivedKey,
+    query_prefix = EcashBackupReceivedKeyPrefix
+);
+
 /// User's backup, received at certain time, containing encrypted payload
 #[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
 pub struct ECashUserBackupSnapshot {
@@ -17,11 +17,15 @@ pub mod common;
 pub mod db;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("mint");
-const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+// Version 1 charges the proportional fees of `FeeConsensus`
+const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(1);
 
 /// By default, the maximum notes per denomination when change-making for users
 pub const DEFAULT_MAX_NOTES_PER_DENOMINATION: u16 = 3;
 
+/// Maximum number of nonces a single `note_spent_status` request may query
+pub const MAX_NOTE_SPENT_STATUS_NONCES: usize = 1000;
+
 /// Data structures taking into account different amount tiers
 
 /// A consenus item from one of the federation members contributing partials
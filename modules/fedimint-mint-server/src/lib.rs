@@ -1,7 +1,9 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
+use std::time::SystemTime;
 
 use anyhow::bail;
+use fedimint_core::backup::{BackupLimits, BackupQuota};
 use fedimint_core::config::{
     ClientModuleConfig, ConfigGenModuleParams, DkgResult, ServerModuleConfig,
     ServerModuleConsensusConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
@@ -22,11 +24,12 @@ use fedimint_core::{
 };
 pub use fedimint_mint_common as common;
 use fedimint_mint_common::config::{
-    FeeConsensus, MintClientConfig, MintConfig, MintConfigConsensus, MintConfigLocal,
-    MintConfigPrivate, MintGenParams,
+    MintClientConfig, MintConfig, MintConfigConsensus, MintConfigLocal, MintConfigPrivate,
+    MintGenParams,
 };
 use fedimint_mint_common::db::{
-    DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupKey, EcashBackupKeyPrefix, MintAuditItemKey,
+    DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupCountKey, EcashBackupKey,
+    EcashBackupKeyPrefix, EcashBackupReceivedKey, EcashBackupReceivedKeyPrefix, MintAuditItemKey,
     MintAuditItemKeyPrefix, NonceKey, NonceKeyPrefix, OutputOutcomeKey, OutputOutcomeKeyPrefix,
     ProposedPartialSignatureKey, ProposedPartialSignaturesKeyPrefix, ReceivedPartialSignatureKey,
     ReceivedPartialSignatureKeyOutputPrefix, ReceivedPartialSignaturesKeyPrefix,
@@ -34,8 +37,8 @@ use fedimint_mint_common::db::{
 pub use fedimint_mint_common::{BackupRequest, SignedBackupRequest};
 use fedimint_mint_common::{
     BlindNonce, MintCommonGen, MintConsensusItem, MintError, MintInput, MintModuleTypes,
-    MintOutput, MintOutputBlindSignatures, MintOutputOutcome, MintOutputSignatureShare, Note,
-    DEFAULT_MAX_NOTES_PER_DENOMINATION,
+    MintOutput, MintOutputBlindSignatures, MintOutputOutcome, MintOutputSignatureShare, Nonce,
+    Note, DEFAULT_MAX_NOTES_PER_DENOMINATION, MAX_NOTE_SPENT_STATUS_NONCES,
 };
 use fedimint_server::config::distributedgen::{scalar, PeerHandleOps};
 use futures::StreamExt;
@@ -51,6 +54,10 @@ use tbs::{
 use threshold_crypto::group::Curve;
 use tracing::{debug, info, warn};
 
+use crate::metrics::{MINT_ISSUED_ECASH_MSATS, MINT_REDEEMED_ECASH_MSATS};
+
+mod metrics;
+
 #[derive(Debug, Clone)]
 pub struct MintGen;
 
@@ -64,7 +71,7 @@ impl ServerModuleGen for MintGen {
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+        &[ModuleConsensusVersion(1)]
     }
 
     async fn init(
@@ -73,7 +80,7 @@ impl ServerModuleGen for MintGen {
         _db: Database,
         _task_group: &mut TaskGroup,
     ) -> anyhow::Result<DynServerModule> {
-        Ok(Mint::new(cfg.to_typed()?).into())
+        Ok(Mint::new(cfg.to_typed()?)?.into())
     }
 
     fn trusted_dealer_gen(
@@ -82,6 +89,11 @@ impl ServerModuleGen for MintGen {
         params: &ConfigGenModuleParams,
     ) -> BTreeMap<PeerId, ServerModuleConfig> {
         let params = self.parse_params(params).unwrap();
+        params
+            .consensus
+            .fee_consensus
+            .validate()
+            .expect("Invalid mint fees");
 
         let tbs_keys = params
             .consensus
@@ -113,7 +125,7 @@ impl ServerModuleGen for MintGen {
                                 (key_peer, keys)
                             })
                             .collect(),
-                        fee_consensus: FeeConsensus::default(),
+                        fee_consensus: params.consensus.fee_consensus.clone(),
                         max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
                     },
                     private: MintConfigPrivate {
@@ -141,6 +153,7 @@ impl ServerModuleGen for MintGen {
         params: &ConfigGenModuleParams,
     ) -> DkgResult<ServerModuleConfig> {
         let params = self.parse_params(params).unwrap();
+        params.consensus.fee_consensus.validate()?;
 
         let g2 = peers
             .run_dkg_multi_g2(params.consensus.mint_amounts.to_vec())
@@ -175,7 +188,7 @@ impl ServerModuleGen for MintGen {
                         (*peer, pks)
                     })
                     .collect(),
-                fee_consensus: Default::default(),
+                fee_consensus: params.consensus.fee_consensus,
                 max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
             },
         };
@@ -206,6 +219,7 @@ impl ServerModuleGen for MintGen {
         if !sks.keys().contains(&Amount::from_msats(1)) {
             bail!("No msat 1 denomination");
         }
+        config.consensus.fee_consensus.validate()?;
 
         Ok(())
     }
@@ -309,6 +323,21 @@ impl ServerModuleGen for MintGen {
                         "User Ecash Backup"
                     );
                 }
+                DbKeyPrefix::EcashBackupCount => {
+                    if let Some(count) = dbtx.get_value(&EcashBackupCountKey).await {
+                        mint.insert("User Ecash Backup Count".to_string(), Box::new(count));
+                    }
+                }
+                DbKeyPrefix::EcashBackupReceived => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EcashBackupReceivedKeyPrefix,
+                        EcashBackupReceivedKey,
+                        SystemTime,
+                        mint,
+                        "User Ecash Backup Receive Times"
+                    );
+                }
             }
         }
 
@@ -322,6 +351,8 @@ pub struct Mint {
     sec_key: Tiered<SecretKeyShare>,
     pub_key_shares: BTreeMap<PeerId, Tiered<PublicKeyShare>>,
     pub_key: HashMap<Amount, AggregatePublicKey>,
+    /// Limits on the ecash backups we store
+    backup_quota: BackupQuota,
 }
 #[apply(async_trait_maybe_send!)]
 impl ServerModule for Mint {
@@ -466,6 +497,13 @@ impl ServerModule for Mint {
             dbtx.remove_entry(&ProposedPartialSignatureKey(out_point))
                 .await;
 
+            // the signatures are only combined once for an accepted output
+            for (amount, _) in blind_signatures.iter_items() {
+                MINT_ISSUED_ECASH_MSATS
+                    .with_label_values(&[&amount.msats.to_string()])
+                    .inc_by(amount.msats);
+            }
+
             // insert the final blind signatures
             dbtx.insert_entry(
                 &OutputOutcomeKey(out_point),
@@ -482,7 +520,13 @@ impl ServerModule for Mint {
                     match key {
                         MintAuditItemKey::Issuance(_) => issuances += amount,
                         MintAuditItemKey::IssuanceTotal => issuances += amount,
-                        MintAuditItemKey::Redemption(_) => redemptions += amount,
+                        MintAuditItemKey::Redemption(_) => {
+                            // redemptions of accepted transactions are consolidated exactly once
+                            MINT_REDEEMED_ECASH_MSATS
+                                .with_label_values(&[&amount.msats.to_string()])
+                                .inc_by(amount.msats);
+                            redemptions += amount
+                        }
                         MintAuditItemKey::RedemptionTotal => redemptions += amount,
                     }
                     key
@@ -554,7 +598,7 @@ impl ServerModule for Mint {
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: input.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_spend_abs * (input.count_items() as u64),
+                fee: self.cfg.consensus.fee_consensus.spend_fee(&input.0),
             },
             pub_keys: input
                 .iter_items()
@@ -608,8 +652,7 @@ impl ServerModule for Mint {
         } else {
             Ok(TransactionItemAmount {
                 amount: output.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_issuance_abs
-                    * (output.count_items() as u64),
+                fee: self.cfg.consensus.fee_consensus.issuance_fee(&output.0),
             })
         }
     }
@@ -702,6 +745,13 @@ impl ServerModule for Mint {
                         .handle_recover_request(&mut context.dbtx(), id).await)
                 }
             },
+            api_endpoint! {
+                "note_spent_status",
+                async |module: &Mint, context, nonces: Vec<Nonce>| -> Vec<bool> {
+                    module
+                        .handle_note_spent_status_request(&mut context.dbtx(), nonces).await
+                }
+            },
         ]
     }
 }
@@ -717,13 +767,31 @@ impl Mint {
             .map_err(|_| ApiError::bad_request("invalid request".into()))?;
 
         debug!(id = %request.id, len = request.payload.len(), "Received user e-cash backup request");
-        if let Some(prev) = dbtx.get_value(&EcashBackupKey(request.id)).await {
+        let prev = dbtx.get_value(&EcashBackupKey(request.id)).await;
+        if let Some(prev) = &prev {
             if request.timestamp <= prev.timestamp {
                 debug!(id = %request.id, len = request.payload.len(), "Received user e-cash backup request with old timestamp - ignoring");
                 return Err(ApiError::bad_request("timestamp too small".into()));
             }
         }
 
+        self.backup_quota
+            .check_upload(
+                dbtx,
+                &EcashBackupKeyPrefix,
+                &EcashBackupCountKey,
+                request.id,
+                request.payload.len(),
+                prev.is_none(),
+                EcashBackupReceivedKey,
+                |key: &EcashBackupKey| key.0,
+            )
+            .await
+            .map_err(|e| {
+                info!(id = %request.id, len = request.payload.len(), code = e.code, "Rejected user e-cash backup: {}", e.message);
+                e
+            })?;
+
         info!(id = %request.id, len = request.payload.len(), "Storing new user e-cash backup");
         dbtx.insert_entry(
             &EcashBackupKey(request.id),
@@ -744,17 +812,38 @@ impl Mint {
     ) -> Option<ECashUserBackupSnapshot> {
         dbtx.get_value(&EcashBackupKey(id)).await
     }
+
+    /// Returns for every nonce whether a note with it was already spent, fails
+    /// if more than [`MAX_NOTE_SPENT_STATUS_NONCES`] nonces are queried
+    async fn handle_note_spent_status_request(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        nonces: Vec<Nonce>,
+    ) -> Result<Vec<bool>, ApiError> {
+        if nonces.len() > MAX_NOTE_SPENT_STATUS_NONCES {
+            return Err(ApiError::bad_request(format!(
+                "At most {MAX_NOTE_SPENT_STATUS_NONCES} nonces can be queried at once"
+            )));
+        }
+
+        let mut spent = Vec::with_capacity(nonces.len());
+        for nonce in nonces {
+            spent.push(dbtx.get_value(&NonceKey(nonce)).await.is_some());
+        }
+        Ok(spent)
+    }
 }
 
 impl Mint {
-    /// Constructs a new mint
+    /// Constructs a new mint, fails if the backup limits configured in the
+    /// environment are invalid
     ///
     /// # Panics
     /// * If there are no amount tiers
     /// * If the amount tiers for secret and public keys are inconsistent
     /// * If the pub key belonging to the secret key share is not in the pub key
     ///   list.
-    pub fn new(cfg: MintConfig) -> Mint {
+    pub fn new(cfg: MintConfig) -> anyhow::Result<Mint> {
         assert!(cfg.private.tbs_sks.tiers().count() > 0);
 
         // The amount tiers are implicitly provided by the key sets, make sure they are
@@ -799,12 +888,13 @@ impl Mint {
         })
         .collect();
 
-        Mint {
+        Ok(Mint {
             cfg: cfg.clone(),
             sec_key: cfg.private.tbs_sks,
             pub_key_shares: cfg.consensus.peer_tbs_pks.into_iter().collect(),
             pub_key: aggregate_pub_keys,
-        }
+            backup_quota: BackupQuota::new(BackupLimits::from_env_vars()?),
+        })
     }
 
     pub fn pub_key(&self) -> HashMap<Amount, AggregatePublicKey> {
@@ -827,12 +917,22 @@ impl Mint {
 
 #[cfg(test)]
 mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use bitcoin_hashes::Hash;
     use fedimint_core::config::{ClientModuleConfig, ConfigGenModuleParams, ServerModuleConfig};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::module::ServerModuleGen;
-    use fedimint_core::{Amount, PeerId};
+    use fedimint_core::{Amount, OutPoint, PeerId, ServerModule, TieredMulti, TransactionId};
     use fedimint_mint_common::config::FeeConsensus;
+    use fedimint_mint_common::{BlindNonce, MintOutput, Nonce, MAX_NOTE_SPENT_STATUS_NONCES};
+    use rand::rngs::OsRng;
+    use tbs::{blind_message, BlindingKey, Message};
 
     use crate::common::config::MintGenParamsConsensus;
+    use crate::metrics::MINT_ISSUED_ECASH_MSATS;
     use crate::{
         Mint, MintConfig, MintConfigConsensus, MintConfigLocal, MintConfigPrivate, MintGen,
         MintGenParams,
@@ -848,6 +948,7 @@ mod test {
                 local: Default::default(),
                 consensus: MintGenParamsConsensus {
                     mint_amounts: vec![Amount::from_sats(1)],
+                    fee_consensus: FeeConsensus::default(),
                 },
             })
             .unwrap(),
@@ -883,7 +984,95 @@ mod test {
                     .private
                     .tbs_sks,
             },
-        });
+        })
+        .unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_issued_ecash_counted_once_signed() {
+        let (mint_server_cfgs, _) = build_configs();
+        let mints = mint_server_cfgs
+            .iter()
+            .map(|cfg| Mint::new(cfg.to_typed().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        let issued = || MINT_ISSUED_ECASH_MSATS.with_label_values(&["1000"]).get();
+
+        let blind_nonce = BlindNonce(blind_message(
+            Message::from_bytes(&[1; 8]),
+            BlindingKey::random(),
+        ));
+        let output = MintOutput(TieredMulti::new(BTreeMap::from([(
+            Amount::from_sats(1),
+            vec![blind_nonce],
+        )])));
+        let out_point = OutPoint {
+            txid: TransactionId::all_zeros(),
+            out_idx: 0,
+        };
+        let peers = (0..MINTS as u16).map(PeerId::from).collect::<BTreeSet<_>>();
+        let issued_before = issued();
+
+        // Every peer accepts the output and proposes its signature share
+        let mut consensus_items = vec![];
+        let mut dbs = vec![];
+        for (peer, mint) in peers.iter().zip(&mints) {
+            let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+            let mut dbtx = db.begin_transaction().await;
+            mint.apply_output(&mut dbtx.with_module_prefix(0), &output, out_point)
+                .await
+                .unwrap();
+            let proposal = mint
+                .consensus_proposal(&mut dbtx.with_module_prefix(0))
+                .await;
+            consensus_items.extend(proposal.into_items().into_iter().map(|item| (*peer, item)));
+            dbtx.commit_tx().await;
+            dbs.push(db);
+        }
+        assert_eq!(issued(), issued_before);
+
+        let mut dbtx = dbs[0].begin_transaction().await;
+        mints[0]
+            .begin_consensus_epoch(
+                &mut dbtx.with_module_prefix(0),
+                consensus_items.clone(),
+                &peers,
+            )
+            .await;
+        assert_eq!(issued(), issued_before + 1000);
+
+        // Shares for an output whose signatures were combined already are ignored
+        mints[0]
+            .begin_consensus_epoch(&mut dbtx.with_module_prefix(0), consensus_items, &peers)
+            .await;
+        assert_eq!(issued(), issued_before + 1000);
+        dbtx.commit_tx().await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_note_spent_status_request_is_capped() {
+        let (mint_server_cfg, _) = build_configs();
+        let mint = Mint::new(mint_server_cfg[0].to_typed().unwrap()).unwrap();
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let (_, pk) = secp256k1::generate_keypair(&mut OsRng);
+        let nonce = Nonce(pk.x_only_public_key().0);
+
+        let spent = mint
+            .handle_note_spent_status_request(
+                &mut dbtx.with_module_prefix(0),
+                vec![nonce; MAX_NOTE_SPENT_STATUS_NONCES],
+            )
+            .await
+            .unwrap();
+        assert_eq!(spent, vec![false; MAX_NOTE_SPENT_STATUS_NONCES]);
+
+        assert!(mint
+            .handle_note_spent_status_request(
+                &mut dbtx.with_module_prefix(0),
+                vec![nonce; MAX_NOTE_SPENT_STATUS_NONCES + 1],
+            )
+            .await
+            .is_err());
     }
 }
 
@@ -1113,6 +1302,7 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any EcashBackups"
                             );
                         }
+                        DbKeyPrefix::EcashBackupCount | DbKeyPrefix::EcashBackupReceived => {}
                     }
                 }
             },
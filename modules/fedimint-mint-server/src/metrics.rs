@@ -0,0 +1,25 @@
+use fedimint_metrics::{opts, register_int_counter_vec_with_registry, IntCounterVec, REGISTRY};
+use once_cell::sync::Lazy;
+
+pub(crate) static MINT_ISSUED_ECASH_MSATS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "mint_issued_ecash_msats",
+            "Value of e-cash notes issued by denomination"
+        ),
+        &["denomination"],
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static MINT_REDEEMED_ECASH_MSATS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "mint_redeemed_ecash_msats",
+            "Value of e-cash notes redeemed by denomination"
+        ),
+        &["denomination"],
+        REGISTRY
+    )
+    .unwrap()
+});
@@ -1,17 +1,28 @@
-use fedimint_core::sats;
+use std::sync::Arc;
+use std::time::Duration;
+
+use fedimint_client::sm::{DynState, OperationId};
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::{Client, DynGlobalClientContext};
+use fedimint_core::core::DynOutput;
+use fedimint_core::task::sleep;
 use fedimint_core::util::NextOrPending;
-use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
+use fedimint_core::{msats, sats, Amount, TieredSummary};
+use fedimint_dummy_client::{DummyClientExt, DummyClientGen, DummyClientModule};
 use fedimint_dummy_common::config::DummyGenParams;
+use fedimint_dummy_common::DummyOutput;
 use fedimint_dummy_server::DummyGen;
 use fedimint_mint_client::{
-    MintClientExt, MintClientGen, ReissueExternalNotesState, SpendOOBState,
+    DenominationTarget, MintClientExt, MintClientGen, MintClientModule, RebalanceConfig,
+    ReissueExternalNotesState, SpendOOBState, KIND,
 };
-use fedimint_mint_common::config::MintGenParams;
+use fedimint_mint_common::config::{FeeConsensus, MintGenParams};
 use fedimint_mint_server::MintGen;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
 
 fn fixtures() -> Fixtures {
-    let fixtures = Fixtures::new_primary(MintClientGen, MintGen, MintGenParams::default());
+    let fixtures =
+        Fixtures::new_primary(MintClientGen::default(), MintGen, MintGenParams::default());
     fixtures.with_module(DummyClientGen, DummyGen, DummyGenParams::default())
 }
 
@@ -40,3 +51,181 @@ async fn sends_ecash_out_of_band() -> anyhow::Result<()> {
     assert_eq!(client2.get_balance().await, sats(750));
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn validates_ecash_before_reissue() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let (_, notes) = client1.spend_notes(sats(750), TIMEOUT, ()).await?;
+    assert_eq!(client2.validate_notes(&notes).await?, notes.total_amount());
+
+    let op = client2.reissue_external_notes(notes.clone(), ()).await?;
+    let sub = &mut client2
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    assert!(client2.validate_notes(&notes).await.is_err());
+    Ok(())
+}
+
+async fn held_notes(client: &Client) -> TieredSummary {
+    let (mint, instance) = client.get_first_module::<MintClientModule>(&KIND);
+    let mut dbtx = client.db().begin_transaction().await;
+    mint.get_wallet_summary(&mut dbtx.with_module_prefix(instance.id))
+        .await
+}
+
+fn notes_of(notes: &TieredSummary, tier: Amount) -> usize {
+    notes
+        .iter()
+        .find(|(amount, _)| *amount == tier)
+        .map_or(0, |(_, count)| count)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rebalances_denominations_in_the_background() -> anyhow::Result<()> {
+    let smallest = msats(1);
+    let target = 4;
+    let config = RebalanceConfig {
+        target: DenominationTarget::PerTier(vec![(smallest, target)].into_iter().collect()),
+        max_surplus: 0,
+        max_fee: Amount::ZERO,
+        interval: Duration::from_millis(100),
+    };
+    let fixtures = Fixtures::new_primary(
+        MintClientGen::default().with_rebalancing(config),
+        MintGen,
+        MintGenParams::default(),
+    )
+    .with_module(DummyClientGen, DummyGen, DummyGenParams::default());
+
+    let fed = fixtures.new_fed().await;
+    let client = fed.new_client().await;
+    let (op, outpoint) = client.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    // The mint client module rebalances on its own once the client was started
+    fedimint_core::task::timeout(TIMEOUT, async {
+        while notes_of(&held_notes(&client).await, smallest) != target {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    // The notes were reissued by a rebalancing operation of the mint module
+    let operations = client.operation_log().list_operations(10, None).await;
+    assert!(operations
+        .iter()
+        .any(|(_, entry)| entry.operation_type() == KIND.as_str()));
+    assert_eq!(client.get_balance().await, sats(1000));
+    Ok(())
+}
+
+fn proportional_fee_fixtures() -> Fixtures {
+    let mut params = MintGenParams::default();
+    params.consensus.fee_consensus = FeeConsensus {
+        note_issuance_ppm: 1_000,
+        note_spend_ppm: 2_000,
+        ..FeeConsensus::default()
+    };
+    Fixtures::new_primary(MintClientGen::default(), MintGen, params).with_module(
+        DummyClientGen,
+        DummyGen,
+        DummyGenParams::default(),
+    )
+}
+
+/// Transaction paying `amount` to the dummy account of `recipient`, which has
+/// to be funded by the primary module of `client`
+fn dummy_payment(client: &Client, recipient: &Client, amount: Amount) -> TransactionBuilder {
+    let (_, instance) = client.get_first_module::<DummyClientModule>(&fedimint_dummy_common::KIND);
+    let output = ClientOutput {
+        output: DynOutput::from_typed(
+            instance.id,
+            DummyOutput {
+                amount,
+                account: recipient.account(),
+            },
+        ),
+        state_machines: Arc::new(|_, _| Vec::<DynState<DynGlobalClientContext>>::new()),
+    };
+    TransactionBuilder::new().with_output(output)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn funds_transactions_with_proportional_fees() -> anyhow::Result<()> {
+    let fed = proportional_fee_fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    // The printed notes and their issuance fees add up to the printed amount
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+    let balance = client1.get_balance().await;
+    assert!(sats(999) <= balance && balance < sats(1000));
+
+    // Spending notes to fund an output has to cover their spend fees
+    let tx = dummy_payment(&client1, &client2, sats(250));
+    let op = OperationId::new_random();
+    let txid = client1
+        .finalize_and_submit_transaction(op, "dummy", |_, _| (), tx)
+        .await?;
+    client1
+        .transaction_updates(op)
+        .await
+        .await_tx_accepted(txid)
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn previews_transactions_with_proportional_fees() -> anyhow::Result<()> {
+    let fed = proportional_fee_fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+    let balance = client1.get_balance().await;
+
+    let tx = dummy_payment(&client1, &client2, sats(250));
+    let preview = client1.preview_transaction(&tx).await?;
+    let funding_input = preview.funding_input.expect("Payment has to be funded");
+    let change_output = preview.change_output.expect("Notes exceed the payment");
+    assert!(Amount::ZERO < funding_input.fee);
+    assert_eq!(preview.total_input, funding_input.amount);
+    assert_eq!(preview.total_output, sats(250) + change_output.amount);
+    assert_eq!(
+        preview.total_input,
+        preview.total_output + preview.total_fee
+    );
+
+    // Previewing doesn't spend any notes
+    assert_eq!(client1.get_balance().await, balance);
+
+    // The submitted transaction spends the previewed notes and issues the
+    // previewed change
+    let op = OperationId::new_random();
+    let txid = client1
+        .finalize_and_submit_transaction(op, "dummy", |_, _| (), tx)
+        .await?;
+    client1
+        .transaction_updates(op)
+        .await
+        .await_tx_accepted(txid)
+        .await?;
+    let expected_balance = balance - funding_input.amount + change_output.amount;
+    fedimint_core::task::timeout(TIMEOUT, async {
+        while client1.get_balance().await != expected_balance {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    Ok(())
+}
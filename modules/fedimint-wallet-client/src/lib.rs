@@ -13,7 +13,7 @@ use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_client::derivable_secret::DerivableSecret;
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationAmount, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
@@ -486,6 +486,16 @@ impl ClientModule for WalletClientModule {
             fee: self.cfg.fee_consensus.peg_out_abs,
         }
     }
+
+    fn operation_amount(&self, meta: &serde_json::Value) -> Option<OperationAmount> {
+        match serde_json::from_value::<WalletOperationMeta>(meta.clone()).ok()? {
+            // The deposited amount is only known once the deposit confirmed
+            WalletOperationMeta::Deposit { .. } => None,
+            WalletOperationMeta::Withdraw { amount, fee, .. } => {
+                Some(OperationAmount::Outgoing((amount + fee.amount()).into()))
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -5,11 +5,16 @@ use fedimint_client::sm::{OperationId, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::task::sleep;
 use fedimint_core::OutPoint;
 use fedimint_wallet_common::WalletOutputOutcome;
+use tracing::debug;
 
 use crate::WalletClientContext;
 
+/// How often to check if the batch transaction of a queued peg-out was created
+const QUEUED_PEG_OUT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 // TODO: track tx confirmations
 #[aquamarine::aquamarine]
 /// graph LR
@@ -60,16 +65,25 @@ async fn await_withdraw_processed(
     context: WalletClientContext,
     created: CreatedWithdrawState,
 ) -> Result<Txid, String> {
-    global_context
-        .api()
-        .await_output_outcome::<WalletOutputOutcome>(
-            created.fm_outpoint,
-            Duration::MAX,
-            &context.wallet_decoder,
-        )
-        .await
-        .map(|outcome| outcome.0)
-        .map_err(|e| e.to_string())
+    loop {
+        let outcome = global_context
+            .api()
+            .await_output_outcome::<WalletOutputOutcome>(
+                created.fm_outpoint,
+                Duration::MAX,
+                &context.wallet_decoder,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match outcome {
+            WalletOutputOutcome::Transaction(txid) => return Ok(txid),
+            WalletOutputOutcome::Queued => {
+                debug!(out_point = %created.fm_outpoint, "Peg-out is waiting for its batch");
+                sleep(QUEUED_PEG_OUT_POLL_INTERVAL).await;
+            }
+        }
+    }
 }
 
 async fn transition_withdraw_processed(
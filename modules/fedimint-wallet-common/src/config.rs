@@ -1,15 +1,18 @@
 use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use bitcoin::Network;
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{plugin_types_trait_impl_config, Feerate, PeerId};
-use miniscript::descriptor::Wsh;
+use miniscript::descriptor::{TapTree, Wsh};
+use miniscript::{Miniscript, Terminal};
 use secp256k1::SecretKey;
 use serde::{Deserialize, Serialize};
 
-use crate::keys::CompressedPublicKey;
+use crate::keys::{unspendable_internal_key, CompressedPublicKey};
 use crate::{PegInDescriptor, WalletCommonGen};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +28,8 @@ impl WalletGenParams {
             consensus: WalletGenParamsConsensus {
                 network: Network::Regtest,
                 finality_delay: 10,
+                peg_out_batch_window: None,
+                peg_in_descriptor_type: PegInDescriptorType::Wsh,
             },
         }
     }
@@ -39,6 +44,37 @@ pub struct WalletGenParamsLocal {
 pub struct WalletGenParamsConsensus {
     pub network: Network,
     pub finality_delay: u32,
+    #[serde(default)]
+    pub peg_out_batch_window: Option<u32>,
+    #[serde(default)]
+    pub peg_in_descriptor_type: PegInDescriptorType,
+}
+
+/// Kind of output script the federation receives peg-ins to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PegInDescriptorType {
+    /// P2WSH output with a `sortedmulti` script of all guardian keys
+    #[default]
+    Wsh,
+    /// Script-only P2TR output: the internal key is unspendable and the only
+    /// script path is a threshold `multi_a` of all guardian keys. Spending
+    /// through an aggregated MuSig2 key path is not supported yet since it
+    /// needs interactive signing by all guardians.
+    #[serde(rename = "tr-script-only")]
+    TrScriptOnly,
+}
+
+impl FromStr for PegInDescriptorType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wsh" => Ok(PegInDescriptorType::Wsh),
+            "tr-script-only" => Ok(PegInDescriptorType::TrScriptOnly),
+            _ => Err(anyhow::format_err!("Unknown peg-in descriptor type {s}")),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -76,6 +112,11 @@ pub struct WalletConfigConsensus {
     pub default_fee: Feerate,
     /// Fees for bitcoin transactions
     pub fee_consensus: FeeConsensus,
+    /// If set, peg-outs are paid out together in a single transaction once
+    /// this many blocks passed since the first peg-out of the batch was
+    /// accepted (0 batches the peg-outs of each consensus epoch)
+    #[serde(default)]
+    pub peg_out_batch_window: Option<u32>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
@@ -105,17 +146,32 @@ impl Default for FeeConsensus {
 }
 
 impl WalletConfig {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pubkeys: BTreeMap<PeerId, CompressedPublicKey>,
         sk: SecretKey,
         threshold: usize,
         network: Network,
         finality_delay: u32,
+        peg_out_batch_window: Option<u32>,
+        peg_in_descriptor_type: PegInDescriptorType,
         bitcoin_rpc: BitcoinRpcConfig,
     ) -> Self {
-        let peg_in_descriptor = PegInDescriptor::Wsh(
-            Wsh::new_sortedmulti(threshold, pubkeys.values().copied().collect()).unwrap(),
-        );
+        let keys = pubkeys.values().copied().collect::<Vec<_>>();
+        let peg_in_descriptor = match peg_in_descriptor_type {
+            PegInDescriptorType::Wsh => {
+                PegInDescriptor::Wsh(Wsh::new_sortedmulti(threshold, keys).unwrap())
+            }
+            PegInDescriptorType::TrScriptOnly => {
+                let multisig = Miniscript::from_ast(Terminal::MultiA(threshold, keys.clone()))
+                    .expect("threshold is valid");
+                PegInDescriptor::new_tr(
+                    unspendable_internal_key(),
+                    Some(TapTree::Leaf(Arc::new(multisig))),
+                )
+                .unwrap()
+            }
+        };
 
         Self {
             local: WalletConfigLocal { bitcoin_rpc },
@@ -127,6 +183,7 @@ impl WalletConfig {
                 finality_delay,
                 default_fee: Feerate { sats_per_kvb: 1000 },
                 fee_consensus: Default::default(),
+                peg_out_batch_window,
             },
         }
     }
@@ -1,12 +1,16 @@
-use bitcoin::{BlockHash, Txid};
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Amount, BlockHash, Script, Txid};
+use fedimint_core::db::DatabaseTransaction;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use futures::StreamExt;
 use secp256k1::ecdsa::Signature;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
 use crate::{
-    PendingTransaction, RoundConsensus, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome,
+    PegOutFees, PegOutSignature, PegOutSignatureItem, PendingTransaction, QueuedPegOut, Rbf,
+    RoundConsensus, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome,
 };
 
 #[repr(u8)]
@@ -19,6 +23,8 @@ pub enum DbKeyPrefix {
     PendingTransaction = 0x35,
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
+    QueuedPegOut = 0x38,
+    QueuedPegOutTotal = 0x39,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -102,7 +108,7 @@ pub struct PegOutTxSignatureCIPrefix;
 
 impl_db_record!(
     key = PegOutTxSignatureCI,
-    value = Vec<Signature>,
+    value = Vec<PegOutSignature>,
     db_prefix = DbKeyPrefix::PegOutTxSigCi,
 );
 impl_db_lookup!(
@@ -125,3 +131,178 @@ impl_db_lookup!(
     key = PegOutBitcoinTransaction,
     query_prefix = PegOutBitcoinTransactionPrefix
 );
+
+/// Version 0 of [`PegOutBitcoinTransaction`] that could only hold the txid
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutBitcoinTransactionV0(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutBitcoinTransactionPrefixV0;
+
+impl_db_record!(
+    key = PegOutBitcoinTransactionV0,
+    value = Txid,
+    db_prefix = DbKeyPrefix::PegOutBitcoinOutPoint,
+);
+impl_db_lookup!(
+    key = PegOutBitcoinTransactionV0,
+    query_prefix = PegOutBitcoinTransactionPrefixV0
+);
+
+/// Migrates the peg-out outcomes to [`WalletOutputOutcome`] which can also
+/// represent queued peg-outs
+pub async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let v0_entries = dbtx
+        .find_by_prefix(&PegOutBitcoinTransactionPrefixV0)
+        .await
+        .collect::<Vec<(PegOutBitcoinTransactionV0, Txid)>>()
+        .await;
+
+    dbtx.remove_by_prefix(&PegOutBitcoinTransactionPrefixV0)
+        .await;
+
+    for (v0_key, txid) in v0_entries {
+        dbtx.insert_new_entry(
+            &PegOutBitcoinTransaction(v0_key.0),
+            &WalletOutputOutcome::Transaction(txid),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Peg-outs waiting to be paid out in the next batch transaction
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct QueuedPegOutKey(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct QueuedPegOutPrefixKey;
+
+impl_db_record!(
+    key = QueuedPegOutKey,
+    value = QueuedPegOut,
+    db_prefix = DbKeyPrefix::QueuedPegOut,
+);
+impl_db_lookup!(key = QueuedPegOutKey, query_prefix = QueuedPegOutPrefixKey);
+
+/// Sum of the amounts and fees of all [`QueuedPegOutKey`]s, missing if no
+/// peg-out is queued
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct QueuedPegOutTotalKey;
+
+impl_db_record!(
+    key = QueuedPegOutTotalKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::QueuedPegOutTotal,
+);
+
+/// Version 1 of [`PegOutTxSignatureCI`] that could only hold ECDSA signatures
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutTxSignatureCIV1(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutTxSignatureCIPrefixV1;
+
+impl_db_record!(
+    key = PegOutTxSignatureCIV1,
+    value = Vec<Signature>,
+    db_prefix = DbKeyPrefix::PegOutTxSigCi,
+);
+impl_db_lookup!(
+    key = PegOutTxSignatureCIV1,
+    query_prefix = PegOutTxSignatureCIPrefixV1
+);
+
+/// Version 1 of [`PegOutSignatureItem`] that could only hold ECDSA signatures
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutSignatureItemV1 {
+    pub txid: Txid,
+    pub signature: Vec<Signature>,
+}
+
+/// Version 1 of [`UnsignedTransaction`] containing [`PegOutSignatureItemV1`]s
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct UnsignedTransactionV1 {
+    pub psbt: PartiallySignedTransaction,
+    pub signatures: Vec<(PeerId, PegOutSignatureItemV1)>,
+    pub change: Amount,
+    pub fees: PegOutFees,
+    pub destination: Script,
+    pub selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+    pub peg_out_amount: Amount,
+    pub rbf: Option<Rbf>,
+}
+
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct UnsignedTransactionKeyV1(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct UnsignedTransactionPrefixKeyV1;
+
+impl_db_record!(
+    key = UnsignedTransactionKeyV1,
+    value = UnsignedTransactionV1,
+    db_prefix = DbKeyPrefix::UnsignedTransaction,
+);
+impl_db_lookup!(
+    key = UnsignedTransactionKeyV1,
+    query_prefix = UnsignedTransactionPrefixKeyV1
+);
+
+/// Migrates peg-out signatures to [`PegOutSignature`] which can also hold the
+/// Schnorr signatures of taproot inputs
+pub async fn migrate_to_v2(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let v1_signatures = dbtx
+        .find_by_prefix(&PegOutTxSignatureCIPrefixV1)
+        .await
+        .collect::<Vec<(PegOutTxSignatureCIV1, Vec<Signature>)>>()
+        .await;
+    let v1_unsigned_txs = dbtx
+        .find_by_prefix(&UnsignedTransactionPrefixKeyV1)
+        .await
+        .collect::<Vec<(UnsignedTransactionKeyV1, UnsignedTransactionV1)>>()
+        .await;
+
+    dbtx.remove_by_prefix(&PegOutTxSignatureCIPrefixV1).await;
+    dbtx.remove_by_prefix(&UnsignedTransactionPrefixKeyV1).await;
+
+    for (v1_key, signatures) in v1_signatures {
+        let signatures = signatures
+            .into_iter()
+            .map(PegOutSignature::Ecdsa)
+            .collect::<Vec<_>>();
+        dbtx.insert_new_entry(&PegOutTxSignatureCI(v1_key.0), &signatures)
+            .await;
+    }
+
+    for (v1_key, unsigned) in v1_unsigned_txs {
+        let signatures = unsigned
+            .signatures
+            .into_iter()
+            .map(|(peer, item)| {
+                let item = PegOutSignatureItem {
+                    txid: item.txid,
+                    signature: item
+                        .signature
+                        .into_iter()
+                        .map(PegOutSignature::Ecdsa)
+                        .collect(),
+                };
+                (peer, item)
+            })
+            .collect();
+        let unsigned = UnsignedTransaction {
+            psbt: unsigned.psbt,
+            signatures,
+            change: unsigned.change,
+            fees: unsigned.fees,
+            destination: unsigned.destination,
+            selected_utxos: unsigned.selected_utxos,
+            peg_out_amount: unsigned.peg_out_amount,
+            rbf: unsigned.rbf,
+        };
+        dbtx.insert_new_entry(&UnsignedTransactionKey(v1_key.0), &unsigned)
+            .await;
+    }
+    Ok(())
+}
@@ -111,3 +111,41 @@ impl From<CompressedPublicKey> for bitcoin::PublicKey {
         }
     }
 }
+
+/// x coordinate of the point `H` from BIP-341, which has no known discrete
+/// logarithm
+const UNSPENDABLE_KEY_X: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Key nobody knows the private key of, used as internal key of taproot peg-in
+/// descriptors so they can only be spent through their script path
+pub fn unspendable_internal_key() -> CompressedPublicKey {
+    CompressedPublicKey::from_str(&format!("02{UNSPENDABLE_KEY_X}")).expect("H is a valid point")
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::hashes::{sha256, Hash};
+    use secp256k1::PublicKey;
+
+    use super::unspendable_internal_key;
+
+    /// BIP-341 derives `H` by hashing the uncompressed encoding of the
+    /// generator `G`
+    #[test]
+    fn test_unspendable_internal_key() {
+        let generator = PublicKey::from_slice(&[
+            0x04, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+            0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+            0x5b, 0x16, 0xf8, 0x17, 0x98, 0x48, 0x3a, 0xda, 0x77, 0x26, 0xa3, 0xc4, 0x65, 0x5d,
+            0xa4, 0xfb, 0xfc, 0x0e, 0x11, 0x08, 0xa8, 0xfd, 0x17, 0xb4, 0x48, 0xa6, 0x85, 0x54,
+            0x19, 0x9c, 0x47, 0xd0, 0x8f, 0xfb, 0x10, 0xd4, 0xb8,
+        ])
+        .unwrap();
+
+        assert_eq!(
+            unspendable_internal_key().key.serialize()[1..].to_hex(),
+            sha256::Hash::hash(&generator.serialize_uncompressed()).to_hex()
+        );
+    }
+}
@@ -25,7 +25,9 @@ pub mod tweakable;
 pub mod txoproof;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("wallet");
-const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+// Version 1 encodes queued peg-outs in `WalletOutputOutcome`, version 2 the
+// Schnorr signatures of taproot inputs in `PegOutSignatureItem`
+const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(2);
 
 pub const CONFIRMATION_TARGET: u16 = 10;
 
@@ -65,7 +67,17 @@ pub struct RoundConsensusItem {
 #[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
 pub struct PegOutSignatureItem {
     pub txid: Txid,
-    pub signature: Vec<secp256k1::ecdsa::Signature>,
+    pub signature: Vec<PegOutSignature>,
+}
+
+/// Signature of a guardian for one input of a peg-out transaction, the kind
+/// depends on the peg-in descriptor the input was received with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PegOutSignature {
+    /// Signature for the `sortedmulti` script of a P2WSH input
+    Ecdsa(secp256k1::ecdsa::Signature),
+    /// Signature for the `multi_a` script path of a P2TR input
+    Schnorr(secp256k1::schnorr::Signature),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
@@ -88,9 +100,12 @@ pub struct PendingTransaction {
     pub tx: Transaction,
     pub tweak: [u8; 32],
     pub change: bitcoin::Amount,
+    /// Script of the first peg-out output, batched transactions have one
+    /// output per peg-out
     pub destination: Script,
     pub fees: PegOutFees,
     pub selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+    /// Sum of all peg-out outputs
     pub peg_out_amount: Amount,
     pub rbf: Option<Rbf>,
 }
@@ -119,8 +134,11 @@ pub struct UnsignedTransaction {
     pub signatures: Vec<(PeerId, PegOutSignatureItem)>,
     pub change: bitcoin::Amount,
     pub fees: PegOutFees,
+    /// Script of the first peg-out output, batched transactions have one
+    /// output per peg-out
     pub destination: Script,
     pub selected_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+    /// Sum of all peg-out outputs
     pub peg_out_amount: Amount,
     pub rbf: Option<Rbf>,
 }
@@ -168,14 +186,45 @@ pub struct PegOut {
     pub fees: PegOutFees,
 }
 
+/// A peg-out that waits for its batch to be created, see
+/// [`config::WalletConfigConsensus::peg_out_batch_window`]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct QueuedPegOut {
+    pub peg_out: PegOut,
+    /// Consensus block height at which the peg-out was accepted
+    pub block_height: u32,
+}
+
 /// Contains the Bitcoin transaction id of the transaction created by the
 /// withdraw request
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct WalletOutputOutcome(pub bitcoin::Txid);
+pub enum WalletOutputOutcome {
+    /// The output was paid out by this Bitcoin transaction
+    Transaction(bitcoin::Txid),
+    /// The peg-out is waiting for its batch transaction to be created, query
+    /// the outcome again later
+    Queued,
+}
+
+impl WalletOutputOutcome {
+    /// Returns the Bitcoin transaction id if the transaction was created
+    /// already
+    pub fn txid(&self) -> Option<bitcoin::Txid> {
+        match self {
+            WalletOutputOutcome::Transaction(txid) => Some(*txid),
+            WalletOutputOutcome::Queued => None,
+        }
+    }
+}
 
 impl std::fmt::Display for WalletOutputOutcome {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Wallet PegOut Bitcoin TxId {}", self.0)
+        match self {
+            WalletOutputOutcome::Transaction(txid) => {
+                write!(f, "Wallet PegOut Bitcoin TxId {txid}")
+            }
+            WalletOutputOutcome::Queued => write!(f, "Wallet PegOut queued for next batch"),
+        }
     }
 }
 
@@ -253,7 +302,10 @@ impl std::hash::Hash for PegOutSignatureItem {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.txid.hash(state);
         for sig in self.signature.iter() {
-            sig.serialize_der().hash(state);
+            match sig {
+                PegOutSignature::Ecdsa(sig) => sig.serialize_der().hash(state),
+                PegOutSignature::Schnorr(sig) => sig[..].hash(state),
+            }
         }
     }
 }
@@ -300,6 +352,8 @@ pub enum WalletError {
     TxWeightIncorrect(u64, u64),
     #[error("Peg-out fee rate is below min relay fee")]
     BelowMinRelayFee,
+    #[error("No round consensus was reached yet")]
+    NoRoundConsensus,
 }
 
 #[derive(Debug, Error)]
@@ -314,6 +368,8 @@ pub enum ProcessPegOutSigError {
     MalformedSignature(secp256k1::Error),
     #[error("Invalid signature")]
     InvalidSignature,
+    #[error("Signature type doesn't match the input's script")]
+    WrongSignatureType,
     #[error("Duplicate signature")]
     DuplicateSignature,
     #[error("Missing change tweak")]
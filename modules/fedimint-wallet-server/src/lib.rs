@@ -9,7 +9,9 @@ use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine}
 use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
 use bitcoin::util::psbt::{Input, PartiallySignedTransaction};
-use bitcoin::util::sighash::SighashCache;
+use bitcoin::util::schnorr::SchnorrSig;
+use bitcoin::util::sighash::{Prevouts, SchnorrSighashType, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
 use bitcoin::{
     Address, BlockHash, EcdsaSig, EcdsaSighashType, Network, PackedLockTime, Script, Sequence,
     Transaction, TxIn, TxOut, Txid,
@@ -17,11 +19,11 @@ use bitcoin::{
 use common::config::WalletConfigConsensus;
 use common::db::DbKeyPrefix;
 use common::{
-    proprietary_tweak_key, IterUnzipWalletConsensusItem, PegOutFees, PegOutSignatureItem,
-    PendingTransaction, ProcessPegOutSigError, RoundConsensus, RoundConsensusItem, SpendableUTXO,
-    UnsignedTransaction, UnzipWalletConsensusItem, WalletCommonGen, WalletConsensusItem,
-    WalletError, WalletInput, WalletModuleTypes, WalletOutput, WalletOutputOutcome,
-    CONFIRMATION_TARGET,
+    proprietary_tweak_key, IterUnzipWalletConsensusItem, PegOut, PegOutFees, PegOutSignature,
+    PegOutSignatureItem, PendingTransaction, ProcessPegOutSigError, QueuedPegOut, RoundConsensus,
+    RoundConsensusItem, SpendableUTXO, UnsignedTransaction, UnzipWalletConsensusItem,
+    WalletCommonGen, WalletConsensusItem, WalletError, WalletInput, WalletModuleTypes,
+    WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET,
 };
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_core::config::{
@@ -29,7 +31,7 @@ use fedimint_core::config::{
     ServerModuleConsensusConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::db::{
-    Database, DatabaseTransaction, DatabaseVersion, ModuleDatabaseTransaction,
+    Database, DatabaseTransaction, DatabaseVersion, MigrationMap, ModuleDatabaseTransaction,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::audit::Audit;
@@ -50,24 +52,29 @@ use fedimint_server::config::distributedgen::PeerHandleOps;
 pub use fedimint_wallet_common as common;
 use fedimint_wallet_common::config::{WalletClientConfig, WalletConfig, WalletGenParams};
 use fedimint_wallet_common::db::{
-    BlockHashKey, BlockHashKeyPrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
-    PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingTransactionKey,
-    PendingTransactionPrefixKey, RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
+    migrate_to_v1, migrate_to_v2, BlockHashKey, BlockHashKeyPrefix, PegOutBitcoinTransaction,
+    PegOutBitcoinTransactionPrefix, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix,
+    PendingTransactionKey, PendingTransactionPrefixKey, QueuedPegOutKey, QueuedPegOutPrefixKey,
+    QueuedPegOutTotalKey, RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
     UnsignedTransactionPrefixKey,
 };
 use fedimint_wallet_common::keys::CompressedPublicKey;
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::Rbf;
-use futures::{stream, StreamExt};
+use futures::{stream, FutureExt, StreamExt};
 use miniscript::psbt::PsbtExt;
 use miniscript::{Descriptor, TranslatePk};
 use rand::rngs::OsRng;
 use rand::Rng;
-use secp256k1::{Message, Scalar};
+use secp256k1::{KeyPair, Message, Scalar};
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use tracing::{debug, error, info, instrument, trace, warn};
 
+use crate::metrics::{WALLET_PENDING_PEGOUTS, WALLET_QUEUED_PEGOUTS, WALLET_UTXO_VALUE_SATS};
+
+mod metrics;
+
 #[derive(Debug, Clone)]
 pub struct WalletGen;
 
@@ -78,10 +85,17 @@ impl ExtendsCommonModuleGen for WalletGen {
 #[apply(async_trait_maybe_send!)]
 impl ServerModuleGen for WalletGen {
     type Params = WalletGenParams;
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(2);
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+        &[ModuleConsensusVersion(2)]
+    }
+
+    fn get_database_migrations(&self) -> MigrationMap {
+        let mut migrations = MigrationMap::new();
+        migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+        migrations.insert(DatabaseVersion(1), move |dbtx| migrate_to_v2(dbtx).boxed());
+        migrations
     }
 
     async fn init(
@@ -118,6 +132,8 @@ impl ServerModuleGen for WalletGen {
                     peers.threshold(),
                     params.consensus.network,
                     params.consensus.finality_delay,
+                    params.consensus.peg_out_batch_window,
+                    params.consensus.peg_in_descriptor_type,
                     params.local.bitcoin_rpc.clone(),
                 );
                 (*id, cfg)
@@ -152,6 +168,8 @@ impl ServerModuleGen for WalletGen {
             peers.peer_ids().threshold(),
             params.consensus.network,
             params.consensus.finality_delay,
+            params.consensus.peg_out_batch_window,
+            params.consensus.peg_in_descriptor_type,
             params.local.bitcoin_rpc.clone(),
         );
 
@@ -222,7 +240,7 @@ impl ServerModuleGen for WalletGen {
                         dbtx,
                         PegOutTxSignatureCIPrefix,
                         PegOutTxSignatureCI,
-                        Vec<secp256k1::ecdsa::Signature>,
+                        Vec<PegOutSignature>,
                         wallet,
                         "Peg Out Transaction Signatures"
                     );
@@ -263,6 +281,21 @@ impl ServerModuleGen for WalletGen {
                         "UTXOs"
                     );
                 }
+                DbKeyPrefix::QueuedPegOut => {
+                    push_db_pair_items!(
+                        dbtx,
+                        QueuedPegOutPrefixKey,
+                        QueuedPegOutKey,
+                        QueuedPegOut,
+                        wallet,
+                        "Queued Peg Outs"
+                    );
+                }
+                DbKeyPrefix::QueuedPegOutTotal => {
+                    if let Some(total) = dbtx.get_value(&QueuedPegOutTotalKey).await {
+                        wallet.insert("Queued Peg Out Total".to_string(), Box::new(total));
+                    }
+                }
             }
         }
 
@@ -447,6 +480,20 @@ impl ServerModule for Wallet {
             .validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)
             .into_module_error_other()?;
 
+        // The UTXOs are only selected once the batch is created, so we have to make
+        // sure they suffice to pay out all queued peg-outs. The batch never pays more
+        // fees than the individual transactions would, so comparing totals suffices.
+        if let (WalletOutput::PegOut(peg_out), Some(_)) =
+            (output, self.cfg.consensus.peg_out_batch_window)
+        {
+            let queued_total = self.queued_peg_out_total(dbtx).await;
+            if self.get_wallet_value(dbtx).await
+                < queued_total + peg_out.amount + peg_out.fees.amount()
+            {
+                return Err(WalletError::NotEnoughSpendableUTXO).into_module_error_other();
+            }
+        }
+
         Ok(TransactionItemAmount {
             amount: output.amount().into(),
             fee: self.cfg.consensus.fee_consensus.peg_out_abs,
@@ -461,55 +508,42 @@ impl ServerModule for Wallet {
     ) -> Result<TransactionItemAmount, ModuleError> {
         let amount = self.validate_output(dbtx, output).await?;
 
-        let mut tx = self
+        if let (WalletOutput::PegOut(peg_out), Some(_)) =
+            (output, self.cfg.consensus.peg_out_batch_window)
+        {
+            let block_height = self.consensus_height(dbtx).await.unwrap_or(0);
+            debug!(%out_point, block_height, "Queueing peg out");
+            dbtx.insert_new_entry(
+                &QueuedPegOutKey(out_point),
+                &QueuedPegOut {
+                    peg_out: peg_out.clone(),
+                    block_height,
+                },
+            )
+            .await;
+            let queued_total = self.queued_peg_out_total(dbtx).await;
+            dbtx.insert_entry(
+                &QueuedPegOutTotalKey,
+                &(queued_total + peg_out.amount + peg_out.fees.amount()),
+            )
+            .await;
+            dbtx.insert_new_entry(
+                &PegOutBitcoinTransaction(out_point),
+                &WalletOutputOutcome::Queued,
+            )
+            .await;
+            return Ok(amount);
+        }
+
+        let tx = self
             .create_peg_out_tx(dbtx, output)
             .await
             .expect("Should have been validated");
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
-        let txid = tx.psbt.unsigned_tx.txid();
-        info!(
-            %txid,
-            "Signing peg out",
-        );
-
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
+        let txid = self.sign_and_save_peg_out_tx(dbtx, tx).await;
 
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
-
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
-
-        // Delete used UTXOs
-        for input in tx.psbt.unsigned_tx.input.iter() {
-            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
-        }
-
-        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
-            .await;
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await;
         dbtx.insert_new_entry(
             &PegOutBitcoinTransaction(out_point),
-            &WalletOutputOutcome(txid),
+            &WalletOutputOutcome::Transaction(txid),
         )
         .await;
         Ok(amount)
@@ -568,6 +602,13 @@ impl ServerModule for Wallet {
                 }
             }
         }
+
+        if let Some(batch_window) = self.cfg.consensus.peg_out_batch_window {
+            self.create_peg_out_batch(dbtx, batch_window).await;
+        }
+
+        self.update_metrics(dbtx).await;
+
         drop_peers
     }
 
@@ -595,6 +636,12 @@ impl ServerModule for Wallet {
                 Some(rbf) => rbf.fees.amount().to_sat() as i64 * -1000,
             })
             .await;
+        // Queued peg-outs will be paid from UTXOs that are still counted above
+        audit
+            .add_items(dbtx, &QueuedPegOutPrefixKey, |_, v| {
+                (v.peg_out.amount + v.peg_out.fees.amount()).to_sat() as i64 * -1000
+            })
+            .await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -611,8 +658,10 @@ impl ServerModule for Wallet {
                     let (address, sats) = params;
                     let consensus = module.current_round_consensus(&mut context.dbtx()).await.unwrap();
                     let tx = module.offline_wallet().create_tx(
-                        bitcoin::Amount::from_sat(sats),
-                        address.script_pubkey(),
+                        vec![TxOut {
+                            value: sats,
+                            script_pubkey: address.script_pubkey(),
+                        }],
                         vec![],
                         module.available_utxos(&mut context.dbtx()).await,
                         consensus.fee_rate,
@@ -751,6 +800,11 @@ impl Wallet {
             ));
         }
 
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
         for (idx, (input, signature)) in psbt
             .inputs
@@ -758,39 +812,78 @@ impl Wallet {
             .zip(signature.signature.iter())
             .enumerate()
         {
-            let tx_hash = tx_hasher
-                .segwit_signature_hash(
-                    idx,
-                    input
-                        .witness_script
-                        .as_ref()
-                        .expect("Missing witness script"),
-                    input.witness_utxo.as_ref().expect("Missing UTXO").value,
-                    EcdsaSighashType::All,
-                )
-                .map_err(|_| ProcessPegOutSigError::SighashError)?;
-
             let tweak = input
                 .proprietary
                 .get(&proprietary_tweak_key())
                 .expect("we saved it with a tweak");
 
             let tweaked_peer_key = peer_key.tweak(tweak, &self.secp);
-            self.secp
-                .verify_ecdsa(
-                    &Message::from_slice(&tx_hash[..]).unwrap(),
-                    signature,
-                    &tweaked_peer_key.key,
-                )
-                .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
 
-            if input
-                .partial_sigs
-                .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
-                .is_some()
-            {
-                // Should never happen since peers only sign a PSBT once
-                return Err(ProcessPegOutSigError::DuplicateSignature);
+            match signature {
+                PegOutSignature::Ecdsa(signature) if input.tap_scripts.is_empty() => {
+                    let tx_hash = tx_hasher
+                        .segwit_signature_hash(
+                            idx,
+                            input
+                                .witness_script
+                                .as_ref()
+                                .expect("Missing witness script"),
+                            input.witness_utxo.as_ref().expect("Missing UTXO").value,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    self.secp
+                        .verify_ecdsa(
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            signature,
+                            &tweaked_peer_key.key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    if input
+                        .partial_sigs
+                        .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
+                PegOutSignature::Schnorr(signature) if !input.tap_scripts.is_empty() => {
+                    let leaf_hash = tap_leaf_hash(input);
+                    let tx_hash = tx_hasher
+                        .taproot_script_spend_signature_hash(
+                            idx,
+                            &Prevouts::All(&prevouts),
+                            leaf_hash,
+                            SchnorrSighashType::Default,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    let (tweaked_peer_key, _) = tweaked_peer_key.key.x_only_public_key();
+                    self.secp
+                        .verify_schnorr(
+                            signature,
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            &tweaked_peer_key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    let signature = SchnorrSig {
+                        sig: *signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    };
+                    if input
+                        .tap_script_sigs
+                        .insert((tweaked_peer_key, leaf_hash), signature)
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
+                _ => return Err(ProcessPegOutSigError::WrongSignatureType),
             }
         }
         Ok(())
@@ -1072,8 +1165,10 @@ impl Wallet {
 
         match output {
             WalletOutput::PegOut(peg_out) => self.offline_wallet().create_tx(
-                peg_out.amount,
-                peg_out.recipient.script_pubkey(),
+                vec![TxOut {
+                    value: peg_out.amount.to_sat(),
+                    script_pubkey: peg_out.recipient.script_pubkey(),
+                }],
                 vec![],
                 self.available_utxos(dbtx).await,
                 peg_out.fees.fee_rate,
@@ -1086,9 +1181,18 @@ impl Wallet {
                     .await
                     .ok_or(WalletError::RbfTransactionIdNotFound)?;
 
+                // Batched transactions have multiple peg-out outputs, all of which need to be
+                // kept when replacing the transaction
+                let change_script = self.offline_wallet().derive_script(&tx.tweak);
+                let destinations = tx
+                    .tx
+                    .output
+                    .into_iter()
+                    .filter(|output| output.script_pubkey != change_script)
+                    .collect();
+
                 self.offline_wallet().create_tx(
-                    tx.peg_out_amount,
-                    tx.destination,
+                    destinations,
                     tx.selected_utxos,
                     self.available_utxos(dbtx).await,
                     tx.fees.fee_rate,
@@ -1099,6 +1203,156 @@ impl Wallet {
         }
     }
 
+    async fn queued_peg_outs(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+    ) -> Vec<(QueuedPegOutKey, QueuedPegOut)> {
+        dbtx.find_by_prefix(&QueuedPegOutPrefixKey)
+            .await
+            .collect::<Vec<(QueuedPegOutKey, QueuedPegOut)>>()
+            .await
+    }
+
+    /// Sum of the amounts and fees of all queued peg-outs
+    async fn queued_peg_out_total(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+    ) -> bitcoin::Amount {
+        dbtx.get_value(&QueuedPegOutTotalKey)
+            .await
+            .unwrap_or(bitcoin::Amount::ZERO)
+    }
+
+    async fn create_peg_out_batch_tx(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        peg_outs: &[PegOut],
+    ) -> Result<UnsignedTransaction, WalletError> {
+        let change_tweak = self
+            .current_round_consensus(dbtx)
+            .await
+            .ok_or(WalletError::NoRoundConsensus)?
+            .randomness_beacon;
+
+        self.offline_wallet().create_batch_tx(
+            peg_outs,
+            self.available_utxos(dbtx).await,
+            &change_tweak,
+        )
+    }
+
+    /// Pays out all queued peg-outs in a single transaction once the first of
+    /// them waited for `batch_window` blocks
+    async fn create_peg_out_batch(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        batch_window: u32,
+    ) {
+        let queued = self.queued_peg_outs(dbtx).await;
+        let Some(first_height) = queued.iter().map(|(_, queued)| queued.block_height).min() else {
+            return;
+        };
+
+        let consensus_height = self.consensus_height(dbtx).await.unwrap_or(0);
+        if consensus_height < first_height.saturating_add(batch_window) {
+            return;
+        }
+
+        let peg_outs = queued
+            .iter()
+            .map(|(_, queued)| queued.peg_out.clone())
+            .collect::<Vec<_>>();
+        let tx = match self.create_peg_out_batch_tx(dbtx, &peg_outs).await {
+            Ok(tx) => tx,
+            Err(error) => {
+                // Can only happen if UTXOs were spent by RBF transactions in the meantime,
+                // we try again in the next epoch
+                error!(%error, peg_outs = peg_outs.len(), "Unable to create peg-out batch");
+                return;
+            }
+        };
+
+        let txid = self.sign_and_save_peg_out_tx(dbtx, tx).await;
+        info!(%txid, peg_outs = peg_outs.len(), "Created peg-out batch");
+
+        for (key, _) in queued {
+            dbtx.remove_entry(&key).await;
+            dbtx.insert_entry(
+                &PegOutBitcoinTransaction(key.0),
+                &WalletOutputOutcome::Transaction(txid),
+            )
+            .await;
+        }
+        dbtx.remove_entry(&QueuedPegOutTotalKey).await;
+    }
+
+    /// Signs a peg-out transaction, marks its inputs as spent and proposes our
+    /// signatures to the other peers
+    async fn sign_and_save_peg_out_tx(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        mut tx: UnsignedTransaction,
+    ) -> Txid {
+        self.offline_wallet().sign_psbt(&mut tx.psbt);
+        let txid = tx.psbt.unsigned_tx.txid();
+        info!(
+            %txid,
+            "Signing peg out",
+        );
+
+        let sigs = tx
+            .psbt
+            .inputs
+            .iter_mut()
+            .map(|input| {
+                if !input.tap_scripts.is_empty() {
+                    assert_eq!(
+                        input.tap_script_sigs.len(),
+                        1,
+                        "There was already more than one (our) or no signatures in input"
+                    );
+                    let sig = std::mem::take(&mut input.tap_script_sigs)
+                        .into_values()
+                        .next()
+                        .expect("asserted previously");
+                    return PegOutSignature::Schnorr(sig.sig);
+                }
+
+                assert_eq!(
+                    input.partial_sigs.len(),
+                    1,
+                    "There was already more than one (our) or no signatures in input"
+                );
+
+                // TODO: don't put sig into PSBT in the first place
+                // We actually take out our own signature so everyone finalizes the tx in the
+                // same epoch.
+                let sig = std::mem::take(&mut input.partial_sigs)
+                    .into_values()
+                    .next()
+                    .expect("asserted previously");
+
+                // We drop SIGHASH_ALL, because we always use that and it is only present in the
+                // PSBT for compatibility with other tools.
+                PegOutSignature::Ecdsa(
+                    secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                        .expect("we serialized it ourselves that way"),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Delete used UTXOs
+        for input in tx.psbt.unsigned_tx.input.iter() {
+            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+        }
+
+        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
+            .await;
+        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
+            .await;
+        txid
+    }
+
     async fn available_utxos(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
@@ -1122,6 +1376,30 @@ impl Wallet {
         bitcoin::Amount::from_sat(sat_sum)
     }
 
+    async fn update_metrics(&self, dbtx: &mut ModuleDatabaseTransaction<'_>) {
+        let wallet_value = self.get_wallet_value(dbtx).await;
+        WALLET_UTXO_VALUE_SATS.set(wallet_value.to_sat() as i64);
+
+        let unsigned = dbtx
+            .find_by_prefix(&UnsignedTransactionPrefixKey)
+            .await
+            .count()
+            .await;
+        let pending = dbtx
+            .find_by_prefix(&PendingTransactionPrefixKey)
+            .await
+            .count()
+            .await;
+        WALLET_PENDING_PEGOUTS.set((unsigned + pending) as i64);
+
+        let queued = dbtx
+            .find_by_prefix(&QueuedPegOutPrefixKey)
+            .await
+            .count()
+            .await;
+        WALLET_QUEUED_PEGOUTS.set(queued as i64);
+    }
+
     fn offline_wallet(&self) -> StatelessWallet {
         StatelessWallet {
             descriptor: &self.cfg.consensus.peg_in_descriptor,
@@ -1231,18 +1509,15 @@ impl<'a> StatelessWallet<'a> {
 
     /// Attempts to create a tx ready to be signed from available UTXOs.
     //
-    // * `peg_out_amount`: How much the peg-out should be
-    // * `destination`: The address the user is pegging-out to
+    // * `destinations`: The outputs the users are pegging-out to, at least one
     // * `included_utxos`: UXTOs that must be included (for RBF)
     // * `remaining_utxos`: All other spendable UXTOs
     // * `fee_rate`: How much needs to be spent on fees
     // * `change_tweak`: How the federation can recognize it's change UTXO
     // * `rbf`: If this is an RBF transaction
-    #[allow(clippy::too_many_arguments)]
     fn create_tx(
         &self,
-        peg_out_amount: bitcoin::Amount,
-        destination: Script,
+        destinations: Vec<TxOut>,
         mut included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut fee_rate: Feerate,
@@ -1261,8 +1536,18 @@ impl<'a> StatelessWallet<'a> {
         // We then go on to calculate the base size of the transaction `total_weight`
         // and the maximum weight per added input which we will add every time
         // we select an input.
+        let destination = destinations
+            .first()
+            .expect("At least one peg-out output")
+            .script_pubkey
+            .clone();
+        let peg_out_amount =
+            bitcoin::Amount::from_sat(destinations.iter().map(|output| output.value).sum::<u64>());
         let change_script = self.derive_script(change_tweak);
-        let out_weight = (destination.len() * 4 + 1 + 32
+        let out_weight = (destinations
+            .iter()
+            .map(|output| output.script_pubkey.len() * 4 + 1 + 32)
+            .sum::<usize>()
             // Add change script weight, it's very likely to be needed if not we just overpay in fees
             + 1 // script len varint, 1 byte for all addresses we accept
             + change_script.len() * 4 // script len
@@ -1305,16 +1590,12 @@ impl<'a> StatelessWallet<'a> {
         // We always pay ourselves change back to ensure that we don't lose anything due
         // to dust
         let change = total_selected_value - fees - peg_out_amount;
-        let output: Vec<TxOut> = vec![
-            TxOut {
-                value: peg_out_amount.to_sat(),
-                script_pubkey: destination.clone(),
-            },
-            TxOut {
-                value: change.to_sat(),
-                script_pubkey: change_script,
-            },
-        ];
+        let num_destinations = destinations.len();
+        let mut output = destinations;
+        output.push(TxOut {
+            value: change.to_sat(),
+            script_pubkey: change_script,
+        });
         let mut change_out = bitcoin::util::psbt::Output::default();
         change_out
             .proprietary
@@ -1357,10 +1638,40 @@ impl<'a> StatelessWallet<'a> {
             inputs: selected_utxos
                 .iter()
                 .map(|(_utxo_key, utxo)| {
-                    let script_pubkey = self
-                        .descriptor
-                        .tweak(&utxo.tweak, self.secp)
-                        .script_pubkey();
+                    let descriptor = self.descriptor.tweak(&utxo.tweak, self.secp);
+                    let script_pubkey = descriptor.script_pubkey();
+                    let (witness_script, tap_scripts, tap_internal_key, tap_merkle_root) =
+                        match &descriptor {
+                            Descriptor::Tr(tr) => {
+                                let spend_info = tr.spend_info();
+                                let tap_scripts = tr
+                                    .iter_scripts()
+                                    .map(|(_, script)| {
+                                        let leaf = (script.encode(), LeafVersion::TapScript);
+                                        let control_block = spend_info
+                                            .control_block(&leaf)
+                                            .expect("script is part of the tree");
+                                        (control_block, leaf)
+                                    })
+                                    .collect();
+                                (
+                                    None,
+                                    tap_scripts,
+                                    Some(spend_info.internal_key()),
+                                    spend_info.merkle_root(),
+                                )
+                            }
+                            _ => (
+                                Some(
+                                    descriptor
+                                        .script_code()
+                                        .expect("Failed to tweak descriptor"),
+                                ),
+                                Default::default(),
+                                None,
+                                None,
+                            ),
+                        };
                     Input {
                         non_witness_utxo: None,
                         witness_utxo: Some(TxOut {
@@ -1370,12 +1681,7 @@ impl<'a> StatelessWallet<'a> {
                         partial_sigs: Default::default(),
                         sighash_type: None,
                         redeem_script: None,
-                        witness_script: Some(
-                            self.descriptor
-                                .tweak(&utxo.tweak, self.secp)
-                                .script_code()
-                                .expect("Failed to tweak descriptor"),
-                        ),
+                        witness_script,
                         bip32_derivation: Default::default(),
                         final_script_sig: None,
                         final_script_witness: None,
@@ -1388,15 +1694,18 @@ impl<'a> StatelessWallet<'a> {
                             .collect(),
                         tap_key_sig: Default::default(),
                         tap_script_sigs: Default::default(),
-                        tap_scripts: Default::default(),
+                        tap_scripts,
                         tap_key_origins: Default::default(),
-                        tap_internal_key: Default::default(),
-                        tap_merkle_root: Default::default(),
+                        tap_internal_key,
+                        tap_merkle_root,
                         unknown: Default::default(),
                     }
                 })
                 .collect(),
-            outputs: vec![Default::default(), change_out],
+            outputs: std::iter::repeat_with(Default::default)
+                .take(num_destinations)
+                .chain(std::iter::once(change_out))
+                .collect(),
         };
 
         Ok(UnsignedTransaction {
@@ -1414,7 +1723,98 @@ impl<'a> StatelessWallet<'a> {
         })
     }
 
+    /// Creates a tx paying out all `peg_outs` at once.
+    ///
+    /// Every peg-out pays the fees it would have paid on its own, the fees of
+    /// the batch are split among the peg-outs pro rata to these fees. Whatever
+    /// a peg-out paid in excess of its share is added to its output. The
+    /// batch uses the highest fee rate of all peg-outs if the paid fees
+    /// suffice, otherwise all paid fees go to the miners.
+    fn create_batch_tx(
+        &self,
+        peg_outs: &[PegOut],
+        remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        change_tweak: &[u8],
+    ) -> Result<UnsignedTransaction, WalletError> {
+        let fee_rate = peg_outs
+            .iter()
+            .map(|peg_out| peg_out.fees.fee_rate)
+            .max()
+            .expect("Batch contains at least one peg-out");
+        let paid_fees = peg_outs
+            .iter()
+            .map(|peg_out| peg_out.fees.amount().to_sat())
+            .collect::<Vec<_>>();
+        let total_paid_fees: u64 = paid_fees.iter().sum();
+
+        // Select enough UTXOs to pay out the peg-outs including all fees they paid,
+        // the batch fees are deducted from the outputs afterwards
+        let destinations = peg_outs
+            .iter()
+            .zip(&paid_fees)
+            .map(|(peg_out, paid_fee)| TxOut {
+                value: peg_out.amount.to_sat() + paid_fee,
+                script_pubkey: peg_out.recipient.script_pubkey(),
+            })
+            .collect();
+        let mut tx = self.create_tx(
+            destinations,
+            vec![],
+            remaining_utxos,
+            fee_rate,
+            change_tweak,
+            None,
+        )?;
+
+        let batch_fee = tx.fees.amount().to_sat().min(total_paid_fees);
+        let mut fee_shares = 0;
+        for (output, paid_fee) in tx.psbt.unsigned_tx.output.iter_mut().zip(&paid_fees) {
+            let fee_share = if total_paid_fees == 0 {
+                0
+            } else {
+                (batch_fee as u128 * *paid_fee as u128 / total_paid_fees as u128) as u64
+            };
+            output.value -= fee_share;
+            fee_shares += fee_share;
+        }
+
+        // The change doesn't pay any fees since they were all taken from the outputs
+        tx.change += tx.fees.amount();
+        tx.psbt
+            .unsigned_tx
+            .output
+            .last_mut()
+            .expect("Contains change output")
+            .value = tx.change.to_sat();
+        tx.peg_out_amount = bitcoin::Amount::from_sat(
+            tx.psbt.unsigned_tx.output[..peg_outs.len()]
+                .iter()
+                .map(|output| output.value)
+                .sum(),
+        );
+        tx.fees = PegOutFees::new(
+            fee_shares * 1000 / tx.fees.total_weight,
+            tx.fees.total_weight,
+        );
+
+        info!(
+            peg_outs = peg_outs.len(),
+            paid_fees_sats = total_paid_fees,
+            fees_sats = fee_shares,
+            change_sats = tx.change.to_sat(),
+            txid = %tx.psbt.unsigned_tx.txid(),
+            "Creating peg-out batch tx",
+        );
+
+        Ok(tx)
+    }
+
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
+        let prevouts = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+            .collect::<Vec<_>>();
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
 
         for (idx, (psbt_input, _tx_input)) in psbt
@@ -1432,6 +1832,32 @@ impl<'a> StatelessWallet<'a> {
                 self.secret_key.tweak(tweak, self.secp)
             };
 
+            if !psbt_input.tap_scripts.is_empty() {
+                let leaf_hash = tap_leaf_hash(psbt_input);
+                let tx_hash = tx_hasher
+                    .taproot_script_spend_signature_hash(
+                        idx,
+                        &Prevouts::All(&prevouts),
+                        leaf_hash,
+                        SchnorrSighashType::Default,
+                    )
+                    .expect("Failed to create taproot sighash");
+
+                let keypair = KeyPair::from_secret_key(self.secp, &tweaked_secret);
+                let signature = self
+                    .secp
+                    .sign_schnorr(&Message::from_slice(&tx_hash[..]).unwrap(), &keypair);
+
+                psbt_input.tap_script_sigs.insert(
+                    (keypair.x_only_public_key().0, leaf_hash),
+                    SchnorrSig {
+                        sig: signature,
+                        hash_ty: SchnorrSighashType::Default,
+                    },
+                );
+                continue;
+            }
+
             let tx_hash = tx_hasher
                 .segwit_signature_hash(
                     idx,
@@ -1510,18 +1936,31 @@ impl<'a> StatelessWallet<'a> {
     }
 }
 
+/// Hash of the multisig script path leaf of a taproot peg-out input
+fn tap_leaf_hash(input: &Input) -> TapLeafHash {
+    let (script, leaf_version) = input
+        .tap_scripts
+        .values()
+        .next()
+        .expect("Missing tap script");
+    TapLeafHash::from_script(script, *leaf_version)
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeSet;
     use std::str::FromStr;
 
     use bitcoin::Network::{Bitcoin, Testnet};
-    use bitcoin::{Address, Amount, Network, OutPoint, Txid};
+    use bitcoin::{Address, Amount, Network, OutPoint, TxOut, Txid};
+    use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
     use fedimint_core::{BitcoinHash, Feerate, PeerId};
+    use fedimint_wallet_common::config::{PegInDescriptorType, WalletConfig};
     use fedimint_wallet_common::{
         PegOut, PegOutFees, Rbf, RoundConsensus, RoundConsensusItem, WalletOutput,
     };
     use miniscript::descriptor::Wsh;
+    use miniscript::psbt::PsbtExt;
 
     use crate::common::PegInDescriptor;
     use crate::{
@@ -1612,8 +2051,10 @@ mod tests {
 
         // not enough SpendableUTXO
         let tx = wallet.create_tx(
-            Amount::from_sat(2000),
-            recipient.script_pubkey(),
+            vec![TxOut {
+                value: 2000,
+                script_pubkey: recipient.script_pubkey(),
+            }],
             vec![],
             vec![(UTXOKey(OutPoint::null()), spendable.clone())],
             fee,
@@ -1625,8 +2066,10 @@ mod tests {
         // successful tx creation
         let mut tx = wallet
             .create_tx(
-                Amount::from_sat(1000),
-                recipient.script_pubkey(),
+                vec![TxOut {
+                    value: 1000,
+                    script_pubkey: recipient.script_pubkey(),
+                }],
                 vec![],
                 vec![(UTXOKey(OutPoint::null()), spendable)],
                 fee,
@@ -1673,6 +2116,177 @@ mod tests {
         assert_eq!(res, Err(WalletError::WrongNetwork(Testnet, Bitcoin)));
     }
 
+    #[test]
+    fn create_batch_tx_should_split_fees() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let utxos = (0..2)
+            .map(|vout| {
+                (
+                    UTXOKey(OutPoint {
+                        txid: Txid::all_zeros(),
+                        vout,
+                    }),
+                    SpendableUTXO {
+                        tweak: [0; 32],
+                        amount: Amount::from_sat(100_000),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let peg_outs = [(10_000, 1000), (20_000, 2000)]
+            .into_iter()
+            .map(|(sats, sats_per_kvb)| {
+                // Every peg-out pays the fees quoted for a single peg-out
+                let fees = wallet
+                    .create_tx(
+                        vec![TxOut {
+                            value: sats,
+                            script_pubkey: recipient.script_pubkey(),
+                        }],
+                        vec![],
+                        utxos.clone(),
+                        Feerate { sats_per_kvb },
+                        &[],
+                        None,
+                    )
+                    .expect("is ok")
+                    .fees;
+                PegOut {
+                    recipient: recipient.clone(),
+                    amount: Amount::from_sat(sats),
+                    fees,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let tx = wallet
+            .create_batch_tx(&peg_outs, utxos, &[])
+            .expect("is ok");
+        let outputs = &tx.psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 3);
+
+        let input_sats: u64 = tx
+            .selected_utxos
+            .iter()
+            .map(|(_, utxo)| utxo.amount.to_sat())
+            .sum();
+        let paid_sats: u64 = peg_outs
+            .iter()
+            .map(|peg_out| (peg_out.amount + peg_out.fees.amount()).to_sat())
+            .sum();
+        let output_sats: u64 = outputs.iter().map(|output| output.value).sum();
+        let fee_sats = input_sats - output_sats;
+
+        // The change is the same as if every peg-out had been paid out on its own
+        assert_eq!(tx.change.to_sat(), input_sats - paid_sats);
+        assert_eq!(outputs[2].value, tx.change.to_sat());
+        assert_eq!(
+            tx.peg_out_amount.to_sat(),
+            outputs[0].value + outputs[1].value
+        );
+
+        // Batching is cheaper, so every peg-out gets back part of its fees
+        for (output, peg_out) in outputs.iter().zip(&peg_outs) {
+            assert!(output.value > peg_out.amount.to_sat());
+            assert!(output.value < (peg_out.amount + peg_out.fees.amount()).to_sat());
+        }
+        assert!(fee_sats < peg_outs.iter().map(|p| p.fees.amount().to_sat()).sum());
+        assert!(tx.fees.amount().to_sat() <= fee_sats);
+    }
+
+    #[test]
+    fn tr_peg_out_tx_should_be_finalizable() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let secret_keys = (0..4)
+            .map(|_| secp.generate_keypair(&mut OsRng).0)
+            .collect::<Vec<_>>();
+        let cfg = WalletConfig::new(
+            secret_keys
+                .iter()
+                .enumerate()
+                .map(|(idx, secret_key)| {
+                    let key = secp256k1::PublicKey::from_secret_key(&secp, secret_key);
+                    (PeerId::from(idx as u16), CompressedPublicKey { key })
+                })
+                .collect(),
+            secret_keys[0],
+            3,
+            Network::Regtest,
+            10,
+            None,
+            PegInDescriptorType::TrScriptOnly,
+            BitcoinRpcConfig {
+                kind: "bitcoind".to_string(),
+                url: "http://127.0.0.1:18443".parse().unwrap(),
+            },
+        );
+        let descriptor = cfg.consensus.peg_in_descriptor;
+        assert!(matches!(descriptor, PegInDescriptor::Tr(_)));
+
+        let wallets = secret_keys
+            .iter()
+            .map(|secret_key| StatelessWallet {
+                descriptor: &descriptor,
+                secret_key,
+                secp: &secp,
+            })
+            .collect::<Vec<_>>();
+
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let utxo = SpendableUTXO {
+            tweak: [1; 32],
+            amount: Amount::from_sat(100_000),
+        };
+
+        let mut tx = wallets[0]
+            .create_tx(
+                vec![TxOut {
+                    value: 50_000,
+                    script_pubkey: recipient.script_pubkey(),
+                }],
+                vec![],
+                vec![(UTXOKey(OutPoint::null()), utxo)],
+                Feerate { sats_per_kvb: 1000 },
+                &[2; 32],
+                None,
+            )
+            .expect("is ok");
+
+        for wallet in &wallets[..3] {
+            wallet.sign_psbt(&mut tx.psbt);
+        }
+        assert_eq!(tx.psbt.inputs[0].tap_script_sigs.len(), 3);
+
+        tx.psbt.finalize_mut(&secp).expect("is finalizable");
+
+        // Fees have to be estimated for the largest possible witness
+        let signed_tx = tx.psbt.extract_tx();
+        assert!(signed_tx.weight() as u64 <= tx.fees.total_weight);
+    }
+
     fn rbf(sats_per_kvb: u64, total_weight: u64) -> WalletOutput {
         WalletOutput::Rbf(Rbf {
             fees: PegOutFees::new(sats_per_kvb, total_weight),
@@ -1695,14 +2309,14 @@ mod fedimint_migration_tests {
     use fedimint_core::{BitcoinHash, Feerate, OutPoint, ServerModule, TransactionId};
     use fedimint_testing::db::{prepare_snapshot, validate_migrations, BYTE_20, BYTE_32};
     use fedimint_wallet_common::db::{
-        BlockHashKey, BlockHashKeyPrefix, DbKeyPrefix, PegOutBitcoinTransaction,
-        PegOutBitcoinTransactionPrefix, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix,
+        BlockHashKey, BlockHashKeyPrefix, DbKeyPrefix, PegOutBitcoinTransactionPrefix,
+        PegOutBitcoinTransactionV0, PegOutTxSignatureCIPrefix, PegOutTxSignatureCIV1,
         PendingTransactionKey, PendingTransactionPrefixKey, RoundConsensusKey, UTXOKey,
         UTXOPrefixKey, UnsignedTransactionKey, UnsignedTransactionPrefixKey,
     };
     use fedimint_wallet_common::{
-        PegOutFees, PendingTransaction, Rbf, RoundConsensus, SpendableUTXO, UnsignedTransaction,
-        WalletCommonGen, WalletOutputOutcome,
+        PegOutFees, PegOutSignature, PendingTransaction, Rbf, RoundConsensus, SpendableUTXO,
+        UnsignedTransaction, WalletCommonGen, WalletOutputOutcome,
     };
     use futures::StreamExt;
     use rand::rngs::OsRng;
@@ -1843,21 +2457,18 @@ mod fedimint_migration_tests {
         let secp = secp256k1::Secp256k1::new();
         let signature = secp.sign_ecdsa(&Message::from_slice(&BYTE_32).unwrap(), &sk);
         dbtx.insert_new_entry(
-            &PegOutTxSignatureCI(Txid::from_slice(&BYTE_32).unwrap()),
+            &PegOutTxSignatureCIV1(Txid::from_slice(&BYTE_32).unwrap()),
             &vec![signature],
         )
         .await;
 
-        let peg_out_bitcoin_tx = PegOutBitcoinTransaction(OutPoint {
+        let peg_out_bitcoin_tx = PegOutBitcoinTransactionV0(OutPoint {
             txid: TransactionId::from_slice(&BYTE_32).unwrap(),
             out_idx: 0,
         });
 
-        dbtx.insert_new_entry(
-            &peg_out_bitcoin_tx,
-            &WalletOutputOutcome(Txid::from_slice(&BYTE_32).unwrap()),
-        )
-        .await;
+        dbtx.insert_new_entry(&peg_out_bitcoin_tx, &Txid::from_slice(&BYTE_32).unwrap())
+            .await;
 
         dbtx.commit_tx().await;
     }
@@ -1925,6 +2536,10 @@ mod fedimint_migration_tests {
                                 num_outpoints > 0,
                                 "validate_migrations was not able to read any PegOutBitcoinTransactions"
                             );
+                            assert!(outpoints.iter().all(|(_, outcome)| matches!(
+                                outcome,
+                                WalletOutputOutcome::Transaction(_)
+                            )));
                         }
                         DbKeyPrefix::PegOutTxSigCi => {
                             let sigs = dbtx
@@ -1937,6 +2552,10 @@ mod fedimint_migration_tests {
                                 num_sigs > 0,
                                 "validate_migrations was not able to read any PegOutTxSigCi"
                             );
+                            assert!(sigs.iter().flat_map(|(_, sigs)| sigs).all(|sig| matches!(
+                                sig,
+                                PegOutSignature::Ecdsa(_)
+                            )));
                         }
                         DbKeyPrefix::PendingTransaction => {
                             let pending_txs = dbtx
@@ -1980,6 +2599,7 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any UTXOs"
                             );
                         }
+                        DbKeyPrefix::QueuedPegOut | DbKeyPrefix::QueuedPegOutTotal => {}
                     }
                 }
             },
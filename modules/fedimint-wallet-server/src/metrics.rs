@@ -0,0 +1,33 @@
+use fedimint_metrics::{opts, register_int_gauge_with_registry, IntGauge, REGISTRY};
+use once_cell::sync::Lazy;
+
+pub(crate) static WALLET_UTXO_VALUE_SATS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        opts!(
+            "wallet_utxo_value_sats",
+            "Value of the UTXOs controlled by the federation wallet"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static WALLET_PENDING_PEGOUTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        opts!(
+            "wallet_pending_pegouts",
+            "Number of peg-out transactions that are not signed or confirmed yet"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+pub(crate) static WALLET_QUEUED_PEGOUTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        opts!(
+            "wallet_queued_pegouts",
+            "Number of peg-outs waiting to be paid out in the next batch transaction"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
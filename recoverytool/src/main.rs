@@ -286,6 +286,9 @@ fn round_tweak(module_cis: impl Iterator<Item = DynModuleConsensusItem>) -> [u8;
         .fold([0; 32], xor)
 }
 
+/// Tweaks all keys of the descriptor and replaces our tweaked public key with
+/// the corresponding private key. `tr` descriptors have an unspendable internal
+/// key, the funds can only be spent through the multisig script path.
 fn tweak_descriptor(
     base_descriptor: &PegInDescriptor,
     base_sk: &SecretKey,